@@ -22,6 +22,13 @@
         float4x4 model; \
     }
 
+// cbuffer MaterialData
+#define CBUFFER_MATERIAL_DATA(slot) \
+    cbuffer MaterialData : register(slot) { \
+        float4 material_color; \
+        float4 material_parameters; \
+    }
+
 // cbuffer DirectionalLight
 #define CBUFFER_DIRECTIONAL_LIGHT(slot) \
     cbuffer DirectionalLight : register(slot) { \
@@ -40,6 +47,7 @@
 #define CBUFFER_IMAGE_LIGHT(slot) \
     cbuffer ImageLight : register(slot) { \
         float4 image_intensity; \
+        float4 image_irradiance[9]; \
     }
 
 #endif
@@ -3,6 +3,7 @@
 
 CBUFFER_VIEW_MATRICES(b0);
 CBUFFER_MODEL_DATA(b1);
+CBUFFER_MATERIAL_DATA(b2);
 
 Texture2D albedo : register(t0);
 
@@ -17,7 +18,10 @@ GBufferInput vertex_main(VsInput input) {
 
 GBufferOutput pixel_main(GBufferInput input) {
     GBufferOutput output;
-    output.albedo = float4(albedo.Sample(globalSampler, input.uv).rgb, 1.0);
+    float4 albedo_color = albedo.Sample(globalSampler, input.uv) * material_color;
+    clip(albedo_color.a - material_parameters.x);
+
+    output.albedo = float4(albedo_color.rgb, 1.0);
     output.world_position = input.world_position;
     output.world_normal = float4(input.world_normal.rgb, 1.0);
 
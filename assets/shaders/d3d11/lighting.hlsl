@@ -67,21 +67,43 @@ LightingOutput pixel_point(LightingInput input) {
     return output;
 }
 
+/// 3 次の球面調和関数で表された放射照度を評価する。
+/// 係数の順序は derky::common::ibl::SphericalHarmonics に従う。
+float3 evaluate_irradiance(float3 n) {
+    return image_irradiance[0].rgb * 0.282095
+        + image_irradiance[1].rgb * 0.488603 * n.y
+        + image_irradiance[2].rgb * 0.488603 * n.z
+        + image_irradiance[3].rgb * 0.488603 * n.x
+        + image_irradiance[4].rgb * 1.092548 * n.x * n.y
+        + image_irradiance[5].rgb * 1.092548 * n.y * n.z
+        + image_irradiance[6].rgb * 0.315392 * (3.0 * n.z * n.z - 1.0)
+        + image_irradiance[7].rgb * 1.092548 * n.x * n.z
+        + image_irradiance[8].rgb * 0.546274 * (n.x * n.x - n.y * n.y);
+}
+
 /// Image Light
+/// 拡散反射には球面調和関数の放射照度を使う。
+/// 放射照度がない場合 (image_intensity.y が 0) だけ、反射方向の環境光で代用する。
 LightingOutput pixel_image(LightingInput input) {
     float3 position = world_position.Sample(globalSampler, input.uv).xyz;
-    float3 normal = world_normal.Sample(globalSampler, input.uv).xyz;
-
-    float3 camera_position = transpose(view_inv)[3].xyz;
-    float3 camera_ray = normalize(position - camera_position);
-    float3 reflection = reflect(camera_ray, normal);
-
-    float xz_angle = atan2(reflection.z, reflection.x);
-    float yz_angle = -sign(reflection.y) * acos(dot(reflection, normalize(float3(reflection.x, 0.0, reflection.z))));
-    float2 image_uv = float2((xz_angle + PI) / (2.0 * PI), yz_angle / PI + 0.5);
+    float3 normal = normalize(world_normal.Sample(globalSampler, input.uv).xyz);
+
+    float3 diffuse;
+    if (image_intensity.y > 0.0) {
+        diffuse = max(0, evaluate_irradiance(normal));
+    } else {
+        float3 camera_position = transpose(view_inv)[3].xyz;
+        float3 camera_ray = normalize(position - camera_position);
+        float3 reflection = reflect(camera_ray, normal);
+
+        float xz_angle = atan2(reflection.z, reflection.x);
+        float yz_angle = -sign(reflection.y) * acos(dot(reflection, normalize(float3(reflection.x, 0.0, reflection.z))));
+        float2 image_uv = float2((xz_angle + PI) / (2.0 * PI), yz_angle / PI + 0.5);
+        diffuse = light_image.Sample(globalSampler, image_uv).rgb;
+    }
 
     LightingOutput output;
-    output.intensity = float4(albedo.Sample(globalSampler, input.uv).rgb * light_image.Sample(globalSampler, image_uv).rgb * image_intensity.r, 1.0);
+    output.intensity = float4(albedo.Sample(globalSampler, input.uv).rgb * diffuse * image_intensity.r, 1.0);
 
     return output;
 }
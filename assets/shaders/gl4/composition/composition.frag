@@ -94,8 +94,8 @@ void main() {
     // ACES Filmic
     final_color = vec3(aces_filmic(final_color.r), aces_filmic(final_color.g), aces_filmic(final_color.b));
 
-    // OpenGL は線形出力に対して常に x^(1.0/2.2) のガンマ補正を行う？
-    // final_color = vec3(pow(final_color.r, 1.0 / 2.2), pow(final_color.g, 1.0 / 2.2), pow(final_color.b, 1.0 / 2.2));
+    // アルベドは線形にデコードされているので、出力前にガンマ補正する
+    final_color = vec3(pow(final_color.r, 1.0 / 2.2), pow(final_color.g, 1.0 / 2.2), pow(final_color.b, 1.0 / 2.2));
 
     color = vec4(final_color, 1.0);
 }
@@ -1,6 +1,8 @@
 #version 450
 
 uniform sampler2D material_albedo;
+uniform vec4 material_color;
+uniform float material_alpha_cutoff;
 
 // per fragment
 smooth in vec2 v_uv;
@@ -12,9 +14,9 @@ out vec4 out_position;
 out vec4 out_world_normal;
 
 void main() {
-    vec4 albedo = texture(material_albedo, v_uv);
+    vec4 albedo = texture(material_albedo, v_uv) * material_color;
 
-    if (albedo.a < 0.5) {
+    if (albedo.a < material_alpha_cutoff) {
         discard;
     }
 
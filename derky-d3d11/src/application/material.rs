@@ -0,0 +1,80 @@
+//! マテリアル内容を記述するモジュール。
+
+use anyhow::Result;
+use derky::{
+    common::{
+        cache::AssetCache,
+        material::{AlphaMode, MaterialDescription, MaterialTextures},
+        texture::{BlockFormat, MipmapOptions},
+    },
+    d3d11::{buffer::ConstantBuffer, context::Device, texture::Texture},
+};
+use ultraviolet::Vec4;
+
+/// シェーダーに渡るマテリアルのパラメーター。
+/// `_cbuffers.hlsli` の `MaterialData` に対応する。
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MaterialData {
+    /// アルベドに乗算される色
+    pub color: Vec4,
+
+    /// x: アルファテストの閾値
+    pub parameters: Vec4,
+}
+
+/// マテリアル定義
+pub struct Material {
+    /// アルベドテクスチャ
+    pub albedo: Texture,
+
+    /// `MaterialData` の `ConstantBuffer`
+    pub constants: ConstantBuffer<MaterialData>,
+}
+
+impl Material {
+    /// `MaterialDescription` と解決済みのテクスチャから GPU リソースを生成する。
+    /// シェーダーが対応していないので、アルベド以外のテクスチャはまだ使われない。
+    /// 圧縮したテクスチャは `AssetCache` に保存される。
+    pub fn new(
+        device: &Device,
+        cache: &AssetCache,
+        textures: &MaterialTextures,
+        description: &MaterialDescription,
+    ) -> Result<Material> {
+        // G-Buffer には半透明を書き込めないので、Blend はアルファテストで代用する
+        // ラスタライザーステートは未実装なので double_sided は無視される
+        let alpha_cutoff = match description.alpha_mode {
+            AlphaMode::Blend => 0.5,
+            _ => description.alpha_cutoff(),
+        };
+
+        // カットアウトが遠くで消えないように、アルファテストを通る割合を保つ
+        let mipmaps = textures.albedo.image.generate_mipmaps(&MipmapOptions {
+            wrap: true,
+            alpha_cutoff: if alpha_cutoff > 0.0 {
+                Some(alpha_cutoff)
+            } else {
+                None
+            },
+            ..Default::default()
+        });
+
+        // VRAM を節約するため BC7 に圧縮してアップロードする
+        // D3D11 のブロック圧縮テクスチャは大きさが 4 の倍数でなければならない
+        let (width, height) = mipmaps.base().dimensions();
+        let albedo = if width.is_multiple_of(4) && height.is_multiple_of(4) {
+            Texture::compressed(device, &cache.compress_mipmaps(&mipmaps, BlockFormat::Bc7))?
+        } else {
+            Texture::with_mipmaps(device, &mipmaps)?
+        };
+        let constants = ConstantBuffer::new_immutable(
+            device,
+            &MaterialData {
+                color: description.base_color,
+                parameters: Vec4::new(alpha_cutoff, 0.0, 0.0, 0.0),
+            },
+        )?;
+
+        Ok(Material { albedo, constants })
+    }
+}
@@ -1,5 +1,7 @@
+mod material;
 mod model;
 
+use material::Material;
 use model::{load_obj, ModelVertex, MODEL_VERTEX_LAYOUT};
 
 use std::{
@@ -11,9 +13,14 @@ use std::{
 use anyhow::Result;
 use derky::{
     common::{
+        asset::DirectorySource,
+        cache::AssetCache,
         environment::{Environment, ImageLight, PointLight, View},
-        model::Model,
-        texture::Rgba,
+        frustum::{DepthRange, Frustum},
+        ibl::{ShOrder, SphericalHarmonics},
+        material::{MaterialDescription, TextureResolver},
+        scene::{Scene, Transform},
+        texture::{load_hdr_image, Rgba},
     },
     d3d11::{
         buffer::{ConstantBuffer, IndexBuffer, RwBuffer, VertexBuffer},
@@ -23,7 +30,9 @@ use derky::{
         },
         shader::{ComputeShader, InputLayout, PixelShader, VertexShader},
         texture::{DepthStencil, RenderTarget, Sampler, Texture},
-        vertex::{Topology, Vertex, SCREEN_QUAD_INDICES, SCREEN_QUAD_VERTICES},
+        vertex::{
+            Topology, Vertex, SCREEN_QUAD_INDICES, SCREEN_QUAD_VERTICES, VERTEX_INPUT_LAYOUT,
+        },
     },
 };
 use log::info;
@@ -62,11 +71,11 @@ pub struct Application {
     /// 環境
     environment: Environment<Texture>,
 
-    /// モデル
-    model: Model<(VertexBuffer<ModelVertex>, IndexBuffer<u32>), Texture>,
+    /// シーン
+    scene: Scene<(VertexBuffer<ModelVertex>, IndexBuffer<u32>), Material>,
 
-    /// 部屋のモデル
-    room_model: Model<(VertexBuffer<ModelVertex>, IndexBuffer<u32>), Texture>,
+    /// マテリアルが指定されていないグループに使う `Material`
+    default_material: Material,
 
     // D3D11 に対応するリソース ---------------------------------
     /// `VertexShader` のコレクション
@@ -87,6 +96,9 @@ pub struct Application {
     /// モデル用の `InputLayout`
     input_layout: InputLayout,
 
+    /// スクリーン用の `InputLayout`
+    screen_input_layout: InputLayout,
+
     /// 共通の `Sampler`,
     sampler: Sampler,
 
@@ -96,6 +108,10 @@ pub struct Application {
     /// ライトパラメータ共通の `ConstantBuffer`
     cb_light: ConstantBuffer<[Vec4; 4]>,
 
+    /// `ImageLight` の `ConstantBuffer`
+    /// 強度と放射照度の球面調和関数係数 9 個
+    cb_image_light: ConstantBuffer<[Vec4; 10]>,
+
     /// モデル行列の `ConstantBuffer`
     cb_model: ConstantBuffer<Mat4>,
 
@@ -132,9 +148,14 @@ impl Application {
             perspective_dx(60f32.to_radians(), 16.0 / 9.0, 0.1, 1024.0),
             Vec2::new(1280.0, 720.0),
         ));
+        let background = load_hdr_image("assets/models/background.exr")?;
         environment.image_light = Some(ImageLight {
             intensity: 0.5,
-            texture: Texture::load_hdr(device, "assets/models/background.exr")?,
+            texture: Texture::new(device, &background.resize_to_power_of_2())?,
+            irradiance: Some(
+                SphericalHarmonics::project_equirect(&background, ShOrder::Third)
+                    .convolve_lambert(),
+            ),
         });
         environment.point_lights = vec![
             PointLight {
@@ -155,8 +176,21 @@ impl Application {
             },
         ];
 
-        let model = load_obj(device, "assets/models/Natsuki.obj")?;
-        let room_model = load_obj(device, "assets/models/Room.obj")?;
+        let assets = DirectorySource::new("assets");
+        let cache = AssetCache::new(".derky-cache");
+        let mut scene = Scene::new();
+        let model = scene.add_model(load_obj(device, &assets, &cache, "models/Natsuki.obj")?);
+        let room_model = scene.add_model(load_obj(device, &assets, &cache, "models/Room.obj")?);
+        scene.add_node(None, Some("Natsuki"), Transform::identity(), Some(model));
+        scene.add_node(None, Some("Room"), Transform::identity(), Some(room_model));
+
+        let default_description = MaterialDescription::default();
+        let default_material = Material::new(
+            device,
+            &cache,
+            &TextureResolver::new(&assets, None).resolve(&default_description),
+            &default_description,
+        )?;
 
         let mut vertex_shaders = HashMap::new();
         let mut pixel_shaders = HashMap::new();
@@ -241,6 +275,11 @@ impl Application {
             &MODEL_VERTEX_LAYOUT,
             &vertex_shaders[&ShaderKind::Geometry].binary(),
         )?;
+        let screen_input_layout = InputLayout::create(
+            device,
+            &VERTEX_INPUT_LAYOUT,
+            vertex_shaders[&ShaderKind::Screen].binary(),
+        )?;
         let sampler = Sampler::new(device)?;
 
         // Buffers
@@ -252,6 +291,7 @@ impl Application {
         let cb_view = ConstantBuffer::new(device, &Default::default())?;
         let cb_model = ConstantBuffer::new(device, &Mat4::identity())?;
         let cb_light = ConstantBuffer::new(device, &[Vec4::zero(); 4])?;
+        let cb_image_light = ConstantBuffer::new(device, &[Vec4::zero(); 10])?;
 
         // G-Buffer
         let g_buffer: Box<_> = (0..4)
@@ -270,18 +310,20 @@ impl Application {
 
         Ok(Application {
             environment,
-            model,
-            room_model,
+            scene,
+            default_material,
             vertex_shaders,
             pixel_shaders,
             cs_luminance,
             blend_states,
             input_layout,
+            screen_input_layout,
             sampler,
             screen_buffers,
             cb_view,
             cb_model,
             cb_light,
+            cb_image_light,
             uav_luminance,
             g_buffer,
             g_buffer_texture,
@@ -348,16 +390,18 @@ impl Application {
         context.set_constant_buffer_pixel(1, &self.cb_model);
         context.set_sampler(0, Some(&self.sampler));
 
-        for ((vb, ib), texture) in self.model.visit() {
-            context.set_texture(0, texture);
-            context.set_vertices(&vb, &ib, Topology::Triangles);
-            context.draw_with_indices(ib.len());
-        }
-
-        for ((vb, ib), texture) in self.room_model.visit() {
-            context.set_texture(0, texture);
-            context.set_vertices(&vb, &ib, Topology::Triangles);
-            context.draw_with_indices(ib.len());
+        let view_projection = self.environment.view.view_projection();
+        for (world_matrix, model) in self.scene.instances() {
+            self.cb_model.update(context, world_matrix);
+            let frustum =
+                Frustum::from_matrix(&(view_projection * *world_matrix), DepthRange::ZeroToOne);
+            for ((vb, ib), material, _) in model.visit_visible(&frustum) {
+                let material = material.unwrap_or(&self.default_material);
+                context.set_texture(0, Some(&material.albedo));
+                context.set_constant_buffer_pixel(2, &material.constants);
+                context.set_vertices(vb, ib, Topology::Triangles);
+                context.draw_with_indices(ib.len());
+            }
         }
 
         context.reset_render_targets();
@@ -388,7 +432,7 @@ impl Application {
 
         // Directional Lighting
         context.set_shaders(
-            &self.input_layout,
+            &self.screen_input_layout,
             &self.vertex_shaders[&ShaderKind::Screen],
             &self.pixel_shaders[&ShaderKind::DirectionalLighting],
         );
@@ -408,7 +452,7 @@ impl Application {
 
         // Point Lighting
         context.set_shaders(
-            &self.input_layout,
+            &self.screen_input_layout,
             &self.vertex_shaders[&ShaderKind::Screen],
             &self.pixel_shaders[&ShaderKind::PointLighting],
         );
@@ -429,20 +473,20 @@ impl Application {
         // Image Lighting
         if let Some(light) = &self.environment.image_light {
             context.set_shaders(
-                &self.input_layout,
+                &self.screen_input_layout,
                 &self.vertex_shaders[&ShaderKind::Screen],
                 &self.pixel_shaders[&ShaderKind::ImageLighting],
             );
-            self.cb_light.update(
-                context,
-                &[
-                    Vec4::new(light.intensity, 0.0, 0.0, 0.0),
-                    Vec4::zero(),
-                    Vec4::zero(),
-                    Vec4::zero(),
-                ],
-            );
-            context.set_constant_buffer_pixel(1, &self.cb_light);
+            // 放射照度があれば y を 1 にして拡散反射に使う
+            // なければ反射方向の環境光で代用する
+            let mut constants = [Vec4::zero(); 10];
+            constants[0] = Vec4::new(light.intensity, 0.0, 0.0, 0.0);
+            if let Some(irradiance) = &light.irradiance {
+                constants[0].y = 1.0;
+                constants[1..].copy_from_slice(&irradiance.to_constants());
+            }
+            self.cb_image_light.update(context, &constants);
+            context.set_constant_buffer_pixel(1, &self.cb_image_light);
             context.set_texture(3, Some(&light.texture));
             context.draw_with_indices(self.screen_buffers.1.len());
         }
@@ -485,7 +529,7 @@ impl Application {
         context.set_texture(4, Some(&self.g_buffer_ds_texture));
         context.set_texture(5, Some(&self.lighting_buffer_texture));
         context.set_shaders(
-            &self.input_layout,
+            &self.screen_input_layout,
             &self.vertex_shaders[&ShaderKind::Screen],
             &self.pixel_shaders[&ShaderKind::Screen],
         );
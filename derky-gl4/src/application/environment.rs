@@ -4,6 +4,7 @@ use crate::rendering::load_exr_texture;
 use std::time::Duration;
 
 use anyhow::Result;
+use derky::common::frustum::{DepthRange, Frustum};
 use glium::{backend::Facade, uniform, uniforms::Uniforms, Texture2d};
 use ultraviolet::{projection::perspective_gl, Mat4, Vec3};
 
@@ -142,6 +143,15 @@ impl Environment {
         self.camera_position = position;
     }
 
+    /// モデル空間での視錐台を取得する。
+    pub fn frustum(&self, model_matrix: Mat4) -> Frustum {
+        let view = Mat4::from_translation(-self.camera_position);
+        Frustum::from_matrix(
+            &(self.projection_matrix * view * model_matrix),
+            DepthRange::NegativeOneToOne,
+        )
+    }
+
     /// uniforms を追加する。
     pub fn get_unforms(&self) -> impl Uniforms {
         let view: [[f32; 4]; 4] = Mat4::from_translation(-self.camera_position).into();
@@ -1,22 +1,69 @@
 //! マテリアル内容を記述するモジュール。
 
-use glium::Texture2d;
+use crate::rendering::compressed_srgb_texture;
+
+use anyhow::Result;
+use derky::common::{
+    cache::AssetCache,
+    material::{AlphaMode, MaterialDescription, MaterialTextures},
+    texture::{BlockFormat, MipmapOptions},
+};
+use glium::{backend::Facade, texture::CompressedSrgbTexture2d};
 use ultraviolet::Vec4;
 
 /// マテリアル定義
 #[derive(Debug)]
-#[allow(dead_code)]
-pub enum Material {
-    /// Lambert モデル
-    Diffuse { albedo: Texture2d, color: Vec4 },
-
-    /// Phong モデル
-    Specular {
-        albedo: Texture2d,
-        color: Vec4,
-        intensity: f32,
-    },
-
-    /// Unlit (単純なテクスチャマッピング)
-    Unlit { albedo: Texture2d },
+pub struct Material {
+    /// アルベドテクスチャ
+    pub albedo: CompressedSrgbTexture2d,
+
+    /// アルベドに乗算される色
+    pub color: Vec4,
+
+    /// これ未満のアルファ値のフラグメントは破棄される
+    pub alpha_cutoff: f32,
+
+    /// 裏面も描画するかどうか
+    pub double_sided: bool,
+}
+
+impl Material {
+    /// `MaterialDescription` と解決済みのテクスチャから GPU リソースを生成する。
+    /// シェーダーが対応していないので、アルベド以外のテクスチャはまだ使われない。
+    /// 圧縮したテクスチャは `AssetCache` に保存される。
+    pub fn new(
+        facade: &impl Facade,
+        cache: &AssetCache,
+        textures: &MaterialTextures,
+        description: &MaterialDescription,
+    ) -> Result<Material> {
+        // G-Buffer には半透明を書き込めないので、Blend はアルファテストで代用する
+        let alpha_cutoff = match description.alpha_mode {
+            AlphaMode::Blend => 0.5,
+            _ => description.alpha_cutoff(),
+        };
+
+        // カットアウトが遠くで消えないように、アルファテストを通る割合を保つ
+        let mipmaps = textures.albedo.image.generate_mipmaps(&MipmapOptions {
+            wrap: true,
+            alpha_cutoff: if alpha_cutoff > 0.0 {
+                Some(alpha_cutoff)
+            } else {
+                None
+            },
+            ..Default::default()
+        });
+
+        // VRAM を節約するため BC7 に圧縮してアップロードする
+        // アルベドは sRGB なので、サンプリング時に線形へデコードされる
+        let albedo =
+            compressed_srgb_texture(facade, &cache.compress_mipmaps(&mipmaps, BlockFormat::Bc7))?;
+
+        Ok(Material {
+            albedo,
+            color: description.base_color,
+            alpha_cutoff,
+            double_sided: description.double_sided,
+        })
+    }
 }
@@ -15,7 +15,12 @@ use model::{load_obj, ModelGroup};
 use std::time::Duration;
 
 use anyhow::Result;
-use derky::common::model::Model;
+use derky::common::{
+    asset::DirectorySource,
+    cache::AssetCache,
+    material::{MaterialDescription, TextureResolver},
+    scene::{Scene, Transform},
+};
 use glium::{
     framebuffer::{MultiOutputFrameBuffer, SimpleFrameBuffer},
     index::PrimitiveType,
@@ -24,7 +29,7 @@ use glium::{
     BackfaceCullingMode, Blend, BlendingFunction, Depth, DepthTest, Display, DrawParameters, Frame,
     IndexBuffer, LinearBlendingFactor, Program, Surface, VertexBuffer,
 };
-use ultraviolet::{Mat4, Vec3};
+use ultraviolet::Vec3;
 
 pub struct Application {
     environment: Environment,
@@ -37,14 +42,28 @@ pub struct Application {
     program_composition: Program,
     vertices_screen: VertexBuffer<CompositionVertex>,
     indices_screen: IndexBuffer<u16>,
-    model: Model<ModelGroup, Material>,
-    model_room: Model<ModelGroup, Material>,
+    scene: Scene<ModelGroup, Material>,
+    default_material: Material,
 }
 
 impl Application {
     pub fn new(display: &Display) -> Result<Application> {
-        let model = load_obj(display, "assets/models/Natsuki.obj")?;
-        let model_room = load_obj(display, "assets/models/Room.obj")?;
+        let assets = DirectorySource::new("assets");
+        let cache = AssetCache::new(".derky-cache");
+        let mut scene = Scene::new();
+        let model = scene.add_model(load_obj(display, &assets, &cache, "models/Natsuki.obj")?);
+        let model_room = scene.add_model(load_obj(display, &assets, &cache, "models/Room.obj")?);
+        scene.add_node(None, Some("Room"), Transform::identity(), Some(model_room));
+        scene.add_node(None, Some("Natsuki"), Transform::identity(), Some(model));
+
+        // マテリアルが指定されていないグループはデフォルトのマテリアルで描画する
+        let default_description = MaterialDescription::default();
+        let default_material = Material::new(
+            display,
+            &cache,
+            &TextureResolver::new(&assets, None).resolve(&default_description),
+            &default_description,
+        )?;
 
         let program_geometry = load_program(display, "assets/shaders/gl4/geometry/geometry")?;
         let program_ambient_lighting =
@@ -76,8 +95,8 @@ impl Application {
             program_composition,
             vertices_screen,
             indices_screen,
-            model,
-            model_room,
+            scene,
+            default_material,
         })
     }
 
@@ -93,8 +112,6 @@ impl Application {
         geometry_buffer: &mut MultiOutputFrameBuffer,
         generate_uniforms: UG,
     ) -> Result<()> {
-        let room_matrix: [[f32; 4]; 4] = Mat4::identity().into();
-
         let params = DrawParameters {
             depth: Depth {
                 test: DepthTest::IfLess,
@@ -104,22 +121,32 @@ impl Application {
             backface_culling: BackfaceCullingMode::CullClockwise,
             ..Default::default()
         };
+        let params_double_sided = DrawParameters {
+            backface_culling: BackfaceCullingMode::CullingDisabled,
+            ..params.clone()
+        };
 
         let program = &self.program_geometry;
-        let models = [&self.model_room, &self.model];
 
-        for &target in &models {
-            for (mg, mat) in target.visit() {
-                let albedo = match mat {
-                    Some(Material::Diffuse { albedo, .. }) => albedo,
-                    _ => return Ok(()),
+        for (world_matrix, target) in self.scene.instances() {
+            let model_matrix: [[f32; 4]; 4] = (*world_matrix).into();
+            let frustum = self.environment.frustum(*world_matrix);
+            for (mg, mat, _) in target.visit_visible(&frustum) {
+                let material = mat.unwrap_or(&self.default_material);
+                let color: [f32; 4] = material.color.into();
+                let params = if material.double_sided {
+                    &params_double_sided
+                } else {
+                    &params
                 };
 
                 let uniforms = UniformsSet::new(generate_uniforms())
                     .add(self.environment.get_unforms())
                     .add(uniform! {
-                        model_matrix: room_matrix,
-                        material_albedo: albedo,
+                        model_matrix: model_matrix,
+                        material_albedo: &material.albedo,
+                        material_color: color,
+                        material_alpha_cutoff: material.alpha_cutoff,
                     });
 
                 geometry_buffer.draw(
@@ -127,7 +154,7 @@ impl Application {
                     &mg.index_buffer,
                     program,
                     &uniforms,
-                    &params,
+                    params,
                 )?;
             }
         }
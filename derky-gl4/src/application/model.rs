@@ -1,31 +1,28 @@
 //! 描画用モデルに関係するモジュール。
 
 use super::material::Material;
-use std::path::{Path, PathBuf};
+use std::path::Path;
 
-use anyhow::{format_err, Result};
+use anyhow::Result;
 use derky::common::{
+    asset::AssetSource,
+    cache::{AssetCache, ProcessingOptions},
+    material::TextureResolver,
+    mesh::MeshConversion,
     model::Model,
-    texture::{load_ldr_image, ImageData},
-};
-use glium::{
-    backend::Facade,
-    implement_vertex,
-    index::PrimitiveType,
-    texture::{RawImage2d, Texture2d},
-    IndexBuffer, VertexBuffer,
 };
+use glium::{backend::Facade, implement_vertex, index::PrimitiveType, IndexBuffer, VertexBuffer};
 use log::info;
-use ultraviolet::{Vec2, Vec3, Vec4};
 
 /// 頂点シェーダーに渡る頂点情報を表す。
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Vertex {
     position: [f32; 3],
     normal: [f32; 3],
+    tangent: [f32; 4],
     uv: [f32; 2],
 }
-implement_vertex!(Vertex, position, normal, uv);
+implement_vertex!(Vertex, position, normal, tangent, uv);
 
 #[derive(Debug)]
 pub struct ModelGroup {
@@ -35,81 +32,45 @@ pub struct ModelGroup {
 
 pub fn load_obj(
     facade: &impl Facade,
+    source: &dyn AssetSource,
+    cache: &AssetCache,
     filename: impl AsRef<Path>,
 ) -> Result<Model<ModelGroup, Material>> {
-    let filename = filename.as_ref();
-    let base_path = filename
-        .parent()
-        .ok_or_else(|| format_err!("Invalid path"))?;
-    Model::load_obj(
-        filename,
-        |faces| {
-            let mut vertices = vec![];
-            let mut indices = vec![];
-            for face in &faces[..] {
-                let vertex_base = vertices.len();
-                for original_vertice in &face[..] {
-                    // Blender の出力する .obj は bottom-left が (0, 0) になるらしいので(.obj の仕様？)、
-                    // この時点で V を反転する
-                    let uv = {
-                        let original = original_vertice.1.unwrap_or_default();
-                        Vec2::new(original.x, 1.0 - original.y)
-                    };
-                    vertices.push(Vertex {
-                        position: original_vertice.0.into(),
-                        normal: original_vertice
-                            .2
-                            .unwrap_or(Vec3::new(0.0, 1.0, 0.0))
-                            .into(),
-                        uv: uv.into(),
-                    });
-                }
-                for i in 0..(face.len() - 2) {
-                    indices.push(vertex_base as u32);
-                    indices.push((vertex_base + i + 1) as u32);
-                    indices.push((vertex_base + i + 2) as u32);
-                }
-            }
+    let mut resolver = TextureResolver::new(source, Some(cache));
+    Model::load_obj_cached(
+        cache,
+        source,
+        filename.as_ref(),
+        &ProcessingOptions {
+            conversion: MeshConversion {
+                // Blender の出力する .obj は bottom-left が (0, 0) になるらしいので(.obj の仕様？)、
+                // この時点で V を反転する
+                flip_v: true,
+                ..Default::default()
+            },
+            optimize: true,
+        },
+        |mesh| {
+            let vertices: Vec<_> = (0..mesh.vertex_count())
+                .map(|i| Vertex {
+                    position: mesh.positions()[i].into(),
+                    normal: mesh.normals()[i].into(),
+                    tangent: mesh.tangents()[i].into(),
+                    uv: mesh.uvs()[i].into(),
+                })
+                .collect();
             let vertex_buffer = VertexBuffer::new(facade, &vertices)?;
-            let index_buffer = IndexBuffer::new(facade, PrimitiveType::TrianglesList, &indices)?;
+            let index_buffer =
+                IndexBuffer::new(facade, PrimitiveType::TrianglesList, mesh.indices())?;
             Ok(ModelGroup {
                 vertex_buffer,
                 index_buffer,
             })
         },
-        |material| {
-            info!("Loading material {}", material.name());
-            let image = if let Some(path) = material.diffuse_map() {
-                let mut filename = PathBuf::from(base_path);
-                filename.push(path);
-                load_ldr_image(filename)?
-            } else {
-                let color = material.diffuse_color().unwrap_or(Vec3::new(1.0, 1.0, 1.0));
-                info!("Creating dummy image: {:?}", color);
-
-                ImageData::new(
-                    &[
-                        (color.x * 255.0) as u8,
-                        (color.y * 255.0) as u8,
-                        (color.z * 255.0) as u8,
-                        255,
-                    ],
-                    1,
-                    1,
-                )?
-            };
-
-            let dimensions = image.dimensions();
-            let raw_image = RawImage2d::from_raw_rgba(
-                image.into_data().into_vec(),
-                (dimensions.0 as u32, dimensions.1 as u32),
-            );
-            let texture = Texture2d::new(facade, raw_image)?;
-
-            Ok(Material::Diffuse {
-                color: Vec4::new(1.0, 1.0, 1.0, 1.0),
-                albedo: texture,
-            })
+        |description| {
+            info!("Loading material {:?}", description.name);
+            let textures = resolver.resolve(&description);
+            Material::new(facade, cache, &textures, &description)
         },
     )
 }
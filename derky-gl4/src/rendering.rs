@@ -5,17 +5,22 @@ use std::{
     io::{prelude::*, BufReader},
 };
 
-use anyhow::Result;
-use derky::common::texture::load_hdr_image;
+use anyhow::{bail, format_err, Result};
+use derky::common::texture::{
+    load_hdr_image, BlockFormat, ColorSpace, CompressedImage, ImageData, ImageElement, MipChain,
+    MipmapFilter, MipmapOptions, Rgba,
+};
 use glium::{
     backend::Facade,
     glutin::{dpi::PhysicalSize, event_loop::EventLoop, window::WindowBuilder, ContextBuilder},
     implement_vertex,
     texture::{
-        DepthFormat, DepthTexture2d, MipmapsOption, RawImage2d, Texture2d, UncompressedFloatFormat,
+        CompressedMipmapsOption, CompressedSrgbFormat, CompressedSrgbTexture2d, DepthFormat,
+        DepthTexture2d, MipmapsOption, PixelValue, RawImage2d, Texture2d, ToClientFormat,
+        UncompressedFloatFormat,
     },
     uniforms::{EmptyUniforms, UniformValue, Uniforms},
-    Display, Program,
+    Display, Program, Rect,
 };
 use log::{error, info};
 
@@ -202,15 +207,89 @@ pub fn initialize_buffers(display: &Display) -> Result<Buffers> {
 
 pub fn load_exr_texture(facade: &impl Facade, filename: &str) -> Result<Texture2d> {
     let image = load_hdr_image(filename)?;
-    let (width, height) = image.dimensions();
-    let raw_image =
-        RawImage2d::from_raw_rgba(image.into_data().into_vec(), (width as u32, height as u32));
+    let mipmaps = image.generate_mipmaps(&MipmapOptions {
+        filter: MipmapFilter::Kaiser,
+        ..Default::default()
+    });
     let texture = Texture2d::with_format(
         facade,
-        raw_image,
+        raw_image(mipmaps.base()),
         UncompressedFloatFormat::F32F32F32F32,
-        MipmapsOption::NoMipmap,
+        MipmapsOption::EmptyMipmapsMax(mipmaps.level_count() as u32 - 1),
     )?;
+    write_mipmaps(&texture, &mipmaps);
+
+    Ok(texture)
+}
+
+/// `ImageData` を glium にアップロードできる形式に変換する。
+pub fn raw_image<T>(image: &ImageData<T, Rgba>) -> RawImage2d<'static, T>
+where
+    T: ImageElement + ToClientFormat,
+{
+    let (width, height) = image.dimensions();
+    RawImage2d::from_raw_rgba(image.data().to_vec(), (width as u32, height as u32))
+}
+
+/// ミップチェーンの 2 段目以降をテクスチャに書き込む。
+/// テクスチャは `MipmapsOption::EmptyMipmapsMax` で段数を確保しておくこと。
+pub fn write_mipmaps<T>(texture: &Texture2d, mipmaps: &MipChain<T, Rgba>)
+where
+    T: ImageElement + ToClientFormat + PixelValue,
+{
+    for (level, image) in mipmaps.levels().iter().enumerate().skip(1) {
+        let (width, height) = image.dimensions();
+        if let Some(mipmap) = texture.mipmap(level as u32) {
+            let rect = Rect {
+                left: 0,
+                bottom: 0,
+                width: width as u32,
+                height: height as u32,
+            };
+            mipmap.write(rect, raw_image(image));
+        }
+    }
+}
+
+/// sRGB のブロック圧縮された画像を全ミップレベルごとアップロードする。
+/// サンプリング時にハードウェアで線形にデコードされる。
+pub fn compressed_srgb_texture(
+    facade: &impl Facade,
+    image: &CompressedImage,
+) -> Result<CompressedSrgbTexture2d> {
+    if image.color_space() != ColorSpace::Srgb {
+        bail!("Only sRGB images can be uploaded as sRGB textures");
+    }
+    let format = match image.format() {
+        BlockFormat::Bc1 => CompressedSrgbFormat::S3tcDxt1Alpha,
+        BlockFormat::Bc3 => CompressedSrgbFormat::S3tcDxt5Alpha,
+        BlockFormat::Bc7 => CompressedSrgbFormat::Bptc,
+        format => bail!("{:?} has no sRGB format", format),
+    };
+    let (width, height) = image.dimensions();
+    let texture = CompressedSrgbTexture2d::with_compressed_data(
+        facade,
+        &image.levels()[0],
+        width as u32,
+        height as u32,
+        format,
+        CompressedMipmapsOption::EmptyMipmapsMax(image.level_count() as u32 - 1),
+    )?;
+
+    for (level, data) in image.levels().iter().enumerate().skip(1) {
+        let (width, height) = image.level_dimensions(level);
+        if let Some(mipmap) = texture.mipmap(level as u32) {
+            let rect = Rect {
+                left: 0,
+                bottom: 0,
+                width: width as u32,
+                height: height as u32,
+            };
+            mipmap
+                .write_compressed_data(rect, data, width as u32, height as u32, format)
+                .map_err(|_| format_err!("Failed to write mip level {}", level))?;
+        }
+    }
 
     Ok(texture)
 }
@@ -0,0 +1,439 @@
+//! Contains animation clips and keyframe interpolation.
+
+use crate::common::skeleton::Pose;
+
+use std::{
+    cmp::Ordering,
+    ops::{Add, Mul},
+};
+
+use anyhow::{bail, Result};
+use ultraviolet::{Lerp, Rotor3, Slerp, Vec3};
+
+/// Represents how values between keyframes are calculated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interpolation {
+    /// The value of the previous keyframe is held.
+    Step,
+
+    /// Values are interpolated linearly; rotations are interpolated spherically.
+    Linear,
+
+    /// Values are interpolated with cubic Hermite splines.
+    /// Each keyframe has an in-tangent, a value and an out-tangent in this order.
+    CubicSpline,
+}
+
+/// Represents keyframe values of a channel.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChannelValues {
+    /// Translations
+    Translation(Box<[Vec3]>),
+
+    /// Rotations
+    Rotation(Box<[Rotor3]>),
+
+    /// Scales
+    Scale(Box<[Vec3]>),
+}
+
+impl ChannelValues {
+    /// The number of stored values.
+    fn len(&self) -> usize {
+        match self {
+            ChannelValues::Translation(v) => v.len(),
+            ChannelValues::Rotation(v) => v.len(),
+            ChannelValues::Scale(v) => v.len(),
+        }
+    }
+}
+
+/// Represents keyframes animating a property of a joint.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Channel {
+    joint: usize,
+    interpolation: Interpolation,
+    times: Box<[f32]>,
+    values: ChannelValues,
+}
+
+impl Channel {
+    /// Creates a channel.
+    /// # Parameters
+    /// * `joint`: index of the target joint
+    /// * `interpolation`: interpolation method
+    /// * `times`: keyframe times in seconds, in ascending order
+    /// * `values`: keyframe values; three times as many as `times` for `CubicSpline`
+    pub fn new(
+        joint: usize,
+        interpolation: Interpolation,
+        times: Vec<f32>,
+        values: ChannelValues,
+    ) -> Result<Channel> {
+        if times.is_empty() {
+            bail!("At least one keyframe is required");
+        }
+        if times.windows(2).any(|w| w[0] >= w[1]) {
+            bail!("Keyframe times must be in ascending order");
+        }
+        let expected = match interpolation {
+            Interpolation::CubicSpline => times.len() * 3,
+            _ => times.len(),
+        };
+        if values.len() != expected {
+            bail!("Expected {} values, found {}", expected, values.len());
+        }
+
+        Ok(Channel {
+            joint,
+            interpolation,
+            times: times.into_boxed_slice(),
+            values,
+        })
+    }
+
+    /// The index of the target joint.
+    pub fn joint(&self) -> usize {
+        self.joint
+    }
+
+    /// The interpolation method.
+    pub fn interpolation(&self) -> Interpolation {
+        self.interpolation
+    }
+
+    /// The keyframe times.
+    pub fn times(&self) -> &[f32] {
+        &self.times
+    }
+
+    /// The keyframe values.
+    pub fn values(&self) -> &ChannelValues {
+        &self.values
+    }
+
+    /// Writes the value at the time into the pose.
+    pub fn apply(&self, time: f32, pose: &mut Pose) {
+        let transform = match pose.transforms_mut().get_mut(self.joint) {
+            Some(t) => t,
+            None => return,
+        };
+        match &self.values {
+            ChannelValues::Translation(v) => {
+                transform.translation = sample(&self.times, v, self.interpolation, time);
+            }
+            ChannelValues::Rotation(v) => {
+                transform.rotation = sample(&self.times, v, self.interpolation, time);
+            }
+            ChannelValues::Scale(v) => {
+                transform.scale = sample(&self.times, v, self.interpolation, time);
+            }
+        }
+    }
+}
+
+/// Represents a set of channels played together.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationClip {
+    name: Option<Box<str>>,
+    channels: Box<[Channel]>,
+    duration: f32,
+}
+
+impl AnimationClip {
+    /// Creates a clip; the duration is the time of the last keyframe.
+    pub fn new(name: Option<&str>, channels: Vec<Channel>) -> AnimationClip {
+        let duration = channels
+            .iter()
+            .filter_map(|c| c.times.last())
+            .fold(0.0f32, |a, &b| a.max(b));
+
+        AnimationClip {
+            name: name.map(|n| n.into()),
+            channels: channels.into_boxed_slice(),
+            duration,
+        }
+    }
+
+    /// The name of this clip.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The channels.
+    pub fn channels(&self) -> &[Channel] {
+        &self.channels
+    }
+
+    /// The duration in seconds.
+    pub fn duration(&self) -> f32 {
+        self.duration
+    }
+
+    /// Writes values at the time into the pose.
+    /// Properties without channels keep their values, so start from the rest pose.
+    pub fn sample(&self, time: f32, pose: &mut Pose) {
+        for channel in self.channels.iter() {
+            channel.apply(time, pose);
+        }
+    }
+
+    /// Same as `sample`, but the time wraps around the duration.
+    pub fn sample_looped(&self, time: f32, pose: &mut Pose) {
+        let time = if self.duration > 0.0 {
+            time.rem_euclid(self.duration)
+        } else {
+            0.0
+        };
+        self.sample(time, pose);
+    }
+}
+
+/// Values which can be interpolated between keyframes.
+trait Keyframe: Copy + Add<Output = Self> + Mul<f32, Output = Self> {
+    /// Interpolates linearly.
+    fn interpolate(self, next: Self, t: f32) -> Self;
+
+    /// Corrects the result of spline interpolation.
+    fn finish(self) -> Self {
+        self
+    }
+}
+
+impl Keyframe for Vec3 {
+    fn interpolate(self, next: Vec3, t: f32) -> Vec3 {
+        self.lerp(next, t)
+    }
+}
+
+impl Keyframe for Rotor3 {
+    fn interpolate(self, next: Rotor3, t: f32) -> Rotor3 {
+        // Takes the shorter arc, since `r` and `-r` represent the same rotation.
+        let next = if self.dot(next) < 0.0 {
+            next * -1.0
+        } else {
+            next
+        };
+        self.slerp(next, t).normalized()
+    }
+
+    fn finish(self) -> Rotor3 {
+        self.normalized()
+    }
+}
+
+/// Calculates the value at the time.
+fn sample<T: Keyframe>(times: &[f32], values: &[T], interpolation: Interpolation, time: f32) -> T {
+    // Cubic splines store the value at the middle of each triplet.
+    let value = |k: usize| match interpolation {
+        Interpolation::CubicSpline => values[k * 3 + 1],
+        _ => values[k],
+    };
+
+    let last = times.len() - 1;
+    let next = match times.binary_search_by(|t| t.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+        Ok(k) => return value(k),
+        Err(0) => return value(0),
+        Err(k) if k > last => return value(last),
+        Err(k) => k,
+    };
+    let previous = next - 1;
+
+    let delta = times[next] - times[previous];
+    let t = (time - times[previous]) / delta;
+    match interpolation {
+        Interpolation::Step => value(previous),
+        Interpolation::Linear => value(previous).interpolate(value(next), t),
+        Interpolation::CubicSpline => {
+            let t2 = t * t;
+            let t3 = t2 * t;
+            let out_tangent = values[previous * 3 + 2];
+            let in_tangent = values[next * 3];
+            let result = value(previous) * (2.0 * t3 - 3.0 * t2 + 1.0)
+                + out_tangent * (delta * (t3 - 2.0 * t2 + t))
+                + value(next) * (-2.0 * t3 + 3.0 * t2)
+                + in_tangent * (delta * (t3 - t2));
+            result.finish()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{
+        scene::Transform,
+        skeleton::{Joint, Skeleton},
+    };
+
+    use std::f32::consts::{FRAC_PI_2, FRAC_PI_4};
+    use ultraviolet::Mat4;
+
+    fn assert_vec3(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).mag() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// Rotates +X to find the angle of a rotation in the XZ plane.
+    fn angle_xz(rotation: Rotor3) -> f32 {
+        let rotated = rotation * Vec3::unit_x();
+        rotated.z.atan2(rotated.x)
+    }
+
+    fn single_joint_pose() -> Pose {
+        Skeleton::new(vec![Joint {
+            name: None,
+            parent: None,
+            rest: Transform::identity(),
+            inverse_bind_matrix: Mat4::identity(),
+        }])
+        .expect("Invalid skeleton")
+        .rest_pose()
+    }
+
+    fn translation_channel(interpolation: Interpolation) -> Channel {
+        Channel::new(
+            0,
+            interpolation,
+            vec![1.0, 2.0, 4.0],
+            ChannelValues::Translation(
+                vec![
+                    Vec3::zero(),
+                    Vec3::new(2.0, 0.0, 0.0),
+                    Vec3::new(2.0, 4.0, 0.0),
+                ]
+                .into_boxed_slice(),
+            ),
+        )
+        .expect("Invalid channel")
+    }
+
+    fn translation_at(channel: &Channel, time: f32) -> Vec3 {
+        let mut pose = single_joint_pose();
+        channel.apply(time, &mut pose);
+        pose.transforms()[0].translation
+    }
+
+    #[test]
+    fn rejects_invalid_keyframes() {
+        let values = |n| ChannelValues::Scale(vec![Vec3::one(); n].into_boxed_slice());
+        assert!(Channel::new(0, Interpolation::Linear, vec![], values(0)).is_err());
+        assert!(Channel::new(0, Interpolation::Linear, vec![1.0, 1.0], values(2)).is_err());
+        assert!(Channel::new(0, Interpolation::Linear, vec![0.0, 1.0], values(3)).is_err());
+        assert!(Channel::new(0, Interpolation::CubicSpline, vec![0.0, 1.0], values(2)).is_err());
+        assert!(Channel::new(0, Interpolation::CubicSpline, vec![0.0, 1.0], values(6)).is_ok());
+    }
+
+    #[test]
+    fn step_holds_previous_keyframe() {
+        let channel = translation_channel(Interpolation::Step);
+        assert_vec3(translation_at(&channel, 0.0), Vec3::zero());
+        assert_vec3(translation_at(&channel, 1.5), Vec3::zero());
+        assert_vec3(translation_at(&channel, 2.0), Vec3::new(2.0, 0.0, 0.0));
+        assert_vec3(translation_at(&channel, 3.9), Vec3::new(2.0, 0.0, 0.0));
+        assert_vec3(translation_at(&channel, 5.0), Vec3::new(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn linear_interpolates_translations() {
+        let channel = translation_channel(Interpolation::Linear);
+        assert_vec3(translation_at(&channel, 0.5), Vec3::zero());
+        assert_vec3(translation_at(&channel, 1.25), Vec3::new(0.5, 0.0, 0.0));
+        assert_vec3(translation_at(&channel, 2.0), Vec3::new(2.0, 0.0, 0.0));
+        assert_vec3(translation_at(&channel, 3.0), Vec3::new(2.0, 2.0, 0.0));
+        assert_vec3(translation_at(&channel, 10.0), Vec3::new(2.0, 4.0, 0.0));
+    }
+
+    #[test]
+    fn linear_slerps_rotations() {
+        let quarter = Rotor3::from_rotation_xz(FRAC_PI_2);
+        let rotation_at = |second: Rotor3, time: f32| {
+            let channel = Channel::new(
+                0,
+                Interpolation::Linear,
+                vec![0.0, 1.0],
+                ChannelValues::Rotation(vec![Rotor3::identity(), second].into_boxed_slice()),
+            )
+            .expect("Invalid channel");
+            let mut pose = single_joint_pose();
+            channel.apply(time, &mut pose);
+            pose.transforms()[0].rotation
+        };
+
+        let end = angle_xz(quarter);
+        for &t in &[0.25, 0.5, 0.75] {
+            let rotation = rotation_at(quarter, t);
+            assert!((rotation.mag() - 1.0).abs() < 1e-5);
+            assert!(
+                (angle_xz(rotation) - end * t).abs() < 1e-4,
+                "{:?}",
+                rotation
+            );
+        }
+        assert!((angle_xz(rotation_at(quarter, 0.5)).abs() - FRAC_PI_4).abs() < 1e-4);
+
+        // The negated rotor is the same rotation, so the shorter arc is taken.
+        let negated = rotation_at(quarter * -1.0, 0.5);
+        assert!(
+            (angle_xz(negated) - end * 0.5).abs() < 1e-4,
+            "{:?}",
+            negated
+        );
+    }
+
+    #[test]
+    fn cubic_spline_uses_tangents() {
+        let channel = |out_tangent: Vec3| {
+            Channel::new(
+                0,
+                Interpolation::CubicSpline,
+                vec![0.0, 2.0],
+                ChannelValues::Translation(
+                    vec![
+                        Vec3::zero(),
+                        Vec3::zero(),
+                        out_tangent,
+                        Vec3::zero(),
+                        Vec3::new(4.0, 0.0, 0.0),
+                        Vec3::zero(),
+                    ]
+                    .into_boxed_slice(),
+                ),
+            )
+            .expect("Invalid channel")
+        };
+
+        // Zero tangents ease in and out symmetrically.
+        let flat = channel(Vec3::zero());
+        assert_vec3(translation_at(&flat, 1.0), Vec3::new(2.0, 0.0, 0.0));
+        assert_vec3(translation_at(&flat, 0.5), Vec3::new(0.625, 0.0, 0.0));
+        assert_vec3(translation_at(&flat, 2.0), Vec3::new(4.0, 0.0, 0.0));
+
+        // Tangents are scaled by the keyframe interval; h10(0.5) = 0.125.
+        let steep = channel(Vec3::new(0.0, 1.0, 0.0));
+        assert_vec3(translation_at(&steep, 1.0), Vec3::new(2.0, 0.25, 0.0));
+    }
+
+    #[test]
+    fn clip_loops_over_duration() {
+        let clip = AnimationClip::new(
+            Some("walk"),
+            vec![translation_channel(Interpolation::Linear)],
+        );
+        assert_eq!(clip.name(), Some("walk"));
+        assert_eq!(clip.duration(), 4.0);
+
+        let mut looped = single_joint_pose();
+        let mut direct = single_joint_pose();
+        clip.sample_looped(7.0, &mut looped);
+        clip.sample(3.0, &mut direct);
+        assert_eq!(looped, direct);
+        assert_vec3(looped.transforms()[0].translation, Vec3::new(2.0, 2.0, 0.0));
+        assert_eq!(looped.transforms()[0].rotation, Rotor3::identity());
+    }
+}
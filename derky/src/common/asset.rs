@@ -0,0 +1,287 @@
+//! Contains asset sources, which abstract where asset files are stored.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fs::{read, File},
+    io::{BufReader, Read, Seek},
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use zip::ZipArchive;
+
+/// The trait implemented by asset sources.
+///
+/// Paths are relative to the root of the source, and `/` separates components.
+pub trait AssetSource {
+    /// Reads the whole content of the asset.
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+
+    /// Checks whether the asset exists.
+    fn exists(&self, path: &Path) -> bool;
+
+    /// Reads the whole content of the asset as a UTF-8 string.
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        let data = self.read(path)?;
+        String::from_utf8(data).with_context(|| format!("{:?} is not valid UTF-8", path))
+    }
+}
+
+/// Resolves a path referenced from the asset at `base`, such as a MTL file from an OBJ file.
+pub fn resolve(base: &Path, reference: &Path) -> PathBuf {
+    let mut resolved = base.parent().map(Path::to_path_buf).unwrap_or_default();
+    resolved.push(reference);
+    normalize(&resolved)
+}
+
+/// Removes `.` and resolves `..` components without accessing the file system.
+pub fn normalize(path: &Path) -> PathBuf {
+    let mut components = vec![];
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => match components.last() {
+                Some(Component::Normal(_)) => {
+                    components.pop();
+                }
+                _ => components.push(component),
+            },
+            _ => components.push(component),
+        }
+    }
+    components.iter().collect()
+}
+
+/// Represents assets in a directory.
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    /// Creates a source; an empty path means the current directory.
+    pub fn new(root: impl Into<PathBuf>) -> DirectorySource {
+        DirectorySource { root: root.into() }
+    }
+}
+
+impl AssetSource for DirectorySource {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let full_path = self.root.join(path);
+        read(&full_path).with_context(|| format!("Failed to read {:?}", full_path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.root.join(path).is_file()
+    }
+}
+
+/// Represents assets in memory.
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    files: HashMap<PathBuf, Box<[u8]>>,
+}
+
+impl MemorySource {
+    /// Creates an empty source.
+    pub fn new() -> MemorySource {
+        Default::default()
+    }
+
+    /// Adds an asset, replacing the existing one.
+    pub fn insert(&mut self, path: impl AsRef<Path>, data: impl Into<Box<[u8]>>) {
+        self.files.insert(normalize(path.as_ref()), data.into());
+    }
+}
+
+impl AssetSource for MemorySource {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        self.files
+            .get(&normalize(path))
+            .map(|data| data.to_vec())
+            .with_context(|| format!("{:?} not found", path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.files.contains_key(&normalize(path))
+    }
+}
+
+/// Represents assets in a ZIP archive.
+pub struct ZipSource<R: Read + Seek> {
+    archive: RefCell<ZipArchive<R>>,
+}
+
+impl ZipSource<BufReader<File>> {
+    /// Opens a ZIP file.
+    pub fn open(filename: impl AsRef<Path>) -> Result<ZipSource<BufReader<File>>> {
+        let file = File::open(filename).context("Failed to open ZIP file")?;
+        ZipSource::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read + Seek> ZipSource<R> {
+    /// Creates a source from a reader of ZIP archive.
+    pub fn new(reader: R) -> Result<ZipSource<R>> {
+        let archive = ZipArchive::new(reader).context("Invalid ZIP archive")?;
+        Ok(ZipSource {
+            archive: RefCell::new(archive),
+        })
+    }
+
+    /// Converts the path into the entry name.
+    /// Paths escaping the root of the archive are rejected, instead of being clamped to it.
+    fn entry_name(path: &Path) -> Result<String> {
+        let mut components = vec![];
+        for component in normalize(path).components() {
+            match component {
+                Component::Normal(name) => components.push(name.to_string_lossy().into_owned()),
+                Component::ParentDir => bail!("{:?} is outside of the archive", path),
+                _ => (),
+            }
+        }
+        Ok(components.join("/"))
+    }
+}
+
+impl<R: Read + Seek> AssetSource for ZipSource<R> {
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let name = ZipSource::<R>::entry_name(path)?;
+        let mut archive = self.archive.borrow_mut();
+        let mut entry = archive
+            .by_name(&name)
+            .with_context(|| format!("{:?} not found", path))?;
+
+        let mut data = Vec::with_capacity(entry.size() as usize);
+        entry.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        let name = match ZipSource::<R>::entry_name(path) {
+            Ok(name) => name,
+            Err(_) => return false,
+        };
+        let mut archive = self.archive.borrow_mut();
+        let exists = archive.by_name(&name).is_ok();
+        exists
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{
+        material::{MaterialDescription, TextureResolver},
+        mesh::MeshConversion,
+        model::Model,
+        texture::{encode_png_image, ColorSpace, ImageData, Rgba},
+    };
+
+    use std::io::{Cursor, Write};
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    /// Creates a ZIP archive in memory.
+    fn zip_source(files: &[(&str, &[u8])]) -> ZipSource<Cursor<Vec<u8>>> {
+        let mut writer = ZipWriter::new(Cursor::new(vec![]));
+        for &(name, data) in files {
+            writer.start_file(name, FileOptions::default()).unwrap();
+            writer.write_all(data).unwrap();
+        }
+        let mut reader = writer.finish().unwrap();
+        reader.set_position(0);
+        ZipSource::new(reader).unwrap()
+    }
+
+    #[test]
+    fn paths_are_normalized_and_resolved() {
+        assert_eq!(normalize(Path::new("a/./b/../c")), Path::new("a/c"));
+        assert_eq!(normalize(Path::new("../a/../../b")), Path::new("../../b"));
+        assert_eq!(
+            resolve(Path::new("models/box.obj"), Path::new("box.mtl")),
+            Path::new("models/box.mtl")
+        );
+        assert_eq!(
+            resolve(Path::new("models/box.obj"), Path::new("../textures/a.png")),
+            Path::new("textures/a.png")
+        );
+        assert_eq!(
+            resolve(Path::new("box.obj"), Path::new("../a.png")),
+            Path::new("../a.png")
+        );
+    }
+
+    #[test]
+    fn memory_source_normalizes_paths() {
+        let mut source = MemorySource::new();
+        source.insert("models/./box.obj", b"v 0 0 0".to_vec());
+        assert!(source.exists(Path::new("models/box.obj")));
+        assert!(source.exists(Path::new("textures/../models/box.obj")));
+        assert!(!source.exists(Path::new("box.obj")));
+        assert_eq!(
+            source.read_to_string(Path::new("models/box.obj")).unwrap(),
+            "v 0 0 0"
+        );
+        assert!(source.read(Path::new("models/missing.obj")).is_err());
+
+        source.insert("models/box.obj", vec![0xff]);
+        assert!(source.read_to_string(Path::new("models/box.obj")).is_err());
+    }
+
+    #[test]
+    fn zip_source_rejects_escaping_paths() {
+        let source = zip_source(&[("textures/a.png", b"a"), ("models/box.obj", b"b")]);
+        assert_eq!(source.read(Path::new("textures/a.png")).unwrap(), b"a");
+        assert_eq!(
+            source
+                .read(Path::new("models/../textures/./a.png"))
+                .unwrap(),
+            b"a"
+        );
+        assert!(source.exists(Path::new("models/box.obj")));
+        assert!(!source.exists(Path::new("models/a.png")));
+
+        // A reference above the root never resolves to an entry with the same tail.
+        assert!(source.read(Path::new("../textures/a.png")).is_err());
+        assert!(!source.exists(Path::new("../textures/a.png")));
+        assert!(source
+            .read(Path::new("models/../../textures/a.png"))
+            .is_err());
+    }
+
+    #[test]
+    fn obj_loads_from_zip() {
+        let texture = ImageData::<u8, Rgba>::new(&[255, 0, 0, 255, 0, 0, 255, 255], 2, 1)
+            .unwrap()
+            .with_color_space(ColorSpace::Srgb);
+        let png = encode_png_image(&texture).unwrap();
+        let obj = b"mtllib box.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\n\
+                    usemtl red\nf 1/1 2/2 3/3\n";
+        let mtl = b"newmtl red\nKd 1 1 1\nmap_Kd ../textures/red.png\n";
+        let source = zip_source(&[
+            ("models/box.obj", obj),
+            ("models/box.mtl", mtl),
+            ("textures/red.png", &png),
+        ]);
+
+        let path = Path::new("models/box.obj");
+        let model = Model::load_obj_from(
+            &source,
+            path,
+            &MeshConversion::default(),
+            |mesh| Ok(mesh.vertex_count()),
+            |m| Ok(MaterialDescription::from_obj(&m, path)),
+        )
+        .unwrap();
+        let (&vertex_count, material, _) = model.visit().next().expect("No vertex groups");
+        assert_eq!(vertex_count, 3);
+
+        let textures = TextureResolver::new(&source, None).resolve(material.unwrap());
+        assert!(!textures.albedo.fallback);
+        assert_eq!(textures.albedo.image.dimensions(), (2, 1));
+        assert_eq!(textures.albedo.image.data(), texture.data());
+    }
+}
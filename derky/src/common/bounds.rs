@@ -0,0 +1,202 @@
+//! Contains bounding volumes.
+
+use ultraviolet::{Mat4, Vec3};
+
+/// Represents the bounding volumes of a mesh.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Bounds {
+    /// Axis-aligned bounding box
+    pub aabb: Aabb,
+
+    /// Bounding sphere
+    pub sphere: BoundingSphere,
+}
+
+impl Bounds {
+    /// Calculates bounding volumes of points.
+    pub fn from_points(points: &[Vec3]) -> Bounds {
+        let aabb = Aabb::from_points(points.iter().copied());
+        let sphere = BoundingSphere::from_points_around(aabb.center(), points);
+        Bounds { aabb, sphere }
+    }
+
+    /// Returns the bounds containing both of them.
+    pub fn merged(&self, other: &Bounds) -> Bounds {
+        Bounds {
+            aabb: self.aabb.merged(&other.aabb),
+            sphere: self.sphere.merged(&other.sphere),
+        }
+    }
+
+    /// Returns the bounds containing the transformed volumes.
+    pub fn transformed(&self, matrix: &Mat4) -> Bounds {
+        Bounds {
+            aabb: self.aabb.transformed(matrix),
+            sphere: self.sphere.transformed(matrix),
+        }
+    }
+}
+
+/// Represents an axis-aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Aabb {
+    /// Minimum corner
+    pub min: Vec3,
+
+    /// Maximum corner
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Creates an empty box, which contains nothing.
+    pub fn empty() -> Aabb {
+        Aabb {
+            min: Vec3::broadcast(f32::INFINITY),
+            max: Vec3::broadcast(f32::NEG_INFINITY),
+        }
+    }
+
+    /// Creates the smallest box containing all points.
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Aabb {
+        points.into_iter().fold(Aabb::empty(), |aabb, p| aabb.extended(p))
+    }
+
+    /// Checks whether this box contains nothing.
+    pub fn is_empty(&self) -> bool {
+        self.min.x > self.max.x || self.min.y > self.max.y || self.min.z > self.max.z
+    }
+
+    /// Returns the box extended to contain the point.
+    pub fn extended(&self, point: Vec3) -> Aabb {
+        Aabb {
+            min: self.min.min_by_component(point),
+            max: self.max.max_by_component(point),
+        }
+    }
+
+    /// Returns the box containing both of them.
+    pub fn merged(&self, other: &Aabb) -> Aabb {
+        Aabb {
+            min: self.min.min_by_component(other.min),
+            max: self.max.max_by_component(other.max),
+        }
+    }
+
+    /// Returns the box containing all corners of this box transformed by the matrix.
+    pub fn transformed(&self, matrix: &Mat4) -> Aabb {
+        if self.is_empty() {
+            return *self;
+        }
+
+        (0..8)
+            .map(|i| {
+                let corner = Vec3::new(
+                    if i & 1 == 0 { self.min.x } else { self.max.x },
+                    if i & 2 == 0 { self.min.y } else { self.max.y },
+                    if i & 4 == 0 { self.min.z } else { self.max.z },
+                );
+                matrix.transform_point3(corner)
+            })
+            .fold(Aabb::empty(), |aabb, p| aabb.extended(p))
+    }
+
+    /// The center point.
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) * 0.5
+    }
+
+    /// The half size along each axis.
+    pub fn half_extents(&self) -> Vec3 {
+        (self.max - self.min) * 0.5
+    }
+}
+
+impl Default for Aabb {
+    fn default() -> Aabb {
+        Aabb::empty()
+    }
+}
+
+/// Represents a bounding sphere.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundingSphere {
+    /// Center point
+    pub center: Vec3,
+
+    /// Radius; negative if the sphere contains nothing
+    pub radius: f32,
+}
+
+impl BoundingSphere {
+    /// Creates an empty sphere, which contains nothing.
+    pub fn empty() -> BoundingSphere {
+        BoundingSphere {
+            center: Vec3::zero(),
+            radius: -1.0,
+        }
+    }
+
+    /// Creates the smallest sphere centered at `center` which contains all points.
+    pub fn from_points_around(center: Vec3, points: &[Vec3]) -> BoundingSphere {
+        if points.is_empty() {
+            return BoundingSphere::empty();
+        }
+
+        let radius_sq = points
+            .iter()
+            .map(|p| (*p - center).mag_sq())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center,
+            radius: radius_sq.sqrt(),
+        }
+    }
+
+    /// Checks whether this sphere contains nothing.
+    pub fn is_empty(&self) -> bool {
+        self.radius < 0.0
+    }
+
+    /// Returns the sphere containing both of them.
+    pub fn merged(&self, other: &BoundingSphere) -> BoundingSphere {
+        if self.is_empty() {
+            return *other;
+        } else if other.is_empty() {
+            return *self;
+        }
+
+        let offset = other.center - self.center;
+        let distance = offset.mag();
+        if distance + other.radius <= self.radius {
+            *self
+        } else if distance + self.radius <= other.radius {
+            *other
+        } else {
+            let radius = (distance + self.radius + other.radius) * 0.5;
+            let center = self.center + offset * ((radius - self.radius) / distance);
+            BoundingSphere { center, radius }
+        }
+    }
+
+    /// Returns the sphere containing this sphere transformed by the matrix.
+    /// Non-uniform scales enlarge the radius by the largest axis scale.
+    pub fn transformed(&self, matrix: &Mat4) -> BoundingSphere {
+        if self.is_empty() {
+            return *self;
+        }
+
+        let scale_sq = (0..3)
+            .map(|i| matrix.cols[i].truncated().mag_sq())
+            .fold(0.0, f32::max);
+        BoundingSphere {
+            center: matrix.transform_point3(self.center),
+            radius: self.radius * scale_sq.sqrt(),
+        }
+    }
+}
+
+impl Default for BoundingSphere {
+    fn default() -> BoundingSphere {
+        BoundingSphere::empty()
+    }
+}
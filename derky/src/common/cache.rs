@@ -0,0 +1,815 @@
+//! Contains the cache of processed assets.
+//!
+//! Each entry is a binary file in the cache directory, named after the asset path and
+//! the processing options, so that assets processed differently do not evict each other.
+//! It starts with a header holding the format version and the key, which is a FNV-1a
+//! hash of source files and processing options; entries are rebuilt when either differs.
+
+use crate::common::{
+    asset::{resolve, AssetSource},
+    material::{AlphaMode, MaterialDescription, MaterialTexture, TextureSource},
+    mesh::{MeshConversion, MeshData, Submesh},
+    model::MorphTarget,
+    texture::{
+        brdf_lut, decode_hdr_image, decode_ldr_image, prefilter_environment, BlockFormat, Channels,
+        ColorSpace, CompressedImage, CubeMap, ImageData, MipChain, PrefilterOptions,
+        PrefilteredEnvironment, Rg, Rgba,
+    },
+};
+
+use std::{
+    convert::TryInto,
+    fs::{create_dir_all, read, rename, write},
+    hash::{Hash, Hasher},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context, Result};
+use log::{debug, warn};
+use ultraviolet::{Vec2, Vec3, Vec4};
+
+/// The version of the cache format; increment it when the format or processing changes.
+pub const CACHE_VERSION: u32 = 3;
+
+/// The magic bytes of cache files.
+const MAGIC: &[u8; 4] = b"DKYC";
+
+/// Calculates 64-bit FNV-1a hashes, which are stable unlike `DefaultHasher`.
+#[derive(Debug, Clone, Copy)]
+pub struct FnvHasher(u64);
+
+impl Default for FnvHasher {
+    fn default() -> FnvHasher {
+        FnvHasher(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for FnvHasher {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Represents options of processing applied before models are cached.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct ProcessingOptions {
+    /// Conversion applied to each mesh
+    pub conversion: MeshConversion,
+
+    /// Whether `optimize_mesh` is applied
+    pub optimize: bool,
+}
+
+/// Represents a processed model stored in the cache.
+pub struct CachedModel {
+    /// Meshes; each of them becomes a vertex group
+    pub meshes: Vec<MeshData>,
+
+    /// Materials referred by submeshes
+    pub materials: Vec<MaterialDescription>,
+}
+
+/// Represents a directory storing processed assets.
+#[derive(Debug, Clone)]
+pub struct AssetCache {
+    directory: PathBuf,
+}
+
+impl AssetCache {
+    /// Creates a cache in the directory, which is created when the first entry is written.
+    pub fn new(directory: impl Into<PathBuf>) -> AssetCache {
+        AssetCache {
+            directory: directory.into(),
+        }
+    }
+
+    /// The cache directory.
+    pub fn directory(&self) -> &Path {
+        &self.directory
+    }
+
+    /// Calculates the key of an OBJ model from the OBJ file, its MTL files and options.
+    pub fn obj_key(
+        source: &dyn AssetSource,
+        path: &Path,
+        options: &ProcessingOptions,
+    ) -> Result<u64> {
+        let obj_file = source.read(path)?;
+        let mut hasher = FnvHasher::default();
+        hasher.write_u32(CACHE_VERSION);
+        options.hash(&mut hasher);
+        hasher.write(&obj_file);
+
+        for line in String::from_utf8_lossy(&obj_file).lines() {
+            let mtllib = match line.trim().strip_prefix("mtllib") {
+                Some(rest) => rest.split_whitespace().next(),
+                None => None,
+            };
+            if let Some(mtllib) = mtllib {
+                // Missing MTL files are reported by the parser.
+                let mtl_path = resolve(path, Path::new(&mtllib.replace("\\\\", "\\")));
+                hasher.write(&source.read(&mtl_path).unwrap_or_default());
+            }
+        }
+        Ok(hasher.finish())
+    }
+
+    /// Reads the model entry; returns `None` if it is missing, stale or broken.
+    pub fn read_model(
+        &self,
+        path: &Path,
+        options: &ProcessingOptions,
+        key: u64,
+    ) -> Option<CachedModel> {
+        let payload = self.read_entry(EntryKind::Model, path, options_hash(options), key)?;
+        let result = (|| {
+            let mut decoder = Decoder::new(&payload);
+            let meshes = (0..decoder.u32()?)
+                .map(|_| decoder.mesh())
+                .collect::<Result<_>>()?;
+            let materials = (0..decoder.u32()?)
+                .map(|_| decoder.material())
+                .collect::<Result<_>>()?;
+            Ok(CachedModel { meshes, materials })
+        })();
+        ok_or_warn(result, path)
+    }
+
+    /// Writes the model entry.
+    /// Textures of materials are stored only if they refer paths.
+    pub fn write_model(
+        &self,
+        path: &Path,
+        options: &ProcessingOptions,
+        key: u64,
+        model: &CachedModel,
+    ) -> Result<()> {
+        let mut encoder = Encoder::default();
+        encoder.u32(model.meshes.len() as u32);
+        for mesh in &model.meshes {
+            encoder.mesh(mesh);
+        }
+        encoder.u32(model.materials.len() as u32);
+        for material in &model.materials {
+            encoder.material(material);
+        }
+        self.write_entry(
+            EntryKind::Model,
+            path,
+            options_hash(options),
+            key,
+            &encoder.data,
+        )
+    }
+
+    /// Loads a LDR image from the asset source, decoding it only if it is not cached.
+    pub fn load_ldr_image(
+        &self,
+        source: &dyn AssetSource,
+        path: &Path,
+    ) -> Result<ImageData<u8, Rgba>> {
+        let file = source.read(path)?;
+        let mut hasher = FnvHasher::default();
+        hasher.write_u32(CACHE_VERSION);
+        hasher.write(&file);
+        let key = hasher.finish();
+
+        if let Some(payload) = self.read_entry(EntryKind::Image, path, 0, key) {
+            let result = (|| {
+                let mut decoder = Decoder::new(&payload);
+                let width = decoder.u32()? as usize;
+                let height = decoder.u32()? as usize;
+                let data = decoder.bytes(width * height * 4)?;
+                Ok(ImageData::new(data, width, height)?.with_color_space(ColorSpace::Srgb))
+            })();
+            if let Some(image) = ok_or_warn(result, path) {
+                return Ok(image);
+            }
+        }
+
+        let image = decode_ldr_image(&file)?;
+        let mut encoder = Encoder::default();
+        encoder.u32(image.dimensions().0 as u32);
+        encoder.u32(image.dimensions().1 as u32);
+        encoder.data.extend_from_slice(image.data());
+        if let Err(e) = self.write_entry(EntryKind::Image, path, 0, key, &encoder.data) {
+            warn!("Failed to write cache of {:?}: {}", path, e);
+        }
+        Ok(image)
+    }
+
+    /// Loads a HDR equirectangular environment from the asset source and prefilters it,
+    /// only if the result is not cached.
+    pub fn load_prefiltered_environment(
+        &self,
+        source: &dyn AssetSource,
+        path: &Path,
+        options: &PrefilterOptions,
+    ) -> Result<PrefilteredEnvironment> {
+        let file = source.read(path)?;
+        let variant = options_hash(options);
+        let mut hasher = FnvHasher::default();
+        hasher.write_u32(CACHE_VERSION);
+        options.hash(&mut hasher);
+        hasher.write(&file);
+        let key = hasher.finish();
+
+        if let Some(payload) = self.read_entry(EntryKind::Prefiltered, path, variant, key) {
+            let result = (|| {
+                let mut decoder = Decoder::new(&payload);
+                let levels = (0..decoder.u32()?)
+                    .map(|_| CubeMap::new((0..6).map(|_| decoder.image()).collect::<Result<_>>()?))
+                    .collect::<Result<_>>()?;
+                Ok(PrefilteredEnvironment::new(levels))
+            })();
+            if let Some(environment) = ok_or_warn(result, path) {
+                return Ok(environment);
+            }
+        }
+
+        let cubemap = decode_hdr_image(&file)?.equirect_to_cubemap(options.base_size);
+        let environment = prefilter_environment(&cubemap, options);
+        let mut encoder = Encoder::default();
+        encoder.u32(environment.level_count() as u32);
+        for level in environment.levels() {
+            for face in level.faces() {
+                encoder.image(face);
+            }
+        }
+        if let Err(e) = self.write_entry(EntryKind::Prefiltered, path, variant, key, &encoder.data)
+        {
+            warn!("Failed to write cache of {:?}: {}", path, e);
+        }
+        Ok(environment)
+    }
+
+    /// Generates the BRDF integration LUT, only if it is not cached.
+    pub fn load_brdf_lut(&self, size: usize, sample_count: u32) -> Result<ImageData<f32, Rg>> {
+        let path = Path::new("brdf_lut");
+        let variant = options_hash(&(size, sample_count));
+        let mut hasher = FnvHasher::default();
+        hasher.write_u32(CACHE_VERSION);
+        hasher.write_usize(size);
+        hasher.write_u32(sample_count);
+        let key = hasher.finish();
+
+        if let Some(payload) = self.read_entry(EntryKind::BrdfLut, path, variant, key) {
+            if let Some(lut) = ok_or_warn(Decoder::new(&payload).image(), path) {
+                return Ok(lut);
+            }
+        }
+
+        let lut = brdf_lut(size, sample_count);
+        let mut encoder = Encoder::default();
+        encoder.image(&lut);
+        if let Err(e) = self.write_entry(EntryKind::BrdfLut, path, variant, key, &encoder.data) {
+            warn!("Failed to write cache of {:?}: {}", path, e);
+        }
+        Ok(lut)
+    }
+
+    /// Block-compresses all levels of the mip chain, only if the result is not cached.
+    ///
+    /// Entries are named after the content, since images may be generated or embedded
+    /// in models; stale ones are never overwritten, so clear the directory occasionally.
+    pub fn compress_mipmaps(
+        &self,
+        chain: &MipChain<u8, Rgba>,
+        format: BlockFormat,
+    ) -> CompressedImage {
+        let path = Path::new("compressed");
+        let mut hasher = FnvHasher::default();
+        hasher.write_u32(CACHE_VERSION);
+        format.hash(&mut hasher);
+        chain.base().color_space().hash(&mut hasher);
+        for level in chain.levels() {
+            hasher.write_usize(level.dimensions().0);
+            hasher.write_usize(level.dimensions().1);
+            hasher.write(level.data());
+        }
+        let key = hasher.finish();
+
+        if let Some(payload) = self.read_entry(EntryKind::Compressed, path, key, key) {
+            let result = (|| {
+                let mut decoder = Decoder::new(&payload);
+                let color_space = decoder.color_space()?;
+                let (width, height) = chain.base().dimensions();
+                let levels = (0..chain.level_count())
+                    .map(|level| {
+                        let (level_width, level_height) = chain.levels()[level].dimensions();
+                        Ok(decoder
+                            .bytes(format.image_bytes(level_width, level_height))?
+                            .into())
+                    })
+                    .collect::<Result<_>>()?;
+                CompressedImage::new(format, color_space, width, height, levels)
+            })();
+            if let Some(image) = ok_or_warn(result, path) {
+                return image;
+            }
+        }
+
+        let image = CompressedImage::compress_mipmaps(chain, format);
+        let mut encoder = Encoder::default();
+        encoder.color_space(image.color_space());
+        for level in image.levels() {
+            encoder.data.extend_from_slice(level);
+        }
+        if let Err(e) = self.write_entry(EntryKind::Compressed, path, key, key, &encoder.data) {
+            warn!("Failed to write cache of {:?}: {}", path, e);
+        }
+        image
+    }
+
+    /// Reads the payload of the entry if the header matches.
+    fn read_entry(&self, kind: EntryKind, path: &Path, variant: u64, key: u64) -> Option<Vec<u8>> {
+        let mut data = read(self.entry_path(kind, path, variant)).ok()?;
+        let header = Decoder::new(&data).header().ok()?;
+        if header != (kind as u8, key) {
+            debug!("Cache of {:?} is stale", path);
+            return None;
+        }
+
+        debug!("Cache of {:?} hit", path);
+        data.drain(..HEADER_SIZE);
+        Some(data)
+    }
+
+    /// Writes the entry through a temporary file, so that readers never see partial ones.
+    fn write_entry(
+        &self,
+        kind: EntryKind,
+        path: &Path,
+        variant: u64,
+        key: u64,
+        payload: &[u8],
+    ) -> Result<()> {
+        create_dir_all(&self.directory).context("Failed to create cache directory")?;
+
+        let mut data = Vec::with_capacity(HEADER_SIZE + payload.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&CACHE_VERSION.to_le_bytes());
+        data.push(kind as u8);
+        data.extend_from_slice(&key.to_le_bytes());
+        data.extend_from_slice(payload);
+
+        let entry_path = self.entry_path(kind, path, variant);
+        let temporary_path = entry_path.with_extension("tmp");
+        write(&temporary_path, data)?;
+        rename(&temporary_path, &entry_path)?;
+        Ok(())
+    }
+
+    /// Determines the file name of the entry from the asset path and the hash of options.
+    fn entry_path(&self, kind: EntryKind, path: &Path, variant: u64) -> PathBuf {
+        let mut hasher = FnvHasher::default();
+        hasher.write_u8(kind as u8);
+        hasher.write(path.to_string_lossy().as_bytes());
+        hasher.write_u64(variant);
+        self.directory.join(format!("{:016x}.bin", hasher.finish()))
+    }
+}
+
+/// The size of entry headers; magic, version, kind and key.
+const HEADER_SIZE: usize = 4 + 4 + 1 + 8;
+
+/// Kinds of cache entries.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EntryKind {
+    Model = 1,
+    Image = 2,
+    Prefiltered = 3,
+    BrdfLut = 4,
+    Compressed = 5,
+}
+
+/// Hashes processing options, which distinguish entries of the same asset.
+fn options_hash(options: &impl Hash) -> u64 {
+    let mut hasher = FnvHasher::default();
+    options.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Logs the error of broken entries.
+fn ok_or_warn<T>(result: Result<T>, path: &Path) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(e) => {
+            warn!("Cache of {:?} is broken: {}", path, e);
+            None
+        }
+    }
+}
+
+/// Writes values in little endian.
+#[derive(Default)]
+struct Encoder {
+    data: Vec<u8>,
+}
+
+impl Encoder {
+    fn u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn f32(&mut self, value: f32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn floats(&mut self, values: &[f32]) {
+        for &value in values {
+            self.f32(value);
+        }
+    }
+
+    fn image<C: Channels>(&mut self, image: &ImageData<f32, C>) {
+        self.u32(image.dimensions().0 as u32);
+        self.u32(image.dimensions().1 as u32);
+        self.color_space(image.color_space());
+        self.floats(image.data());
+    }
+
+    fn color_space(&mut self, color_space: ColorSpace) {
+        match color_space {
+            ColorSpace::Srgb => self.u8(0),
+            ColorSpace::Linear => self.u8(1),
+            ColorSpace::LinearRec2020 => self.u8(2),
+        }
+    }
+
+    fn str(&mut self, value: Option<&str>) {
+        match value {
+            Some(s) => {
+                self.u8(1);
+                self.u32(s.len() as u32);
+                self.data.extend_from_slice(s.as_bytes());
+            }
+            None => self.u8(0),
+        }
+    }
+
+    fn vec3s(&mut self, values: &[Vec3]) {
+        self.u32(values.len() as u32);
+        for v in values {
+            self.floats(v.as_slice());
+        }
+    }
+
+    fn mesh(&mut self, mesh: &MeshData) {
+        self.vec3s(&mesh.positions);
+        self.vec3s(&mesh.normals);
+        for uv in mesh.uvs.iter() {
+            self.floats(uv.as_slice());
+        }
+        for tangent in mesh.tangents.iter() {
+            self.floats(tangent.as_slice());
+        }
+
+        self.u32(mesh.indices.len() as u32);
+        for &index in mesh.indices.iter() {
+            self.u32(index);
+        }
+        self.u32(mesh.submeshes.len() as u32);
+        for submesh in mesh.submeshes.iter() {
+            self.u32(submesh.indices.start as u32);
+            self.u32(submesh.indices.end as u32);
+            self.u32(submesh.material.map_or(u32::MAX, |m| m as u32));
+        }
+
+        self.u32(mesh.morph_targets.len() as u32);
+        for target in mesh.morph_targets.iter() {
+            self.str(target.name());
+            self.f32(target.default_weight());
+            self.vec3s(target.position_deltas());
+            self.vec3s(target.normal_deltas());
+            self.vec3s(target.tangent_deltas());
+        }
+    }
+
+    fn material(&mut self, material: &MaterialDescription) {
+        self.str(material.name.as_deref());
+        self.floats(material.base_color.as_slice());
+        self.texture(&material.base_color_texture);
+        self.f32(material.metallic);
+        self.f32(material.roughness);
+        self.texture(&material.metallic_roughness_texture);
+        self.texture(&material.metallic_texture);
+        self.texture(&material.roughness_texture);
+        self.texture(&material.normal_texture);
+        self.f32(material.normal_scale);
+        self.texture(&material.occlusion_texture);
+        self.f32(material.occlusion_strength);
+        self.floats(material.emissive.as_slice());
+        self.texture(&material.emissive_texture);
+        self.floats(material.specular.as_slice());
+        self.texture(&material.specular_texture);
+        self.texture(&material.alpha_texture);
+        match material.alpha_mode {
+            AlphaMode::Opaque => self.u8(0),
+            AlphaMode::Mask(cutoff) => {
+                self.u8(1);
+                self.f32(cutoff);
+            }
+            AlphaMode::Blend => self.u8(2),
+        }
+        self.u8(material.double_sided as u8);
+    }
+
+    fn texture(&mut self, texture: &Option<MaterialTexture>) {
+        match texture {
+            Some(MaterialTexture {
+                source: TextureSource::Path(path),
+                tex_coord,
+            }) => {
+                self.str(Some(&path.to_string_lossy()));
+                self.u32(*tex_coord);
+            }
+            _ => self.str(None),
+        }
+    }
+}
+
+/// Reads values written by `Encoder`.
+struct Decoder<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &[u8]) -> Decoder<'_> {
+        Decoder { data }
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8]> {
+        if self.data.len() < length {
+            bail!("Unexpected end of data");
+        }
+        let (bytes, rest) = self.data.split_at(length);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn header(&mut self) -> Result<(u8, u64)> {
+        if self.bytes(4)? != MAGIC {
+            bail!("Invalid magic");
+        }
+        if self.u32()? != CACHE_VERSION {
+            bail!("Unsupported version");
+        }
+        let kind = self.u8()?;
+        let key = u64::from_le_bytes(self.bytes(8)?.try_into()?);
+        Ok((kind, key))
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn str(&mut self) -> Result<Option<String>> {
+        match self.u8()? {
+            0 => Ok(None),
+            _ => {
+                let length = self.u32()? as usize;
+                Ok(Some(String::from_utf8(self.bytes(length)?.to_vec())?))
+            }
+        }
+    }
+
+    fn vec3(&mut self) -> Result<Vec3> {
+        Ok(Vec3::new(self.f32()?, self.f32()?, self.f32()?))
+    }
+
+    fn vec4(&mut self) -> Result<Vec4> {
+        Ok(Vec4::new(
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+            self.f32()?,
+        ))
+    }
+
+    fn image<C: Channels>(&mut self) -> Result<ImageData<f32, C>> {
+        let width = self.u32()? as usize;
+        let height = self.u32()? as usize;
+        let color_space = self.color_space()?;
+        let data = self
+            .bytes(width * height * C::CHANNELS * 4)?
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect::<Vec<_>>();
+        Ok(ImageData::new(&data, width, height)?.with_color_space(color_space))
+    }
+
+    fn color_space(&mut self) -> Result<ColorSpace> {
+        Ok(match self.u8()? {
+            0 => ColorSpace::Srgb,
+            1 => ColorSpace::Linear,
+            2 => ColorSpace::LinearRec2020,
+            other => bail!("Unknown color space: {}", other),
+        })
+    }
+
+    fn vec3s(&mut self) -> Result<Vec<Vec3>> {
+        (0..self.u32()?).map(|_| self.vec3()).collect()
+    }
+
+    fn mesh(&mut self) -> Result<MeshData> {
+        let positions = self.vec3s()?;
+        let normals = self.vec3s()?;
+        let vertices = positions.len();
+        let uvs = (0..vertices)
+            .map(|_| Ok(Vec2::new(self.f32()?, self.f32()?)))
+            .collect::<Result<Vec<_>>>()?;
+        let tangents = (0..vertices)
+            .map(|_| self.vec4())
+            .collect::<Result<Vec<_>>>()?;
+
+        let indices = (0..self.u32()?)
+            .map(|_| self.u32())
+            .collect::<Result<Vec<_>>>()?;
+        let submeshes = (0..self.u32()?)
+            .map(|_| {
+                let start = self.u32()? as usize;
+                let end = self.u32()? as usize;
+                let material = match self.u32()? {
+                    u32::MAX => None,
+                    m => Some(m as usize),
+                };
+                if start > end || end > indices.len() {
+                    bail!("Invalid submesh range");
+                }
+                Ok(Submesh {
+                    indices: start..end,
+                    material,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let morph_targets = (0..self.u32()?)
+            .map(|_| {
+                let name = self.str()?;
+                let weight = self.f32()?;
+                let positions = self.vec3s()?;
+                let normals = self.vec3s()?;
+                let tangents = self.vec3s()?;
+                MorphTarget::new(name.as_deref(), weight, positions, normals, tangents)
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        let mut mesh = MeshData::new(
+            positions.into_boxed_slice(),
+            normals.into_boxed_slice(),
+            uvs.into_boxed_slice(),
+            tangents.into_boxed_slice(),
+            indices.into_boxed_slice(),
+            None,
+        )?;
+        mesh.submeshes = submeshes.into_boxed_slice();
+        mesh.set_morph_targets(morph_targets)?;
+        Ok(mesh)
+    }
+
+    fn material(&mut self) -> Result<MaterialDescription> {
+        Ok(MaterialDescription {
+            name: self.str()?,
+            base_color: self.vec4()?,
+            base_color_texture: self.texture()?,
+            metallic: self.f32()?,
+            roughness: self.f32()?,
+            metallic_roughness_texture: self.texture()?,
+            metallic_texture: self.texture()?,
+            roughness_texture: self.texture()?,
+            normal_texture: self.texture()?,
+            normal_scale: self.f32()?,
+            occlusion_texture: self.texture()?,
+            occlusion_strength: self.f32()?,
+            emissive: self.vec3()?,
+            emissive_texture: self.texture()?,
+            specular: self.vec3()?,
+            specular_texture: self.texture()?,
+            alpha_texture: self.texture()?,
+            alpha_mode: match self.u8()? {
+                0 => AlphaMode::Opaque,
+                1 => AlphaMode::Mask(self.f32()?),
+                _ => AlphaMode::Blend,
+            },
+            double_sided: self.u8()? != 0,
+        })
+    }
+
+    fn texture(&mut self) -> Result<Option<MaterialTexture>> {
+        match self.str()? {
+            Some(path) => Ok(Some(MaterialTexture {
+                source: TextureSource::Path(path.into()),
+                tex_coord: self.u32()?,
+            })),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{
+        env::temp_dir,
+        fs::{read_dir, remove_dir_all},
+    };
+
+    #[test]
+    fn options_do_not_evict_each_other() {
+        let directory = temp_dir().join("derky-cache-options");
+        let _ = remove_dir_all(&directory);
+        let cache = AssetCache::new(&directory);
+        let path = Path::new("models/a.obj");
+        let optimized = ProcessingOptions {
+            optimize: true,
+            ..Default::default()
+        };
+        let plain = ProcessingOptions::default();
+        let empty = || CachedModel {
+            meshes: vec![],
+            materials: vec![],
+        };
+
+        cache.write_model(path, &optimized, 1, &empty()).unwrap();
+        cache.write_model(path, &plain, 2, &empty()).unwrap();
+        assert!(cache.read_model(path, &optimized, 1).is_some());
+        assert!(cache.read_model(path, &plain, 2).is_some());
+        assert!(cache.read_model(path, &plain, 1).is_none());
+
+        // A stale entry is replaced in place, instead of accumulating.
+        cache.write_model(path, &plain, 3, &empty()).unwrap();
+        assert!(cache.read_model(path, &plain, 3).is_some());
+        assert!(cache.read_model(path, &optimized, 1).is_some());
+        assert_eq!(read_dir(&directory).unwrap().count(), 2);
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn compressed_images_are_reused() {
+        let directory = temp_dir().join("derky-cache-compressed");
+        let _ = remove_dir_all(&directory);
+        let cache = AssetCache::new(&directory);
+        let data: Vec<u8> = (0..(8 * 8 * 4)).map(|i| (i * 7 % 256) as u8).collect();
+        let image = ImageData::<u8, Rgba>::new(&data, 8, 8)
+            .unwrap()
+            .with_color_space(ColorSpace::Srgb);
+        let chain = image.generate_mipmaps(&Default::default());
+
+        let compressed = cache.compress_mipmaps(&chain, BlockFormat::Bc7);
+        let cached = cache.compress_mipmaps(&chain, BlockFormat::Bc7);
+        assert_eq!(cached.levels(), compressed.levels());
+        assert_eq!(cached.color_space(), ColorSpace::Srgb);
+        assert_eq!(read_dir(&directory).unwrap().count(), 1);
+
+        // Other formats and contents have their own entries.
+        cache.compress_mipmaps(&chain, BlockFormat::Bc3);
+        let linear = image.with_color_space(ColorSpace::Linear);
+        let linear = cache.compress_mipmaps(
+            &linear.generate_mipmaps(&Default::default()),
+            BlockFormat::Bc7,
+        );
+        assert_eq!(linear.color_space(), ColorSpace::Linear);
+        assert_eq!(read_dir(&directory).unwrap().count(), 3);
+
+        remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn color_spaces_round_trip() {
+        for &color_space in &[
+            ColorSpace::Srgb,
+            ColorSpace::Linear,
+            ColorSpace::LinearRec2020,
+        ] {
+            let mut encoder = Encoder::default();
+            encoder.color_space(color_space);
+            assert_eq!(
+                Decoder::new(&encoder.data).color_space().unwrap(),
+                color_space
+            );
+        }
+        assert!(Decoder::new(&[3]).color_space().is_err());
+    }
+}
@@ -1,5 +1,10 @@
 //! Contains lights and environmental types.
 
+use crate::common::{
+    frustum::{DepthRange, Frustum},
+    ibl::SphericalHarmonics,
+};
+
 use std::time::Duration;
 
 use ultraviolet::{Mat4, Vec2, Vec3};
@@ -19,6 +24,11 @@ pub struct ImageLight<T> {
 
     /// Light intensity multipler
     pub intensity: f32,
+
+    /// Diffuse lighting convolved with `SphericalHarmonics::convolve_lambert`; upload
+    /// `to_constants()` to shaders with a flag set in `y` of the intensity.
+    /// If it is `None`, the texture sampled in the reflection direction is used instead
+    pub irradiance: Option<SphericalHarmonics>,
 }
 
 impl<T: Clone> Clone for ImageLight<T> {
@@ -26,6 +36,7 @@ impl<T: Clone> Clone for ImageLight<T> {
         ImageLight {
             texture: self.texture.clone(),
             intensity: self.intensity,
+            irradiance: self.irradiance.clone(),
         }
     }
 }
@@ -67,6 +78,16 @@ impl View {
             screen_dimensions,
         }
     }
+
+    /// Returns the combined matrix of projection and view.
+    pub fn view_projection(&self) -> Mat4 {
+        self.projection_matrix * self.view_matrix
+    }
+
+    /// Extracts the view frustum in world space.
+    pub fn frustum(&self, depth_range: DepthRange) -> Frustum {
+        Frustum::from_matrix(&self.view_projection(), depth_range)
+    }
 }
 
 pub struct Environment<T> {
@@ -0,0 +1,257 @@
+//! Contains view frustums and culling tests.
+
+use crate::common::bounds::{Aabb, BoundingSphere, Bounds};
+
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+/// Represents the depth range of clip space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DepthRange {
+    /// `-w <= z <= w`, used by OpenGL.
+    NegativeOneToOne,
+
+    /// `0 <= z <= w`, used by Direct3D.
+    ZeroToOne,
+}
+
+/// Represents a plane; points `p` with `normal.dot(p) + distance >= 0` are inside.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Plane {
+    /// Unit normal vector pointing inside
+    pub normal: Vec3,
+
+    /// Signed distance from the origin
+    pub distance: f32,
+}
+
+impl Plane {
+    /// Creates a normalized plane from `(a, b, c, d)` of `ax + by + cz + d = 0`.
+    pub fn from_coefficients(coefficients: Vec4) -> Plane {
+        let normal = coefficients.truncated();
+        let length = normal.mag();
+        Plane {
+            normal: normal / length,
+            distance: coefficients.w / length,
+        }
+    }
+
+    /// Calculates the signed distance from this plane to the point.
+    pub fn signed_distance(&self, point: Vec3) -> f32 {
+        self.normal.dot(point) + self.distance
+    }
+}
+
+/// Represents a view frustum with six planes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frustum {
+    planes: [Plane; 6],
+}
+
+impl Frustum {
+    /// Extracts the frustum from a clip matrix (projection × view, optionally × model).
+    /// The frustum is in the space which the matrix transforms from.
+    pub fn from_matrix(matrix: &Mat4, depth_range: DepthRange) -> Frustum {
+        let row = |r: usize| {
+            Vec4::new(
+                matrix.cols[0][r],
+                matrix.cols[1][r],
+                matrix.cols[2][r],
+                matrix.cols[3][r],
+            )
+        };
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let near = match depth_range {
+            DepthRange::NegativeOneToOne => w + z,
+            DepthRange::ZeroToOne => z,
+        };
+        Frustum {
+            planes: [
+                Plane::from_coefficients(w + x),
+                Plane::from_coefficients(w - x),
+                Plane::from_coefficients(w + y),
+                Plane::from_coefficients(w - y),
+                Plane::from_coefficients(near),
+                Plane::from_coefficients(w - z),
+            ],
+        }
+    }
+
+    /// The planes in order of left, right, bottom, top, near and far.
+    pub fn planes(&self) -> &[Plane; 6] {
+        &self.planes
+    }
+
+    /// Checks whether the point is inside this frustum.
+    pub fn contains_point(&self, point: Vec3) -> bool {
+        self.planes.iter().all(|p| p.signed_distance(point) >= 0.0)
+    }
+
+    /// Checks whether the sphere intersects this frustum.
+    pub fn intersects_sphere(&self, sphere: &BoundingSphere) -> bool {
+        !sphere.is_empty()
+            && self
+                .planes
+                .iter()
+                .all(|p| p.signed_distance(sphere.center) >= -sphere.radius)
+    }
+
+    /// Checks whether the box intersects this frustum.
+    /// It may report intersection for some boxes near edges of the frustum.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        self.planes.iter().all(|p| {
+            let farthest = Vec3::new(
+                if p.normal.x >= 0.0 {
+                    aabb.max.x
+                } else {
+                    aabb.min.x
+                },
+                if p.normal.y >= 0.0 {
+                    aabb.max.y
+                } else {
+                    aabb.min.y
+                },
+                if p.normal.z >= 0.0 {
+                    aabb.max.z
+                } else {
+                    aabb.min.z
+                },
+            );
+            p.signed_distance(farthest) >= 0.0
+        })
+    }
+
+    /// Checks whether the bounding volumes intersect this frustum.
+    /// The sphere is tested first, and then the box.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        self.intersects_sphere(&bounds.sphere) && self.intersects_aabb(&bounds.aabb)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ultraviolet::projection::rh_yup::{
+        orthographic_gl, orthographic_wgpu_dx, perspective_gl, perspective_wgpu_dx,
+    };
+
+    const DEPTH_RANGES: [DepthRange; 2] = [DepthRange::NegativeOneToOne, DepthRange::ZeroToOne];
+
+    /// A 90 degree perspective frustum looking at -Z, from 1 to 10.
+    fn perspective(depth_range: DepthRange) -> Frustum {
+        let projection = match depth_range {
+            DepthRange::NegativeOneToOne => perspective_gl(90f32.to_radians(), 1.0, 1.0, 10.0),
+            DepthRange::ZeroToOne => perspective_wgpu_dx(90f32.to_radians(), 1.0, 1.0, 10.0),
+        };
+        Frustum::from_matrix(&projection, depth_range)
+    }
+
+    /// A box frustum of [-2, 2] x [-1, 1] looking at -Z, from 1 to 5.
+    fn orthographic(depth_range: DepthRange) -> Frustum {
+        let projection = match depth_range {
+            DepthRange::NegativeOneToOne => orthographic_gl(-2.0, 2.0, -1.0, 1.0, 1.0, 5.0),
+            DepthRange::ZeroToOne => orthographic_wgpu_dx(-2.0, 2.0, -1.0, 1.0, 1.0, 5.0),
+        };
+        Frustum::from_matrix(&projection, depth_range)
+    }
+
+    fn assert_plane(plane: &Plane, normal: Vec3, distance: f32) {
+        assert!(
+            (plane.normal - normal).mag() < 1e-4 && (plane.distance - distance).abs() < 1e-4,
+            "{:?} is not ({:?}, {})",
+            plane,
+            normal,
+            distance
+        );
+    }
+
+    #[test]
+    fn perspective_planes() {
+        let diagonal = 0.5f32.sqrt();
+        for &depth_range in &DEPTH_RANGES {
+            let planes = *perspective(depth_range).planes();
+            assert_plane(&planes[0], Vec3::new(diagonal, 0.0, -diagonal), 0.0);
+            assert_plane(&planes[1], Vec3::new(-diagonal, 0.0, -diagonal), 0.0);
+            assert_plane(&planes[2], Vec3::new(0.0, diagonal, -diagonal), 0.0);
+            assert_plane(&planes[3], Vec3::new(0.0, -diagonal, -diagonal), 0.0);
+            assert_plane(&planes[4], Vec3::new(0.0, 0.0, -1.0), -1.0);
+            assert_plane(&planes[5], Vec3::new(0.0, 0.0, 1.0), 10.0);
+        }
+    }
+
+    #[test]
+    fn orthographic_planes() {
+        for &depth_range in &DEPTH_RANGES {
+            let planes = *orthographic(depth_range).planes();
+            assert_plane(&planes[0], Vec3::new(1.0, 0.0, 0.0), 2.0);
+            assert_plane(&planes[1], Vec3::new(-1.0, 0.0, 0.0), 2.0);
+            assert_plane(&planes[2], Vec3::new(0.0, 1.0, 0.0), 1.0);
+            assert_plane(&planes[3], Vec3::new(0.0, -1.0, 0.0), 1.0);
+            assert_plane(&planes[4], Vec3::new(0.0, 0.0, -1.0), -1.0);
+            assert_plane(&planes[5], Vec3::new(0.0, 0.0, 1.0), 5.0);
+        }
+    }
+
+    #[test]
+    fn perspective_culling() {
+        let sphere = |x, y, z, radius| BoundingSphere {
+            center: Vec3::new(x, y, z),
+            radius,
+        };
+        let aabb = |min: (f32, f32, f32), max: (f32, f32, f32)| Aabb {
+            min: Vec3::new(min.0, min.1, min.2),
+            max: Vec3::new(max.0, max.1, max.2),
+        };
+
+        for &depth_range in &DEPTH_RANGES {
+            let frustum = perspective(depth_range);
+            assert!(frustum.contains_point(Vec3::new(0.0, 0.0, -5.0)));
+            assert!(!frustum.contains_point(Vec3::new(0.0, 0.0, -0.5)));
+            assert!(!frustum.contains_point(Vec3::new(6.0, 0.0, -5.0)));
+
+            assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -5.0, 1.0)));
+            assert!(frustum.intersects_sphere(&sphere(0.0, 0.0, -11.0, 1.5)));
+            assert!(frustum.intersects_sphere(&sphere(6.0, 0.0, -5.0, 1.0)));
+            assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, -12.0, 1.0)));
+            assert!(!frustum.intersects_sphere(&sphere(0.0, 0.0, 2.0, 1.0)));
+            assert!(!frustum.intersects_sphere(&sphere(0.0, 8.0, -5.0, 1.0)));
+            assert!(!frustum.intersects_sphere(&BoundingSphere::empty()));
+
+            assert!(frustum.intersects_aabb(&aabb((-1.0, -1.0, -6.0), (1.0, 1.0, -4.0))));
+            assert!(frustum.intersects_aabb(&aabb((4.0, -1.0, -6.0), (6.0, 1.0, -4.0))));
+            assert!(frustum.intersects_aabb(&aabb((-20.0, -20.0, -9.0), (20.0, 20.0, -2.0))));
+            assert!(!frustum.intersects_aabb(&aabb((7.0, -1.0, -6.0), (9.0, 1.0, -4.0))));
+            assert!(!frustum.intersects_aabb(&aabb((-1.0, -1.0, -0.9), (1.0, 1.0, 3.0))));
+            assert!(!frustum.intersects_aabb(&aabb((-1.0, -1.0, -20.0), (1.0, 1.0, -11.0))));
+            assert!(!frustum.intersects_aabb(&Aabb::empty()));
+        }
+    }
+
+    #[test]
+    fn orthographic_culling() {
+        for &depth_range in &DEPTH_RANGES {
+            let frustum = orthographic(depth_range);
+            let bounds = |center: Vec3| {
+                Bounds::from_points(&[
+                    center - Vec3::broadcast(0.25),
+                    center + Vec3::broadcast(0.25),
+                ])
+            };
+
+            assert!(frustum.contains_point(Vec3::new(1.9, -0.9, -4.9)));
+            assert!(!frustum.contains_point(Vec3::new(2.1, 0.0, -3.0)));
+            assert!(frustum.intersects(&bounds(Vec3::new(0.0, 0.0, -3.0))));
+            assert!(frustum.intersects(&bounds(Vec3::new(2.2, 0.0, -3.0))));
+            assert!(frustum.intersects(&bounds(Vec3::new(0.0, 1.2, -5.2))));
+            assert!(!frustum.intersects(&bounds(Vec3::new(2.5, 0.0, -3.0))));
+            assert!(!frustum.intersects(&bounds(Vec3::new(0.0, -1.5, -3.0))));
+            assert!(!frustum.intersects(&bounds(Vec3::new(0.0, 0.0, -0.5))));
+            assert!(!frustum.intersects(&bounds(Vec3::new(0.0, 0.0, -5.5))));
+        }
+    }
+}
@@ -0,0 +1,388 @@
+//! Contains precomputation for image-based lighting.
+
+use crate::common::texture::{equirect_direction, CubeFace, CubeMap, ImageData, Rgba};
+
+use std::f32::consts::PI;
+
+use anyhow::{bail, Result};
+use ultraviolet::{Vec3, Vec4};
+
+/// The normalization constants of the real spherical harmonics basis.
+const SH_C0: f32 = 0.282_095;
+const SH_C1: f32 = 0.488_603;
+const SH_C2: f32 = 1.092_548;
+const SH_C3: f32 = 0.315_392;
+const SH_C4: f32 = 0.546_274;
+
+/// The ratios of the clamped cosine lobe to π for each band.
+const LAMBERT_BANDS: [f32; 3] = [1.0, 2.0 / 3.0, 1.0 / 4.0];
+
+/// Represents orders of spherical harmonics.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ShOrder {
+    /// Bands 0 and 1; 4 coefficients
+    Second,
+
+    /// Bands 0 to 2; 9 coefficients
+    Third,
+}
+
+impl ShOrder {
+    /// The number of coefficients.
+    pub fn coefficient_count(self) -> usize {
+        match self {
+            ShOrder::Second => 4,
+            ShOrder::Third => 9,
+        }
+    }
+}
+
+/// Represents a RGB function on the sphere, projected onto spherical harmonics.
+///
+/// Coefficients are ordered by (l, m) as (0, 0), (1, -1), (1, 0), (1, 1), (2, -2),
+/// (2, -1), (2, 0), (2, 1), (2, 2), and the basis functions of the unit direction
+/// `(x, y, z)` are, without normalization constants,
+/// `1`, `y`, `z`, `x`, `xy`, `yz`, `3z^2 - 1`, `xz`, and `x^2 - y^2`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SphericalHarmonics {
+    coefficients: Vec<Vec3>,
+}
+
+impl SphericalHarmonics {
+    /// Creates from coefficients; their number must be 4 or 9.
+    pub fn from_coefficients(coefficients: Vec<Vec3>) -> Result<SphericalHarmonics> {
+        match coefficients.len() {
+            4 | 9 => Ok(SphericalHarmonics { coefficients }),
+            n => bail!(
+                "Spherical harmonics must have 4 or 9 coefficients, but {} given",
+                n
+            ),
+        }
+    }
+
+    /// Projects an equirectangular environment image, mapped as `CubeMap::to_equirect`.
+    pub fn project_equirect(image: &ImageData<f32, Rgba>, order: ShOrder) -> SphericalHarmonics {
+        let (width, height) = image.dimensions();
+        let mut coefficients = vec![Vec3::zero(); order.coefficient_count()];
+        let mut basis = [0.0; 9];
+        for y in 0..height {
+            // The exact solid angle of the texel row, so that the total is 4π.
+            let top = (0.5 - y as f32 / height as f32) * PI;
+            let bottom = (0.5 - (y + 1) as f32 / height as f32) * PI;
+            let solid_angle = (top.sin() - bottom.sin()) * 2.0 * PI / width as f32;
+
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                evaluate_basis(equirect_direction(u, v), &mut basis);
+
+                let base_index = (y * width + x) * 4;
+                let texel = &image.data()[base_index..(base_index + 3)];
+                let radiance = Vec3::new(texel[0], texel[1], texel[2]) * solid_angle;
+                for (coefficient, b) in coefficients.iter_mut().zip(&basis) {
+                    *coefficient += radiance * *b;
+                }
+            }
+        }
+
+        SphericalHarmonics { coefficients }
+    }
+
+    /// Projects a cube map environment.
+    pub fn project_cubemap(cubemap: &CubeMap<f32, Rgba>, order: ShOrder) -> SphericalHarmonics {
+        let size = cubemap.size();
+        let mut coefficients = vec![Vec3::zero(); order.coefficient_count()];
+        let mut basis = [0.0; 9];
+        for &face in CubeFace::ALL.iter() {
+            let data = cubemap.face(face).data();
+            for y in 0..size {
+                for x in 0..size {
+                    let u = (x as f32 + 0.5) / size as f32;
+                    let v = (y as f32 + 0.5) / size as f32;
+                    evaluate_basis(face.direction(u, v).normalized(), &mut basis);
+
+                    let base_index = (y * size + x) * 4;
+                    let texel = &data[base_index..(base_index + 3)];
+                    let solid_angle = texel_solid_angle(x, y, size);
+                    let radiance = Vec3::new(texel[0], texel[1], texel[2]) * solid_angle;
+                    for (coefficient, b) in coefficients.iter_mut().zip(&basis) {
+                        *coefficient += radiance * *b;
+                    }
+                }
+            }
+        }
+
+        SphericalHarmonics { coefficients }
+    }
+
+    /// The order.
+    pub fn order(&self) -> ShOrder {
+        if self.coefficients.len() == 9 {
+            ShOrder::Third
+        } else {
+            ShOrder::Second
+        }
+    }
+
+    /// The coefficients.
+    pub fn coefficients(&self) -> &[Vec3] {
+        &self.coefficients
+    }
+
+    /// Evaluates the function in the direction, which must be normalized.
+    /// The result may be negative around strong lights due to ringing.
+    pub fn evaluate(&self, direction: Vec3) -> Vec3 {
+        let mut basis = [0.0; 9];
+        evaluate_basis(direction, &mut basis);
+        self.coefficients
+            .iter()
+            .zip(&basis)
+            .fold(Vec3::zero(), |sum, (&c, &b)| sum + c * b)
+    }
+
+    /// Convolves radiance with the clamped cosine lobe, divided by π.
+    ///
+    /// Evaluating the result in a normal direction gives the outgoing radiance of a
+    /// white Lambertian surface, so it can be multiplied by albedo directly.
+    pub fn convolve_lambert(&self) -> SphericalHarmonics {
+        let coefficients = self
+            .coefficients
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| c * LAMBERT_BANDS[band(i)])
+            .collect();
+        SphericalHarmonics { coefficients }
+    }
+
+    /// Converts into constants for shaders; the fourth elements are zero,
+    /// and coefficients beyond the order are also zero.
+    pub fn to_constants(&self) -> [Vec4; 9] {
+        let mut constants = [Vec4::zero(); 9];
+        for (constant, c) in constants.iter_mut().zip(&self.coefficients) {
+            *constant = Vec4::new(c.x, c.y, c.z, 0.0);
+        }
+        constants
+    }
+
+    /// Renders into an equirectangular image. Negative values are clamped to zero.
+    pub fn to_equirect(&self, width: usize, height: usize) -> ImageData<f32, Rgba> {
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                let value = self.evaluate(equirect_direction(u, v));
+                data.extend_from_slice(&[
+                    value.x.max(0.0),
+                    value.y.max(0.0),
+                    value.z.max(0.0),
+                    1.0,
+                ]);
+            }
+        }
+
+        ImageData::new(&data, width, height).expect("Invalid dimensions")
+    }
+}
+
+/// Generates an equirectangular irradiance map from an equirectangular environment,
+/// through 3rd order spherical harmonics. Values are divided by π as `convolve_lambert`.
+///
+/// The irradiance is smooth, so small dimensions such as 32x16 are enough.
+pub fn irradiance_map(
+    environment: &ImageData<f32, Rgba>,
+    width: usize,
+    height: usize,
+) -> ImageData<f32, Rgba> {
+    SphericalHarmonics::project_equirect(environment, ShOrder::Third)
+        .convolve_lambert()
+        .to_equirect(width, height)
+}
+
+/// Evaluates all 9 basis functions.
+fn evaluate_basis(Vec3 { x, y, z }: Vec3, basis: &mut [f32; 9]) {
+    *basis = [
+        SH_C0,
+        SH_C1 * y,
+        SH_C1 * z,
+        SH_C1 * x,
+        SH_C2 * x * y,
+        SH_C2 * y * z,
+        SH_C3 * (3.0 * z * z - 1.0),
+        SH_C2 * x * z,
+        SH_C4 * (x * x - y * y),
+    ];
+}
+
+/// Gets the band of the coefficient index.
+fn band(index: usize) -> usize {
+    match index {
+        0 => 0,
+        1..=3 => 1,
+        _ => 2,
+    }
+}
+
+/// Calculates the solid angle of a cube map texel.
+fn texel_solid_angle(x: usize, y: usize, size: usize) -> f32 {
+    let area = |x: f32, y: f32| (x * y).atan2((x * x + y * y + 1.0).sqrt());
+    let to_face = |i: usize| i as f32 / size as f32 * 2.0 - 1.0;
+    let (x0, x1) = (to_face(x), to_face(x + 1));
+    let (y0, y1) = (to_face(y), to_face(y + 1));
+    area(x0, y0) - area(x0, y1) - area(x1, y0) + area(x1, y1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Renders a function of directions into an equirectangular image.
+    fn equirect(width: usize, height: usize, f: impl Fn(Vec3) -> Vec3) -> ImageData<f32, Rgba> {
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                let value = f(equirect_direction(u, v));
+                data.extend_from_slice(&[value.x, value.y, value.z, 1.0]);
+            }
+        }
+        ImageData::new(&data, width, height).unwrap()
+    }
+
+    /// Renders a function of directions into a cube map.
+    fn cubemap(size: usize, f: impl Fn(Vec3) -> Vec3) -> CubeMap<f32, Rgba> {
+        let faces = CubeFace::ALL
+            .iter()
+            .map(|&face| {
+                let mut data = Vec::with_capacity(size * size * 4);
+                for y in 0..size {
+                    for x in 0..size {
+                        let u = (x as f32 + 0.5) / size as f32;
+                        let v = (y as f32 + 0.5) / size as f32;
+                        let value = f(face.direction(u, v).normalized());
+                        data.extend_from_slice(&[value.x, value.y, value.z, 1.0]);
+                    }
+                }
+                ImageData::new(&data, size, size).unwrap()
+            })
+            .collect();
+        CubeMap::new(faces).unwrap()
+    }
+
+    fn assert_close(actual: Vec3, expected: Vec3, tolerance: f32) {
+        assert!(
+            (actual - expected).abs().component_max() < tolerance,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    fn directions() -> Vec<Vec3> {
+        vec![
+            Vec3::unit_x(),
+            -Vec3::unit_y(),
+            Vec3::unit_z(),
+            Vec3::new(1.0, 2.0, -3.0).normalized(),
+            Vec3::new(-0.5, 0.5, 0.7).normalized(),
+        ]
+    }
+
+    #[test]
+    fn constant_environment_projects_to_first_band() {
+        let radiance = Vec3::new(0.25, 0.5, 2.0);
+        let projections = [
+            SphericalHarmonics::project_equirect(&equirect(64, 32, |_| radiance), ShOrder::Third),
+            SphericalHarmonics::project_cubemap(&cubemap(16, |_| radiance), ShOrder::Third),
+        ];
+
+        for sh in &projections {
+            assert_eq!(sh.order(), ShOrder::Third);
+            assert_close(sh.coefficients()[0], radiance * SH_C0 * 4.0 * PI, 1e-3);
+            for &c in &sh.coefficients()[1..] {
+                assert_close(c, Vec3::zero(), 1e-2);
+            }
+
+            let irradiance = sh.convolve_lambert();
+            for direction in directions() {
+                assert_close(irradiance.evaluate(direction), radiance, 5e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn upper_hemisphere_peaks_at_top() {
+        let environment = equirect(64, 32, |d| Vec3::broadcast(d.y.max(0.0)));
+        let irradiance =
+            SphericalHarmonics::project_equirect(&environment, ShOrder::Third).convolve_lambert();
+        let top = irradiance.evaluate(Vec3::unit_y());
+        for direction in directions() {
+            assert!(irradiance.evaluate(direction).x < top.x);
+        }
+        // The exact value is 2/3; 3rd order approximates it closely.
+        assert_close(top, Vec3::broadcast(2.0 / 3.0), 0.02);
+
+        let map = irradiance_map(&environment, 32, 16);
+        let row_average =
+            |y: usize| (0..32).map(|x| map.data()[(y * 32 + x) * 4]).sum::<f32>() / 32.0;
+        let rows: Vec<_> = (0..16).map(row_average).collect();
+        assert!(rows.windows(2).all(|w| w[0] >= w[1]));
+        assert!(rows[0] > rows[15]);
+    }
+
+    #[test]
+    fn equirect_and_cubemap_projections_agree() {
+        let f = |d: Vec3| Vec3::new(1.0 + d.x, 1.0 + 0.5 * d.y * d.z, 0.5 + d.z * d.z);
+        let from_equirect =
+            SphericalHarmonics::project_equirect(&equirect(128, 64, f), ShOrder::Third);
+        let from_cubemap = SphericalHarmonics::project_cubemap(&cubemap(32, f), ShOrder::Third);
+        for (&a, &b) in from_equirect
+            .coefficients()
+            .iter()
+            .zip(from_cubemap.coefficients())
+        {
+            assert_close(a, b, 5e-3);
+        }
+        // The function is in the span of the basis, so it is reconstructed.
+        for direction in directions() {
+            assert_close(from_cubemap.evaluate(direction), f(direction), 5e-3);
+        }
+    }
+
+    #[test]
+    fn basis_order_matches_shaders() {
+        // Each linear function projects onto a single coefficient of band 1.
+        let axes = [
+            (Vec3::unit_y(), 1),
+            (Vec3::unit_z(), 2),
+            (Vec3::unit_x(), 3),
+        ];
+        for &(axis, index) in &axes {
+            let environment = equirect(128, 64, |d| Vec3::broadcast(d.dot(axis)));
+            let sh = SphericalHarmonics::project_equirect(&environment, ShOrder::Third);
+            for (i, &c) in sh.coefficients().iter().enumerate() {
+                let expected = if i == index {
+                    SH_C1 * 4.0 * PI / 3.0
+                } else {
+                    0.0
+                };
+                assert_close(c, Vec3::broadcast(expected), 5e-3);
+            }
+        }
+    }
+
+    #[test]
+    fn second_order_and_constants() {
+        let environment = equirect(32, 16, |d| Vec3::new(1.0, d.x.max(0.0), 0.0));
+        let sh = SphericalHarmonics::project_equirect(&environment, ShOrder::Second);
+        assert_eq!(sh.order(), ShOrder::Second);
+        assert_eq!(sh.coefficients().len(), 4);
+
+        let constants = sh.to_constants();
+        assert_eq!(constants[3].y, sh.coefficients()[3].y);
+        assert!(constants[4..].iter().all(|c| *c == Vec4::zero()));
+        assert!(constants.iter().all(|c| c.w == 0.0));
+        assert!(SphericalHarmonics::from_coefficients(vec![Vec3::zero(); 5]).is_err());
+    }
+}
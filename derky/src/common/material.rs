@@ -0,0 +1,575 @@
+//! Contains backend-neutral material description.
+
+use crate::common::{
+    asset::{resolve, AssetSource},
+    cache::AssetCache,
+    texture::{load_ldr_image_from, ColorSpace, ImageData, Rgba},
+};
+
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use anyhow::Result;
+use log::warn;
+use ultraviolet::{Vec3, Vec4};
+use weavy_crab::Material as ObjMaterial;
+
+/// Represents how the alpha value of a material is interpreted.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AlphaMode {
+    /// Alpha is ignored.
+    Opaque,
+
+    /// Fragments with alpha less than the cutoff are discarded.
+    Mask(f32),
+
+    /// Alpha is used for blending.
+    Blend,
+}
+
+/// Represents where the image of a texture comes from.
+#[derive(Clone)]
+pub enum TextureSource {
+    /// Path in the asset source
+    Path(PathBuf),
+
+    /// Decoded image, shared by all textures referring the same image
+    Image(Rc<ImageData<u8, Rgba>>),
+}
+
+impl TextureSource {
+    /// Loads the image; decoded images are returned as they are.
+    /// With the cache, images are decoded only if their files changed.
+    pub fn load(
+        &self,
+        source: &dyn AssetSource,
+        cache: Option<&AssetCache>,
+    ) -> Result<Rc<ImageData<u8, Rgba>>> {
+        match (self, cache) {
+            (TextureSource::Path(path), Some(cache)) => {
+                Ok(Rc::new(cache.load_ldr_image(source, path)?))
+            }
+            (TextureSource::Path(path), None) => Ok(Rc::new(load_ldr_image_from(source, path)?)),
+            (TextureSource::Image(image), _) => Ok(image.clone()),
+        }
+    }
+}
+
+/// Represents a texture bound to a material slot.
+#[derive(Clone)]
+pub struct MaterialTexture {
+    /// Image source
+    pub source: TextureSource,
+
+    /// Index of the UV set
+    pub tex_coord: u32,
+}
+
+/// Represents a metallic-roughness material independent of rendering backends.
+///
+/// Factors multiply the corresponding textures, and colors are in linear space.
+#[derive(Clone)]
+pub struct MaterialDescription {
+    /// Material name
+    pub name: Option<String>,
+
+    /// Base color factor
+    pub base_color: Vec4,
+
+    /// Base color texture in sRGB
+    pub base_color_texture: Option<MaterialTexture>,
+
+    /// Metalness factor
+    pub metallic: f32,
+
+    /// Roughness factor
+    pub roughness: f32,
+
+    /// Texture with roughness in G channel and metalness in B channel
+    pub metallic_roughness_texture: Option<MaterialTexture>,
+
+    /// Metalness texture in R channel, used without `metallic_roughness_texture`
+    pub metallic_texture: Option<MaterialTexture>,
+
+    /// Roughness texture in R channel, used without `metallic_roughness_texture`
+    pub roughness_texture: Option<MaterialTexture>,
+
+    /// Tangent space normal map
+    pub normal_texture: Option<MaterialTexture>,
+
+    /// Scale of XY components of the normal map
+    pub normal_scale: f32,
+
+    /// Ambient occlusion texture in R channel
+    pub occlusion_texture: Option<MaterialTexture>,
+
+    /// Strength of ambient occlusion
+    pub occlusion_strength: f32,
+
+    /// Emissive factor
+    pub emissive: Vec3,
+
+    /// Emissive texture in sRGB
+    pub emissive_texture: Option<MaterialTexture>,
+
+    /// Specular color factor
+    pub specular: Vec3,
+
+    /// Specular color texture in sRGB
+    pub specular_texture: Option<MaterialTexture>,
+
+    /// Opacity texture in R channel, multiplied to the alpha of base color
+    pub alpha_texture: Option<MaterialTexture>,
+
+    /// Alpha mode
+    pub alpha_mode: AlphaMode,
+
+    /// Whether back faces should be rendered
+    pub double_sided: bool,
+}
+
+impl Default for MaterialDescription {
+    /// Returns the default material of glTF 2.0.
+    fn default() -> MaterialDescription {
+        MaterialDescription {
+            name: None,
+            base_color: Vec4::new(1.0, 1.0, 1.0, 1.0),
+            base_color_texture: None,
+            metallic: 1.0,
+            roughness: 1.0,
+            metallic_roughness_texture: None,
+            metallic_texture: None,
+            roughness_texture: None,
+            normal_texture: None,
+            normal_scale: 1.0,
+            occlusion_texture: None,
+            occlusion_strength: 1.0,
+            emissive: Vec3::zero(),
+            emissive_texture: None,
+            specular: Vec3::new(1.0, 1.0, 1.0),
+            specular_texture: None,
+            alpha_texture: None,
+            alpha_mode: AlphaMode::Opaque,
+            double_sided: false,
+        }
+    }
+}
+
+impl MaterialDescription {
+    /// Builds a description from a material defined in MTL file.
+    ///
+    /// Texture paths are resolved relatively to `obj_path`, the path of the OBJ file
+    /// in the asset source. Without `Pr`, the roughness is approximated from the
+    /// Phong exponent `Ns`. Materials with a diffuse map use alpha testing, since
+    /// OBJ has no way to tell cutouts from opaque textures.
+    pub fn from_obj(material: &ObjMaterial, obj_path: &Path) -> MaterialDescription {
+        let texture = |path: Option<&Path>| {
+            path.map(|p| MaterialTexture {
+                source: TextureSource::Path(resolve(obj_path, p)),
+                tex_coord: 0,
+            })
+        };
+
+        let diffuse = material
+            .diffuse_color()
+            .unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0));
+        let opacity = material.dissolve().unwrap_or(1.0);
+        let roughness = match (material.roughness(), material.specular_intensity()) {
+            (Some(roughness), _) => roughness,
+            (None, Some(exponent)) => (2.0 / (exponent.max(0.0) + 2.0)).powf(0.25),
+            (None, None) => 1.0,
+        };
+        let alpha_mode = if opacity < 1.0 {
+            AlphaMode::Blend
+        } else if material.diffuse_map().is_some() || material.dissolve_map().is_some() {
+            AlphaMode::Mask(0.5)
+        } else {
+            AlphaMode::Opaque
+        };
+
+        MaterialDescription {
+            name: Some(material.name().to_string()),
+            base_color: Vec4::new(diffuse.x, diffuse.y, diffuse.z, opacity),
+            base_color_texture: texture(material.diffuse_map()),
+            metallic: material.metalness().unwrap_or(0.0),
+            roughness,
+            metallic_texture: texture(material.metalness_map()),
+            roughness_texture: texture(material.roughness_map()),
+            normal_texture: texture(material.normal_map()),
+            emissive: material.emissive_color().unwrap_or_else(Vec3::zero),
+            emissive_texture: texture(material.emissive_map()),
+            specular: material
+                .specular_color()
+                .unwrap_or_else(|| Vec3::new(1.0, 1.0, 1.0)),
+            specular_texture: texture(material.specular_map()),
+            alpha_texture: texture(material.dissolve_map()),
+            alpha_mode,
+            ..Default::default()
+        }
+    }
+
+    /// The alpha cutoff for alpha testing; zero means no fragments are discarded.
+    pub fn alpha_cutoff(&self) -> f32 {
+        match self.alpha_mode {
+            AlphaMode::Mask(cutoff) => cutoff,
+            _ => 0.0,
+        }
+    }
+}
+
+/// Represents a texture image ready for upload.
+#[derive(Clone)]
+pub struct ResolvedTexture {
+    /// RGBA image, tagged with the color space of the slot
+    pub image: Rc<ImageData<u8, Rgba>>,
+
+    /// Whether the image is a fallback for a missing or broken texture
+    pub fallback: bool,
+}
+
+/// Represents the complete set of textures of a material.
+///
+/// Channel layouts follow glTF 2.0 so that shaders can sample them uniformly.
+/// Fallbacks are 1x1 images which leave factors as they are; white for all textures
+/// except the normal map, which falls back to the flat normal.
+#[derive(Clone)]
+pub struct MaterialTextures {
+    /// Base color in RGB and opacity in A, in sRGB
+    pub albedo: ResolvedTexture,
+
+    /// Tangent space normal in RGB, linear
+    pub normal: ResolvedTexture,
+
+    /// Roughness in G and metalness in B, linear
+    pub metallic_roughness: ResolvedTexture,
+
+    /// Ambient occlusion in R, linear
+    pub occlusion: ResolvedTexture,
+
+    /// Emissive color in RGB, in sRGB
+    pub emissive: ResolvedTexture,
+
+    /// Specular color in RGB, in sRGB
+    pub specular: ResolvedTexture,
+}
+
+/// Loads all textures of materials and packs them into `MaterialTextures`.
+///
+/// Images are shared among materials referring the same path. Textures which fail
+/// to load are replaced by fallbacks with warnings, so a broken texture never prevents
+/// the model from loading.
+pub struct TextureResolver<'a> {
+    source: &'a dyn AssetSource,
+    cache: Option<&'a AssetCache>,
+    images: HashMap<PathBuf, Option<Rc<ImageData<u8, Rgba>>>>,
+    white: Rc<ImageData<u8, Rgba>>,
+    flat_normal: Rc<ImageData<u8, Rgba>>,
+}
+
+impl<'a> TextureResolver<'a> {
+    /// Creates a resolver loading images from the asset source through the cache.
+    pub fn new(source: &'a dyn AssetSource, cache: Option<&'a AssetCache>) -> TextureResolver<'a> {
+        let pixel = |p: [u8; 4]| Rc::new(ImageData::new(&p, 1, 1).expect("Invalid pixel"));
+        TextureResolver {
+            source,
+            cache,
+            images: HashMap::new(),
+            white: pixel([255, 255, 255, 255]),
+            flat_normal: pixel([128, 128, 255, 255]),
+        }
+    }
+
+    /// Resolves all textures of the material.
+    ///
+    /// Separate metalness and roughness maps are packed into `metallic_roughness`,
+    /// and the alpha map is multiplied into the alpha of `albedo`.
+    pub fn resolve(&mut self, material: &MaterialDescription) -> MaterialTextures {
+        let albedo = match (
+            self.load(&material.base_color_texture),
+            self.load(&material.alpha_texture),
+        ) {
+            (Some(base), Some(alpha)) => Some(Rc::new(combine(&base, &alpha, |b, a| {
+                [b[0], b[1], b[2], multiply(b[3], a[0])]
+            }))),
+            (Some(base), None) => Some(base),
+            (None, Some(alpha)) => Some(Rc::new(combine(&alpha, &alpha, |_, a| {
+                [255, 255, 255, a[0]]
+            }))),
+            (None, None) => None,
+        };
+
+        let metallic_roughness = match self.load(&material.metallic_roughness_texture) {
+            Some(packed) => Some(packed),
+            None => match (
+                self.load(&material.metallic_texture),
+                self.load(&material.roughness_texture),
+            ) {
+                (Some(metallic), Some(roughness)) => {
+                    Some(Rc::new(combine(&metallic, &roughness, |m, r| {
+                        [255, r[0], m[0], 255]
+                    })))
+                }
+                (Some(metallic), None) => Some(Rc::new(combine(&metallic, &metallic, |m, _| {
+                    [255, 255, m[0], 255]
+                }))),
+                (None, Some(roughness)) => {
+                    Some(Rc::new(combine(&roughness, &roughness, |_, r| {
+                        [255, r[0], 255, 255]
+                    })))
+                }
+                (None, None) => None,
+            },
+        };
+
+        let normal = self.load(&material.normal_texture);
+        let occlusion = self.load(&material.occlusion_texture);
+        let emissive = self.load(&material.emissive_texture);
+        let specular = self.load(&material.specular_texture);
+        MaterialTextures {
+            albedo: self.finish(albedo, ColorSpace::Srgb, false),
+            normal: self.finish(normal, ColorSpace::Linear, true),
+            metallic_roughness: self.finish(metallic_roughness, ColorSpace::Linear, false),
+            occlusion: self.finish(occlusion, ColorSpace::Linear, false),
+            emissive: self.finish(emissive, ColorSpace::Srgb, false),
+            specular: self.finish(specular, ColorSpace::Srgb, false),
+        }
+    }
+
+    /// Loads the image of the texture, or returns `None` if it is absent or broken.
+    fn load(&mut self, texture: &Option<MaterialTexture>) -> Option<Rc<ImageData<u8, Rgba>>> {
+        let path = match texture.as_ref().map(|t| &t.source) {
+            Some(TextureSource::Path(path)) => path,
+            Some(TextureSource::Image(image)) => return Some(image.clone()),
+            None => return None,
+        };
+
+        if let Some(image) = self.images.get(path) {
+            return image.clone();
+        }
+        let image = match TextureSource::Path(path.clone()).load(self.source, self.cache) {
+            Ok(image) => Some(image),
+            Err(e) => {
+                warn!("Failed to load texture {:?}: {}", path, e);
+                None
+            }
+        };
+        self.images.insert(path.clone(), image.clone());
+        image
+    }
+
+    /// Wraps the image, substituting the fallback for `None`.
+    /// The image is tagged with the color space, copied if it is tagged differently.
+    fn finish(
+        &self,
+        image: Option<Rc<ImageData<u8, Rgba>>>,
+        color_space: ColorSpace,
+        normal: bool,
+    ) -> ResolvedTexture {
+        let fallback = image.is_none();
+        let mut image = image.unwrap_or_else(|| {
+            if normal {
+                self.flat_normal.clone()
+            } else {
+                self.white.clone()
+            }
+        });
+        if image.color_space() != color_space {
+            image = Rc::new((*image).clone().with_color_space(color_space));
+        }
+
+        ResolvedTexture { image, fallback }
+    }
+}
+
+/// Multiplies two normalized 8-bit values.
+fn multiply(a: u8, b: u8) -> u8 {
+    ((a as u32 * b as u32 + 127) / 255) as u8
+}
+
+/// Combines pixels of two images into a new image with the size of the larger one.
+/// The other image is sampled with the nearest neighbor.
+fn combine(
+    first: &ImageData<u8, Rgba>,
+    second: &ImageData<u8, Rgba>,
+    mut pixel: impl FnMut(&[u8], &[u8]) -> [u8; 4],
+) -> ImageData<u8, Rgba> {
+    let (first_width, first_height) = first.dimensions();
+    let (second_width, second_height) = second.dimensions();
+    let width = first_width.max(second_width);
+    let height = first_height.max(second_height);
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for y in 0..height {
+        for x in 0..width {
+            let a = sample_nearest(first, x, y, width, height);
+            let b = sample_nearest(second, x, y, width, height);
+            data.extend_from_slice(&pixel(a, b));
+        }
+    }
+    ImageData::new(&data, width, height).expect("Invalid dimensions")
+}
+
+/// Samples the pixel at `(x, y)` in the grid of `width` x `height` with the nearest neighbor.
+fn sample_nearest(
+    image: &ImageData<u8, Rgba>,
+    x: usize,
+    y: usize,
+    width: usize,
+    height: usize,
+) -> &[u8] {
+    let (image_width, image_height) = image.dimensions();
+    let index = (y * image_height / height * image_width + x * image_width / width) * 4;
+    &image.data()[index..(index + 4)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{asset::MemorySource, texture::encode_png_image};
+
+    use std::io::{Cursor, Error as IoError, ErrorKind};
+
+    use weavy_crab::Parser;
+
+    const OBJ: &str = "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\n\
+                       usemtl textured\nf 1/1 2/2 3/3\nusemtl plain\nf 1/1 2/2 3/3\n";
+
+    const MTL: &str = "newmtl textured\nKd 0.5 0.25 1\nmap_Kd textures/albedo.png\n\
+                       map_d textures/alpha.png\nmap_Pm textures/metal.png\n\
+                       map_Pr textures/rough.png\nnorm textures/normal.png\n\
+                       map_Ke textures/albedo.png\nmap_Ks textures/missing.png\n\
+                       newmtl plain\nKd 0.5 0.25 1\nKs 0.1 0.2 0.3\nKe 2 1 0\nPm 0.75\nPr 0.5\n";
+
+    /// Encodes the RGBA pixels into PNG.
+    fn png(pixels: &[u8], width: usize, height: usize) -> Vec<u8> {
+        encode_png_image(&ImageData::<u8, Rgba>::new(pixels, width, height).unwrap()).unwrap()
+    }
+
+    /// Creates a source with the model and its textures.
+    fn source() -> MemorySource {
+        let mut source = MemorySource::new();
+        source.insert("model.obj", OBJ.as_bytes().to_vec());
+        source.insert("model.mtl", MTL.as_bytes().to_vec());
+        source.insert(
+            "textures/albedo.png",
+            png(&[200, 100, 50, 255, 10, 20, 30, 128], 2, 1),
+        );
+        source.insert("textures/alpha.png", png(&[128, 0, 0, 255], 1, 1));
+        source.insert(
+            "textures/metal.png",
+            png(&[10, 0, 0, 255, 20, 0, 0, 255], 2, 1),
+        );
+        source.insert(
+            "textures/rough.png",
+            png(
+                &[30, 0, 0, 255, 40, 0, 0, 255, 50, 0, 0, 255, 60, 0, 0, 255],
+                2,
+                2,
+            ),
+        );
+        source.insert("textures/normal.png", png(&[100, 150, 200, 255], 1, 1));
+        source
+    }
+
+    /// Parses the model and builds descriptions of its materials.
+    fn materials(source: &MemorySource) -> Vec<MaterialDescription> {
+        let mut parser = Parser::new(|mtllib: &Path, source: &&MemorySource| {
+            let mtl_file = source
+                .read(mtllib)
+                .map_err(|e| IoError::new(ErrorKind::NotFound, e))?;
+            Ok(Cursor::new(mtl_file))
+        });
+        let obj = parser
+            .parse(Cursor::new(OBJ.as_bytes().to_vec()), source)
+            .expect("Invalid OBJ");
+        obj.materials()
+            .iter()
+            .map(|m| MaterialDescription::from_obj(m, Path::new("model.obj")))
+            .collect()
+    }
+
+    #[test]
+    fn resolves_obj_maps() {
+        let source = source();
+        let materials = materials(&source);
+        let mut resolver = TextureResolver::new(&source, None);
+        let textures = resolver.resolve(&materials[0]);
+
+        // The alpha map is multiplied into the alpha of albedo.
+        assert!(!textures.albedo.fallback);
+        assert_eq!(textures.albedo.image.dimensions(), (2, 1));
+        assert_eq!(
+            textures.albedo.image.data(),
+            &[200, 100, 50, 128, 10, 20, 30, 64]
+        );
+
+        // Metalness and roughness are packed into B and G with the larger size.
+        let metallic_roughness = &textures.metallic_roughness.image;
+        assert!(!textures.metallic_roughness.fallback);
+        assert_eq!(metallic_roughness.dimensions(), (2, 2));
+        assert_eq!(
+            metallic_roughness.data(),
+            &[255, 30, 10, 255, 255, 40, 20, 255, 255, 50, 10, 255, 255, 60, 20, 255]
+        );
+
+        // Decoded images are in sRGB; data slots are retagged to linear.
+        assert_eq!(textures.albedo.image.color_space(), ColorSpace::Srgb);
+        assert_eq!(textures.normal.image.color_space(), ColorSpace::Linear);
+        assert_eq!(textures.normal.image.data(), &[100, 150, 200, 255]);
+        assert_eq!(metallic_roughness.color_space(), ColorSpace::Linear);
+        assert_eq!(textures.occlusion.image.color_space(), ColorSpace::Linear);
+        assert_eq!(textures.emissive.image.color_space(), ColorSpace::Srgb);
+        assert_eq!(textures.specular.image.color_space(), ColorSpace::Srgb);
+
+        // Missing textures fall back without failing the whole material.
+        assert!(textures.occlusion.fallback);
+        assert!(textures.specular.fallback);
+        assert_eq!(textures.specular.image.data(), &[255, 255, 255, 255]);
+
+        // Images are shared among slots and materials referring the same path.
+        let emissive = resolver.resolve(&materials[0]).emissive;
+        assert!(Rc::ptr_eq(&textures.emissive.image, &emissive.image));
+        assert_eq!(
+            textures.emissive.image.data(),
+            &[200, 100, 50, 255, 10, 20, 30, 128]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_factors() {
+        let source = source();
+        let materials = materials(&source);
+        let plain = &materials[1];
+        let textures = TextureResolver::new(&source, None).resolve(plain);
+
+        // Kd, Ks, Ke, Pm, and Pr are kept as factors...
+        assert_eq!(plain.base_color, Vec4::new(0.5, 0.25, 1.0, 1.0));
+        assert_eq!(plain.specular, Vec3::new(0.1, 0.2, 0.3));
+        assert_eq!(plain.emissive, Vec3::new(2.0, 1.0, 0.0));
+        assert_eq!(plain.metallic, 0.75);
+        assert_eq!(plain.roughness, 0.5);
+        assert_eq!(plain.alpha_mode, AlphaMode::Opaque);
+
+        // ...and the 1x1 fallbacks multiplied by them leave them as they are.
+        let slots = [
+            (&textures.albedo, ColorSpace::Srgb),
+            (&textures.metallic_roughness, ColorSpace::Linear),
+            (&textures.occlusion, ColorSpace::Linear),
+            (&textures.emissive, ColorSpace::Srgb),
+            (&textures.specular, ColorSpace::Srgb),
+        ];
+        for (texture, color_space) in &slots {
+            assert!(texture.fallback);
+            assert_eq!(texture.image.dimensions(), (1, 1));
+            assert_eq!(texture.image.data(), &[255, 255, 255, 255]);
+            assert_eq!(texture.image.color_space(), *color_space);
+        }
+        assert!(textures.normal.fallback);
+        assert_eq!(textures.normal.image.data(), &[128, 128, 255, 255]);
+        assert_eq!(textures.normal.image.color_space(), ColorSpace::Linear);
+    }
+}
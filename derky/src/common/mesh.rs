@@ -0,0 +1,299 @@
+//! Contains backend-neutral mesh data.
+
+use crate::common::{
+    bounds::Bounds,
+    model::{generate_tangents, MorphTarget},
+};
+
+use std::{collections::HashMap, ops::Range};
+
+use anyhow::{bail, Result};
+use ultraviolet::{Vec2, Vec3, Vec4};
+use weavy_crab::FaceVertexPair;
+
+/// Represents conversions applied to mesh data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct MeshConversion {
+    /// Flips V coordinates of UVs (`v` to `1 - v`).
+    pub flip_v: bool,
+
+    /// Converts right-handed coordinates into left-handed ones (and vice versa)
+    /// by negating Z coordinates.
+    pub convert_handedness: bool,
+
+    /// Reverses the winding order of triangles.
+    pub reverse_winding: bool,
+}
+
+/// Represents a range of indices sharing a material.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Submesh {
+    /// Range in the index array
+    pub indices: Range<usize>,
+
+    /// Material slot index
+    pub material: Option<usize>,
+}
+
+/// Represents an indexed triangle mesh on the CPU.
+///
+/// All vertex attribute streams have the same length.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshData {
+    pub(crate) positions: Box<[Vec3]>,
+    pub(crate) normals: Box<[Vec3]>,
+    pub(crate) uvs: Box<[Vec2]>,
+    pub(crate) tangents: Box<[Vec4]>,
+    pub(crate) indices: Box<[u32]>,
+    pub(crate) submeshes: Box<[Submesh]>,
+    pub(crate) bounds: Bounds,
+    pub(crate) morph_targets: Box<[MorphTarget]>,
+}
+
+impl MeshData {
+    /// Creates mesh data from attribute streams and triangle indices.
+    /// The whole index range becomes a single submesh.
+    pub fn new(
+        positions: Box<[Vec3]>,
+        normals: Box<[Vec3]>,
+        uvs: Box<[Vec2]>,
+        tangents: Box<[Vec4]>,
+        indices: Box<[u32]>,
+        material: Option<usize>,
+    ) -> Result<MeshData> {
+        let vertices = positions.len();
+        if normals.len() != vertices || uvs.len() != vertices || tangents.len() != vertices {
+            bail!("The lengths of vertex attribute streams differ");
+        }
+        if !indices.len().is_multiple_of(3) {
+            bail!("The length of indices is not multiple of 3");
+        }
+        if indices.iter().any(|&i| i as usize >= vertices) {
+            bail!("An index is out of range");
+        }
+
+        let bounds = Bounds::from_points(&positions);
+        let submeshes = vec![Submesh {
+            indices: 0..indices.len(),
+            material,
+        }];
+        Ok(MeshData {
+            positions,
+            normals,
+            uvs,
+            tangents,
+            indices,
+            submeshes: submeshes.into_boxed_slice(),
+            bounds,
+            morph_targets: Box::new([]),
+        })
+    }
+
+    /// Creates mesh data from polygon faces.
+    ///
+    /// Polygons are triangulated as fans and identical vertices are merged.
+    /// Missing normals are filled with face normals, missing UVs with zero,
+    /// and tangents are generated before the conversion is applied.
+    pub fn from_faces(
+        faces: &[Box<[FaceVertexPair]>],
+        material: Option<usize>,
+        conversion: &MeshConversion,
+    ) -> MeshData {
+        let tangents = generate_tangents(faces);
+
+        let mut vertex_indices = HashMap::new();
+        let mut positions = vec![];
+        let mut normals = vec![];
+        let mut uvs = vec![];
+        let mut tangent_stream = vec![];
+        let mut indices = vec![];
+        for (face, face_tangents) in faces.iter().zip(&tangents[..]) {
+            if face.len() < 3 {
+                continue;
+            }
+            let face_normal = (face[1].0 - face[0].0)
+                .cross(face[2].0 - face[0].0)
+                .normalized();
+
+            let mut face_indices = Vec::with_capacity(face.len());
+            for (vertex, &tangent) in face.iter().zip(&face_tangents[..]) {
+                let position = vertex.0;
+                let normal = vertex.2.unwrap_or(face_normal);
+                let uv = vertex.1.unwrap_or_default();
+
+                let mut key = [0u32; 12];
+                let elements = [
+                    position.x, position.y, position.z, normal.x, normal.y, normal.z, uv.x, uv.y,
+                    tangent.x, tangent.y, tangent.z, tangent.w,
+                ];
+                for (k, e) in key.iter_mut().zip(&elements) {
+                    *k = e.to_bits();
+                }
+                let index = *vertex_indices.entry(key).or_insert_with(|| {
+                    positions.push(position);
+                    normals.push(normal);
+                    uvs.push(uv);
+                    tangent_stream.push(tangent);
+                    positions.len() as u32 - 1
+                });
+                face_indices.push(index);
+            }
+
+            for i in 0..(face_indices.len() - 2) {
+                indices.push(face_indices[0]);
+                indices.push(face_indices[i + 1]);
+                indices.push(face_indices[i + 2]);
+            }
+        }
+
+        let mut mesh = MeshData {
+            bounds: Bounds::from_points(&positions),
+            submeshes: vec![Submesh {
+                indices: 0..indices.len(),
+                material,
+            }]
+            .into_boxed_slice(),
+            positions: positions.into_boxed_slice(),
+            normals: normals.into_boxed_slice(),
+            uvs: uvs.into_boxed_slice(),
+            tangents: tangent_stream.into_boxed_slice(),
+            indices: indices.into_boxed_slice(),
+            morph_targets: Box::new([]),
+        };
+        mesh.convert(conversion);
+        mesh
+    }
+
+    /// Applies the conversion.
+    pub fn convert(&mut self, conversion: &MeshConversion) {
+        if conversion.flip_v {
+            for uv in &mut self.uvs[..] {
+                uv.y = 1.0 - uv.y;
+            }
+        }
+
+        if conversion.convert_handedness {
+            for position in &mut self.positions[..] {
+                position.z = -position.z;
+            }
+            for normal in &mut self.normals[..] {
+                normal.z = -normal.z;
+            }
+            // Mirroring swaps the handedness of the tangent frame too.
+            for tangent in &mut self.tangents[..] {
+                tangent.z = -tangent.z;
+                tangent.w = -tangent.w;
+            }
+            for target in &mut self.morph_targets[..] {
+                target.negate_z();
+            }
+            self.bounds = Bounds::from_points(&self.positions);
+        }
+
+        if conversion.reverse_winding {
+            for triangle in self.indices.chunks_exact_mut(3) {
+                triangle.swap(1, 2);
+            }
+        }
+    }
+
+    /// The vertex positions.
+    pub fn positions(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// The vertex normals.
+    pub fn normals(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    /// The texture UVs.
+    pub fn uvs(&self) -> &[Vec2] {
+        &self.uvs
+    }
+
+    /// The tangents; `w` holds the bitangent sign.
+    pub fn tangents(&self) -> &[Vec4] {
+        &self.tangents
+    }
+
+    /// The triangle list indices.
+    pub fn indices(&self) -> &[u32] {
+        &self.indices
+    }
+
+    /// The submeshes, which correspond to material slots.
+    pub fn submeshes(&self) -> &[Submesh] {
+        &self.submeshes
+    }
+
+    /// The bounding volumes of vertex positions.
+    pub fn bounds(&self) -> &Bounds {
+        &self.bounds
+    }
+
+    /// The number of vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// The morph targets.
+    pub fn morph_targets(&self) -> &[MorphTarget] {
+        &self.morph_targets
+    }
+
+    /// Replaces the morph targets.
+    pub fn set_morph_targets(&mut self, targets: Vec<MorphTarget>) -> Result<()> {
+        if targets
+            .iter()
+            .any(|t| t.vertex_count() != self.positions.len())
+        {
+            bail!("The vertex count does not match the morph targets");
+        }
+        self.morph_targets = targets.into_boxed_slice();
+        Ok(())
+    }
+}
+
+/// Represents deformable vertex attribute streams, such as results of skinning.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct VertexStream {
+    /// Vertex positions
+    pub positions: Vec<Vec3>,
+
+    /// Vertex normals
+    pub normals: Vec<Vec3>,
+
+    /// Tangents; `w` holds the bitangent sign
+    pub tangents: Vec<Vec4>,
+}
+
+impl VertexStream {
+    /// Copies the deformable attributes of the mesh.
+    pub fn from_mesh(mesh: &MeshData) -> VertexStream {
+        VertexStream {
+            positions: mesh.positions.to_vec(),
+            normals: mesh.normals.to_vec(),
+            tangents: mesh.tangents.to_vec(),
+        }
+    }
+
+    /// The number of vertices.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Whether this stream has no vertices.
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Checks that all attribute streams have the same length.
+    pub(crate) fn validate(&self) -> Result<()> {
+        let vertices = self.positions.len();
+        if self.normals.len() != vertices || self.tangents.len() != vertices {
+            bail!("All vertex attribute streams must have the same length");
+        }
+        Ok(())
+    }
+}
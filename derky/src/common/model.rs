@@ -1,54 +1,96 @@
 //! Contains general model operations.
 
-use std::{fs::read_to_string, io::Cursor, path::Path};
+mod gltf_import;
+mod lod;
+mod morph;
+mod optimize;
+mod simplify;
+mod tangent;
+#[cfg(test)]
+mod test_support;
+mod validate;
+
+pub use lod::{projected_size, LodSelector};
+pub use morph::{apply_morph_targets, MorphTarget, MorphWeights};
+pub use optimize::{
+    analyze_vertex_cache, optimize_mesh, optimize_overdraw, optimize_vertex_cache,
+    optimize_vertex_fetch, CacheStatistics, DEFAULT_CACHE_SIZE, DEFAULT_OVERDRAW_THRESHOLD,
+};
+pub use simplify::{generate_lods, simplify};
+pub use tangent::generate_tangents;
+pub use validate::{validate_model, validate_obj, GroupReport, MaterialReport, ValidationReport};
+
+use crate::common::{
+    asset::{resolve, AssetSource, DirectorySource},
+    bounds::Bounds,
+    cache::{AssetCache, CachedModel, ProcessingOptions},
+    frustum::Frustum,
+    material::MaterialDescription,
+    mesh::{MeshConversion, MeshData},
+};
+
+use std::{
+    io::{Cursor, Error as IoError, ErrorKind},
+    path::Path,
+};
 
 use anyhow::{Context, Result};
 use itertools::Itertools;
-use weavy_crab::{FaceVertexPair, Material, Parser};
+use log::warn;
+use weavy_crab::{Material, Parser};
 
 /// Represents a generic model data structure.
 pub struct Model<VG, M> {
     vertex_groups: Box<[VG]>,
+    group_bounds: Box<[Bounds]>,
+    group_morph_targets: Box<[Box<[MorphTarget]>]>,
     material_mapping: Box<[Option<usize>]>,
     materials: Box<[M]>,
+    bounds: Bounds,
 }
 
 impl<VG, M> Model<VG, M> {
     /// Loads a Wavefront OBJ file and transform it.
     /// # Parameters
     /// * `filename`: path to file
-    /// * `vertex_mapper` a closure that converts faces into `VG`
+    /// * `conversion`: conversion applied to each `MeshData`
+    /// * `vertex_mapper` a closure that converts `MeshData` into `VG`, keeping the vertex order
     /// * `material_mapper` a closure that converts `Material` into `M`
     pub fn load_obj<
         P: AsRef<Path>,
-        VM: FnMut(Box<[Box<[FaceVertexPair]>]>) -> Result<VG>,
+        VM: FnMut(&MeshData) -> Result<VG>,
         MM: FnMut(Material) -> Result<M>,
     >(
         filename: P,
+        conversion: &MeshConversion,
         vertex_mapper: VM,
         material_mapper: MM,
     ) -> Result<Model<VG, M>> {
-        let filename = filename.as_ref();
-        let parent_directory = filename
-            .parent()
-            .context("Parent directory not found")?
-            .to_owned();
-
-        let wfobj = {
-            let obj_file = read_to_string(filename).context("Failed to read OBJ file")?;
-            let mut parser = Parser::new(move |mtllib, _| {
-                let mut mtl_path = parent_directory.clone();
-                mtl_path.push(mtllib);
-
-                let mtl_file = Cursor::new(read_to_string(mtl_path)?);
-                Ok(mtl_file)
-            });
-
-            parser.parse(Cursor::new(obj_file), ())?
-        };
-
-        let (wf_objects, wf_materials) = wfobj.split();
+        Model::load_obj_from(
+            &DirectorySource::new(""),
+            filename.as_ref(),
+            conversion,
+            vertex_mapper,
+            material_mapper,
+        )
+    }
 
+    /// Loads a Wavefront OBJ file from the asset source and transform it.
+    /// MTL files are resolved relatively to the OBJ file in the same source.
+    /// # Parameters
+    /// * `source`: asset source
+    /// * `path`: path to file in the source
+    /// * `conversion`: conversion applied to each `MeshData`
+    /// * `vertex_mapper` a closure that converts `MeshData` into `VG`, keeping the vertex order
+    /// * `material_mapper` a closure that converts `Material` into `M`
+    pub fn load_obj_from<VM: FnMut(&MeshData) -> Result<VG>, MM: FnMut(Material) -> Result<M>>(
+        source: &dyn AssetSource,
+        path: &Path,
+        conversion: &MeshConversion,
+        vertex_mapper: VM,
+        material_mapper: MM,
+    ) -> Result<Model<VG, M>> {
+        let (meshes, wf_materials) = read_obj(source, path, conversion)?;
         let materials = wf_materials
             .into_vec()
             .into_iter()
@@ -56,28 +98,126 @@ impl<VG, M> Model<VG, M> {
             .collect::<Result<Box<[M]>>>()
             .context("Error occured during material mapping")?;
 
+        Model::from_meshes(meshes, materials, vertex_mapper)
+    }
+
+    /// Loads a Wavefront OBJ file through the cache of processed meshes.
+    ///
+    /// The cache entry is used if neither the OBJ file, its MTL files nor the options
+    /// changed; otherwise the model is processed again and the entry is rewritten.
+    /// # Parameters
+    /// * `cache`: asset cache
+    /// * `source`: asset source
+    /// * `path`: path to file in the source
+    /// * `options`: processing applied before caching
+    /// * `vertex_mapper` a closure that converts `MeshData` into `VG`, keeping the vertex order
+    /// * `material_mapper` a closure that converts `MaterialDescription` into `M`
+    pub fn load_obj_cached<
+        VM: FnMut(&MeshData) -> Result<VG>,
+        MM: FnMut(MaterialDescription) -> Result<M>,
+    >(
+        cache: &AssetCache,
+        source: &dyn AssetSource,
+        path: &Path,
+        options: &ProcessingOptions,
+        vertex_mapper: VM,
+        material_mapper: MM,
+    ) -> Result<Model<VG, M>> {
+        let key = AssetCache::obj_key(source, path, options).context("Failed to read OBJ file")?;
+        let cached = match cache.read_model(path, options, key) {
+            Some(cached) => cached,
+            None => {
+                let (mut meshes, wf_materials) = read_obj(source, path, &options.conversion)?;
+                if options.optimize {
+                    meshes.iter_mut().for_each(optimize_mesh);
+                }
+                let materials = wf_materials
+                    .iter()
+                    .map(|m| MaterialDescription::from_obj(m, path))
+                    .collect();
+
+                let cached = CachedModel { meshes, materials };
+                if let Err(e) = cache.write_model(path, options, key, &cached) {
+                    warn!("Failed to write cache of {:?}: {}", path, e);
+                }
+                cached
+            }
+        };
+
+        let materials = cached
+            .materials
+            .into_iter()
+            .map(material_mapper)
+            .collect::<Result<Box<[M]>>>()
+            .context("Error occured during material mapping")?;
+
+        Model::from_meshes(cached.meshes, materials, vertex_mapper)
+    }
+
+    /// Creates a model by mapping each mesh into a vertex group.
+    fn from_meshes<VM: FnMut(&MeshData) -> Result<VG>>(
+        meshes: Vec<MeshData>,
+        materials: Box<[M]>,
+        vertex_mapper: VM,
+    ) -> Result<Model<VG, M>> {
         let mut vertex_groups = vec![];
+        let mut group_bounds = vec![];
+        let mut group_morph_targets = vec![];
         let mut material_mapping = vec![];
         let mut vertex_mapper = vertex_mapper;
-        for object in wf_objects.into_vec() {
-            for group in object.into_groups().into_vec() {
-                for (material_index, faces) in &group.faces().group_by(|f| f.1) {
-                    let vertex_group =
-                        vertex_mapper(faces.map(|(face, _)| face.collect()).collect())?;
-                    vertex_groups.push(vertex_group);
-                    material_mapping.push(material_index);
-                }
-            }
+        for mesh in meshes {
+            vertex_groups.push(vertex_mapper(&mesh)?);
+            group_bounds.push(*mesh.bounds());
+            material_mapping.push(mesh.submeshes.first().and_then(|s| s.material));
+            group_morph_targets.push(mesh.morph_targets);
         }
 
-        Ok(Model {
+        Ok(Model::from_groups(
+            vertex_groups,
+            group_bounds,
+            group_morph_targets,
+            material_mapping,
+            materials,
+        ))
+    }
+
+    /// Creates a model from mapped vertex groups and materials.
+    fn from_groups(
+        vertex_groups: Vec<VG>,
+        group_bounds: Vec<Bounds>,
+        group_morph_targets: Vec<Box<[MorphTarget]>>,
+        material_mapping: Vec<Option<usize>>,
+        materials: Box<[M]>,
+    ) -> Model<VG, M> {
+        let bounds = group_bounds
+            .iter()
+            .fold(Bounds::default(), |model, group| model.merged(group));
+        Model {
             vertex_groups: vertex_groups.into_boxed_slice(),
+            group_bounds: group_bounds.into_boxed_slice(),
+            group_morph_targets: group_morph_targets.into_boxed_slice(),
             material_mapping: material_mapping.into_boxed_slice(),
             materials,
-        })
+            bounds,
+        }
+    }
+
+    /// The bounding volumes of the whole model.
+    pub fn bounds(&self) -> &Bounds {
+        &self.bounds
+    }
+
+    /// The bounding volumes of each vertex group.
+    pub fn group_bounds(&self) -> &[Bounds] {
+        &self.group_bounds
     }
 
-    /// Visits all vertex groups.
+    /// The morph targets of each vertex group, indexed by the vertices given to the vertex mapper.
+    pub fn group_morph_targets(&self) -> &[Box<[MorphTarget]>] {
+        &self.group_morph_targets
+    }
+
+    /// Visits all vertex groups with their materials and bounding volumes.
     pub fn visit(&self) -> Visit<VG, M> {
         Visit {
             model: self,
@@ -85,6 +225,53 @@ impl<VG, M> Model<VG, M> {
             terminated: false,
         }
     }
+
+    /// Visits vertex groups whose bounding volumes intersect the frustum.
+    /// The frustum should be in model space; for a model matrix,
+    /// create it from `projection * view * model` with `Frustum::from_matrix`.
+    pub fn visit_visible<'a>(&'a self, frustum: &'a Frustum) -> VisitVisible<'a, VG, M> {
+        VisitVisible {
+            visit: self.visit(),
+            frustum,
+            model_visible: frustum.intersects(&self.bounds),
+        }
+    }
+}
+
+/// Parses a Wavefront OBJ file into meshes split by groups and materials.
+fn read_obj(
+    source: &dyn AssetSource,
+    path: &Path,
+    conversion: &MeshConversion,
+) -> Result<(Vec<MeshData>, Box<[Material]>)> {
+    let wfobj = {
+        let obj_file = source
+            .read_to_string(path)
+            .context("Failed to read OBJ file")?;
+        let mut parser = Parser::new(
+            |mtllib: &Path, (source, obj_path): &(&dyn AssetSource, &Path)| {
+                let mtl_file = source
+                    .read(&resolve(obj_path, mtllib))
+                    .map_err(|e| IoError::new(ErrorKind::NotFound, e))?;
+                Ok(Cursor::new(mtl_file))
+            },
+        );
+
+        parser.parse(Cursor::new(obj_file), (source, path))?
+    };
+
+    let (wf_objects, wf_materials) = wfobj.split();
+    let mut meshes = vec![];
+    for object in wf_objects.into_vec() {
+        for group in object.into_groups().into_vec() {
+            for (material_index, faces) in &group.faces().group_by(|f| f.1) {
+                let faces: Box<[_]> = faces.map(|(face, _)| face.collect()).collect();
+                meshes.push(MeshData::from_faces(&faces, material_index, conversion));
+            }
+        }
+    }
+
+    Ok((meshes, wf_materials))
 }
 
 /// The iterator adaptor for `Model::visit`.
@@ -95,7 +282,7 @@ pub struct Visit<'a, VG, M> {
 }
 
 impl<'a, VG, M> Iterator for Visit<'a, VG, M> {
-    type Item = (&'a VG, Option<&'a M>);
+    type Item = (&'a VG, Option<&'a M>, &'a Bounds);
 
     fn next(&mut self) -> Option<Self::Item> {
         if self.terminated {
@@ -105,11 +292,32 @@ impl<'a, VG, M> Iterator for Visit<'a, VG, M> {
             return None;
         }
 
-        let pair = (
+        let item = (
             &self.model.vertex_groups[self.index],
             self.model.material_mapping[self.index].map(|i| &self.model.materials[i]),
+            &self.model.group_bounds[self.index],
         );
         self.index += 1;
-        Some(pair)
+        Some(item)
+    }
+}
+
+/// The iterator adaptor for `Model::visit_visible`.
+pub struct VisitVisible<'a, VG, M> {
+    visit: Visit<'a, VG, M>,
+    frustum: &'a Frustum,
+    model_visible: bool,
+}
+
+impl<'a, VG, M> Iterator for VisitVisible<'a, VG, M> {
+    type Item = (&'a VG, Option<&'a M>, &'a Bounds);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if !self.model_visible {
+            return None;
+        }
+
+        let frustum = self.frustum;
+        self.visit.find(|(_, _, bounds)| frustum.intersects(bounds))
     }
 }
@@ -0,0 +1,741 @@
+//! Contains glTF 2.0 import.
+
+use crate::common::{
+    asset::{resolve, AssetSource, DirectorySource},
+    material::{AlphaMode, MaterialDescription, MaterialTexture, TextureSource},
+    mesh::{MeshConversion, MeshData},
+    model::{generate_tangents, Model, MorphTarget},
+    texture::{decode_ldr_image, ColorSpace, ImageData, Rgba},
+};
+
+use std::{path::Path, rc::Rc};
+
+use anyhow::{bail, Context, Result};
+use gltf::{
+    buffer::Source as BufferSource, image::Source as ImageSource, mesh::Mode, texture::Texture,
+    Document, Gltf, Primitive,
+};
+use log::{info, warn};
+use serde_json::Value;
+use ultraviolet::{Mat3, Mat4, Vec2, Vec3, Vec4};
+use weavy_crab::FaceVertexPair;
+
+impl<VG, M> Model<VG, M> {
+    /// Loads a glTF 2.0 file (`.gltf` or `.glb`) and transform it.
+    ///
+    /// Node transforms of the default scene are baked into vertices, and
+    /// each primitive becomes a vertex group. Note that glTF places the
+    /// origin of UVs at the top-left corner, unlike Wavefront OBJ.
+    /// # Parameters
+    /// * `filename`: path to file
+    /// * `conversion`: conversion applied to each `MeshData`
+    /// * `vertex_mapper` a closure that converts `MeshData` into `VG`, keeping the vertex order
+    /// * `material_mapper` a closure that converts `MaterialDescription` into `M`
+    pub fn load_gltf<
+        P: AsRef<Path>,
+        VM: FnMut(&MeshData) -> Result<VG>,
+        MM: FnMut(MaterialDescription) -> Result<M>,
+    >(
+        filename: P,
+        conversion: &MeshConversion,
+        vertex_mapper: VM,
+        material_mapper: MM,
+    ) -> Result<Model<VG, M>> {
+        Model::load_gltf_from(
+            &DirectorySource::new(""),
+            filename.as_ref(),
+            conversion,
+            vertex_mapper,
+            material_mapper,
+        )
+    }
+
+    /// Loads a glTF 2.0 file from the asset source and transform it.
+    /// External buffers and images are resolved relatively to the glTF file in the same source.
+    /// # Parameters
+    /// * `source`: asset source
+    /// * `path`: path to file in the source
+    /// * `conversion`: conversion applied to each `MeshData`
+    /// * `vertex_mapper` a closure that converts `MeshData` into `VG`, keeping the vertex order
+    /// * `material_mapper` a closure that converts `MaterialDescription` into `M`
+    pub fn load_gltf_from<
+        VM: FnMut(&MeshData) -> Result<VG>,
+        MM: FnMut(MaterialDescription) -> Result<M>,
+    >(
+        source: &dyn AssetSource,
+        path: &Path,
+        conversion: &MeshConversion,
+        vertex_mapper: VM,
+        material_mapper: MM,
+    ) -> Result<Model<VG, M>> {
+        let Gltf { document, blob } =
+            Gltf::from_slice(&source.read(path)?).context("Failed to parse glTF file")?;
+        let buffers = read_buffers(source, path, &document, blob)?;
+        let mut images = read_images(source, path, &document, &buffers)?;
+        info!(
+            "Loaded glTF: {} meshes, {} materials, {} images",
+            document.meshes().len(),
+            document.materials().len(),
+            images.srgb.len()
+        );
+
+        let materials = document
+            .materials()
+            .map(|m| convert_material(&m, &mut images))
+            .map(material_mapper)
+            .collect::<Result<Box<[M]>>>()
+            .context("Error occured during material mapping")?;
+
+        let mut vertex_groups = vec![];
+        let mut group_bounds = vec![];
+        let mut group_morph_targets = vec![];
+        let mut material_mapping = vec![];
+        let mut vertex_mapper = vertex_mapper;
+        for (mesh, matrix) in mesh_instances(&document) {
+            let target_names = morph_target_names(&mesh);
+            for primitive in mesh.primitives() {
+                let material_index = primitive.material().index();
+                let mut mesh_data = match primitive_mesh(
+                    &primitive,
+                    &buffers,
+                    &matrix,
+                    material_index,
+                    &target_names,
+                    mesh.weights().unwrap_or(&[]),
+                )? {
+                    Some(m) => m,
+                    None => continue,
+                };
+                mesh_data.convert(conversion);
+
+                vertex_groups.push(vertex_mapper(&mesh_data)?);
+                group_bounds.push(*mesh_data.bounds());
+                group_morph_targets.push(mesh_data.morph_targets);
+                material_mapping.push(material_index);
+            }
+        }
+
+        Ok(Model::from_groups(
+            vertex_groups,
+            group_bounds,
+            group_morph_targets,
+            material_mapping,
+            materials,
+        ))
+    }
+}
+
+/// Enumerates meshes placed in the default scene with their world matrices.
+/// Documents without scenes place all meshes at the origin.
+fn mesh_instances(document: &Document) -> Vec<(gltf::Mesh<'_>, Mat4)> {
+    let scene = match document
+        .default_scene()
+        .or_else(|| document.scenes().next())
+    {
+        Some(s) => s,
+        None => return document.meshes().map(|m| (m, Mat4::identity())).collect(),
+    };
+
+    let mut instances = vec![];
+    let mut stack: Vec<_> = scene.nodes().map(|n| (n, Mat4::identity())).collect();
+    while let Some((node, parent_matrix)) = stack.pop() {
+        let world_matrix = parent_matrix * Mat4::from(node.transform().matrix());
+        if let Some(mesh) = node.mesh() {
+            instances.push((mesh, world_matrix));
+        }
+        stack.extend(node.children().map(|c| (c, world_matrix)));
+    }
+    instances
+}
+
+/// Reads a primitive with its morph targets and transforms it into world space.
+/// Returns `None` for primitives which are not composed of triangles.
+fn primitive_mesh(
+    primitive: &Primitive,
+    buffers: &[Vec<u8>],
+    matrix: &Mat4,
+    material: Option<usize>,
+    target_names: &[Option<String>],
+    target_weights: &[f32],
+) -> Result<Option<MeshData>> {
+    let reader = primitive.reader(|b| buffers.get(b.index()).map(|d| &d[..]));
+
+    let positions: Vec<Vec3> = reader
+        .read_positions()
+        .context("The primitive has no positions")?
+        .map(|p| matrix.transform_point3(p.into()))
+        .collect();
+    let vertices = positions.len() as u32;
+    let indices: Vec<u32> = match reader.read_indices() {
+        Some(indices) => indices.into_u32().collect(),
+        None => (0..vertices).collect(),
+    };
+    if indices.iter().any(|&i| i >= vertices) {
+        bail!("An index is out of range");
+    }
+
+    // Mirroring transforms flip the winding order and the tangent frame.
+    let linear = Mat3::new(
+        matrix.cols[0].truncated(),
+        matrix.cols[1].truncated(),
+        matrix.cols[2].truncated(),
+    );
+    let mirrored = linear.determinant() < 0.0;
+    let normal_matrix = linear.inversed().transposed();
+
+    let morph_targets = reader
+        .read_morph_targets()
+        .enumerate()
+        .map(|(i, (positions, normals, tangents))| {
+            let positions = match positions {
+                Some(deltas) => deltas.map(Vec3::from).collect(),
+                None => vec![Vec3::zero(); vertices as usize],
+            };
+            let normals = normals.map_or_else(Vec::new, |ds| ds.map(Vec3::from).collect());
+            let tangents = tangents.map_or_else(Vec::new, |ds| ds.map(Vec3::from).collect());
+
+            let name = target_names.get(i).and_then(|n| n.as_deref());
+            let weight = target_weights.get(i).copied().unwrap_or(0.0);
+            let mut target = MorphTarget::new(name, weight, positions, normals, tangents)?;
+            target.transform(&linear, &normal_matrix);
+            Ok(target)
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut triangles = match triangulate(primitive.mode(), &indices) {
+        Some(t) => t,
+        None => {
+            warn!("Skipped a primitive in {:?} mode", primitive.mode());
+            return Ok(None);
+        }
+    };
+    if mirrored {
+        for triangle in triangles.chunks_exact_mut(3) {
+            triangle.swap(1, 2);
+        }
+    }
+
+    let normals: Option<Vec<Vec3>> = reader.read_normals().map(|ns| {
+        ns.map(|n| (normal_matrix * Vec3::from(n)).normalized())
+            .collect()
+    });
+    let uvs: Option<Vec<Vec2>> = reader
+        .read_tex_coords(0)
+        .map(|uvs| uvs.into_f32().map(Vec2::from).collect());
+    let tangents: Option<Vec<Vec4>> = reader.read_tangents().map(|ts| {
+        ts.map(|t| {
+            let tangent = (linear * Vec3::new(t[0], t[1], t[2])).normalized();
+            let sign = if mirrored { -t[3] } else { t[3] };
+            Vec4::new(tangent.x, tangent.y, tangent.z, sign)
+        })
+        .collect()
+    });
+
+    let mesh = match (normals, tangents) {
+        (Some(normals), Some(tangents)) => {
+            let uvs = uvs.unwrap_or_else(|| vec![Vec2::zero(); positions.len()]);
+            let mut mesh = MeshData::new(
+                positions.into_boxed_slice(),
+                normals.into_boxed_slice(),
+                uvs.into_boxed_slice(),
+                tangents.into_boxed_slice(),
+                triangles.into_boxed_slice(),
+                material,
+            )?;
+            mesh.set_morph_targets(morph_targets)?;
+            mesh
+        }
+        (normals, _) => {
+            generate_vertices(positions, normals, uvs, triangles, material, morph_targets)?
+        }
+    };
+    Ok(Some(mesh))
+}
+
+/// Creates mesh data of a primitive without tangents, generating them.
+///
+/// Tangents are assigned to the indexed vertices, so morph targets stay valid.
+/// Missing normals become flat ones, which splits vertices by triangles;
+/// then morph targets are remapped to the split vertices.
+fn generate_vertices(
+    positions: Vec<Vec3>,
+    normals: Option<Vec<Vec3>>,
+    uvs: Option<Vec<Vec2>>,
+    triangles: Vec<u32>,
+    material: Option<usize>,
+    morph_targets: Vec<MorphTarget>,
+) -> Result<MeshData> {
+    let uvs = uvs.unwrap_or_else(|| vec![Vec2::zero(); positions.len()]);
+    let faces: Vec<Box<[FaceVertexPair]>> = triangles
+        .chunks_exact(3)
+        .map(|triangle| {
+            triangle
+                .iter()
+                .map(|&i| {
+                    let i = i as usize;
+                    (positions[i], Some(uvs[i]), normals.as_ref().map(|ns| ns[i]))
+                })
+                .collect()
+        })
+        .collect();
+    let corner_tangents = generate_tangents(&faces);
+
+    let (mut mesh, morph_targets) = match normals {
+        Some(normals) => {
+            // Corners sharing a vertex have the same tangent unless UVs are mirrored there.
+            let mut tangents = vec![None; positions.len()];
+            for (triangle, face_tangents) in triangles.chunks_exact(3).zip(&corner_tangents[..]) {
+                for (&i, &tangent) in triangle.iter().zip(&face_tangents[..]) {
+                    tangents[i as usize].get_or_insert(tangent);
+                }
+            }
+            let tangents: Vec<_> = tangents
+                .into_iter()
+                .map(|t| t.unwrap_or_else(|| Vec4::new(1.0, 0.0, 0.0, 1.0)))
+                .collect();
+            let mesh = MeshData::new(
+                positions.into_boxed_slice(),
+                normals.into_boxed_slice(),
+                uvs.into_boxed_slice(),
+                tangents.into_boxed_slice(),
+                triangles.into_boxed_slice(),
+                material,
+            )?;
+            (mesh, morph_targets)
+        }
+        None => {
+            let corners: Vec<usize> = triangles.iter().map(|&i| i as usize).collect();
+            let flat_normals = faces.iter().flat_map(|face| {
+                let normal = (face[1].0 - face[0].0)
+                    .cross(face[2].0 - face[0].0)
+                    .normalized();
+                vec![normal; 3]
+            });
+            let mesh = MeshData::new(
+                corners.iter().map(|&i| positions[i]).collect(),
+                flat_normals.collect(),
+                corners.iter().map(|&i| uvs[i]).collect(),
+                corner_tangents
+                    .iter()
+                    .flat_map(|t| t.iter().copied())
+                    .collect(),
+                (0..corners.len() as u32).collect(),
+                material,
+            )?;
+            let morph_targets = morph_targets.iter().map(|t| t.remapped(&corners)).collect();
+            (mesh, morph_targets)
+        }
+    };
+    mesh.set_morph_targets(morph_targets)?;
+    Ok(mesh)
+}
+
+/// Converts indices of the primitive mode into a triangle list.
+fn triangulate(mode: Mode, indices: &[u32]) -> Option<Vec<u32>> {
+    let mut triangles = vec![];
+    match mode {
+        Mode::Triangles => {
+            triangles.extend_from_slice(&indices[..(indices.len() / 3 * 3)]);
+        }
+        Mode::TriangleStrip => {
+            for i in 2..indices.len() {
+                if i % 2 == 0 {
+                    triangles.extend_from_slice(&[indices[i - 2], indices[i - 1], indices[i]]);
+                } else {
+                    triangles.extend_from_slice(&[indices[i - 1], indices[i - 2], indices[i]]);
+                }
+            }
+        }
+        Mode::TriangleFan => {
+            for i in 2..indices.len() {
+                triangles.extend_from_slice(&[indices[0], indices[i - 1], indices[i]]);
+            }
+        }
+        _ => return None,
+    }
+    Some(triangles)
+}
+
+/// Converts a glTF material.
+/// Base color and emissive textures are sRGB, and other textures are linear.
+fn convert_material(material: &gltf::Material, images: &mut Images) -> MaterialDescription {
+    let mut texture = |texture: Texture, tex_coord: u32, srgb: bool| {
+        let index = texture.source().index();
+        let image = if srgb {
+            images.srgb[index].clone()
+        } else {
+            images.linear(index)
+        };
+        MaterialTexture {
+            source: TextureSource::Image(image),
+            tex_coord,
+        }
+    };
+
+    let pbr = material.pbr_metallic_roughness();
+    let normal = material.normal_texture();
+    let occlusion = material.occlusion_texture();
+    let alpha_mode = match material.alpha_mode() {
+        gltf::material::AlphaMode::Opaque => AlphaMode::Opaque,
+        gltf::material::AlphaMode::Mask => AlphaMode::Mask(material.alpha_cutoff()),
+        gltf::material::AlphaMode::Blend => AlphaMode::Blend,
+    };
+
+    MaterialDescription {
+        name: material.name().map(|n| n.to_string()),
+        base_color: pbr.base_color_factor().into(),
+        base_color_texture: pbr
+            .base_color_texture()
+            .map(|i| texture(i.texture(), i.tex_coord(), true)),
+        metallic: pbr.metallic_factor(),
+        roughness: pbr.roughness_factor(),
+        metallic_roughness_texture: pbr
+            .metallic_roughness_texture()
+            .map(|i| texture(i.texture(), i.tex_coord(), false)),
+        normal_texture: normal
+            .as_ref()
+            .map(|n| texture(n.texture(), n.tex_coord(), false)),
+        normal_scale: normal.as_ref().map(|n| n.scale()).unwrap_or(1.0),
+        occlusion_texture: occlusion
+            .as_ref()
+            .map(|o| texture(o.texture(), o.tex_coord(), false)),
+        occlusion_strength: occlusion.as_ref().map(|o| o.strength()).unwrap_or(1.0),
+        emissive: material.emissive_factor().into(),
+        emissive_texture: material
+            .emissive_texture()
+            .map(|i| texture(i.texture(), i.tex_coord(), true)),
+        alpha_mode,
+        double_sided: material.double_sided(),
+        ..Default::default()
+    }
+}
+
+/// Reads morph target names, which are stored in `extras.targetNames` by convention.
+fn morph_target_names(mesh: &gltf::Mesh<'_>) -> Vec<Option<String>> {
+    let extras: Option<Value> = mesh
+        .extras()
+        .as_ref()
+        .and_then(|e| serde_json::from_str(e.get()).ok());
+    match extras.as_ref().and_then(|e| e["targetNames"].as_array()) {
+        Some(names) => names.iter().map(|n| n.as_str().map(String::from)).collect(),
+        None => vec![],
+    }
+}
+
+/// Reads all buffers, including the binary chunk of GLB.
+fn read_buffers(
+    source: &dyn AssetSource,
+    path: &Path,
+    document: &Document,
+    blob: Option<Vec<u8>>,
+) -> Result<Vec<Vec<u8>>> {
+    let mut blob = blob;
+    document
+        .buffers()
+        .map(|buffer| {
+            let data = match buffer.source() {
+                BufferSource::Bin => blob.take().context("The binary chunk is missing")?,
+                BufferSource::Uri(uri) => read_uri(source, path, uri)?,
+            };
+            if data.len() < buffer.length() {
+                bail!("The buffer is shorter than declared");
+            }
+            Ok(data)
+        })
+        .collect()
+}
+
+/// Represents decoded images of a glTF file.
+struct Images {
+    /// Images tagged as `ColorSpace::Srgb`, as they are decoded
+    srgb: Vec<Rc<ImageData<u8, Rgba>>>,
+
+    /// Images retagged as `ColorSpace::Linear` for data textures, created on demand
+    linear: Vec<Option<Rc<ImageData<u8, Rgba>>>>,
+}
+
+impl Images {
+    /// Gets the image tagged as `ColorSpace::Linear`.
+    fn linear(&mut self, index: usize) -> Rc<ImageData<u8, Rgba>> {
+        let srgb = &self.srgb[index];
+        self.linear[index]
+            .get_or_insert_with(|| {
+                Rc::new(ImageData::clone(srgb).with_color_space(ColorSpace::Linear))
+            })
+            .clone()
+    }
+}
+
+/// Reads and decodes all images.
+fn read_images(
+    source: &dyn AssetSource,
+    path: &Path,
+    document: &Document,
+    buffers: &[Vec<u8>],
+) -> Result<Images> {
+    let srgb = document
+        .images()
+        .map(|image| {
+            let image_data = match image.source() {
+                ImageSource::View { view, .. } => {
+                    let range = view.offset()..(view.offset() + view.length());
+                    let data = buffers[view.buffer().index()]
+                        .get(range)
+                        .context("The buffer view is out of range")?;
+                    decode_ldr_image(data)?
+                }
+                ImageSource::Uri { uri, .. } => decode_ldr_image(&read_uri(source, path, uri)?)?,
+            };
+            Ok(Rc::new(image_data))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(Images {
+        linear: vec![None; srgb.len()],
+        srgb,
+    })
+}
+
+/// Reads the content of a URI; base64 data URIs and relative paths are supported.
+fn read_uri(source: &dyn AssetSource, path: &Path, uri: &str) -> Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let encoded = uri
+            .find(";base64,")
+            .map(|i| &uri[(i + 8)..])
+            .context("Only base64 data URIs are supported")?;
+        Ok(base64::decode(encoded)?)
+    } else {
+        source.read(&resolve(path, Path::new(&percent_decode(uri))))
+    }
+}
+
+/// Decodes percent-encoded characters in the URI.
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = match bytes[i] {
+            b'%' => uri
+                .get((i + 1)..(i + 3))
+                .and_then(|h| u8::from_str_radix(h, 16).ok()),
+            _ => None,
+        };
+        match escaped {
+            Some(b) => {
+                decoded.push(b);
+                i += 3;
+            }
+            None => {
+                decoded.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{asset::MemorySource, texture::ColorSpace};
+
+    /// Loads a sample model, keeping meshes as vertex groups.
+    fn load_sample(name: &str) -> Model<MeshData, MaterialDescription> {
+        let source = DirectorySource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/models/samples"
+        ));
+        Model::load_gltf_from(
+            &source,
+            Path::new(&format!("{0}/{0}.gltf", name)),
+            &MeshConversion::default(),
+            |mesh| Ok(mesh.clone()),
+            Ok,
+        )
+        .expect("Failed to load the sample")
+    }
+
+    fn count(vectors: &[Vec3], expected: Vec3) -> usize {
+        vectors
+            .iter()
+            .filter(|v| (**v - expected).mag() < 1e-5)
+            .count()
+    }
+
+    #[test]
+    fn box_sample() {
+        let model = load_sample("Box");
+        let groups: Vec<_> = model.visit().collect();
+        assert_eq!(groups.len(), 1);
+
+        let (mesh, material, bounds) = groups[0];
+        assert_eq!(mesh.indices().len(), 36);
+        assert_eq!(mesh.vertex_count(), 24);
+        assert!((bounds.aabb.min - Vec3::broadcast(-0.5)).mag() < 1e-5);
+        assert!((bounds.aabb.max - Vec3::broadcast(0.5)).mag() < 1e-5);
+
+        // The root node rotates +Z to +Y.
+        assert_eq!(count(mesh.normals(), Vec3::new(0.0, 1.0, 0.0)), 4);
+        assert_eq!(count(mesh.normals(), Vec3::new(0.0, 0.0, -1.0)), 4);
+        assert!(mesh.tangents().iter().zip(mesh.normals()).all(|(t, n)| t
+            .truncated()
+            .dot(*n)
+            .abs()
+            < 1e-4
+            && t.w.abs() == 1.0));
+
+        let material = material.expect("The material is missing");
+        assert_eq!(material.name.as_deref(), Some("Red"));
+        assert_eq!(material.base_color, Vec4::new(0.8, 0.0, 0.0, 1.0));
+        assert_eq!(material.metallic, 0.0);
+        assert!(material.base_color_texture.is_none());
+    }
+
+    #[test]
+    fn box_textured_sample() {
+        let model = load_sample("BoxTextured");
+        let (mesh, material, _) = model.visit().next().expect("No vertex groups");
+        assert_eq!(mesh.vertex_count(), 24);
+        assert!(mesh
+            .uvs()
+            .iter()
+            .all(|uv| (0.0..=1.0).contains(&uv.x) && (0.0..=1.0).contains(&uv.y)));
+
+        let texture = material
+            .and_then(|m| m.base_color_texture.as_ref())
+            .expect("The texture is missing");
+        assert_eq!(texture.tex_coord, 0);
+        let image = match &texture.source {
+            TextureSource::Image(image) => image,
+            _ => panic!("The texture is not decoded"),
+        };
+        assert_eq!(image.dimensions(), (4, 4));
+        assert_eq!(image.color_space(), ColorSpace::Srgb);
+        assert_eq!(&image.data()[0..4], &[255, 255, 255, 255]);
+        assert_eq!(&image.data()[8..12], &[32, 96, 192, 255]);
+
+        // The same image used by data slots is tagged linear, and shared among them.
+        let directory = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/models/samples/BoxTextured"
+        );
+        let mut document: Value = serde_json::from_slice(
+            &std::fs::read(format!("{}/BoxTextured.gltf", directory)).unwrap(),
+        )
+        .unwrap();
+        let material = &mut document["materials"][0];
+        material["normalTexture"] = serde_json::json!({ "index": 0 });
+        material["occlusionTexture"] = serde_json::json!({ "index": 0 });
+        material["pbrMetallicRoughness"]["metallicRoughnessTexture"] =
+            serde_json::json!({ "index": 0 });
+        material["emissiveTexture"] = serde_json::json!({ "index": 0 });
+        let mut source = MemorySource::new();
+        source.insert("box.gltf", serde_json::to_vec(&document).unwrap());
+        for name in &["BoxTextured.bin", "BoxTextured.png"] {
+            source.insert(
+                *name,
+                std::fs::read(format!("{}/{}", directory, name)).unwrap(),
+            );
+        }
+        let model = Model::<(), MaterialDescription>::load_gltf_from(
+            &source,
+            Path::new("box.gltf"),
+            &MeshConversion::default(),
+            |_| Ok(()),
+            Ok,
+        )
+        .expect("Failed to load the model");
+        let material = model.visit().next().and_then(|(_, m, _)| m).unwrap();
+        let image = |texture: &Option<MaterialTexture>| match &texture.as_ref().unwrap().source {
+            TextureSource::Image(image) => image.clone(),
+            _ => panic!("The texture is not decoded"),
+        };
+        let base_color = image(&material.base_color_texture);
+        let emissive = image(&material.emissive_texture);
+        let normal = image(&material.normal_texture);
+        let occlusion = image(&material.occlusion_texture);
+        let metallic_roughness = image(&material.metallic_roughness_texture);
+        assert_eq!(base_color.color_space(), ColorSpace::Srgb);
+        assert_eq!(emissive.color_space(), ColorSpace::Srgb);
+        assert_eq!(normal.color_space(), ColorSpace::Linear);
+        assert_eq!(occlusion.color_space(), ColorSpace::Linear);
+        assert_eq!(metallic_roughness.color_space(), ColorSpace::Linear);
+        assert!(Rc::ptr_eq(&base_color, &emissive));
+        assert!(Rc::ptr_eq(&normal, &occlusion));
+        assert_eq!(normal.data(), base_color.data());
+    }
+
+    #[test]
+    fn animated_morph_cube_sample() {
+        let model = load_sample("AnimatedMorphCube");
+        let (mesh, _, _) = model.visit().next().expect("No vertex groups");
+        let targets = &model.group_morph_targets()[0];
+        assert_eq!(targets.len(), 2);
+        assert_eq!(targets[0].name(), Some("thin"));
+        assert_eq!(targets[1].name(), Some("angle"));
+        assert_eq!(targets[0].default_weight(), 0.5);
+        assert!(targets
+            .iter()
+            .all(|t| t.vertex_count() == mesh.vertex_count()));
+
+        // The node rotates +X to -Z, and deltas are rotated as well.
+        let deltas = targets[1].position_deltas();
+        assert_eq!(count(deltas, Vec3::new(0.0, 0.0, -0.5)), 12);
+        assert_eq!(count(deltas, Vec3::zero()), 12);
+        assert_eq!(count(targets[0].normal_deltas(), Vec3::zero()), 24);
+    }
+
+    #[test]
+    fn morph_targets_follow_generated_vertices() {
+        let directory = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/models/samples/AnimatedMorphCube"
+        );
+        let json = std::fs::read(format!("{}/AnimatedMorphCube.gltf", directory)).unwrap();
+        let binary = std::fs::read(format!("{}/AnimatedMorphCube.bin", directory)).unwrap();
+
+        // Removes attributes from the sample, so that the importer has to generate them.
+        let load = |removed: &[&str]| {
+            let mut document: Value = serde_json::from_slice(&json).unwrap();
+            let primitive = &mut document["meshes"][0]["primitives"][0];
+            for name in removed {
+                primitive["attributes"]
+                    .as_object_mut()
+                    .unwrap()
+                    .remove(*name);
+                for target in primitive["targets"].as_array_mut().unwrap() {
+                    target.as_object_mut().unwrap().remove(*name);
+                }
+            }
+            let mut source = MemorySource::new();
+            source.insert("cube.gltf", serde_json::to_vec(&document).unwrap());
+            source.insert("AnimatedMorphCube.bin", binary.clone());
+
+            // Maps into a vertex buffer, as renderers do.
+            Model::<Vec<[f32; 3]>, ()>::load_gltf_from(
+                &source,
+                Path::new("cube.gltf"),
+                &MeshConversion::default(),
+                |mesh| Ok(mesh.positions().iter().map(|&p| p.into()).collect()),
+                |_| Ok(()),
+            )
+            .expect("Failed to load the model")
+        };
+
+        for &(removed, vertices) in &[(&["TANGENT"][..], 24), (&["TANGENT", "NORMAL"][..], 36)] {
+            let model = load(removed);
+            let (buffer, _, _) = model.visit().next().expect("No vertex groups");
+            let targets = &model.group_morph_targets()[0];
+            assert_eq!(buffer.len(), vertices);
+            assert_eq!(targets.len(), 2);
+            assert!(targets.iter().all(|t| t.vertex_count() == vertices));
+
+            // Only the top vertices lean in the "angle" target.
+            for (position, delta) in buffer.iter().zip(targets[1].position_deltas()) {
+                assert_eq!(position[1] > 0.0, delta.mag() > 0.0, "{:?}", position);
+            }
+        }
+    }
+}
@@ -0,0 +1,168 @@
+//! Contains level-of-detail selection.
+
+use crate::common::{bounds::BoundingSphere, environment::View};
+
+use anyhow::{bail, Result};
+use ultraviolet::{Mat4, Vec4};
+
+/// Selects levels of detail from projected screen sizes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LodSelector {
+    screen_sizes: Box<[f32]>,
+}
+
+impl LodSelector {
+    /// Creates a selector.
+    /// # Parameters
+    /// * `screen_sizes`: minimum projected diameter in pixels for each level,
+    ///   in descending order; the last level is used for anything smaller
+    pub fn new(screen_sizes: &[f32]) -> Result<LodSelector> {
+        if screen_sizes.is_empty() {
+            bail!("At least one level is required");
+        }
+        if screen_sizes.windows(2).any(|w| w[0] < w[1]) {
+            bail!("Screen sizes must be in descending order");
+        }
+
+        Ok(LodSelector {
+            screen_sizes: screen_sizes.into(),
+        })
+    }
+
+    /// The number of levels.
+    pub fn levels(&self) -> usize {
+        self.screen_sizes.len()
+    }
+
+    /// Selects the level for the bounding sphere in model space.
+    pub fn select(&self, view: &View, world_matrix: &Mat4, sphere: &BoundingSphere) -> usize {
+        let size = projected_size(view, &sphere.transformed(world_matrix));
+        self.screen_sizes
+            .iter()
+            .position(|&s| size >= s)
+            .unwrap_or(self.screen_sizes.len() - 1)
+    }
+}
+
+/// Calculates the projected diameter in pixels of the sphere in world space.
+/// Returns infinity when the viewpoint is inside the sphere.
+pub fn projected_size(view: &View, sphere: &BoundingSphere) -> f32 {
+    if sphere.is_empty() {
+        return 0.0;
+    }
+
+    let center = view.view_matrix.transform_point3(sphere.center);
+    if center.mag() <= sphere.radius {
+        return f32::INFINITY;
+    }
+
+    // w is the depth for perspective projections, and 1 for orthographic ones.
+    let clip = view.projection_matrix * Vec4::new(center.x, center.y, center.z, 1.0);
+    if clip.w <= 0.0 {
+        return 0.0;
+    }
+    let scale = view.projection_matrix.cols[1].y.abs();
+    sphere.radius * scale / clip.w * view.screen_dimensions.y
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ultraviolet::{
+        projection::rh_yup::{orthographic_gl, perspective_gl},
+        Vec2, Vec3,
+    };
+
+    /// A 90 degree perspective view at the position looking at -Z, on a 1000 pixels square screen.
+    fn perspective(position: Vec3) -> View {
+        View::new(
+            Mat4::from_translation(-position),
+            perspective_gl(90f32.to_radians(), 1.0, 0.1, 100.0),
+            Vec2::new(1000.0, 1000.0),
+        )
+    }
+
+    fn sphere(center: Vec3, radius: f32) -> BoundingSphere {
+        BoundingSphere { center, radius }
+    }
+
+    fn assert_size(actual: f32, expected: f32) {
+        assert!(
+            (actual - expected).abs() < 1e-2,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn perspective_sizes() {
+        // The screen height covers 2 units at the distance of 1.
+        let view = perspective(Vec3::zero());
+        let unit = |z: f32| sphere(Vec3::new(0.0, 0.0, z), 1.0);
+        assert_size(projected_size(&view, &unit(-10.0)), 100.0);
+        assert_size(projected_size(&view, &unit(-20.0)), 50.0);
+        assert_size(
+            projected_size(&view, &sphere(Vec3::new(3.0, 2.0, -10.0), 1.0)),
+            100.0,
+        );
+
+        // Moving the viewpoint is the same as moving the sphere.
+        let moved = perspective(Vec3::new(0.0, 0.0, 10.0));
+        assert_size(projected_size(&moved, &unit(0.0)), 100.0);
+
+        assert_eq!(projected_size(&view, &unit(-0.5)), f32::INFINITY);
+        assert_eq!(projected_size(&view, &unit(10.0)), 0.0);
+        assert_eq!(projected_size(&view, &BoundingSphere::empty()), 0.0);
+    }
+
+    #[test]
+    fn orthographic_sizes() {
+        // The screen height covers 2 units at any distance.
+        let view = View::new(
+            Mat4::identity(),
+            orthographic_gl(-2.0, 2.0, -1.0, 1.0, 0.1, 100.0),
+            Vec2::new(2000.0, 1000.0),
+        );
+        for &z in &[-5.0, -50.0] {
+            assert_size(
+                projected_size(&view, &sphere(Vec3::new(0.0, 0.0, z), 0.5)),
+                500.0,
+            );
+        }
+    }
+
+    #[test]
+    fn selection_thresholds() {
+        assert!(LodSelector::new(&[]).is_err());
+        assert!(LodSelector::new(&[50.0, 100.0]).is_err());
+        let selector = LodSelector::new(&[200.0, 100.0, 50.0]).unwrap();
+        assert_eq!(selector.levels(), 3);
+
+        // Spheres of the radius 1 project to 1000 / distance pixels.
+        let view = perspective(Vec3::zero());
+        let unit = sphere(Vec3::zero(), 1.0);
+        let select = |distance: f32| {
+            selector.select(
+                &view,
+                &Mat4::from_translation(Vec3::new(0.0, 0.0, -distance)),
+                &unit,
+            )
+        };
+        assert_eq!(select(0.5), 0);
+        assert_eq!(select(4.0), 0);
+        assert_eq!(select(5.5), 1);
+        assert_eq!(select(9.5), 1);
+        assert_eq!(select(10.5), 2);
+        assert_eq!(select(19.5), 2);
+        assert_eq!(select(20.5), 2);
+        assert_eq!(select(1000.0), 2);
+
+        // World scales enlarge the sphere.
+        let scaled = Mat4::from_translation(Vec3::new(0.0, 0.0, -15.0)) * Mat4::from_scale(2.0);
+        assert_eq!(selector.select(&view, &scaled, &unit), 1);
+        let single = LodSelector::new(&[100.0]).unwrap();
+        assert_eq!(single.select(&view, &Mat4::identity(), &unit), 0);
+    }
+}
@@ -0,0 +1,397 @@
+//! Contains morph targets (blend shapes).
+
+use crate::common::mesh::VertexStream;
+
+use anyhow::{bail, Result};
+use ultraviolet::{Mat3, Vec3, Vec4};
+
+/// Represents a morph target, which displaces vertices of a mesh.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MorphTarget {
+    name: Option<Box<str>>,
+    default_weight: f32,
+    positions: Box<[Vec3]>,
+    normals: Box<[Vec3]>,
+    tangents: Box<[Vec3]>,
+}
+
+impl MorphTarget {
+    /// Creates a morph target.
+    /// # Parameters
+    /// * `name`: target name
+    /// * `default_weight`: initial weight used by `MorphWeights::new`
+    /// * `positions`: position deltas
+    /// * `normals`: normal deltas, or empty if normals are not displaced
+    /// * `tangents`: tangent deltas, or empty if tangents are not displaced
+    pub fn new(
+        name: Option<&str>,
+        default_weight: f32,
+        positions: Vec<Vec3>,
+        normals: Vec<Vec3>,
+        tangents: Vec<Vec3>,
+    ) -> Result<MorphTarget> {
+        let vertices = positions.len();
+        if !(normals.is_empty() || normals.len() == vertices)
+            || !(tangents.is_empty() || tangents.len() == vertices)
+        {
+            bail!("The lengths of delta streams differ");
+        }
+
+        Ok(MorphTarget {
+            name: name.map(|n| n.into()),
+            default_weight,
+            positions: positions.into_boxed_slice(),
+            normals: normals.into_boxed_slice(),
+            tangents: tangents.into_boxed_slice(),
+        })
+    }
+
+    /// The target name.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The initial weight.
+    pub fn default_weight(&self) -> f32 {
+        self.default_weight
+    }
+
+    /// The position deltas.
+    pub fn position_deltas(&self) -> &[Vec3] {
+        &self.positions
+    }
+
+    /// The normal deltas; empty if normals are not displaced.
+    pub fn normal_deltas(&self) -> &[Vec3] {
+        &self.normals
+    }
+
+    /// The tangent deltas; empty if tangents are not displaced.
+    pub fn tangent_deltas(&self) -> &[Vec3] {
+        &self.tangents
+    }
+
+    /// The number of vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.positions.len()
+    }
+
+    /// Creates a target for vertices picked in the order.
+    pub(crate) fn remapped(&self, order: &[usize]) -> MorphTarget {
+        let pick = |deltas: &[Vec3]| -> Box<[Vec3]> {
+            if deltas.is_empty() {
+                Box::new([])
+            } else {
+                order.iter().map(|&v| deltas[v]).collect()
+            }
+        };
+
+        MorphTarget {
+            name: self.name.clone(),
+            default_weight: self.default_weight,
+            positions: pick(&self.positions),
+            normals: pick(&self.normals),
+            tangents: pick(&self.tangents),
+        }
+    }
+
+    /// Transforms deltas with the linear part of a matrix and its normal matrix.
+    ///
+    /// Normals and tangents are normalized after transformation, so their deltas
+    /// are transformed with matrices whose uniform scale is removed.
+    pub(crate) fn transform(&mut self, linear: &Mat3, normal_matrix: &Mat3) {
+        let unscaled = |m: &Mat3| {
+            let scale = m.determinant().abs().cbrt();
+            if scale > 0.0 {
+                *m * (1.0 / scale)
+            } else {
+                *m
+            }
+        };
+        let normal_matrix = unscaled(normal_matrix);
+        let tangent_matrix = unscaled(linear);
+
+        for delta in &mut self.positions[..] {
+            *delta = *linear * *delta;
+        }
+        for delta in &mut self.normals[..] {
+            *delta = normal_matrix * *delta;
+        }
+        for delta in &mut self.tangents[..] {
+            *delta = tangent_matrix * *delta;
+        }
+    }
+
+    /// Negates Z components for handedness conversion.
+    pub(crate) fn negate_z(&mut self) {
+        let deltas = self
+            .positions
+            .iter_mut()
+            .chain(self.normals.iter_mut())
+            .chain(self.tangents.iter_mut());
+        for delta in deltas {
+            delta.z = -delta.z;
+        }
+    }
+}
+
+/// Represents weights of morph targets, which can be set by target names.
+#[derive(Debug, Clone, PartialEq)]
+pub struct MorphWeights {
+    names: Box<[Option<Box<str>>]>,
+    weights: Box<[f32]>,
+}
+
+impl MorphWeights {
+    /// Creates weights initialized with default weights of the targets.
+    pub fn new(targets: &[MorphTarget]) -> MorphWeights {
+        MorphWeights {
+            names: targets.iter().map(|t| t.name.clone()).collect(),
+            weights: targets.iter().map(|t| t.default_weight).collect(),
+        }
+    }
+
+    /// The weight of the target.
+    pub fn get(&self, name: &str) -> Option<f32> {
+        self.position(name).map(|i| self.weights[i])
+    }
+
+    /// Sets the weight of the target. Returns `false` if no target has the name.
+    pub fn set(&mut self, name: &str, weight: f32) -> bool {
+        match self.position(name) {
+            Some(i) => {
+                self.weights[i] = weight;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The weights in the order of targets.
+    pub fn weights(&self) -> &[f32] {
+        &self.weights
+    }
+
+    /// The mutable weights in the order of targets.
+    pub fn weights_mut(&mut self) -> &mut [f32] {
+        &mut self.weights
+    }
+
+    fn position(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|n| n.as_deref() == Some(name))
+    }
+}
+
+/// Blends morph targets into vertices on the CPU.
+///
+/// This is the reference implementation for shaders. Normals and tangents are
+/// normalized after displacement, and bitangent signs are kept.
+/// # Parameters
+/// * `targets`: morph targets of the vertex group
+/// * `weights`: weight of each target
+/// * `source`: vertices without displacement
+/// * `destination`: displaced vertices, resized to the same length as `source`
+pub fn apply_morph_targets(
+    targets: &[MorphTarget],
+    weights: &[f32],
+    source: &VertexStream,
+    destination: &mut VertexStream,
+) -> Result<()> {
+    source.validate()?;
+    if targets.len() != weights.len() {
+        bail!("Each morph target needs a weight");
+    }
+    if targets.iter().any(|t| t.vertex_count() != source.len()) {
+        bail!("The vertex count does not match the morph targets");
+    }
+
+    destination.positions.clear();
+    destination.positions.extend_from_slice(&source.positions);
+    let mut normals = source.normals.clone();
+    let mut tangents: Vec<Vec3> = source.tangents.iter().map(|t| t.truncated()).collect();
+    for (target, &weight) in targets.iter().zip(weights) {
+        if weight == 0.0 {
+            continue;
+        }
+        for (position, delta) in destination.positions.iter_mut().zip(&target.positions[..]) {
+            *position += *delta * weight;
+        }
+        for (normal, delta) in normals.iter_mut().zip(&target.normals[..]) {
+            *normal += *delta * weight;
+        }
+        for (tangent, delta) in tangents.iter_mut().zip(&target.tangents[..]) {
+            *tangent += *delta * weight;
+        }
+    }
+
+    destination.normals.clear();
+    destination
+        .normals
+        .extend(normals.into_iter().map(|n| n.normalized()));
+    destination.tangents.clear();
+    destination.tangents.extend(
+        tangents
+            .into_iter()
+            .zip(&source.tangents)
+            .map(|(t, original)| {
+                let t = t.normalized();
+                Vec4::new(t.x, t.y, t.z, original.w)
+            }),
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{mesh::MeshData, model::optimize_vertex_fetch};
+
+    use ultraviolet::Vec2;
+
+    fn assert_vectors(actual: &[Vec3], expected: &[Vec3]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((*a - *e).mag() < 1e-5, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    /// Three vertices on the XY plane facing +Z, with tangents along +X.
+    fn stream() -> VertexStream {
+        VertexStream {
+            positions: vec![Vec3::zero(), Vec3::unit_x(), Vec3::unit_y()],
+            normals: vec![Vec3::unit_z(); 3],
+            tangents: vec![Vec4::new(1.0, 0.0, 0.0, -1.0); 3],
+        }
+    }
+
+    /// A target moving all vertices to +X and tilting normals, and another moving
+    /// only the second vertex to +Y and tilting tangents.
+    fn targets() -> Vec<MorphTarget> {
+        vec![
+            MorphTarget::new(
+                Some("shift"),
+                0.0,
+                vec![Vec3::unit_x(); 3],
+                vec![Vec3::unit_x(); 3],
+                vec![],
+            )
+            .unwrap(),
+            MorphTarget::new(
+                Some("lift"),
+                1.0,
+                vec![Vec3::zero(), Vec3::unit_y() * 2.0, Vec3::zero()],
+                vec![],
+                vec![Vec3::unit_y(); 3],
+            )
+            .unwrap(),
+        ]
+    }
+
+    #[test]
+    fn blends_partial_weights() {
+        let source = stream();
+        let mut destination = VertexStream {
+            positions: vec![Vec3::broadcast(9.0); 5],
+            ..Default::default()
+        };
+        apply_morph_targets(&targets(), &[0.5, 0.25], &source, &mut destination).unwrap();
+
+        assert_eq!(destination.len(), 3);
+        assert_vectors(
+            &destination.positions,
+            &[
+                Vec3::new(0.5, 0.0, 0.0),
+                Vec3::new(1.5, 0.5, 0.0),
+                Vec3::new(0.5, 1.0, 0.0),
+            ],
+        );
+        let normal = Vec3::new(0.5, 0.0, 1.0).normalized();
+        assert_vectors(&destination.normals, &[normal; 3]);
+        let tangent = Vec3::new(1.0, 0.25, 0.0).normalized();
+        for t in &destination.tangents {
+            assert_vectors(&[t.truncated()], &[tangent]);
+            assert_eq!(t.w, -1.0);
+        }
+
+        // Zero weights leave the source as it is.
+        apply_morph_targets(&targets(), &[0.0, 0.0], &source, &mut destination).unwrap();
+        assert_eq!(destination, source);
+
+        assert!(apply_morph_targets(&targets(), &[1.0], &source, &mut destination).is_err());
+        let short = VertexStream {
+            positions: vec![Vec3::zero(); 2],
+            normals: vec![Vec3::unit_z(); 2],
+            tangents: vec![Vec4::unit_x(); 2],
+        };
+        assert!(apply_morph_targets(&targets(), &[1.0, 1.0], &short, &mut destination).is_err());
+        assert!(
+            MorphTarget::new(None, 0.0, vec![Vec3::zero(); 3], vec![Vec3::zero()], vec![]).is_err()
+        );
+    }
+
+    #[test]
+    fn weights_by_name() {
+        let mut targets = targets();
+        targets.push(MorphTarget::new(None, 0.75, vec![Vec3::zero(); 3], vec![], vec![]).unwrap());
+        let mut weights = MorphWeights::new(&targets);
+        assert_eq!(weights.weights(), &[0.0, 1.0, 0.75]);
+        assert_eq!(weights.get("lift"), Some(1.0));
+        assert_eq!(weights.get("missing"), None);
+
+        assert!(weights.set("shift", 0.5));
+        assert!(!weights.set("missing", 0.5));
+        assert_eq!(weights.weights(), &[0.5, 1.0, 0.75]);
+        weights.weights_mut()[2] = 0.25;
+        assert_eq!(weights.get("shift"), Some(0.5));
+        assert_eq!(weights.weights(), &[0.5, 1.0, 0.25]);
+    }
+
+    #[test]
+    fn follows_vertex_fetch_optimization() {
+        // The second vertex is unused, and the others are used from the last one.
+        let positions = vec![Vec3::zero(), Vec3::unit_z(), Vec3::unit_x(), Vec3::unit_y()];
+        let mut mesh = MeshData::new(
+            positions.clone().into_boxed_slice(),
+            vec![Vec3::unit_z(); 4].into_boxed_slice(),
+            vec![Vec2::zero(); 4].into_boxed_slice(),
+            vec![Vec4::new(1.0, 0.0, 0.0, 1.0); 4].into_boxed_slice(),
+            vec![3, 0, 2].into_boxed_slice(),
+            None,
+        )
+        .unwrap();
+        let deltas: Vec<_> = (0..4).map(|i| Vec3::broadcast(i as f32)).collect();
+        let target = MorphTarget::new(Some("t"), 0.5, deltas.clone(), vec![], deltas).unwrap();
+        mesh.set_morph_targets(vec![target]).unwrap();
+
+        let original = VertexStream::from_mesh(&mesh);
+        let mut expected = VertexStream::default();
+        apply_morph_targets(mesh.morph_targets(), &[1.0], &original, &mut expected).unwrap();
+
+        optimize_vertex_fetch(&mut mesh);
+        assert_eq!(mesh.indices(), &[0, 1, 2]);
+        let remapped = &mesh.morph_targets()[0];
+        assert_eq!(remapped.name(), Some("t"));
+        assert_eq!(remapped.default_weight(), 0.5);
+        assert_eq!(remapped.vertex_count(), 3);
+        assert!(remapped.normal_deltas().is_empty());
+        let order = [3, 0, 2];
+        let picked: Vec<_> = order.iter().map(|&v| Vec3::broadcast(v as f32)).collect();
+        assert_eq!(remapped.position_deltas(), &picked[..]);
+        assert_eq!(remapped.tangent_deltas(), &picked[..]);
+
+        // Blending after the optimization gives the same vertices in the new order.
+        let mut blended = VertexStream::default();
+        apply_morph_targets(
+            mesh.morph_targets(),
+            &[1.0],
+            &VertexStream::from_mesh(&mesh),
+            &mut blended,
+        )
+        .unwrap();
+        let reordered: Vec<_> = order.iter().map(|&v| expected.positions[v]).collect();
+        assert_eq!(blended.positions, reordered);
+        assert_eq!(blended.positions[0], positions[3] + Vec3::broadcast(3.0));
+    }
+}
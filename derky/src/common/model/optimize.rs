@@ -0,0 +1,472 @@
+//! Contains index and vertex order optimization.
+
+use crate::common::{bounds::Bounds, mesh::MeshData};
+
+use std::{cmp::Ordering, collections::VecDeque};
+
+use ultraviolet::Vec3;
+
+/// The post-transform cache size assumed by `optimize_mesh`.
+pub const DEFAULT_CACHE_SIZE: usize = 16;
+
+/// The ACMR degradation allowed for overdraw optimization by `optimize_mesh`.
+pub const DEFAULT_OVERDRAW_THRESHOLD: f32 = 1.05;
+
+/// Represents statistics of a simulated FIFO post-transform vertex cache.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CacheStatistics {
+    /// Number of vertex shader invocations
+    pub transformed: usize,
+
+    /// Average cache miss ratio; transformed vertices per triangle
+    pub acmr: f32,
+
+    /// Average transform to vertex ratio; 1.0 is the optimum
+    pub atvr: f32,
+}
+
+/// Simulates a FIFO post-transform cache for the triangle list.
+pub fn analyze_vertex_cache(
+    indices: &[u32],
+    vertex_count: usize,
+    cache_size: usize,
+) -> CacheStatistics {
+    let transformed = FifoCache::new(vertex_count, cache_size).count_misses(indices);
+    let triangles = indices.len() / 3;
+
+    CacheStatistics {
+        transformed,
+        acmr: if triangles > 0 {
+            transformed as f32 / triangles as f32
+        } else {
+            0.0
+        },
+        atvr: if vertex_count > 0 {
+            transformed as f32 / vertex_count as f32
+        } else {
+            0.0
+        },
+    }
+}
+
+/// Applies vertex cache, overdraw and vertex fetch optimization with default parameters.
+pub fn optimize_mesh(mesh: &mut MeshData) {
+    optimize_vertex_cache(mesh, DEFAULT_CACHE_SIZE);
+    optimize_overdraw(mesh, DEFAULT_CACHE_SIZE, DEFAULT_OVERDRAW_THRESHOLD);
+    optimize_vertex_fetch(mesh);
+}
+
+/// Reorders triangles of each submesh for the post-transform vertex cache with Tipsify.
+pub fn optimize_vertex_cache(mesh: &mut MeshData, cache_size: usize) {
+    let vertex_count = mesh.positions.len();
+    for submesh in mesh.submeshes.iter() {
+        let indices = &mut mesh.indices[submesh.indices.clone()];
+        let order = tipsify(indices, vertex_count, cache_size);
+        let reordered: Vec<u32> = order
+            .iter()
+            .flat_map(|&t| indices[(t * 3)..(t * 3 + 3)].to_vec())
+            .collect();
+        indices.copy_from_slice(&reordered);
+    }
+}
+
+/// Reorders clusters of triangles in each submesh to reduce overdraw.
+///
+/// Triangles should be already optimized for the vertex cache. They are split into
+/// clusters as small as the ACMR allows, and clusters facing outward are drawn first.
+/// # Parameters
+/// * `cache_size`: simulated cache size
+/// * `threshold`: how much ACMR of a cluster may exceed the whole one (e.g. `1.05`);
+///   larger values make more clusters
+pub fn optimize_overdraw(mesh: &mut MeshData, cache_size: usize, threshold: f32) {
+    let vertex_count = mesh.positions.len();
+    let center = mesh.bounds.aabb.center();
+    let positions = &mesh.positions;
+    for submesh in mesh.submeshes.iter() {
+        let indices = &mut mesh.indices[submesh.indices.clone()];
+        let triangles = indices.len() / 3;
+        if triangles == 0 {
+            continue;
+        }
+
+        // Hard boundaries are where the whole triangle misses the cache.
+        let mut cache = FifoCache::new(vertex_count, cache_size);
+        let mut hard_boundaries = vec![];
+        for (t, triangle) in indices.chunks_exact(3).enumerate() {
+            if cache.count_misses(triangle) == 3 {
+                hard_boundaries.push(t);
+            }
+        }
+        hard_boundaries.push(triangles);
+
+        // Soft boundaries are placed where a cluster reaches the allowed ACMR.
+        let mut clusters = vec![];
+        for hard in hard_boundaries.windows(2) {
+            cache.clear();
+            let hard_misses = cache.count_misses(&indices[(hard[0] * 3)..(hard[1] * 3)]);
+            let target_acmr = hard_misses as f32 / (hard[1] - hard[0]) as f32 * threshold;
+
+            cache.clear();
+            let mut cluster_start = hard[0];
+            let mut cluster_misses = 0;
+            for t in hard[0]..hard[1] {
+                cluster_misses += cache.count_misses(&indices[(t * 3)..(t * 3 + 3)]);
+                if cluster_misses as f32 / (t + 1 - cluster_start) as f32 <= target_acmr {
+                    clusters.push(cluster_start);
+                    cluster_start = t + 1;
+                    cluster_misses = 0;
+                    cache.clear();
+                }
+            }
+            if cluster_start < hard[1] {
+                clusters.push(cluster_start);
+            }
+        }
+        clusters.push(triangles);
+
+        let mut keyed: Vec<(f32, usize, usize)> = clusters
+            .windows(2)
+            .map(|w| {
+                let key = cluster_sort_key(positions, &indices[(w[0] * 3)..(w[1] * 3)], center);
+                (key, w[0], w[1])
+            })
+            .collect();
+        keyed.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        let reordered: Vec<u32> = keyed
+            .iter()
+            .flat_map(|&(_, start, end)| indices[(start * 3)..(end * 3)].to_vec())
+            .collect();
+        indices.copy_from_slice(&reordered);
+    }
+}
+
+/// Reorders vertices by their first use in indices and removes unused ones.
+/// Bounds are recalculated, since removed vertices may have enlarged them.
+pub fn optimize_vertex_fetch(mesh: &mut MeshData) {
+    let mut remap: Vec<Option<u32>> = vec![None; mesh.positions.len()];
+    let mut order = vec![];
+    for index in mesh.indices.iter_mut() {
+        let new_index = *remap[*index as usize].get_or_insert_with(|| {
+            order.push(*index as usize);
+            order.len() as u32 - 1
+        });
+        *index = new_index;
+    }
+
+    mesh.positions = order.iter().map(|&v| mesh.positions[v]).collect();
+    mesh.normals = order.iter().map(|&v| mesh.normals[v]).collect();
+    mesh.uvs = order.iter().map(|&v| mesh.uvs[v]).collect();
+    mesh.tangents = order.iter().map(|&v| mesh.tangents[v]).collect();
+    mesh.morph_targets = mesh
+        .morph_targets
+        .iter()
+        .map(|t| t.remapped(&order))
+        .collect();
+    mesh.bounds = Bounds::from_points(&mesh.positions);
+}
+
+/// Simulated FIFO cache.
+struct FifoCache {
+    cached: Vec<bool>,
+    queue: VecDeque<u32>,
+    size: usize,
+}
+
+impl FifoCache {
+    fn new(vertex_count: usize, size: usize) -> FifoCache {
+        FifoCache {
+            cached: vec![false; vertex_count],
+            queue: VecDeque::with_capacity(size + 1),
+            size,
+        }
+    }
+
+    /// Accesses the vertex and returns whether it hits.
+    fn access(&mut self, vertex: u32) -> bool {
+        if self.cached[vertex as usize] {
+            return true;
+        }
+
+        self.cached[vertex as usize] = true;
+        self.queue.push_back(vertex);
+        if self.queue.len() > self.size {
+            if let Some(evicted) = self.queue.pop_front() {
+                self.cached[evicted as usize] = false;
+            }
+        }
+        false
+    }
+
+    /// Accesses the vertices and returns the number of misses.
+    fn count_misses(&mut self, vertices: &[u32]) -> usize {
+        vertices.iter().filter(|&&v| !self.access(v)).count()
+    }
+
+    /// Evicts all vertices.
+    fn clear(&mut self) {
+        for v in self.queue.drain(..) {
+            self.cached[v as usize] = false;
+        }
+    }
+}
+
+/// Calculates the triangle order with Tipsify (Sander et al. 2007).
+fn tipsify(indices: &[u32], vertex_count: usize, cache_size: usize) -> Vec<usize> {
+    let triangles = indices.len() / 3;
+    let mut adjacency = vec![vec![]; vertex_count];
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        for &v in triangle {
+            adjacency[v as usize].push(t);
+        }
+    }
+
+    let mut live: Vec<usize> = adjacency.iter().map(|a| a.len()).collect();
+    let mut timestamps = vec![0usize; vertex_count];
+    let mut emitted = vec![false; triangles];
+    let mut dead_end = vec![];
+    let mut order = Vec::with_capacity(triangles);
+
+    let mut time = cache_size + 1;
+    let mut cursor = 0;
+    let mut fanning = indices.first().map(|&v| v as usize);
+    while let Some(f) = fanning {
+        let mut candidates = vec![];
+        for &t in &adjacency[f] {
+            if emitted[t] {
+                continue;
+            }
+            for &v in &indices[(t * 3)..(t * 3 + 3)] {
+                let v = v as usize;
+                dead_end.push(v);
+                candidates.push(v);
+                live[v] -= 1;
+                if time - timestamps[v] > cache_size {
+                    timestamps[v] = time;
+                    time += 1;
+                }
+            }
+            emitted[t] = true;
+            order.push(t);
+        }
+
+        // Prefers vertices which stay in the cache while their triangles are emitted.
+        let mut best = None;
+        let mut best_priority = -1;
+        for &v in &candidates {
+            if live[v] == 0 {
+                continue;
+            }
+            let age = time - timestamps[v];
+            let priority = if age + 2 * live[v] <= cache_size {
+                age as isize
+            } else {
+                0
+            };
+            if priority > best_priority {
+                best = Some(v);
+                best_priority = priority;
+            }
+        }
+
+        fanning = best.or_else(|| {
+            while let Some(v) = dead_end.pop() {
+                if live[v] > 0 {
+                    return Some(v);
+                }
+            }
+            while cursor < indices.len() {
+                let v = indices[cursor] as usize;
+                cursor += 1;
+                if live[v] > 0 {
+                    return Some(v);
+                }
+            }
+            None
+        });
+    }
+    order
+}
+
+/// Calculates how much the cluster faces outward from the center.
+fn cluster_sort_key(positions: &[Vec3], indices: &[u32], center: Vec3) -> f32 {
+    let mut normal = Vec3::zero();
+    let mut centroid = Vec3::zero();
+    let mut area = 0.0;
+    for triangle in indices.chunks_exact(3) {
+        let p0 = positions[triangle[0] as usize];
+        let p1 = positions[triangle[1] as usize];
+        let p2 = positions[triangle[2] as usize];
+        let cross = (p1 - p0).cross(p2 - p0);
+        let triangle_area = cross.mag();
+
+        normal += cross;
+        centroid += (p0 + p1 + p2) * (triangle_area / 3.0);
+        area += triangle_area;
+    }
+
+    if area <= 0.0 || normal.mag_sq() <= 0.0 {
+        return 0.0;
+    }
+    (centroid / area - center).dot(normal.normalized())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::model::test_support::grid_mesh;
+
+    /// A grid whose triangles are shuffled with a fixed seed.
+    fn shuffled_grid(size: usize) -> MeshData {
+        let grid = grid_mesh(size, |x, y| x * y);
+        let mut triangles: Vec<_> = grid.indices().chunks_exact(3).collect();
+        let mut seed = 12345u32;
+        for i in (1..triangles.len()).rev() {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            triangles.swap(i, (seed >> 8) as usize % (i + 1));
+        }
+        let indices: Vec<u32> = triangles.concat();
+
+        MeshData::new(
+            grid.positions().into(),
+            grid.normals().into(),
+            grid.uvs().into(),
+            grid.tangents().into(),
+            indices.into_boxed_slice(),
+            None,
+        )
+        .expect("Invalid mesh")
+    }
+
+    /// Triangles as positions, in the drawing order.
+    fn triangle_positions(mesh: &MeshData) -> Vec<[Vec3; 3]> {
+        mesh.indices()
+            .chunks_exact(3)
+            .map(|t| {
+                [
+                    mesh.positions()[t[0] as usize],
+                    mesh.positions()[t[1] as usize],
+                    mesh.positions()[t[2] as usize],
+                ]
+            })
+            .collect()
+    }
+
+    /// Sorted triangles rotated to start from the smallest index, to compare as sets.
+    fn triangle_set(mesh: &MeshData) -> Vec<[u32; 3]> {
+        let mut triangles: Vec<_> = mesh
+            .indices()
+            .chunks_exact(3)
+            .map(|t| {
+                let first = (0..3).min_by_key(|&i| t[i]).unwrap_or(0);
+                [t[first], t[(first + 1) % 3], t[(first + 2) % 3]]
+            })
+            .collect();
+        triangles.sort_unstable();
+        triangles
+    }
+
+    #[test]
+    fn vertex_cache_improves() {
+        let mut mesh = shuffled_grid(24);
+        let vertices = mesh.vertex_count();
+        let before = analyze_vertex_cache(mesh.indices(), vertices, DEFAULT_CACHE_SIZE);
+        let triangles = triangle_set(&mesh);
+
+        optimize_vertex_cache(&mut mesh, DEFAULT_CACHE_SIZE);
+        let after = analyze_vertex_cache(mesh.indices(), vertices, DEFAULT_CACHE_SIZE);
+        assert_eq!(triangle_set(&mesh), triangles);
+        assert!(
+            after.acmr < before.acmr * 0.6,
+            "ACMR {} -> {}",
+            before.acmr,
+            after.acmr
+        );
+        assert!(
+            after.atvr < before.atvr && after.atvr < 1.5,
+            "ATVR {}",
+            after.atvr
+        );
+        assert!(after.atvr >= 1.0);
+    }
+
+    #[test]
+    fn overdraw_keeps_triangles() {
+        let mut mesh = shuffled_grid(16);
+        let vertices = mesh.vertex_count();
+        let shuffled = analyze_vertex_cache(mesh.indices(), vertices, DEFAULT_CACHE_SIZE);
+        optimize_vertex_cache(&mut mesh, DEFAULT_CACHE_SIZE);
+        let triangles = triangle_set(&mesh);
+
+        // The threshold applies to each cluster, so the whole ACMR is only bounded loosely.
+        optimize_overdraw(&mut mesh, DEFAULT_CACHE_SIZE, DEFAULT_OVERDRAW_THRESHOLD);
+        let after = analyze_vertex_cache(mesh.indices(), vertices, DEFAULT_CACHE_SIZE);
+        assert_eq!(triangle_set(&mesh), triangles);
+        assert!(after.acmr < shuffled.acmr * 0.75, "ACMR {}", after.acmr);
+    }
+
+    #[test]
+    fn vertex_fetch_keeps_triangles() {
+        let mut mesh = shuffled_grid(8);
+        optimize_vertex_cache(&mut mesh, DEFAULT_CACHE_SIZE);
+        let rendered = triangle_positions(&mesh);
+        // Drops the last two triangles, so that a corner vertex is unused.
+        let used = mesh.indices().len() - 6;
+        let mut partial = MeshData::new(
+            mesh.positions().into(),
+            mesh.normals().into(),
+            mesh.uvs().into(),
+            mesh.tangents().into(),
+            mesh.indices()[..used].into(),
+            None,
+        )
+        .expect("Invalid mesh");
+
+        optimize_vertex_fetch(&mut mesh);
+        assert_eq!(triangle_positions(&mesh), rendered);
+        let mut next = 0;
+        for &index in mesh.indices() {
+            assert!(index <= next, "{} is not used in order", index);
+            next = next.max(index + 1);
+        }
+        assert_eq!(next as usize, mesh.vertex_count());
+
+        let rendered = triangle_positions(&partial);
+        let referenced = {
+            let mut indices = partial.indices().to_vec();
+            indices.sort_unstable();
+            indices.dedup();
+            indices.len()
+        };
+        optimize_vertex_fetch(&mut partial);
+        assert_eq!(triangle_positions(&partial), rendered);
+        assert_eq!(partial.vertex_count(), referenced);
+        assert_eq!(*partial.bounds(), Bounds::from_points(partial.positions()));
+    }
+
+    #[test]
+    fn vertex_fetch_shrinks_bounds() {
+        // An unused vertex far from the grid enlarges the bounds until it is removed.
+        let grid = grid_mesh(2, |_, _| 0.0);
+        let mut positions = grid.positions().to_vec();
+        positions.push(Vec3::new(10.0, 0.0, 0.0));
+        let vertices = positions.len();
+        let mut mesh = MeshData::new(
+            positions.into_boxed_slice(),
+            vec![Vec3::unit_z(); vertices].into_boxed_slice(),
+            vec![Default::default(); vertices].into_boxed_slice(),
+            vec![Default::default(); vertices].into_boxed_slice(),
+            grid.indices().into(),
+            None,
+        )
+        .expect("Invalid mesh");
+        assert_eq!(mesh.bounds().aabb.max.x, 10.0);
+
+        optimize_vertex_fetch(&mut mesh);
+        assert_eq!(mesh.vertex_count(), vertices - 1);
+        assert_eq!(mesh.bounds(), grid.bounds());
+        assert_eq!(mesh.bounds().aabb.max, Vec3::new(1.0, 1.0, 0.0));
+        assert!((mesh.bounds().sphere.center - Vec3::new(0.5, 0.5, 0.0)).mag() < 1e-6);
+    }
+}
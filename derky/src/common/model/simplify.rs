@@ -0,0 +1,439 @@
+//! Contains quadric error mesh simplification.
+
+use crate::common::{
+    bounds::Bounds,
+    mesh::{MeshData, Submesh},
+};
+
+use std::{cmp::Ordering, collections::HashMap};
+
+use ultraviolet::Vec3;
+
+/// Symmetric 4x4 matrix of a quadric error metric, stored as upper triangle.
+#[derive(Debug, Default, Clone, Copy)]
+struct Quadric([f64; 10]);
+
+impl Quadric {
+    /// Creates the quadric of the plane through the triangle, weighted by its area.
+    fn from_triangle(p0: Vec3, p1: Vec3, p2: Vec3) -> Quadric {
+        let cross = (p1 - p0).cross(p2 - p0);
+        let double_area = cross.mag();
+        if double_area <= f32::MIN_POSITIVE {
+            return Quadric::default();
+        }
+
+        let normal = cross / double_area;
+        let n = [normal.x as f64, normal.y as f64, normal.z as f64];
+        let d = -(n[0] * p0.x as f64 + n[1] * p0.y as f64 + n[2] * p0.z as f64);
+        let w = double_area as f64 * 0.5;
+        Quadric([
+            n[0] * n[0] * w,
+            n[0] * n[1] * w,
+            n[0] * n[2] * w,
+            n[0] * d * w,
+            n[1] * n[1] * w,
+            n[1] * n[2] * w,
+            n[1] * d * w,
+            n[2] * n[2] * w,
+            n[2] * d * w,
+            d * d * w,
+        ])
+    }
+
+    /// Adds another quadric.
+    fn add(&mut self, other: &Quadric) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a += b;
+        }
+    }
+
+    /// Evaluates the squared distance sum at the point.
+    fn error(&self, point: Vec3) -> f64 {
+        let q = &self.0;
+        let (x, y, z) = (point.x as f64, point.y as f64, point.z as f64);
+        let e = q[0] * x * x
+            + 2.0 * q[1] * x * y
+            + 2.0 * q[2] * x * z
+            + 2.0 * q[3] * x
+            + q[4] * y * y
+            + 2.0 * q[5] * y * z
+            + 2.0 * q[6] * y
+            + q[7] * z * z
+            + 2.0 * q[8] * z
+            + q[9];
+        e.abs()
+    }
+}
+
+/// A candidate of collapsing vertex `from` into vertex `to`.
+#[derive(Debug, Clone, Copy)]
+struct Collapse {
+    from: u32,
+    to: u32,
+    error: f64,
+}
+
+/// Simplifies the mesh by collapsing edges with the quadric error metric.
+///
+/// Vertices are only moved onto their neighbors, so no new attributes are created.
+/// Vertices on mesh borders, attribute seams (several vertices at the same
+/// position) and submesh boundaries are never moved, which keeps UV seams
+/// and material boundaries intact.
+/// # Parameters
+/// * `target_ratio`: ratio of triangles to keep
+/// * `max_error`: maximum deviation relative to the radius of the mesh
+pub fn simplify(mesh: &MeshData, target_ratio: f32, max_error: f32) -> MeshData {
+    let triangle_count = mesh.indices.len() / 3;
+    let target = ((triangle_count as f32 * target_ratio.clamp(0.0, 1.0)).ceil() as usize).max(1);
+    let radius = mesh.bounds.sphere.radius.max(0.0) as f64;
+    let max_error = (max_error as f64 * radius).powi(2);
+
+    let positions = &mesh.positions;
+    let position_ids = weld_positions(&mesh.positions);
+    let movable = find_movable(mesh, &position_ids);
+
+    let mut quadrics = vec![Quadric::default(); positions.len()];
+    for triangle in mesh.indices.chunks_exact(3) {
+        let quadric = Quadric::from_triangle(
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        );
+        for &v in triangle {
+            quadrics[position_ids[v as usize]].add(&quadric);
+        }
+    }
+
+    // Triangles are tagged with submesh indices to rebuild the ranges later.
+    let mut triangles: Vec<([u32; 3], usize)> = vec![];
+    for (submesh_index, submesh) in mesh.submeshes.iter().enumerate() {
+        for triangle in mesh.indices[submesh.indices.clone()].chunks_exact(3) {
+            triangles.push(([triangle[0], triangle[1], triangle[2]], submesh_index));
+        }
+    }
+
+    let mut remap: Vec<u32> = (0..positions.len() as u32).collect();
+    while triangles.len() > target {
+        let mut candidates = collect_collapses(&triangles, &movable, &position_ids, |from, to| {
+            let mut quadric = quadrics[position_ids[from]];
+            quadric.add(&quadrics[position_ids[to]]);
+            quadric.error(positions[to])
+        });
+        candidates.sort_by(|a, b| a.error.partial_cmp(&b.error).unwrap_or(Ordering::Equal));
+
+        let adjacency = build_adjacency(&triangles, positions.len());
+        let mut touched = vec![false; positions.len()];
+        let mut remaining = (triangles.len() - target) / 2 + 1;
+        let mut collapsed = 0;
+        for collapse in candidates {
+            if remaining == 0 || collapse.error > max_error {
+                break;
+            }
+            let (from, to) = (collapse.from as usize, collapse.to as usize);
+            if touched[from] || touched[to] {
+                continue;
+            }
+            if flips_triangles(&triangles, &adjacency[from], mesh, from, to) {
+                continue;
+            }
+
+            for &t in &adjacency[from] {
+                for &v in &triangles[t].0 {
+                    touched[v as usize] = true;
+                }
+            }
+            remap[from] = to as u32;
+            let from_quadric = quadrics[position_ids[from]];
+            quadrics[position_ids[to]].add(&from_quadric);
+            remaining -= 1;
+            collapsed += 1;
+        }
+        if collapsed == 0 {
+            break;
+        }
+
+        for (triangle, _) in &mut triangles {
+            for v in triangle.iter_mut() {
+                *v = remap[*v as usize];
+            }
+        }
+        triangles.retain(|(t, _)| {
+            let (p0, p1, p2) = (
+                position_ids[t[0] as usize],
+                position_ids[t[1] as usize],
+                position_ids[t[2] as usize],
+            );
+            p0 != p1 && p1 != p2 && p2 != p0
+        });
+    }
+
+    compact(mesh, &triangles)
+}
+
+/// Generates simplified meshes for each ratio.
+/// Each level is simplified from the previous one, so ratios should be in descending order.
+pub fn generate_lods(mesh: &MeshData, ratios: &[f32], max_error: f32) -> Vec<MeshData> {
+    let triangle_count = (mesh.indices.len() / 3) as f32;
+    let mut lods: Vec<MeshData> = vec![];
+    for &ratio in ratios {
+        let source = lods.last().unwrap_or(mesh);
+        let source_count = (source.indices.len() / 3) as f32;
+        let relative_ratio = if source_count > 0.0 {
+            ratio * triangle_count / source_count
+        } else {
+            1.0
+        };
+        lods.push(simplify(source, relative_ratio, max_error));
+    }
+    lods
+}
+
+/// Assigns the same id to vertices at the same position.
+fn weld_positions(positions: &[Vec3]) -> Vec<usize> {
+    let mut ids = HashMap::new();
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+            *ids.entry(key).or_insert(i)
+        })
+        .collect()
+}
+
+/// Determines which vertices can be moved.
+fn find_movable(mesh: &MeshData, position_ids: &[usize]) -> Vec<bool> {
+    let mut movable = vec![true; position_ids.len()];
+    let mut vertex_at_position: HashMap<usize, u32> = HashMap::new();
+    let mut submesh_at_position: HashMap<usize, usize> = HashMap::new();
+    let mut edges: HashMap<(usize, usize), usize> = HashMap::new();
+
+    for (submesh_index, submesh) in mesh.submeshes.iter().enumerate() {
+        for triangle in mesh.indices[submesh.indices.clone()].chunks_exact(3) {
+            for i in 0..3 {
+                let v = triangle[i];
+                let p = position_ids[v as usize];
+                if *vertex_at_position.entry(p).or_insert(v) != v {
+                    // Attribute seam
+                    movable[v as usize] = false;
+                    movable[vertex_at_position[&p] as usize] = false;
+                }
+                if *submesh_at_position.entry(p).or_insert(submesh_index) != submesh_index {
+                    // Material boundary
+                    movable[v as usize] = false;
+                    movable[vertex_at_position[&p] as usize] = false;
+                }
+
+                let q = position_ids[triangle[(i + 1) % 3] as usize];
+                *edges.entry((p.min(q), p.max(q))).or_default() += 1;
+            }
+        }
+    }
+
+    // Positions on border edges (used by only one triangle)
+    let mut border = vec![false; position_ids.len()];
+    for (&(p, q), &count) in &edges {
+        if count == 1 {
+            border[p] = true;
+            border[q] = true;
+        }
+    }
+    for (v, m) in movable.iter_mut().enumerate() {
+        if border[position_ids[v]] {
+            *m = false;
+        }
+    }
+    movable
+}
+
+/// Enumerates collapse candidates along the edges of triangles.
+fn collect_collapses(
+    triangles: &[([u32; 3], usize)],
+    movable: &[bool],
+    position_ids: &[usize],
+    error: impl Fn(usize, usize) -> f64,
+) -> Vec<Collapse> {
+    let mut collapses = vec![];
+    for (triangle, _) in triangles {
+        for i in 0..3 {
+            let (a, b) = (triangle[i], triangle[(i + 1) % 3]);
+            if position_ids[a as usize] == position_ids[b as usize] {
+                continue;
+            }
+            for &(from, to) in &[(a, b), (b, a)] {
+                if movable[from as usize] {
+                    collapses.push(Collapse {
+                        from,
+                        to,
+                        error: error(from as usize, to as usize),
+                    });
+                }
+            }
+        }
+    }
+    collapses
+}
+
+/// Lists triangle indices which refer each vertex.
+fn build_adjacency(triangles: &[([u32; 3], usize)], vertices: usize) -> Vec<Vec<usize>> {
+    let mut adjacency = vec![vec![]; vertices];
+    for (i, (triangle, _)) in triangles.iter().enumerate() {
+        for &v in triangle {
+            adjacency[v as usize].push(i);
+        }
+    }
+    adjacency
+}
+
+/// Checks whether moving `from` onto `to` flips any of the remaining triangles,
+/// either from the current shape or against the vertex normals.
+fn flips_triangles(
+    triangles: &[([u32; 3], usize)],
+    around: &[usize],
+    mesh: &MeshData,
+    from: usize,
+    to: usize,
+) -> bool {
+    let positions = &mesh.positions;
+    around.iter().any(|&t| {
+        let triangle = triangles[t].0;
+        if triangle.iter().any(|&v| v as usize == to) {
+            return false;
+        }
+
+        let corners = [
+            positions[triangle[0] as usize],
+            positions[triangle[1] as usize],
+            positions[triangle[2] as usize],
+        ];
+        let mut moved = corners;
+        let mut normal_sum = Vec3::zero();
+        for (c, &v) in moved.iter_mut().zip(&triangle) {
+            let v = if v as usize == from { to } else { v as usize };
+            *c = positions[v];
+            normal_sum += mesh.normals[v];
+        }
+
+        let before = (corners[1] - corners[0]).cross(corners[2] - corners[0]);
+        let after = (moved[1] - moved[0]).cross(moved[2] - moved[0]);
+        // Collapses which turn triangles nearly perpendicular to the vertex normals
+        // tend to produce slivers along locked vertices.
+        before.dot(after) <= 0.0 || after.dot(normal_sum) < 0.25 * after.mag() * normal_sum.mag()
+    })
+}
+
+/// Builds mesh data from remaining triangles, removing unreferenced vertices.
+fn compact(mesh: &MeshData, triangles: &[([u32; 3], usize)]) -> MeshData {
+    let mut new_index = vec![None; mesh.positions.len()];
+    let mut positions = vec![];
+    let mut normals = vec![];
+    let mut uvs = vec![];
+    let mut tangents = vec![];
+    let mut indices = vec![];
+    let mut submeshes = vec![];
+    let mut order = vec![];
+
+    for (submesh_index, submesh) in mesh.submeshes.iter().enumerate() {
+        let start = indices.len();
+        for (triangle, _) in triangles.iter().filter(|(_, s)| *s == submesh_index) {
+            for &v in triangle {
+                let v = v as usize;
+                let index = *new_index[v].get_or_insert_with(|| {
+                    positions.push(mesh.positions[v]);
+                    normals.push(mesh.normals[v]);
+                    uvs.push(mesh.uvs[v]);
+                    tangents.push(mesh.tangents[v]);
+                    order.push(v);
+                    positions.len() as u32 - 1
+                });
+                indices.push(index);
+            }
+        }
+        submeshes.push(Submesh {
+            indices: start..indices.len(),
+            material: submesh.material,
+        });
+    }
+
+    MeshData {
+        bounds: Bounds::from_points(&positions),
+        positions: positions.into_boxed_slice(),
+        normals: normals.into_boxed_slice(),
+        uvs: uvs.into_boxed_slice(),
+        tangents: tangents.into_boxed_slice(),
+        indices: indices.into_boxed_slice(),
+        submeshes: submeshes.into_boxed_slice(),
+        morph_targets: mesh
+            .morph_targets
+            .iter()
+            .map(|t| t.remapped(&order))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::model::test_support::grid_mesh;
+
+    fn triangle_count(mesh: &MeshData) -> usize {
+        mesh.indices().len() / 3
+    }
+
+    #[test]
+    fn reaches_target() {
+        let grid = grid_mesh(16, |_, _| 0.0);
+        assert_eq!(triangle_count(&grid), 512);
+
+        let simplified = simplify(&grid, 0.25, 0.01);
+        let count = triangle_count(&simplified);
+        assert!(count > 0 && count <= 128, "{} triangles", count);
+        assert!(simplified.vertex_count() < grid.vertex_count());
+        assert_eq!(triangle_count(&simplify(&grid, 1.0, 0.01)), 512);
+    }
+
+    #[test]
+    fn keeps_bounds() {
+        let grid = grid_mesh(16, |x, y| 0.2 * (x * x + y * y));
+        let simplified = simplify(&grid, 0.2, 1.0);
+        assert!(triangle_count(&simplified) < triangle_count(&grid));
+
+        let (original, simplified) = (grid.bounds().aabb, simplified.bounds().aabb);
+        assert!((original.min - simplified.min).mag() < 1e-6);
+        assert!((original.max - simplified.max).mag() < 1e-6);
+    }
+
+    #[test]
+    fn respects_max_error() {
+        let grid = grid_mesh(16, |x, y| 0.1 * ((x * 20.0).sin() + (y * 20.0).cos()));
+        let strict = simplify(&grid, 0.1, 0.0001);
+        let loose = simplify(&grid, 0.1, 1.0);
+        assert!(triangle_count(&strict) > triangle_count(&loose));
+    }
+
+    #[test]
+    fn lods_decrease() {
+        let grid = grid_mesh(16, |x, y| 0.2 * (x * x + y * y));
+        let lods = generate_lods(&grid, &[0.5, 0.25, 0.125], 1.0);
+        assert_eq!(lods.len(), 3);
+
+        let mut previous = triangle_count(&grid);
+        for (lod, &ratio) in lods.iter().zip(&[0.5, 0.25, 0.125]) {
+            let count = triangle_count(lod);
+            assert!(
+                count > 0 && count <= previous,
+                "{} after {}",
+                count,
+                previous
+            );
+            assert!(
+                count <= (512.0 * ratio) as usize + 1,
+                "{} for {}",
+                count,
+                ratio
+            );
+            previous = count;
+        }
+    }
+}
@@ -0,0 +1,377 @@
+//! Contains tangent space generation.
+
+use std::collections::HashMap;
+
+use ultraviolet::{Vec2, Vec3, Vec4};
+use weavy_crab::FaceVertexPair;
+
+/// Identifies a welded vertex; positions, normals and UVs are compared bitwise.
+type VertexKey = [u32; 8];
+
+/// Accumulated tangent for a welded vertex, split by UV orientation.
+#[derive(Debug, Default, Clone, Copy)]
+struct TangentAccumulator {
+    preserving: Vec3,
+    preserving_weight: f32,
+    flipping: Vec3,
+    flipping_weight: f32,
+}
+
+/// Tangent information of a corner of a triangle.
+#[derive(Debug, Clone, Copy)]
+struct Corner {
+    face: usize,
+    vertex: usize,
+    key: VertexKey,
+    normal: Vec3,
+    orientation: Option<bool>,
+}
+
+/// Generates tangents for faces, compatible with MikkTSpace.
+///
+/// The result has the same shape as `faces`; each element is the tangent
+/// in `xyz` and the bitangent sign in `w`. The bitangent should be
+/// reconstructed as `cross(normal, tangent.xyz) * tangent.w`.
+///
+/// Polygons are triangulated as fans, as the vertex mappers do.
+/// Vertices without normals use the geometric face normal, and faces without
+/// UVs receive an arbitrary tangent perpendicular to the normal.
+pub fn generate_tangents(faces: &[Box<[FaceVertexPair]>]) -> Box<[Box<[Vec4]>]> {
+    let mut accumulators: HashMap<VertexKey, TangentAccumulator> = HashMap::new();
+    let mut corners = vec![];
+
+    for (face_index, face) in faces.iter().enumerate() {
+        if face.len() < 3 {
+            continue;
+        }
+        let face_normal = {
+            let (p0, p1, p2) = (face[0].0, face[1].0, face[2].0);
+            (p1 - p0).cross(p2 - p0).normalized()
+        };
+        let normals: Vec<_> = face
+            .iter()
+            .map(|v| v.2.unwrap_or(face_normal).normalized())
+            .collect();
+
+        for i in 0..(face.len() - 2) {
+            let triangle = [0, i + 1, i + 2];
+            let positions = [face[0].0, face[i + 1].0, face[i + 2].0];
+            let uvs = [
+                face[0].1.unwrap_or_default(),
+                face[i + 1].1.unwrap_or_default(),
+                face[i + 2].1.unwrap_or_default(),
+            ];
+            let (tangent, orientation) = triangle_tangent(positions, uvs);
+
+            for c in 0..3 {
+                let vertex = triangle[c];
+                let normal = normals[vertex];
+                let key = vertex_key(&face[vertex], normal);
+                corners.push(Corner {
+                    face: face_index,
+                    vertex,
+                    key,
+                    normal,
+                    orientation,
+                });
+
+                let orientation = match orientation {
+                    Some(o) => o,
+                    None => continue,
+                };
+                let projected = project_normalized(tangent, normal);
+                let weight = corner_angle(
+                    positions[c],
+                    positions[(c + 2) % 3],
+                    positions[(c + 1) % 3],
+                    normal,
+                );
+                let accumulator = accumulators.entry(key).or_default();
+                if orientation {
+                    accumulator.preserving += projected * weight;
+                    accumulator.preserving_weight += weight;
+                } else {
+                    accumulator.flipping += projected * weight;
+                    accumulator.flipping_weight += weight;
+                }
+            }
+        }
+    }
+
+    let mut result: Vec<Vec<Vec4>> = faces
+        .iter()
+        .map(|face| vec![Vec4::zero(); face.len()])
+        .collect();
+    for corner in corners {
+        let accumulator = accumulators.get(&corner.key).copied().unwrap_or_default();
+        let dominant = accumulator.preserving_weight >= accumulator.flipping_weight;
+        let target = &mut result[corner.face][corner.vertex];
+
+        // Fan triangulation visits the pivot vertex once per triangle, all with the same key.
+        // Triangles without UV area keep the orientation of the others, and pivots of
+        // triangles disagreeing on it join the orientation group with the larger weight.
+        let orientation = match (corner.orientation, target.w) {
+            (Some(o), w) if w == 0.0 || (w > 0.0) == o => o,
+            (None, w) if w != 0.0 => w > 0.0,
+            _ => dominant,
+        };
+        let summed = if orientation {
+            accumulator.preserving
+        } else {
+            accumulator.flipping
+        };
+        let tangent = if summed.mag_sq() > 0.0 {
+            summed.normalized()
+        } else {
+            arbitrary_tangent(corner.normal)
+        };
+        let sign = if orientation { 1.0 } else { -1.0 };
+        *target = Vec4::new(tangent.x, tangent.y, tangent.z, sign);
+    }
+
+    result.into_iter().map(|f| f.into_boxed_slice()).collect()
+}
+
+/// Calculates the unnormalized tangent of a triangle and its UV orientation.
+/// Orientation is `None` when the triangle has no area in UV space.
+fn triangle_tangent(positions: [Vec3; 3], uvs: [Vec2; 3]) -> (Vec3, Option<bool>) {
+    let d1 = positions[1] - positions[0];
+    let d2 = positions[2] - positions[0];
+    let t1 = uvs[1] - uvs[0];
+    let t2 = uvs[2] - uvs[0];
+
+    let signed_area = t1.x * t2.y - t1.y * t2.x;
+    if signed_area.abs() <= f32::MIN_POSITIVE {
+        return (Vec3::zero(), None);
+    }
+
+    let orientation = signed_area > 0.0;
+    let tangent = d1 * t2.y - d2 * t1.y;
+    let length = tangent.mag();
+    if length <= f32::MIN_POSITIVE {
+        return (Vec3::zero(), Some(orientation));
+    }
+
+    let sign = if orientation { 1.0 } else { -1.0 };
+    (tangent * (sign / length), Some(orientation))
+}
+
+/// Projects `v` onto the plane perpendicular to `normal` and normalizes it.
+fn project_normalized(v: Vec3, normal: Vec3) -> Vec3 {
+    let projected = v - normal * normal.dot(v);
+    if projected.mag_sq() > 0.0 {
+        projected.normalized()
+    } else {
+        projected
+    }
+}
+
+/// Calculates the angle at `origin` between the two edges, projected on the normal plane.
+fn corner_angle(origin: Vec3, previous: Vec3, next: Vec3, normal: Vec3) -> f32 {
+    let v1 = project_normalized(previous - origin, normal);
+    let v2 = project_normalized(next - origin, normal);
+    v1.dot(v2).clamp(-1.0, 1.0).acos()
+}
+
+/// Creates a key for welding vertices.
+fn vertex_key(vertex: &FaceVertexPair, normal: Vec3) -> VertexKey {
+    let uv = vertex.1.unwrap_or_default();
+    [
+        vertex.0.x.to_bits(),
+        vertex.0.y.to_bits(),
+        vertex.0.z.to_bits(),
+        normal.x.to_bits(),
+        normal.y.to_bits(),
+        normal.z.to_bits(),
+        uv.x.to_bits(),
+        uv.y.to_bits(),
+    ]
+}
+
+/// Creates any unit vector perpendicular to `normal`.
+fn arbitrary_tangent(normal: Vec3) -> Vec3 {
+    let axis = if normal.x.abs() < 0.9 {
+        Vec3::unit_x()
+    } else {
+        Vec3::unit_y()
+    };
+    project_normalized(axis, normal)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{
+        asset::DirectorySource,
+        material::MaterialDescription,
+        mesh::{MeshConversion, MeshData},
+        model::Model,
+    };
+
+    use std::path::Path;
+
+    /// Builds a face from positions and UVs, sharing the normal.
+    fn face(vertices: &[([f32; 3], [f32; 2])], normal: Vec3) -> Box<[FaceVertexPair]> {
+        vertices
+            .iter()
+            .map(|&(p, uv)| (Vec3::from(p), Some(Vec2::from(uv)), Some(normal)))
+            .collect()
+    }
+
+    fn assert_tangent(actual: Vec4, expected: Vec3, sign: f32) {
+        assert!(
+            (actual.truncated() - expected).mag() < 1e-5 && actual.w == sign,
+            "{:?} != ({:?}, {})",
+            actual,
+            expected,
+            sign
+        );
+    }
+
+    #[test]
+    fn planar_quad() {
+        // U goes to +X and V goes to -Z on the face looking at +Y.
+        let quad = face(
+            &[
+                ([0.0, 0.0, 0.0], [0.0, 0.0]),
+                ([1.0, 0.0, 0.0], [1.0, 0.0]),
+                ([1.0, 0.0, -1.0], [1.0, 1.0]),
+                ([0.0, 0.0, -1.0], [0.0, 1.0]),
+            ],
+            Vec3::unit_y(),
+        );
+        let tangents = generate_tangents(&[quad]);
+        assert_eq!(tangents[0].len(), 4);
+        for &tangent in tangents[0].iter() {
+            assert_tangent(tangent, Vec3::unit_x(), 1.0);
+            let bitangent = Vec3::unit_y().cross(tangent.truncated()) * tangent.w;
+            assert!((bitangent + Vec3::unit_z()).mag() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn mirrored_seam() {
+        // The left half mirrors U; both halves share vertices on the seam at x = 0.
+        let left = face(
+            &[
+                ([-1.0, 0.0, 0.0], [1.0, 0.0]),
+                ([0.0, 0.0, 0.0], [0.0, 0.0]),
+                ([0.0, 1.0, 0.0], [0.0, 1.0]),
+                ([-1.0, 1.0, 0.0], [1.0, 1.0]),
+            ],
+            Vec3::unit_z(),
+        );
+        let right = face(
+            &[
+                ([0.0, 0.0, 0.0], [0.0, 0.0]),
+                ([1.0, 0.0, 0.0], [1.0, 0.0]),
+                ([1.0, 1.0, 0.0], [1.0, 1.0]),
+                ([0.0, 1.0, 0.0], [0.0, 1.0]),
+            ],
+            Vec3::unit_z(),
+        );
+        let tangents = generate_tangents(&[left, right]);
+        for &tangent in tangents[0].iter() {
+            assert_tangent(tangent, -Vec3::unit_x(), -1.0);
+        }
+        for &tangent in tangents[1].iter() {
+            assert_tangent(tangent, Vec3::unit_x(), 1.0);
+        }
+
+        // The bitangent follows V on both sides.
+        for &tangent in tangents.iter().flat_map(|f| f.iter()) {
+            let bitangent = Vec3::unit_z().cross(tangent.truncated()) * tangent.w;
+            assert!((bitangent - Vec3::unit_y()).mag() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn degenerate_uvs() {
+        let collapsed = face(
+            &[
+                ([0.0, 0.0, 0.0], [0.5, 0.5]),
+                ([1.0, 0.0, 0.0], [0.5, 0.5]),
+                ([0.0, 1.0, 0.0], [0.5, 0.5]),
+            ],
+            Vec3::unit_z(),
+        );
+        let missing: Box<[FaceVertexPair]> = vec![
+            (Vec3::new(0.0, 0.0, 0.0), None, None),
+            (Vec3::new(0.0, 0.0, -1.0), None, None),
+            (Vec3::new(0.0, 1.0, 0.0), None, None),
+        ]
+        .into_boxed_slice();
+        let line = face(
+            &[([0.0; 3], [0.0; 2]), ([1.0, 0.0, 0.0], [1.0, 0.0])],
+            Vec3::unit_z(),
+        );
+
+        let tangents = generate_tangents(&[collapsed, missing, line]);
+        let normals = [Vec3::unit_z(), Vec3::unit_x()];
+        for (face_tangents, normal) in tangents.iter().zip(&normals) {
+            for tangent in face_tangents.iter() {
+                assert!((tangent.truncated().mag() - 1.0).abs() < 1e-5);
+                assert!(tangent.truncated().dot(*normal).abs() < 1e-5);
+                assert_eq!(tangent.w, 1.0);
+            }
+        }
+        assert_eq!(&tangents[2][..], &[Vec4::zero(); 2]);
+    }
+
+    #[test]
+    fn folded_fan() {
+        // The fan triangles (0, 1, 2) and (0, 2, 3) have opposite UV orientations,
+        // and the first one has larger angles at the shared vertices 0 and 2.
+        let polygon = face(
+            &[
+                ([0.0, 0.0, 0.0], [0.0, 0.0]),
+                ([1.0, 0.0, 0.0], [1.0, 0.0]),
+                ([1.0, 2.0, 0.0], [1.0, 1.0]),
+                ([0.0, 1.0, 0.0], [2.0, 0.5]),
+            ],
+            Vec3::unit_z(),
+        );
+        let tangents = generate_tangents(&[polygon]);
+        let signs: Vec<_> = tangents[0].iter().map(|t| t.w).collect();
+        assert_eq!(signs, vec![1.0, 1.0, 1.0, -1.0]);
+        assert_tangent(tangents[0][0], Vec3::unit_x(), 1.0);
+        assert_tangent(tangents[0][2], Vec3::unit_x(), 1.0);
+    }
+
+    #[test]
+    fn box_textured_sample() {
+        let source = DirectorySource::new(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/../assets/models/samples"
+        ));
+        let model = Model::<MeshData, MaterialDescription>::load_gltf_from(
+            &source,
+            Path::new("BoxTextured/BoxTextured.gltf"),
+            &MeshConversion::default(),
+            |mesh| Ok(mesh.clone()),
+            Ok,
+        )
+        .expect("Failed to load the sample");
+        let (mesh, _, _) = model.visit().next().expect("No vertex groups");
+
+        // Faces share no vertices, so each tangent is the U gradient of its face
+        // solved from positions and UVs, as MikkTSpace gives. glTF puts the origin of UVs
+        // at the top-left, so V goes against the bitangent and all signs are negative.
+        let expected = [
+            (Vec3::unit_x(), -Vec3::unit_y()),
+            (-Vec3::unit_x(), Vec3::unit_y()),
+            (Vec3::unit_y(), Vec3::unit_x()),
+            (-Vec3::unit_y(), -Vec3::unit_x()),
+            (Vec3::unit_z(), Vec3::unit_x()),
+            (-Vec3::unit_z(), Vec3::unit_x()),
+        ];
+        for (normal, &tangent) in mesh.normals().iter().zip(mesh.tangents()) {
+            let &(_, expected) = expected
+                .iter()
+                .find(|(n, _)| (*n - *normal).mag() < 1e-5)
+                .expect("Unexpected normal");
+            assert_tangent(tangent, expected, -1.0);
+        }
+    }
+}
@@ -0,0 +1,37 @@
+//! Contains mesh factories shared by tests of model operations.
+
+use crate::common::mesh::MeshData;
+
+use ultraviolet::{Vec2, Vec3, Vec4};
+
+/// Creates a grid of `size` x `size` quads over [0, 1] x [0, 1] with heights.
+pub fn grid_mesh(size: usize, height: impl Fn(f32, f32) -> f32) -> MeshData {
+    let mut positions = vec![];
+    let mut uvs = vec![];
+    for y in 0..=size {
+        for x in 0..=size {
+            let uv = Vec2::new(x as f32, y as f32) / size as f32;
+            positions.push(Vec3::new(uv.x, uv.y, height(uv.x, uv.y)));
+            uvs.push(uv);
+        }
+    }
+    let mut indices = vec![];
+    for y in 0..size {
+        for x in 0..size {
+            let v = (y * (size + 1) + x) as u32;
+            let above = v + size as u32 + 1;
+            indices.extend_from_slice(&[v, v + 1, above + 1, v, above + 1, above]);
+        }
+    }
+
+    let vertices = positions.len();
+    MeshData::new(
+        positions.into_boxed_slice(),
+        vec![Vec3::unit_z(); vertices].into_boxed_slice(),
+        uvs.into_boxed_slice(),
+        vec![Vec4::unit_x() + Vec4::unit_w(); vertices].into_boxed_slice(),
+        indices.into_boxed_slice(),
+        None,
+    )
+    .expect("Invalid grid")
+}
@@ -0,0 +1,536 @@
+//! Contains statistics and validation of models.
+
+use super::Model;
+use crate::common::{material::MaterialDescription, mesh::MeshData};
+
+use std::collections::HashMap;
+
+use anyhow::Result;
+use serde::Serialize;
+use ultraviolet::{Vec2, Vec3};
+use weavy_crab::WavefrontObj;
+
+/// Faces whose area is below this ratio to the square of the longest edge are degenerate.
+const DEGENERATE_AREA_RATIO: f32 = 1e-6;
+
+/// Polygons whose vertices are farther from their plane than this ratio
+/// to the longest edge are non-planar.
+const PLANARITY_TOLERANCE: f32 = 1e-3;
+
+/// Represents statistics and problems of a whole model.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ValidationReport {
+    /// Totals of all groups
+    pub summary: GroupReport,
+
+    /// Reports of each group
+    pub groups: Vec<GroupReport>,
+
+    /// Reports of each material
+    pub materials: Vec<MaterialReport>,
+
+    /// Number of faces without materials
+    pub faces_without_material: usize,
+}
+
+impl ValidationReport {
+    /// Checks whether the geometry has problems which break rendering;
+    /// degenerate faces, non-manifold edges and inconsistent or flipped winding.
+    ///
+    /// Other counts such as boundary edges or out-of-range UVs can be legitimate,
+    /// so check them in `summary` as needed.
+    pub fn has_geometry_errors(&self) -> bool {
+        let summary = &self.summary;
+        summary.degenerate_faces > 0
+            || summary.non_manifold_edges > 0
+            || summary.inconsistent_edges > 0
+            || summary.flipped_faces > 0
+    }
+
+    /// Serializes this report into JSON.
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    /// Sums up group reports into the summary.
+    fn summarize(&mut self) {
+        let mut summary = GroupReport::default();
+        for group in &self.groups {
+            summary.vertices += group.vertices;
+            summary.faces += group.faces;
+            summary.triangles += group.triangles;
+            summary.degenerate_faces += group.degenerate_faces;
+            summary.non_planar_faces += group.non_planar_faces;
+            summary.unused_vertices += group.unused_vertices;
+            summary.faces_without_uvs += group.faces_without_uvs;
+            summary.faces_without_normals += group.faces_without_normals;
+            summary.faces_with_out_of_range_uvs += group.faces_with_out_of_range_uvs;
+            summary.boundary_edges += group.boundary_edges;
+            summary.non_manifold_edges += group.non_manifold_edges;
+            summary.inconsistent_edges += group.inconsistent_edges;
+            summary.flipped_faces += group.flipped_faces;
+        }
+        self.summary = summary;
+    }
+}
+
+/// Represents statistics and problems of a group.
+///
+/// Edges are counted between distinct positions, so vertices split by
+/// UVs or normals do not make boundaries.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct GroupReport {
+    /// Object name
+    pub object: Option<String>,
+
+    /// Group name
+    pub name: Option<String>,
+
+    /// Number of vertex positions
+    pub vertices: usize,
+
+    /// Number of faces
+    pub faces: usize,
+
+    /// Number of triangles after triangulation
+    pub triangles: usize,
+
+    /// Number of faces with (almost) zero area
+    pub degenerate_faces: usize,
+
+    /// Number of polygons whose vertices are not on a plane
+    pub non_planar_faces: usize,
+
+    /// Number of vertices not referred by any face
+    pub unused_vertices: usize,
+
+    /// Number of faces with vertices lacking UVs
+    pub faces_without_uvs: usize,
+
+    /// Number of faces with vertices lacking normals
+    pub faces_without_normals: usize,
+
+    /// Number of faces with UVs outside [0, 1], which require wrapping samplers
+    pub faces_with_out_of_range_uvs: usize,
+
+    /// Number of edges used by only one face
+    pub boundary_edges: usize,
+
+    /// Number of edges shared by more than two faces
+    pub non_manifold_edges: usize,
+
+    /// Number of edges whose two faces wind in opposite directions
+    pub inconsistent_edges: usize,
+
+    /// Number of faces whose winding disagrees with their vertex normals
+    pub flipped_faces: usize,
+}
+
+/// Represents statistics of a material.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct MaterialReport {
+    /// Material name
+    pub name: Option<String>,
+
+    /// Number of faces using this material
+    pub faces: usize,
+
+    /// Whether the material has a base color (diffuse) texture
+    pub has_texture: bool,
+}
+
+/// Validates a parsed Wavefront OBJ.
+pub fn validate_obj(obj: &WavefrontObj) -> ValidationReport {
+    let mut report = ValidationReport {
+        materials: obj
+            .materials()
+            .iter()
+            .map(|m| MaterialReport {
+                name: Some(m.name().to_string()),
+                faces: 0,
+                has_texture: m.diffuse_map().is_some(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    for object in obj.objects() {
+        for group in object.groups() {
+            let faces: Vec<Vec<Corner>> = group
+                .face_index_pairs()
+                .iter()
+                .map(|(pairs, _)| {
+                    pairs
+                        .iter()
+                        .map(|pair| Corner {
+                            position: pair.0,
+                            uv: pair.1.map(|i| group.texture_uvs()[i]),
+                            normal: pair.2.map(|i| group.normals()[i]),
+                        })
+                        .collect()
+                })
+                .collect();
+
+            let mut group_report = GroupReport {
+                object: object.name().map(|n| n.to_string()),
+                name: group.name().map(|n| n.to_string()),
+                ..Default::default()
+            };
+            analyze_faces(&mut group_report, group.vertices(), &faces);
+            report.groups.push(group_report);
+
+            for (_, material) in group.face_index_pairs() {
+                match material.and_then(|m| report.materials.get_mut(m)) {
+                    Some(material) => material.faces += 1,
+                    None => report.faces_without_material += 1,
+                }
+            }
+        }
+    }
+
+    report.summarize();
+    report
+}
+
+/// Validates a model loaded with `MeshData` and `MaterialDescription` as they are,
+/// for example by passing `Ok` as both mappers.
+///
+/// Processed meshes are triangulated and their missing attributes are filled,
+/// so non-planar faces and faces without UVs or normals are never reported.
+pub fn validate_model(model: &Model<MeshData, MaterialDescription>) -> ValidationReport {
+    let mut report = ValidationReport {
+        materials: model
+            .materials
+            .iter()
+            .map(|m| MaterialReport {
+                name: m.name.clone(),
+                faces: 0,
+                has_texture: m.base_color_texture.is_some(),
+            })
+            .collect(),
+        ..Default::default()
+    };
+
+    for (mesh, material) in model
+        .vertex_groups
+        .iter()
+        .zip(model.material_mapping.iter())
+    {
+        let faces: Vec<Vec<Corner>> = mesh
+            .indices()
+            .chunks_exact(3)
+            .map(|triangle| {
+                triangle
+                    .iter()
+                    .map(|&i| Corner {
+                        position: i as usize,
+                        uv: Some(mesh.uvs()[i as usize]),
+                        normal: Some(mesh.normals()[i as usize]),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut group_report = GroupReport::default();
+        analyze_faces(&mut group_report, mesh.positions(), &faces);
+        report.groups.push(group_report);
+
+        match material.and_then(|m| report.materials.get_mut(m)) {
+            Some(material) => material.faces += faces.len(),
+            None => report.faces_without_material += faces.len(),
+        }
+    }
+
+    report.summarize();
+    report
+}
+
+/// Represents a vertex of a face.
+struct Corner {
+    position: usize,
+    uv: Option<Vec2>,
+    normal: Option<Vec3>,
+}
+
+/// Represents uses of an edge.
+#[derive(Default)]
+struct EdgeUse {
+    forward: usize,
+    backward: usize,
+}
+
+/// Fills the report of a group from its positions and faces.
+fn analyze_faces(report: &mut GroupReport, positions: &[Vec3], faces: &[Vec<Corner>]) {
+    report.vertices = positions.len();
+    report.faces = faces.len();
+
+    let welded = weld_positions(positions);
+    let mut used = vec![false; positions.len()];
+    let mut edges: HashMap<(usize, usize), EdgeUse> = HashMap::new();
+    for face in faces {
+        report.triangles += face.len().saturating_sub(2);
+        for corner in face {
+            used[corner.position] = true;
+        }
+        if face.iter().any(|c| c.uv.is_none()) {
+            report.faces_without_uvs += 1;
+        }
+        if face.iter().any(|c| c.normal.is_none()) {
+            report.faces_without_normals += 1;
+        }
+        let out_of_range = |v: f32| !(0.0..=1.0).contains(&v);
+        if face
+            .iter()
+            .filter_map(|c| c.uv)
+            .any(|uv| out_of_range(uv.x) || out_of_range(uv.y))
+        {
+            report.faces_with_out_of_range_uvs += 1;
+        }
+
+        let points: Vec<Vec3> = face.iter().map(|c| positions[c.position]).collect();
+        let shape = analyze_shape(&points);
+        match shape {
+            FaceShape::Degenerate => report.degenerate_faces += 1,
+            FaceShape::Polygon { normal, planar } => {
+                if !planar {
+                    report.non_planar_faces += 1;
+                }
+                let vertex_normals = face
+                    .iter()
+                    .try_fold(Vec3::zero(), |sum, c| c.normal.map(|n| sum + n));
+                if let Some(vertex_normals) = vertex_normals {
+                    if normal.dot(vertex_normals) < 0.0 {
+                        report.flipped_faces += 1;
+                    }
+                }
+            }
+        }
+
+        for (i, corner) in face.iter().enumerate() {
+            let start = welded[corner.position];
+            let end = welded[face[(i + 1) % face.len()].position];
+            if start == end {
+                continue;
+            }
+            if start < end {
+                edges.entry((start, end)).or_default().forward += 1;
+            } else {
+                edges.entry((end, start)).or_default().backward += 1;
+            }
+        }
+    }
+
+    report.unused_vertices = used.iter().filter(|&&u| !u).count();
+    for edge in edges.values() {
+        match edge.forward + edge.backward {
+            1 => report.boundary_edges += 1,
+            2 if edge.forward != 1 => report.inconsistent_edges += 1,
+            2 => (),
+            _ => report.non_manifold_edges += 1,
+        }
+    }
+}
+
+/// Represents the shape of a face.
+enum FaceShape {
+    Degenerate,
+    Polygon { normal: Vec3, planar: bool },
+}
+
+/// Calculates the normal of the polygon with Newell's method and checks its planarity.
+fn analyze_shape(points: &[Vec3]) -> FaceShape {
+    if points.len() < 3 {
+        return FaceShape::Degenerate;
+    }
+
+    let mut newell = Vec3::zero();
+    let mut longest_sq = 0.0f32;
+    for (i, &current) in points.iter().enumerate() {
+        let next = points[(i + 1) % points.len()];
+        newell += current.cross(next);
+        longest_sq = longest_sq.max((next - current).mag_sq());
+    }
+
+    // The length of the Newell normal is twice the area.
+    let area = newell.mag() * 0.5;
+    if longest_sq == 0.0 || area <= DEGENERATE_AREA_RATIO * longest_sq {
+        return FaceShape::Degenerate;
+    }
+
+    let normal = newell.normalized();
+    let center = points.iter().fold(Vec3::zero(), |a, &p| a + p) / points.len() as f32;
+    let tolerance = PLANARITY_TOLERANCE * longest_sq.sqrt();
+    let planar = points
+        .iter()
+        .all(|&p| normal.dot(p - center).abs() <= tolerance);
+    FaceShape::Polygon { normal, planar }
+}
+
+/// Maps each position to the first index with the identical position.
+fn weld_positions(positions: &[Vec3]) -> Vec<usize> {
+    let mut first_indices = HashMap::new();
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, p)| {
+            let key = [p.x.to_bits(), p.y.to_bits(), p.z.to_bits()];
+            *first_indices.entry(key).or_insert(i)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{
+        asset::{AssetSource, MemorySource},
+        mesh::MeshConversion,
+    };
+
+    use std::{
+        io::{Cursor, Error as IoError, ErrorKind},
+        path::Path,
+    };
+
+    use weavy_crab::Parser;
+
+    /// Creates a source with `model.obj` and `model.mtl`.
+    fn source(obj: &str, mtl: &str) -> MemorySource {
+        let mut source = MemorySource::new();
+        source.insert("model.obj", obj.as_bytes().to_vec());
+        source.insert("model.mtl", mtl.as_bytes().to_vec());
+        source
+    }
+
+    /// Parses `model.obj` and validates it.
+    fn validate(obj: &str, mtl: &str) -> ValidationReport {
+        let source = source(obj, mtl);
+        let mut parser = Parser::new(|mtllib: &Path, source: &&MemorySource| {
+            let mtl_file = source
+                .read(mtllib)
+                .map_err(|e| IoError::new(ErrorKind::NotFound, e))?;
+            Ok(Cursor::new(mtl_file))
+        });
+        let obj = parser
+            .parse(Cursor::new(obj.as_bytes().to_vec()), &source)
+            .expect("Invalid OBJ");
+        validate_obj(&obj)
+    }
+
+    #[test]
+    fn degenerate_and_non_planar_faces() {
+        let report = validate(
+            "v 0 0 0\nv 1 0 0\nv 2 0 0\nv 0 1 0\nv 1 1 0.5\n\
+             f 1 2 4\nf 1 2 3\nf 1 1 4\nf 1 2 5 4\nf 2 3 5\n",
+            "",
+        );
+        assert_eq!(report.summary.faces, 5);
+        assert_eq!(report.summary.triangles, 6);
+        // Collinear vertices and a repeated vertex.
+        assert_eq!(report.summary.degenerate_faces, 2);
+        assert_eq!(report.summary.non_planar_faces, 1);
+        assert!(report.has_geometry_errors());
+    }
+
+    #[test]
+    fn unused_vertices() {
+        let report = validate("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 5 5 5\nv 6 6 6\nf 1 2 3\n", "");
+        assert_eq!(report.summary.vertices, 5);
+        assert_eq!(report.summary.unused_vertices, 2);
+        assert_eq!(report.summary.boundary_edges, 3);
+        assert!(!report.has_geometry_errors());
+    }
+
+    #[test]
+    fn non_manifold_edges() {
+        // Three triangles share the edge between the first two vertices.
+        let report = validate(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 -1 0\nv 0 0 1\n\
+             f 1 2 3\nf 2 1 4\nf 1 2 5\n",
+            "",
+        );
+        assert_eq!(report.summary.non_manifold_edges, 1);
+        assert_eq!(report.summary.inconsistent_edges, 0);
+        assert!(report.has_geometry_errors());
+
+        // Duplicated positions are welded, so split vertices still share edges.
+        let report = validate(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 0\nv 1 0 0\nv 0 -1 0\n\
+             f 1 2 3\nf 5 4 6\n",
+            "",
+        );
+        assert_eq!(report.summary.boundary_edges, 4);
+        assert!(!report.has_geometry_errors());
+    }
+
+    #[test]
+    fn flipped_winding() {
+        let report = validate(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nvn 0 0 1\nvn 0 0 -1\n\
+             f 1//1 2//1 3//1\nf 2//2 4//2 3//2\n",
+            "",
+        );
+        assert_eq!(report.summary.flipped_faces, 1);
+        assert_eq!(report.summary.inconsistent_edges, 0);
+        assert_eq!(report.summary.faces_without_normals, 0);
+
+        // The second triangle winds against the first around the shared edge.
+        let report = validate("v 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\nf 1 2 3\nf 2 3 4\n", "");
+        assert_eq!(report.summary.inconsistent_edges, 1);
+        assert_eq!(report.summary.flipped_faces, 0);
+        assert_eq!(report.summary.faces_without_normals, 2);
+        assert!(report.has_geometry_errors());
+    }
+
+    #[test]
+    fn out_of_range_uvs() {
+        let report = validate(
+            "v 0 0 0\nv 1 0 0\nv 0 1 0\nvt 0 0\nvt 1 0\nvt 0 1\nvt 0 1.5\n\
+             f 1/1 2/2 3/3\nf 1/1 2/2 3/4\nf 1 2 3\n",
+            "",
+        );
+        assert_eq!(report.summary.faces_with_out_of_range_uvs, 1);
+        assert_eq!(report.summary.faces_without_uvs, 1);
+    }
+
+    #[test]
+    fn materials_without_textures() {
+        let obj = "mtllib model.mtl\nv 0 0 0\nv 1 0 0\nv 0 1 0\nv 1 1 0\n\
+                   f 1 2 3\nusemtl textured\nf 2 4 3\nusemtl plain\nf 1 3 2\nf 2 3 4\n";
+        let mtl = "newmtl textured\nmap_Kd a.png\nnewmtl plain\nKd 1 0 0\n";
+        let report = validate(obj, mtl);
+        let materials: Vec<_> = report
+            .materials
+            .iter()
+            .map(|m| (m.name.as_deref(), m.faces, m.has_texture))
+            .collect();
+        assert_eq!(
+            materials,
+            vec![(Some("textured"), 1, true), (Some("plain"), 2, false)]
+        );
+        assert_eq!(report.faces_without_material, 1);
+
+        let json = report.to_json().unwrap();
+        assert!(json.contains("\"faces_without_material\": 1"));
+        assert!(json.contains("\"has_texture\": false"));
+
+        // Loaded models are triangulated, and materials are counted in triangles as well.
+        let source = source(obj, mtl);
+        let path = Path::new("model.obj");
+        let model = Model::load_obj_from(
+            &source,
+            path,
+            &MeshConversion::default(),
+            |mesh| Ok(mesh.clone()),
+            |m| Ok(MaterialDescription::from_obj(&m, path)),
+        )
+        .unwrap();
+        let report = validate_model(&model);
+        assert_eq!(report.summary.triangles, 4);
+        assert_eq!(report.materials[0].faces, 1);
+        assert_eq!(report.materials[1].faces, 2);
+        assert!(report.materials[0].has_texture);
+        assert_eq!(report.faces_without_material, 1);
+    }
+}
@@ -0,0 +1,406 @@
+//! Contains the scene graph.
+
+use crate::common::model::Model;
+
+use anyhow::{bail, Result};
+use ultraviolet::{Mat4, Rotor3, Vec3};
+
+/// Represents a transform composed of translation, rotation and scale.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    /// Translation
+    pub translation: Vec3,
+
+    /// Rotation
+    pub rotation: Rotor3,
+
+    /// Scale along each axis
+    pub scale: Vec3,
+}
+
+impl Transform {
+    /// Creates an identity transform.
+    pub fn identity() -> Transform {
+        Transform {
+            translation: Vec3::zero(),
+            rotation: Rotor3::identity(),
+            scale: Vec3::one(),
+        }
+    }
+
+    /// Creates a transform with translation only.
+    pub fn from_translation(translation: Vec3) -> Transform {
+        Transform {
+            translation,
+            ..Transform::identity()
+        }
+    }
+
+    /// Converts into a matrix, which applies scale, rotation and translation in this order.
+    pub fn to_matrix(&self) -> Mat4 {
+        Mat4::from_translation(self.translation)
+            * self.rotation.into_matrix().into_homogeneous()
+            * Mat4::from_nonuniform_scale(self.scale)
+    }
+}
+
+impl Default for Transform {
+    fn default() -> Transform {
+        Transform::identity()
+    }
+}
+
+/// The handle of a node in `Scene`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct NodeId(usize);
+
+/// The handle of a model in `Scene`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ModelId(usize);
+
+/// Represents a node in the scene graph.
+#[derive(Debug, Clone)]
+pub struct Node {
+    name: Option<Box<str>>,
+    transform: Transform,
+    world_matrix: Mat4,
+    parent: Option<NodeId>,
+    children: Vec<NodeId>,
+    model: Option<ModelId>,
+}
+
+impl Node {
+    /// The name of this node.
+    pub fn name(&self) -> Option<&str> {
+        self.name.as_deref()
+    }
+
+    /// The local transform relative to the parent.
+    pub fn transform(&self) -> &Transform {
+        &self.transform
+    }
+
+    /// The world matrix, calculated by `Scene::update_world_matrices`.
+    pub fn world_matrix(&self) -> &Mat4 {
+        &self.world_matrix
+    }
+
+    /// The parent node.
+    pub fn parent(&self) -> Option<NodeId> {
+        self.parent
+    }
+
+    /// The child nodes.
+    pub fn children(&self) -> &[NodeId] {
+        &self.children
+    }
+
+    /// The model placed at this node.
+    pub fn model(&self) -> Option<ModelId> {
+        self.model
+    }
+}
+
+/// Represents a scene graph; nodes hold transforms and references to shared models.
+pub struct Scene<VG, M> {
+    models: Vec<Model<VG, M>>,
+    nodes: Vec<Node>,
+    roots: Vec<NodeId>,
+}
+
+impl<VG, M> Scene<VG, M> {
+    /// Creates an empty scene.
+    pub fn new() -> Scene<VG, M> {
+        Scene {
+            models: vec![],
+            nodes: vec![],
+            roots: vec![],
+        }
+    }
+
+    /// Adds a model, which can be placed at any number of nodes.
+    pub fn add_model(&mut self, model: Model<VG, M>) -> ModelId {
+        self.models.push(model);
+        ModelId(self.models.len() - 1)
+    }
+
+    /// Gets the model.
+    pub fn model(&self, id: ModelId) -> &Model<VG, M> {
+        &self.models[id.0]
+    }
+
+    /// Adds a node.
+    /// # Parameters
+    /// * `parent`: parent node, or `None` for a root node
+    /// * `name`: node name
+    /// * `transform`: local transform
+    /// * `model`: model placed at this node
+    pub fn add_node(
+        &mut self,
+        parent: Option<NodeId>,
+        name: Option<&str>,
+        transform: Transform,
+        model: Option<ModelId>,
+    ) -> NodeId {
+        let id = NodeId(self.nodes.len());
+        let world_matrix = match parent {
+            Some(p) => self.nodes[p.0].world_matrix * transform.to_matrix(),
+            None => transform.to_matrix(),
+        };
+        self.nodes.push(Node {
+            name: name.map(|n| n.into()),
+            transform,
+            world_matrix,
+            parent,
+            children: vec![],
+            model,
+        });
+
+        match parent {
+            Some(p) => self.nodes[p.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        id
+    }
+
+    /// Gets the node.
+    pub fn node(&self, id: NodeId) -> &Node {
+        &self.nodes[id.0]
+    }
+
+    /// Finds the first node with the name.
+    pub fn find_node(&self, name: &str) -> Option<NodeId> {
+        self.nodes
+            .iter()
+            .position(|n| n.name() == Some(name))
+            .map(NodeId)
+    }
+
+    /// The root nodes.
+    pub fn roots(&self) -> &[NodeId] {
+        &self.roots
+    }
+
+    /// Gets the mutable local transform.
+    /// World matrices are not updated until `update_world_matrices` is called.
+    pub fn transform_mut(&mut self, id: NodeId) -> &mut Transform {
+        &mut self.nodes[id.0].transform
+    }
+
+    /// Sets the model placed at the node.
+    pub fn set_model(&mut self, id: NodeId, model: Option<ModelId>) {
+        self.nodes[id.0].model = model;
+    }
+
+    /// Moves the node under another parent.
+    /// Fails if the new parent is the node itself or one of its descendants.
+    pub fn set_parent(&mut self, id: NodeId, parent: Option<NodeId>) -> Result<()> {
+        let mut ancestor = parent;
+        while let Some(a) = ancestor {
+            if a == id {
+                bail!("The node cannot be a descendant of itself");
+            }
+            ancestor = self.nodes[a.0].parent;
+        }
+
+        match self.nodes[id.0].parent {
+            Some(p) => self.nodes[p.0].children.retain(|&c| c != id),
+            None => self.roots.retain(|&c| c != id),
+        }
+        match parent {
+            Some(p) => self.nodes[p.0].children.push(id),
+            None => self.roots.push(id),
+        }
+        self.nodes[id.0].parent = parent;
+        Ok(())
+    }
+
+    /// Propagates local transforms and recalculates all world matrices.
+    pub fn update_world_matrices(&mut self) {
+        let mut stack: Vec<_> = self.roots.iter().map(|&r| (r, Mat4::identity())).collect();
+        while let Some((id, parent_matrix)) = stack.pop() {
+            let node = &mut self.nodes[id.0];
+            node.world_matrix = parent_matrix * node.transform.to_matrix();
+
+            let world_matrix = node.world_matrix;
+            stack.extend(node.children.iter().map(|&c| (c, world_matrix)));
+        }
+    }
+
+    /// Iterates all placed models with their world matrices.
+    pub fn instances(&self) -> Instances<'_, VG, M> {
+        Instances {
+            scene: self,
+            index: 0,
+        }
+    }
+}
+
+impl<VG, M> Default for Scene<VG, M> {
+    fn default() -> Scene<VG, M> {
+        Scene::new()
+    }
+}
+
+/// The iterator adaptor for `Scene::instances`.
+pub struct Instances<'a, VG, M> {
+    scene: &'a Scene<VG, M>,
+    index: usize,
+}
+
+impl<'a, VG, M> Iterator for Instances<'a, VG, M> {
+    type Item = (&'a Mat4, &'a Model<VG, M>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.index < self.scene.nodes.len() {
+            let node = &self.scene.nodes[self.index];
+            self.index += 1;
+            if let Some(model) = node.model {
+                return Some((&node.world_matrix, &self.scene.models[model.0]));
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::{asset::MemorySource, mesh::MeshConversion};
+
+    use std::{f32::consts::FRAC_PI_2, path::Path};
+
+    /// Loads a model of a single triangle.
+    fn triangle() -> Model<(), ()> {
+        let mut source = MemorySource::new();
+        source.insert(
+            "triangle.obj",
+            b"v 0 0 0\nv 1 0 0\nv 0 1 0\nf 1 2 3\n".to_vec(),
+        );
+        Model::load_obj_from(
+            &source,
+            Path::new("triangle.obj"),
+            &MeshConversion::default(),
+            |_| Ok(()),
+            |_| Ok(()),
+        )
+        .expect("Failed to load the model")
+    }
+
+    fn origin(scene: &Scene<(), ()>, id: NodeId) -> Vec3 {
+        scene.node(id).world_matrix().transform_point3(Vec3::zero())
+    }
+
+    fn assert_point(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).mag() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let mut scene = Scene::<(), ()>::new();
+        let a = scene.add_node(None, Some("a"), Transform::identity(), None);
+        let b = scene.add_node(Some(a), Some("b"), Transform::identity(), None);
+        let c = scene.add_node(Some(b), Some("c"), Transform::identity(), None);
+
+        assert!(scene.set_parent(a, Some(a)).is_err());
+        assert!(scene.set_parent(a, Some(c)).is_err());
+        assert!(scene.set_parent(b, Some(c)).is_err());
+        assert_eq!(scene.roots(), &[a]);
+        assert_eq!(scene.node(a).children(), &[b]);
+        assert_eq!(scene.node(b).children(), &[c]);
+        assert_eq!(scene.node(a).parent(), None);
+
+        // Moving a node under its ancestor or to the roots is allowed.
+        scene.set_parent(c, Some(a)).unwrap();
+        assert_eq!(scene.node(a).children(), &[b, c]);
+        assert!(scene.node(b).children().is_empty());
+        scene.set_parent(b, None).unwrap();
+        assert_eq!(scene.roots(), &[a, b]);
+        assert_eq!(scene.node(b).parent(), None);
+        assert_eq!(scene.find_node("c"), Some(c));
+        assert_eq!(scene.find_node("d"), None);
+    }
+
+    #[test]
+    fn propagates_world_matrices() {
+        let mut scene = Scene::<(), ()>::new();
+        let root = scene.add_node(
+            None,
+            None,
+            Transform::from_translation(Vec3::new(1.0, 0.0, 0.0)),
+            None,
+        );
+        let middle = scene.add_node(
+            Some(root),
+            None,
+            Transform {
+                rotation: Rotor3::from_rotation_xy(FRAC_PI_2),
+                scale: Vec3::broadcast(2.0),
+                ..Transform::identity()
+            },
+            None,
+        );
+        let leaf = scene.add_node(
+            Some(middle),
+            None,
+            Transform::from_translation(Vec3::new(1.0, 0.0, 0.0)),
+            None,
+        );
+
+        // The leaf offset is scaled and rotated from +X to +Y by the middle node.
+        assert_point(origin(&scene, middle), Vec3::new(1.0, 0.0, 0.0));
+        assert_point(origin(&scene, leaf), Vec3::new(1.0, 2.0, 0.0));
+
+        // Local transforms take effect after the update.
+        scene.transform_mut(root).translation = Vec3::new(0.0, 0.0, 5.0);
+        assert_point(origin(&scene, leaf), Vec3::new(1.0, 2.0, 0.0));
+        scene.update_world_matrices();
+        assert_point(origin(&scene, leaf), Vec3::new(0.0, 2.0, 5.0));
+
+        scene.set_parent(leaf, Some(root)).unwrap();
+        scene.update_world_matrices();
+        assert_point(origin(&scene, leaf), Vec3::new(1.0, 0.0, 5.0));
+    }
+
+    #[test]
+    fn instances_share_models() {
+        let mut scene = Scene::new();
+        let shared = scene.add_model(triangle());
+        let other = scene.add_model(triangle());
+        let first = scene.add_node(
+            None,
+            None,
+            Transform::from_translation(Vec3::new(1.0, 0.0, 0.0)),
+            Some(shared),
+        );
+        let empty = scene.add_node(None, None, Transform::identity(), None);
+        scene.add_node(
+            Some(empty),
+            None,
+            Transform::from_translation(Vec3::new(2.0, 0.0, 0.0)),
+            Some(shared),
+        );
+        scene.add_node(Some(first), None, Transform::identity(), Some(other));
+
+        let instances: Vec<_> = scene.instances().collect();
+        assert_eq!(instances.len(), 3);
+        let translations: Vec<_> = instances
+            .iter()
+            .map(|(matrix, _)| matrix.transform_point3(Vec3::zero()).x)
+            .collect();
+        assert_eq!(translations, vec![1.0, 2.0, 1.0]);
+        assert!(std::ptr::eq(instances[0].1, scene.model(shared)));
+        assert!(std::ptr::eq(instances[1].1, scene.model(shared)));
+        assert!(std::ptr::eq(instances[2].1, scene.model(other)));
+
+        // Removing the model from a node removes its instance.
+        scene.set_model(first, None);
+        assert_eq!(scene.instances().count(), 2);
+    }
+}
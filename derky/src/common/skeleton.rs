@@ -0,0 +1,353 @@
+//! Contains skeletons, poses and skinning.
+
+use crate::common::{mesh::VertexStream, scene::Transform};
+
+use anyhow::{bail, Result};
+use ultraviolet::{Mat4, Vec3, Vec4};
+
+/// The maximum number of joints influencing a vertex.
+pub const MAX_JOINT_INFLUENCES: usize = 4;
+
+/// Represents a joint of a skeleton.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Joint {
+    /// Joint name
+    pub name: Option<Box<str>>,
+
+    /// Index of the parent joint, which must precede this joint
+    pub parent: Option<usize>,
+
+    /// Local transform in the rest pose
+    pub rest: Transform,
+
+    /// Matrix which transforms the bind-pose mesh into the joint space
+    pub inverse_bind_matrix: Mat4,
+}
+
+/// Represents a hierarchy of joints.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skeleton {
+    joints: Box<[Joint]>,
+}
+
+impl Skeleton {
+    /// Creates a skeleton. Parents must precede their children.
+    pub fn new(joints: Vec<Joint>) -> Result<Skeleton> {
+        for (i, joint) in joints.iter().enumerate() {
+            match joint.parent {
+                Some(parent) if parent >= i => {
+                    bail!("The parent of joint {} must precede it", i);
+                }
+                _ => (),
+            }
+        }
+
+        Ok(Skeleton {
+            joints: joints.into_boxed_slice(),
+        })
+    }
+
+    /// The joints.
+    pub fn joints(&self) -> &[Joint] {
+        &self.joints
+    }
+
+    /// Finds the joint by its name.
+    pub fn find_joint(&self, name: &str) -> Option<usize> {
+        self.joints
+            .iter()
+            .position(|j| j.name.as_deref() == Some(name))
+    }
+
+    /// Creates a pose in which all joints are at rest.
+    pub fn rest_pose(&self) -> Pose {
+        Pose {
+            transforms: self.joints.iter().map(|j| j.rest).collect(),
+        }
+    }
+}
+
+/// Represents local transforms of all joints of a skeleton.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pose {
+    transforms: Box<[Transform]>,
+}
+
+impl Pose {
+    /// The local transforms of joints.
+    pub fn transforms(&self) -> &[Transform] {
+        &self.transforms
+    }
+
+    /// The mutable local transforms of joints.
+    pub fn transforms_mut(&mut self) -> &mut [Transform] {
+        &mut self.transforms
+    }
+
+    /// Calculates the matrices which transform each joint space into the skeleton space.
+    pub fn global_matrices(&self, skeleton: &Skeleton) -> Vec<Mat4> {
+        let mut matrices: Vec<Mat4> = Vec::with_capacity(self.transforms.len());
+        for (transform, joint) in self.transforms.iter().zip(skeleton.joints.iter()) {
+            let local = transform.to_matrix();
+            let global = match joint.parent {
+                Some(parent) => matrices[parent] * local,
+                None => local,
+            };
+            matrices.push(global);
+        }
+        matrices
+    }
+
+    /// Calculates the skinning matrices, which transform the bind-pose mesh into this pose.
+    pub fn joint_matrices(&self, skeleton: &Skeleton) -> Vec<Mat4> {
+        self.global_matrices(skeleton)
+            .into_iter()
+            .zip(skeleton.joints.iter())
+            .map(|(global, joint)| global * joint.inverse_bind_matrix)
+            .collect()
+    }
+}
+
+/// Represents joint influences of each vertex.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Skin {
+    joints: Box<[[u16; MAX_JOINT_INFLUENCES]]>,
+    weights: Box<[Vec4]>,
+}
+
+impl Skin {
+    /// Creates a skin. Weights are normalized so that they sum up to 1.
+    pub fn new(joints: Vec<[u16; MAX_JOINT_INFLUENCES]>, weights: Vec<Vec4>) -> Result<Skin> {
+        if joints.len() != weights.len() {
+            bail!("Joints and weights must have the same length");
+        }
+
+        let mut weights = weights;
+        for (i, weight) in weights.iter_mut().enumerate() {
+            let sum = weight.x + weight.y + weight.z + weight.w;
+            if sum <= 0.0 || weight.as_slice().iter().any(|&w| w < 0.0) {
+                bail!("Vertex {} has invalid weights", i);
+            }
+            *weight /= sum;
+        }
+
+        Ok(Skin {
+            joints: joints.into_boxed_slice(),
+            weights: weights.into_boxed_slice(),
+        })
+    }
+
+    /// The joint indices of each vertex.
+    pub fn joints(&self) -> &[[u16; MAX_JOINT_INFLUENCES]] {
+        &self.joints
+    }
+
+    /// The normalized joint weights of each vertex.
+    pub fn weights(&self) -> &[Vec4] {
+        &self.weights
+    }
+
+    /// The number of vertices.
+    pub fn vertex_count(&self) -> usize {
+        self.joints.len()
+    }
+
+    /// Deforms vertices with linear blend skinning on the CPU.
+    ///
+    /// This is the reference implementation for shaders; the blended matrix is applied
+    /// to normals and tangents as well, so non-uniform scales are not corrected.
+    /// # Parameters
+    /// * `joint_matrices`: skinning matrices from `Pose::joint_matrices`
+    /// * `source`: vertices in the bind pose
+    /// * `destination`: deformed vertices, resized to the same length as `source`
+    pub fn apply(
+        &self,
+        joint_matrices: &[Mat4],
+        source: &VertexStream,
+        destination: &mut VertexStream,
+    ) -> Result<()> {
+        source.validate()?;
+        if source.len() != self.vertex_count() {
+            bail!("The vertex count does not match the skin");
+        }
+        if let Some(&joint) = self.joints.iter().flatten().max() {
+            if joint as usize >= joint_matrices.len() {
+                bail!("Joint {} has no matrix", joint);
+            }
+        }
+
+        destination.positions.clear();
+        destination.normals.clear();
+        destination.tangents.clear();
+        for (i, (joints, weights)) in self.joints.iter().zip(self.weights.iter()).enumerate() {
+            let mut matrix = Mat4::from([0.0; 16]);
+            for (&joint, &weight) in joints.iter().zip(weights.as_slice()) {
+                if weight > 0.0 {
+                    matrix += joint_matrices[joint as usize] * weight;
+                }
+            }
+
+            let tangent = source.tangents[i];
+            let skinned_tangent = matrix
+                .transform_vec3(Vec3::new(tangent.x, tangent.y, tangent.z))
+                .normalized();
+            destination
+                .positions
+                .push(matrix.transform_point3(source.positions[i]));
+            destination
+                .normals
+                .push(matrix.transform_vec3(source.normals[i]).normalized());
+            destination.tangents.push(Vec4::new(
+                skinned_tangent.x,
+                skinned_tangent.y,
+                skinned_tangent.z,
+                tangent.w,
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::consts::FRAC_PI_2;
+    use ultraviolet::Rotor3;
+
+    fn assert_vec3(actual: Vec3, expected: Vec3) {
+        assert!(
+            (actual - expected).mag() < 1e-5,
+            "{:?} != {:?}",
+            actual,
+            expected
+        );
+    }
+
+    /// A chain of three joints standing along +Y with unit spacing.
+    fn chain() -> Skeleton {
+        let joint = |parent, y: f32| Joint {
+            name: None,
+            parent,
+            rest: Transform::from_translation(Vec3::new(0.0, 1.0, 0.0)),
+            inverse_bind_matrix: Mat4::from_translation(Vec3::new(0.0, -y, 0.0)),
+        };
+        Skeleton::new(vec![
+            joint(None, 1.0),
+            joint(Some(0), 2.0),
+            joint(Some(1), 3.0),
+        ])
+        .expect("Invalid skeleton")
+    }
+
+    #[test]
+    fn rejects_unordered_parents() {
+        let joint = |parent| Joint {
+            name: None,
+            parent,
+            rest: Transform::identity(),
+            inverse_bind_matrix: Mat4::identity(),
+        };
+        assert!(Skeleton::new(vec![joint(Some(1)), joint(None)]).is_err());
+        assert!(Skeleton::new(vec![joint(Some(0))]).is_err());
+    }
+
+    #[test]
+    fn bind_pose_is_identity() {
+        let skeleton = chain();
+        let pose = skeleton.rest_pose();
+
+        let globals = pose.global_matrices(&skeleton);
+        for (i, global) in globals.iter().enumerate() {
+            assert_vec3(
+                global.transform_point3(Vec3::zero()),
+                Vec3::new(0.0, i as f32 + 1.0, 0.0),
+            );
+        }
+        for matrix in pose.joint_matrices(&skeleton) {
+            for (actual, expected) in matrix.as_slice().iter().zip(Mat4::identity().as_slice()) {
+                assert!((actual - expected).abs() < 1e-6, "{:?}", matrix);
+            }
+        }
+    }
+
+    #[test]
+    fn single_rotated_joint() {
+        let skeleton = chain();
+        let mut pose = skeleton.rest_pose();
+        pose.transforms_mut()[1].rotation = Rotor3::from_rotation_xy(FRAC_PI_2);
+        let matrices = pose.joint_matrices(&skeleton);
+
+        // The parent stays, the rotated joint pivots in place, and the child follows it.
+        // A quarter turn in the XY plane carries +Y to -X.
+        let position = |matrix: &Mat4, point: Vec3| matrix.transform_point3(point);
+        let rotated = Vec3::new(-1.0, 2.0, 0.0);
+        assert_vec3(
+            position(&matrices[0], Vec3::new(0.0, 2.0, 0.0)),
+            Vec3::new(0.0, 2.0, 0.0),
+        );
+        assert_vec3(
+            position(&matrices[1], Vec3::new(0.0, 2.0, 0.0)),
+            Vec3::new(0.0, 2.0, 0.0),
+        );
+        assert_vec3(position(&matrices[1], Vec3::new(0.0, 3.0, 0.0)), rotated);
+        assert_vec3(position(&matrices[2], Vec3::new(0.0, 3.0, 0.0)), rotated);
+
+        let skin = Skin::new(
+            vec![[0, 0, 0, 0], [1, 0, 0, 0], [1, 0, 0, 0], [0, 1, 0, 0]],
+            vec![
+                Vec4::new(1.0, 0.0, 0.0, 0.0),
+                Vec4::new(1.0, 0.0, 0.0, 0.0),
+                Vec4::new(2.0, 0.0, 0.0, 0.0),
+                Vec4::new(1.0, 1.0, 0.0, 0.0),
+            ],
+        )
+        .expect("Invalid skin");
+        let source = VertexStream {
+            positions: vec![
+                Vec3::new(0.0, 2.0, 0.0),
+                Vec3::new(0.0, 3.0, 0.0),
+                Vec3::new(0.0, 3.0, 0.0),
+                Vec3::new(0.0, 3.0, 0.0),
+            ],
+            normals: vec![Vec3::unit_z(); 4],
+            tangents: vec![Vec4::new(0.0, 1.0, 0.0, -1.0); 4],
+        };
+        let mut destination = VertexStream::default();
+        skin.apply(&matrices, &source, &mut destination)
+            .expect("Failed to skin");
+
+        assert_eq!(skin.weights()[2], Vec4::new(1.0, 0.0, 0.0, 0.0));
+        assert_vec3(destination.positions[0], source.positions[0]);
+        assert_vec3(destination.positions[1], rotated);
+        assert_vec3(destination.positions[2], rotated);
+        assert_vec3(
+            destination.positions[3],
+            (Vec3::new(0.0, 3.0, 0.0) + rotated) * 0.5,
+        );
+        assert_vec3(destination.normals[1], Vec3::unit_z());
+        let tangent = destination.tangents[1];
+        assert_vec3(
+            Vec3::new(tangent.x, tangent.y, tangent.z),
+            (rotated - Vec3::new(0.0, 2.0, 0.0)).normalized(),
+        );
+        assert_eq!(tangent.w, -1.0);
+    }
+
+    #[test]
+    fn skin_rejects_missing_joints() {
+        let skin = Skin::new(vec![[3, 0, 0, 0]], vec![Vec4::new(1.0, 0.0, 0.0, 0.0)])
+            .expect("Invalid skin");
+        let source = VertexStream {
+            positions: vec![Vec3::zero()],
+            normals: vec![Vec3::unit_z()],
+            tangents: vec![Vec4::new(1.0, 0.0, 0.0, 1.0)],
+        };
+        let matrices = chain().rest_pose().joint_matrices(&chain());
+        assert!(skin
+            .apply(&matrices, &source, &mut VertexStream::default())
+            .is_err());
+        assert!(Skin::new(vec![[0, 0, 0, 0]], vec![Vec4::zero()]).is_err());
+    }
+}
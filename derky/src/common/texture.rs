@@ -1,35 +1,237 @@
 //! Contains texture image operations.
 
-use std::{marker::PhantomData, path::Path};
+mod atlas;
+mod compress;
+mod cubemap;
+mod dds;
+mod export;
+mod ktx2;
+mod mipmap;
+mod pfm;
+mod resize;
+mod rgbe;
+mod specular;
 
-use anyhow::{bail, Result};
-use exr::prelude::rgba_image::*;
+pub use atlas::{AtlasBuilder, AtlasEntry, AtlasOptions, TextureAtlas, UvTransform};
+pub use compress::{
+    decode_compressed_image, encode_dds_image, encode_ktx2_image, load_compressed_image,
+    load_compressed_image_from, save_dds_image, save_ktx2_image, BlockFormat, CompressedImage,
+};
+pub use cubemap::{equirect_direction, equirect_uv, CubeFace, CubeMap};
+pub use export::{
+    encode_exr_image, encode_pfm_image, encode_png_image, save_exr_image, save_pfm_image,
+    save_png_image, ExrCompression, ExrOptions, ExrPrecision,
+};
+pub use mipmap::{mip_level_count, MipChain, MipmapFilter, MipmapOptions};
+pub use resize::{ResizeFilter, ResizeMode, ResizeOptions};
+pub use specular::{
+    brdf_lut, integrate_brdf, prefilter_environment, PrefilterOptions, PrefilteredEnvironment,
+};
+
+use crate::common::asset::{AssetSource, DirectorySource};
+
+use std::{io::Cursor, marker::PhantomData, path::Path};
+
+use anyhow::{bail, format_err, Result};
+use exr::prelude::simple_image::{read_options, Image as ExrImage, Layer as ExrLayer, Samples};
 use image::{
-    imageops::resize, imageops::FilterType, GenericImageView, Primitive, Rgba as ImageRgba,
+    imageops::resize, imageops::FilterType, GenericImageView, Luma, LumaA, Pixel, Primitive,
+    Rgb as ImageRgb, Rgba as ImageRgba,
 };
-use log::{debug, info};
+use log::{debug, info, warn};
+
+/// The magic number of OpenEXR files.
+const EXR_MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
 
 /// The trait implemented by channel meta-types.
 pub trait Channels {
+    /// Number of channels per pixel
     const CHANNELS: usize;
+
+    /// Whether the last channel is alpha
+    const ALPHA: bool;
 }
 
-/// Indicates that the image has 2 channels per pixel.
+/// Associates channel meta-types with pixel types of `image`.
+pub trait ChannelPixel<T: Primitive>: Channels {
+    /// The pixel type
+    type Pixel: Pixel<Subpixel = T> + 'static;
+}
+
+/// The trait implemented by element types of images.
+pub trait ImageElement: 'static + Copy + PartialEq {
+    /// The value of full intensity, which means opaque in alpha channels.
+    const MAX: Self;
+
+    /// Converts into a float, where `MAX` is 1.
+    fn to_f32(self) -> f32;
+
+    /// Converts from a float, where 1 is `MAX`. Integers are clamped and rounded.
+    fn from_f32(value: f32) -> Self;
+}
+
+impl ImageElement for u8 {
+    const MAX: u8 = u8::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 255.0
+    }
+
+    fn from_f32(value: f32) -> u8 {
+        (value.clamp(0.0, 1.0) * 255.0).round() as u8
+    }
+}
+
+impl ImageElement for u16 {
+    const MAX: u16 = u16::MAX;
+
+    fn to_f32(self) -> f32 {
+        self as f32 / 65535.0
+    }
+
+    fn from_f32(value: f32) -> u16 {
+        (value.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+}
+
+impl ImageElement for f32 {
+    const MAX: f32 = 1.0;
+
+    fn to_f32(self) -> f32 {
+        self
+    }
+
+    fn from_f32(value: f32) -> f32 {
+        value
+    }
+}
+
+/// Indicates that the image has 1 channel (luminance or single data) per pixel.
+pub enum R {}
+impl Channels for R {
+    const CHANNELS: usize = 1;
+    const ALPHA: bool = false;
+}
+impl<T: 'static + Primitive> ChannelPixel<T> for R {
+    type Pixel = Luma<T>;
+}
+
+/// Indicates that the image has 2 channels of independent data per pixel.
+///
+/// Neither channel is alpha, so both are filtered and converted as colors.
+/// It maps to `LumaA` only to interoperate with `image`.
 pub enum Rg {}
 impl Channels for Rg {
     const CHANNELS: usize = 2;
+    const ALPHA: bool = false;
+}
+impl<T: 'static + Primitive> ChannelPixel<T> for Rg {
+    type Pixel = LumaA<T>;
 }
 
-/// Indicates that the image has 2 channels per pixel.
+/// Indicates that the image has 3 channels per pixel.
 pub enum Rgb {}
 impl Channels for Rgb {
     const CHANNELS: usize = 3;
+    const ALPHA: bool = false;
+}
+impl<T: 'static + Primitive> ChannelPixel<T> for Rgb {
+    type Pixel = ImageRgb<T>;
 }
 
-/// Indicates that the image has 2 channels per pixel.
+/// Indicates that the image has 4 channels per pixel.
 pub enum Rgba {}
 impl Channels for Rgba {
     const CHANNELS: usize = 4;
+    const ALPHA: bool = true;
+}
+impl<T: 'static + Primitive> ChannelPixel<T> for Rgba {
+    type Pixel = ImageRgba<T>;
+}
+
+/// The matrix converting linear Rec.709 RGB into Rec.2020, from ITU-R BT.2087.
+const REC709_TO_REC2020: [[f32; 3]; 3] = [
+    [0.627_404, 0.329_283, 0.043_313],
+    [0.069_097, 0.919_540, 0.011_362],
+    [0.016_391, 0.088_013, 0.895_595],
+];
+
+/// The matrix converting linear Rec.2020 RGB into Rec.709; the inverse of `REC709_TO_REC2020`.
+const REC2020_TO_REC709: [[f32; 3]; 3] = [
+    [1.660_491, -0.587_641, -0.072_850],
+    [-0.124_550, 1.132_9, -0.008_349],
+    [-0.018_151, -0.100_579, 1.118_73],
+];
+
+/// Represents how color values of an image are encoded.
+///
+/// Alpha is always linear. Data textures such as normal maps are tagged as `Linear`,
+/// and should not be converted into other color spaces.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// sRGB transfer function with Rec.709 primaries; for colors such as albedo and emissive
+    Srgb,
+
+    /// Linear values with Rec.709 primaries; for HDR images, and data such as normals,
+    /// roughness and occlusion
+    Linear,
+
+    /// Linear values with Rec.2020 primaries; for wide gamut HDR images
+    LinearRec2020,
+}
+
+/// Converts a sRGB-encoded value into linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Converts a linear value into sRGB-encoded.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Detects the color space of linear images from chromaticities of red, green and blue
+/// primaries. Primaries other than Rec.709 and Rec.2020 are treated as Rec.709.
+fn linear_color_space(primaries: [(f32, f32); 3]) -> ColorSpace {
+    let matches = |expected: [(f32, f32); 3]| {
+        primaries
+            .iter()
+            .zip(&expected)
+            .all(|(p, e)| (p.0 - e.0).abs() < 0.02 && (p.1 - e.1).abs() < 0.02)
+    };
+    if matches([(0.708, 0.292), (0.170, 0.797), (0.131, 0.046)]) {
+        ColorSpace::LinearRec2020
+    } else {
+        if !matches([(0.640, 0.330), (0.300, 0.600), (0.150, 0.060)]) {
+            warn!("Unsupported primaries {:?}; assumed Rec.709", primaries);
+        }
+        ColorSpace::Linear
+    }
+}
+
+/// Converts a linear Rec.709 color into Rec.2020.
+pub fn rec709_to_rec2020(color: [f32; 3]) -> [f32; 3] {
+    transform_color(&REC709_TO_REC2020, color)
+}
+
+/// Converts a linear Rec.2020 color into Rec.709.
+/// Colors outside the Rec.709 gamut have negative components.
+pub fn rec2020_to_rec709(color: [f32; 3]) -> [f32; 3] {
+    transform_color(&REC2020_TO_REC709, color)
+}
+
+/// Multiplies a color by the matrix.
+fn transform_color(matrix: &[[f32; 3]; 3], color: [f32; 3]) -> [f32; 3] {
+    let row = |r: &[f32; 3]| r[0] * color[0] + r[1] * color[1] + r[2] * color[2];
+    [row(&matrix[0]), row(&matrix[1]), row(&matrix[2])]
 }
 
 /// Represents a RGBA image data with raw element array and dimensions.
@@ -37,9 +239,22 @@ pub struct ImageData<T: Copy, C: Channels> {
     data: Box<[T]>,
     width: usize,
     height: usize,
+    color_space: ColorSpace,
     _channels: PhantomData<fn() -> C>,
 }
 
+impl<T: Copy, C: Channels> Clone for ImageData<T, C> {
+    fn clone(&self) -> ImageData<T, C> {
+        ImageData {
+            data: self.data.clone(),
+            width: self.width,
+            height: self.height,
+            color_space: self.color_space,
+            _channels: Default::default(),
+        }
+    }
+}
+
 impl<T: 'static + Copy, C: Channels> ImageData<T, C> {
     /// Gets the dimension of this image.
     pub fn dimensions(&self) -> (usize, usize) {
@@ -56,11 +271,27 @@ impl<T: 'static + Copy, C: Channels> ImageData<T, C> {
         self.data
     }
 
-    /// Creates a new image from raw data.
+    /// Gets the color space of this image.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Tags this image with the color space, without converting values.
+    /// Use `to_color_space` to convert values.
+    pub fn with_color_space(self, color_space: ColorSpace) -> ImageData<T, C> {
+        ImageData {
+            color_space,
+            ..self
+        }
+    }
+
+    /// Creates a new image from raw data, tagged as `ColorSpace::Linear`.
     pub fn new(data: &[T], width: usize, height: usize) -> Result<ImageData<T, C>> {
         let length = data.len();
         match length {
-            x if x % C::CHANNELS != 0 => bail!("The length of data is not multiple of 4"),
+            x if x % C::CHANNELS != 0 => {
+                bail!("The length of data is not multiple of {}", C::CHANNELS)
+            }
             x if x / C::CHANNELS < width * height => {
                 bail!("The data is not enough for the dimensions")
             }
@@ -70,6 +301,7 @@ impl<T: 'static + Copy, C: Channels> ImageData<T, C> {
                     data: new_data.into_boxed_slice(),
                     width,
                     height,
+                    color_space: ColorSpace::Linear,
                     _channels: Default::default(),
                 })
             }
@@ -80,18 +312,10 @@ impl<T: 'static + Copy, C: Channels> ImageData<T, C> {
     pub fn resize_to_power_of_2(&self) -> ImageData<T, C>
     where
         T: Primitive,
+        C: ChannelPixel<T>,
     {
-        /// Rounds up to power of 2.
-        fn round(x: usize) -> usize {
-            if x.count_ones() == 1 {
-                x
-            } else {
-                1 << (32 - x.leading_zeros())
-            }
-        };
-
-        let new_width = round(self.width);
-        let new_height = round(self.height);
+        let new_width = self.width.next_power_of_two();
+        let new_height = self.height.next_power_of_two();
 
         let new_image = resize(
             self,
@@ -104,13 +328,144 @@ impl<T: 'static + Copy, C: Channels> ImageData<T, C> {
             data: new_image.into_raw().into_boxed_slice(),
             width: new_width,
             height: new_height,
+            color_space: self.color_space,
             _channels: Default::default(),
         }
     }
 }
 
-impl<T: 'static + Primitive, C: Channels> GenericImageView for ImageData<T, C> {
-    type Pixel = ImageRgba<T>;
+impl<T: ImageElement, C: Channels> ImageData<T, C> {
+    /// Converts values into the color space.
+    ///
+    /// Alpha is kept as it is. Primaries of single and two channel images are not
+    /// converted, since gray colors stay gray in both Rec.709 and Rec.2020.
+    /// Both channels of two channel images are encoded with the transfer function.
+    /// Integer images lose precision, and out of gamut colors are clamped;
+    /// convert float images to keep them.
+    pub fn to_color_space(&self, color_space: ColorSpace) -> ImageData<T, C> {
+        let source = self.color_space;
+        let colors = C::CHANNELS - C::ALPHA as usize;
+        let mut data = Vec::with_capacity(self.data.len());
+        for pixel in self.data.chunks_exact(C::CHANNELS) {
+            let mut color = [0.0; 3];
+            for (value, element) in color.iter_mut().zip(&pixel[..colors]) {
+                *value = element.to_f32();
+                if source == ColorSpace::Srgb {
+                    *value = srgb_to_linear(*value);
+                }
+            }
+
+            if colors == 3 {
+                color = match (source, color_space) {
+                    (ColorSpace::LinearRec2020, ColorSpace::LinearRec2020) => color,
+                    (ColorSpace::LinearRec2020, _) => rec2020_to_rec709(color),
+                    (_, ColorSpace::LinearRec2020) => rec709_to_rec2020(color),
+                    _ => color,
+                };
+            }
+
+            for &value in &color[..colors] {
+                let value = if color_space == ColorSpace::Srgb {
+                    linear_to_srgb(value.max(0.0))
+                } else {
+                    value
+                };
+                data.push(T::from_f32(value));
+            }
+            data.extend_from_slice(&pixel[colors..]);
+        }
+
+        ImageData {
+            data: data.into_boxed_slice(),
+            width: self.width,
+            height: self.height,
+            color_space,
+            _channels: Default::default(),
+        }
+    }
+
+    /// Extracts a channel into a single-channel image.
+    pub fn channel(&self, index: usize) -> Result<ImageData<T, R>> {
+        if index >= C::CHANNELS {
+            bail!(
+                "Channel {} does not exist in {} channels",
+                index,
+                C::CHANNELS
+            );
+        }
+
+        Ok(ImageData {
+            data: self
+                .data
+                .chunks_exact(C::CHANNELS)
+                .map(|p| p[index])
+                .collect(),
+            width: self.width,
+            height: self.height,
+            color_space: self.color_space,
+            _channels: Default::default(),
+        })
+    }
+
+    /// Converts into another channel layout.
+    ///
+    /// Single channel images are treated as luminance between RGB; luminance is
+    /// replicated into RGB, and RGB can be converted into luminance only if it is gray.
+    /// Otherwise channels are kept in order, missing ones are filled with zero,
+    /// and missing alpha is filled with opaque. Conversions which lose information fail;
+    /// channels can be dropped only if they are zero, and alpha only if it is opaque.
+    /// Use `channel` to pick a channel.
+    pub fn convert<D: Channels>(&self) -> Result<ImageData<T, D>> {
+        let source_colors = C::CHANNELS - C::ALPHA as usize;
+        let target_colors = D::CHANNELS - D::ALPHA as usize;
+        let zero = T::from_f32(0.0);
+
+        let mut data = Vec::with_capacity(self.width * self.height * D::CHANNELS);
+        for pixel in self.data.chunks_exact(C::CHANNELS) {
+            let colors = &pixel[..source_colors];
+            let alpha = if C::ALPHA {
+                pixel[source_colors]
+            } else {
+                T::MAX
+            };
+            if !D::ALPHA && alpha != T::MAX {
+                bail!("The image is not opaque");
+            }
+
+            match (source_colors, target_colors) {
+                (1, 3) => data.extend_from_slice(&[colors[0]; 3]),
+                (3, 1) => {
+                    if colors.iter().any(|&c| c != colors[0]) {
+                        bail!("The image is not gray");
+                    }
+                    data.push(colors[0]);
+                }
+                _ => {
+                    let kept = source_colors.min(target_colors);
+                    if colors[kept..].iter().any(|&c| c != zero) {
+                        bail!("The image has values in dropped channels");
+                    }
+                    data.extend_from_slice(&colors[..kept]);
+                    data.extend((kept..target_colors).map(|_| zero));
+                }
+            }
+            if D::ALPHA {
+                data.push(alpha);
+            }
+        }
+
+        Ok(ImageData {
+            data: data.into_boxed_slice(),
+            width: self.width,
+            height: self.height,
+            color_space: self.color_space,
+            _channels: Default::default(),
+        })
+    }
+}
+
+impl<T: 'static + Primitive, C: ChannelPixel<T>> GenericImageView for ImageData<T, C> {
+    type Pixel = C::Pixel;
     type InnerImageView = Self;
 
     fn dimensions(&self) -> (u32, u32) {
@@ -123,12 +478,7 @@ impl<T: 'static + Primitive, C: Channels> GenericImageView for ImageData<T, C> {
 
     fn get_pixel(&self, x: u32, y: u32) -> Self::Pixel {
         let base_index = (y as usize * self.width + x as usize) * C::CHANNELS;
-        ImageRgba([
-            self.data[base_index + 0],
-            self.data[base_index + 1],
-            self.data[base_index + 2],
-            self.data[base_index + 3],
-        ])
+        *C::Pixel::from_slice(&self.data[base_index..(base_index + C::CHANNELS)])
     }
 
     fn inner(&self) -> &Self::InnerImageView {
@@ -138,10 +488,18 @@ impl<T: 'static + Primitive, C: Channels> GenericImageView for ImageData<T, C> {
 
 /// Loads a LDR (PNG, JPEG, and DXT) image.
 pub fn load_ldr_image(filename: impl AsRef<Path>) -> Result<ImageData<u8, Rgba>> {
-    let filename = filename.as_ref();
+    load_ldr_image_from(&DirectorySource::new(""), filename.as_ref())
+}
 
-    debug!("Loading LDR image {:?}", filename);
-    let original_image = image::open(filename)?.into_rgba8();
+/// Loads a LDR (PNG, JPEG, and DXT) image from the asset source.
+pub fn load_ldr_image_from(source: &dyn AssetSource, path: &Path) -> Result<ImageData<u8, Rgba>> {
+    debug!("Loading LDR image {:?}", path);
+    decode_ldr_image(&source.read(path)?)
+}
+
+/// Decodes a LDR (PNG, JPEG, and DXT) image in memory.
+pub fn decode_ldr_image(data: &[u8]) -> Result<ImageData<u8, Rgba>> {
+    let original_image = image::load_from_memory(data)?.into_rgba8();
     let dimensions = original_image.dimensions();
     let data = original_image.into_raw().into_boxed_slice();
 
@@ -150,38 +508,302 @@ pub fn load_ldr_image(filename: impl AsRef<Path>) -> Result<ImageData<u8, Rgba>>
         data,
         width: dimensions.0 as usize,
         height: dimensions.1 as usize,
+        color_space: ColorSpace::Srgb,
         _channels: Default::default(),
     })
 }
 
-/// Loads a HDR (OpenEXR only) image.
+/// Loads a HDR (OpenEXR, Radiance HDR, and PFM) image.
 pub fn load_hdr_image(filename: impl AsRef<Path>) -> Result<ImageData<f32, Rgba>> {
-    let filename = filename.as_ref();
-
-    debug!("Loading HDR image {:?}", filename);
-    let (_, (data, width, height)) = ImageInfo::read_pixels_from_file(
-        filename,
-        read_options::high(),
-        |info| {
-            let w = info.resolution.width();
-            let h = info.resolution.height();
-            let image = vec![0f32; w * h * 4];
-            (image, w, h)
-        },
-        |(image, w, _), pos, pixel| {
-            let base_index = (pos.y() * *w + pos.x()) * 4;
-            let pixel_array: [f32; 4] = pixel.into();
-            for i in 0..4 {
-                image[base_index + i] = pixel_array[i];
+    load_hdr_image_from(&DirectorySource::new(""), filename.as_ref())
+}
+
+/// Loads a HDR (OpenEXR, Radiance HDR, and PFM) image from the asset source.
+pub fn load_hdr_image_from(source: &dyn AssetSource, path: &Path) -> Result<ImageData<f32, Rgba>> {
+    debug!("Loading HDR image {:?}", path);
+    decode_hdr_image(&source.read(path)?)
+}
+
+/// Decodes a HDR (OpenEXR, Radiance HDR, and PFM) image in memory.
+/// The format is detected from the content, not the extension.
+pub fn decode_hdr_image(data: &[u8]) -> Result<ImageData<f32, Rgba>> {
+    let image = if data.starts_with(&EXR_MAGIC) {
+        decode_exr_image(data)?
+    } else if rgbe::is_rgbe(data) {
+        rgbe::decode_rgbe(data)?
+    } else if pfm::is_pfm(data) {
+        pfm::decode_pfm(data)?
+    } else {
+        bail!("Unknown HDR image format");
+    };
+
+    info!(
+        "Loaded successfully; dimensions are {:?}",
+        image.dimensions()
+    );
+    Ok(image)
+}
+
+/// Decodes an OpenEXR image in memory.
+/// The first layer which has R, G, and B channels is decoded.
+fn decode_exr_image(data: &[u8]) -> Result<ImageData<f32, Rgba>> {
+    let image = ExrImage::read_from_buffered(Cursor::new(data), read_options::high())
+        .map_err(|e| format_err!("Failed to decode OpenEXR image: {}", e))?;
+
+    let find_channel = |layer: &ExrLayer, name: &str| {
+        layer
+            .channels
+            .iter()
+            .position(|c| c.name.eq_case_insensitive(name))
+    };
+    let (layer, indices) = image
+        .layers
+        .iter()
+        .find_map(|layer| {
+            let indices = [
+                find_channel(layer, "R")?,
+                find_channel(layer, "G")?,
+                find_channel(layer, "B")?,
+            ];
+            Some((layer, indices))
+        })
+        .ok_or_else(|| format_err!("No RGB layer found"))?;
+    let alpha = find_channel(layer, "A");
+
+    let color_space = match image.attributes.chromaticities {
+        Some(c) => linear_color_space([
+            (c.red.x(), c.red.y()),
+            (c.green.x(), c.green.y()),
+            (c.blue.x(), c.blue.y()),
+        ]),
+        None => ColorSpace::Linear,
+    };
+
+    let (width, height) = (layer.size.width(), layer.size.height());
+    let mut pixels = vec![1.0; width * height * 4];
+    let channels = indices.iter().chain(alpha.iter()).enumerate();
+    for (i, channel) in channels.map(|(i, &c)| (i, &layer.channels[c])) {
+        let (sampling_x, sampling_y) = (channel.sampling.x(), channel.sampling.y());
+        let sampled_width = width / sampling_x;
+        for y in 0..height {
+            for x in 0..width {
+                let index = (y / sampling_y) * sampled_width + x / sampling_x;
+                pixels[(y * width + x) * 4 + i] = match &channel.samples {
+                    Samples::F16(samples) => samples[index].to_f32(),
+                    Samples::F32(samples) => samples[index],
+                    Samples::U32(samples) => samples[index] as f32,
+                };
             }
-        },
-    )?;
+        }
+    }
 
-    info!("Loaded successfully; dimensions are {:?}", (width, height));
     Ok(ImageData {
-        data: data.into_boxed_slice(),
+        data: pixels.into_boxed_slice(),
         width,
         height,
+        color_space,
         _channels: Default::default(),
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hdr_format_detection() {
+        let data: Vec<f32> = (0..6)
+            .flat_map(|i| vec![i as f32 * 0.5, 2.0, 0.25, 1.0])
+            .collect();
+        let image = ImageData::<f32, Rgba>::new(&data, 3, 2).unwrap();
+
+        let exr = encode_exr_image(&image, &ExrOptions::default()).unwrap();
+        assert!(exr.starts_with(&EXR_MAGIC));
+        assert_eq!(decode_hdr_image(&exr).unwrap().data(), image.data());
+
+        // PFM has no alpha channel.
+        let rgb: Vec<f32> = data.chunks_exact(4).flat_map(|p| p[..3].to_vec()).collect();
+        let pfm = encode_pfm_image(&ImageData::<f32, Rgb>::new(&rgb, 3, 2).unwrap()).unwrap();
+        let decoded = decode_hdr_image(&pfm).unwrap();
+        assert_eq!(decoded.dimensions(), (3, 2));
+        assert_eq!(decoded.data(), image.data());
+
+        // Radiance HDR is detected before PFM, and the magic must be at the start.
+        let mut rgbe = b"#?RADIANCE\n\n-Y 1 +X 1\n".to_vec();
+        rgbe.extend_from_slice(&[128, 64, 32, 129]);
+        assert_eq!(
+            decode_hdr_image(&rgbe).unwrap().data(),
+            &[128.5 / 128.0, 64.5 / 128.0, 32.5 / 128.0, 1.0]
+        );
+
+        let mut shifted = vec![b' '];
+        shifted.extend_from_slice(&rgbe);
+        assert!(decode_hdr_image(&shifted).is_err());
+        assert!(decode_hdr_image(&[]).is_err());
+        assert!(decode_hdr_image(b"\x89PNG\r\n\x1a\n").is_err());
+    }
+
+    #[test]
+    fn convert_layouts() {
+        let gray = ImageData::<u8, R>::new(&[10, 20], 2, 1).unwrap();
+        assert_eq!(
+            gray.convert::<Rgb>().unwrap().data(),
+            &[10, 10, 10, 20, 20, 20]
+        );
+        assert_eq!(
+            gray.convert::<Rgba>().unwrap().data(),
+            &[10, 10, 10, 255, 20, 20, 20, 255]
+        );
+        assert_eq!(gray.convert::<Rg>().unwrap().data(), &[10, 0, 20, 0]);
+
+        // Two channels are independent values, not luminance and alpha.
+        let pair = ImageData::<f32, Rg>::new(&[0.25, 0.5, 2.0, 0.0], 2, 1).unwrap();
+        assert_eq!(
+            pair.convert::<Rgb>().unwrap().data(),
+            &[0.25, 0.5, 0.0, 2.0, 0.0, 0.0]
+        );
+        assert_eq!(
+            pair.convert::<Rgba>().unwrap().data(),
+            &[0.25, 0.5, 0.0, 1.0, 2.0, 0.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            pair.convert::<Rgb>()
+                .unwrap()
+                .convert::<Rg>()
+                .unwrap()
+                .data(),
+            pair.data()
+        );
+        assert!(pair.convert::<R>().is_err());
+        let first = ImageData::<f32, Rg>::new(&[0.25, 0.0], 1, 1).unwrap();
+        assert_eq!(first.convert::<R>().unwrap().data(), &[0.25]);
+
+        let color = ImageData::<u8, Rgba>::new(&[10, 20, 30, 255, 40, 40, 40, 128], 2, 1).unwrap();
+        assert_eq!(color.convert::<Rgba>().unwrap().data(), color.data());
+        assert!(color.convert::<Rgb>().is_err());
+        assert!(color.convert::<R>().is_err());
+        assert!(color.convert::<Rg>().is_err());
+        let opaque = ImageData::<u8, Rgba>::new(&[40, 40, 0, 255, 10, 20, 0, 255], 2, 1).unwrap();
+        assert_eq!(
+            opaque.convert::<Rgb>().unwrap().data(),
+            &[40, 40, 0, 10, 20, 0]
+        );
+        assert_eq!(opaque.convert::<Rg>().unwrap().data(), &[40, 40, 10, 20]);
+        assert!(opaque.convert::<R>().is_err());
+        assert_eq!(
+            ImageData::<u8, Rgb>::new(&[40, 40, 40], 1, 1)
+                .unwrap()
+                .convert::<R>()
+                .unwrap()
+                .data(),
+            &[40]
+        );
+    }
+
+    #[test]
+    fn channel_extraction() {
+        let image = ImageData::<u8, Rgba>::new(&[1, 2, 3, 4, 5, 6, 7, 8], 2, 1)
+            .unwrap()
+            .with_color_space(ColorSpace::Srgb);
+        let alpha = image.channel(3).unwrap();
+        assert_eq!(alpha.data(), &[4, 8]);
+        assert_eq!(alpha.dimensions(), (2, 1));
+        assert_eq!(alpha.color_space(), ColorSpace::Srgb);
+        assert!(image.channel(4).is_err());
+
+        let pair = ImageData::<f32, Rg>::new(&[0.25, 0.5, 2.0, 3.0], 2, 1).unwrap();
+        assert_eq!(pair.channel(1).unwrap().data(), &[0.5, 3.0]);
+        assert!(pair.channel(2).is_err());
+    }
+
+    #[test]
+    fn color_space_conversion() {
+        // Alpha is linear and kept as it is.
+        let image = ImageData::<u8, Rgba>::new(&[188, 0, 255, 100], 1, 1)
+            .unwrap()
+            .with_color_space(ColorSpace::Srgb);
+        let linear = image.to_color_space(ColorSpace::Linear);
+        assert_eq!(linear.color_space(), ColorSpace::Linear);
+        assert_eq!(linear.data(), &[128, 0, 255, 100]);
+        assert_eq!(linear.to_color_space(ColorSpace::Srgb).data(), image.data());
+
+        // Both channels of two channel images are colors.
+        let pair = ImageData::<f32, Rg>::new(&[0.5, 0.25], 1, 1).unwrap();
+        let encoded = pair.to_color_space(ColorSpace::Srgb);
+        assert_eq!(encoded.data(), &[linear_to_srgb(0.5), linear_to_srgb(0.25)]);
+
+        // Primaries are converted only for RGB.
+        let red = ImageData::<f32, Rgb>::new(&[1.0, 0.0, 0.0], 1, 1).unwrap();
+        let wide = red.to_color_space(ColorSpace::LinearRec2020);
+        assert_eq!(
+            wide.data(),
+            &[
+                REC709_TO_REC2020[0][0],
+                REC709_TO_REC2020[1][0],
+                REC709_TO_REC2020[2][0]
+            ]
+        );
+        wide.to_color_space(ColorSpace::Linear)
+            .data()
+            .iter()
+            .zip(red.data())
+            .for_each(|(a, b)| assert!((a - b).abs() < 1e-4, "{} != {}", a, b));
+        let gray = ImageData::<f32, R>::new(&[0.5], 1, 1).unwrap();
+        assert_eq!(
+            gray.to_color_space(ColorSpace::LinearRec2020).data(),
+            &[0.5]
+        );
+
+        // Out of range values are kept in float images.
+        let bright = ImageData::<f32, Rgb>::new(&[4.0, 0.5, 0.0], 1, 1).unwrap();
+        let srgb = bright.to_color_space(ColorSpace::Srgb);
+        assert!(srgb.data()[0] > 1.0);
+        assert!((srgb.to_color_space(ColorSpace::Linear).data()[0] - 4.0).abs() < 1e-4);
+    }
+
+    #[test]
+    fn get_pixel_layouts() {
+        let data = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12];
+        let r = ImageData::<u8, R>::new(&data[..4], 2, 2).unwrap();
+        assert_eq!(r.get_pixel(1, 1), Luma([4]));
+        let rg = ImageData::<u8, Rg>::new(&data[..8], 2, 2).unwrap();
+        assert_eq!(rg.get_pixel(0, 1), LumaA([5, 6]));
+        let rgb = ImageData::<u8, Rgb>::new(&data, 2, 2).unwrap();
+        assert_eq!(rgb.get_pixel(1, 0), ImageRgb([4, 5, 6]));
+        let rgba = ImageData::<u8, Rgba>::new(&data, 3, 1).unwrap();
+        assert_eq!(rgba.get_pixel(2, 0), ImageRgba([9, 10, 11, 12]));
+        assert_eq!(GenericImageView::dimensions(&rgba), (3, 1));
+    }
+
+    #[test]
+    fn two_channels_are_filtered_as_colors() {
+        let pair = ImageData::<f32, Rg>::new(&[0.2, 3.0, 0.4, 5.0], 2, 1).unwrap();
+        let options = MipmapOptions {
+            alpha_cutoff: Some(0.5),
+            ..Default::default()
+        };
+        let mips = pair.generate_mipmaps(&options);
+        assert_eq!(mips.level_count(), 2);
+        let level = mips.levels()[1].data();
+        assert!(
+            (level[0] - 0.3).abs() < 1e-6 && (level[1] - 4.0).abs() < 1e-6,
+            "{:?}",
+            level
+        );
+
+        let resized = pair.resize(1, 1, &ResizeOptions::default()).unwrap();
+        let data = resized.data();
+        assert!(
+            (data[0] - 0.3).abs() < 1e-6 && (data[1] - 4.0).abs() < 1e-6,
+            "{:?}",
+            data
+        );
+
+        // The LUT keeps the bias in G when it is stored as RGB(A).
+        let exr = encode_exr_image(&pair, &ExrOptions::default()).unwrap();
+        assert_eq!(
+            decode_hdr_image(&exr).unwrap().data(),
+            &[0.2, 3.0, 0.0, 1.0, 0.4, 5.0, 0.0, 1.0]
+        );
+    }
+}
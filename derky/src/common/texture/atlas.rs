@@ -0,0 +1,447 @@
+//! Contains packing of small textures into atlases.
+
+use super::{Channels, ImageData, ImageElement};
+
+use anyhow::{bail, Result};
+use ultraviolet::Vec2;
+
+/// The handle of an image added to `AtlasBuilder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AtlasEntry(usize);
+
+/// Represents options of atlas packing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AtlasOptions {
+    /// The maximum width and height of the atlas
+    pub max_size: usize,
+
+    /// Texels around each image filled with its edges, so that filtering does not bleed
+    pub padding: usize,
+
+    /// Whether the dimensions of the atlas are powers of 2
+    pub power_of_two: bool,
+}
+
+impl Default for AtlasOptions {
+    fn default() -> AtlasOptions {
+        AtlasOptions {
+            max_size: 4096,
+            padding: 1,
+            power_of_two: true,
+        }
+    }
+}
+
+/// Represents the transform of texture coordinates into an atlas, `uv * scale + offset`.
+///
+/// Coordinates must be in [0, 1]; tiled textures have to wrap them before the transform.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct UvTransform {
+    /// Scale of coordinates
+    pub scale: Vec2,
+
+    /// Offset of coordinates
+    pub offset: Vec2,
+}
+
+impl UvTransform {
+    /// Transforms texture coordinates of the original image into the atlas.
+    pub fn apply(&self, uv: Vec2) -> Vec2 {
+        uv * self.scale + self.offset
+    }
+}
+
+/// Collects images and packs them into a `TextureAtlas`.
+///
+/// Identical images are stored only once, so many 1x1 color textures of materials
+/// share a few texels.
+pub struct AtlasBuilder<T: Copy, C: Channels> {
+    images: Vec<ImageData<T, C>>,
+    entries: Vec<usize>,
+}
+
+impl<T: ImageElement, C: Channels> AtlasBuilder<T, C> {
+    /// Creates an empty builder.
+    pub fn new() -> AtlasBuilder<T, C> {
+        AtlasBuilder {
+            images: vec![],
+            entries: vec![],
+        }
+    }
+
+    /// Adds an image.
+    pub fn add(&mut self, image: &ImageData<T, C>) -> AtlasEntry {
+        let index = match self.images.iter().position(|i| {
+            i.dimensions() == image.dimensions()
+                && i.color_space == image.color_space
+                && i.data() == image.data()
+        }) {
+            Some(index) => index,
+            None => {
+                self.images.push(image.clone());
+                self.images.len() - 1
+            }
+        };
+        self.entries.push(index);
+        AtlasEntry(self.entries.len() - 1)
+    }
+
+    /// Packs added images into an atlas.
+    /// All images must have the same color space.
+    pub fn build(&self, options: &AtlasOptions) -> Result<TextureAtlas<T, C>> {
+        let color_space = match self.images.first() {
+            Some(image) => image.color_space,
+            None => bail!("No images are added"),
+        };
+        if self.images.iter().any(|i| i.color_space != color_space) {
+            bail!("Images have different color spaces");
+        }
+        if self.images.iter().any(|i| i.width == 0 || i.height == 0) {
+            bail!("Empty images cannot be packed");
+        }
+
+        let padded: Vec<_> = self
+            .images
+            .iter()
+            .map(|i| {
+                (
+                    i.width + options.padding * 2,
+                    i.height + options.padding * 2,
+                )
+            })
+            .collect();
+        let Packing {
+            width,
+            height,
+            positions,
+        } = pack(&padded, options)?;
+
+        let mut data = vec![T::from_f32(0.0); width * height * C::CHANNELS];
+        for (image, &(x, y)) in self.images.iter().zip(&positions) {
+            blit(&mut data, width, image, (x, y), options.padding);
+        }
+
+        let atlas_size = Vec2::new(width as f32, height as f32);
+        let transforms = self
+            .entries
+            .iter()
+            .map(|&index| {
+                let image = &self.images[index];
+                let (x, y) = positions[index];
+                let origin = (x + options.padding, y + options.padding);
+                UvTransform {
+                    scale: Vec2::new(image.width as f32, image.height as f32) / atlas_size,
+                    offset: Vec2::new(origin.0 as f32, origin.1 as f32) / atlas_size,
+                }
+            })
+            .collect();
+
+        Ok(TextureAtlas {
+            image: ImageData {
+                data: data.into_boxed_slice(),
+                width,
+                height,
+                color_space,
+                _channels: Default::default(),
+            },
+            transforms,
+        })
+    }
+}
+
+impl<T: ImageElement, C: Channels> Default for AtlasBuilder<T, C> {
+    fn default() -> AtlasBuilder<T, C> {
+        AtlasBuilder::new()
+    }
+}
+
+/// Represents packed images and transforms of their texture coordinates.
+pub struct TextureAtlas<T: Copy, C: Channels> {
+    image: ImageData<T, C>,
+    transforms: Vec<UvTransform>,
+}
+
+impl<T: Copy, C: Channels> TextureAtlas<T, C> {
+    /// The atlas image.
+    pub fn image(&self) -> &ImageData<T, C> {
+        &self.image
+    }
+
+    /// Gets the transform of texture coordinates of the entry.
+    pub fn transform(&self, entry: AtlasEntry) -> UvTransform {
+        self.transforms[entry.0]
+    }
+
+    /// Consumes this instance and gets the atlas image.
+    pub fn into_image(self) -> ImageData<T, C> {
+        self.image
+    }
+}
+
+/// Represents the dimensions of an atlas and the positions of rectangles in it.
+struct Packing {
+    width: usize,
+    height: usize,
+    positions: Vec<(usize, usize)>,
+}
+
+/// Packs rectangles into shelves, from the tallest one.
+fn pack(sizes: &[(usize, usize)], options: &AtlasOptions) -> Result<Packing> {
+    let widest = sizes.iter().map(|s| s.0).max().unwrap_or(1);
+    let area: usize = sizes.iter().map(|s| s.0 * s.1).sum();
+    if widest > options.max_size {
+        bail!("An image is larger than the atlas");
+    }
+
+    let mut order: Vec<_> = (0..sizes.len()).collect();
+    order.sort_by(|&a, &b| {
+        sizes[b]
+            .1
+            .cmp(&sizes[a].1)
+            .then(sizes[b].0.cmp(&sizes[a].0))
+    });
+
+    // Starts from a square of the total area, and widens it until the height fits.
+    let mut width = widest.max((area as f32).sqrt().ceil() as usize);
+    loop {
+        if options.power_of_two {
+            width = width.next_power_of_two();
+        }
+        width = width.min(options.max_size);
+
+        let mut positions = vec![(0, 0); sizes.len()];
+        let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+        for &index in &order {
+            let (w, h) = sizes[index];
+            if x + w > width {
+                x = 0;
+                y += shelf_height;
+                shelf_height = 0;
+            }
+            positions[index] = (x, y);
+            x += w;
+            shelf_height = shelf_height.max(h);
+        }
+        let mut height = y + shelf_height;
+        if options.power_of_two {
+            height = height.next_power_of_two();
+        }
+
+        if height <= width || (width == options.max_size && height <= options.max_size) {
+            return Ok(Packing {
+                width,
+                height,
+                positions,
+            });
+        } else if width == options.max_size {
+            bail!("Images do not fit in the atlas of {}", options.max_size);
+        }
+        width = if options.power_of_two {
+            width * 2
+        } else {
+            width + (width / 4).max(1)
+        };
+    }
+}
+
+/// Copies the image into the atlas, extending its edges into the padding.
+fn blit<T: Copy, C: Channels>(
+    data: &mut [T],
+    atlas_width: usize,
+    image: &ImageData<T, C>,
+    (x, y): (usize, usize),
+    padding: usize,
+) {
+    let clamp =
+        |value: usize, length: usize| value.max(padding).min(padding + length - 1) - padding;
+    for dy in 0..(image.height + padding * 2) {
+        let source_y = clamp(dy, image.height);
+        for dx in 0..(image.width + padding * 2) {
+            let source_x = clamp(dx, image.width);
+            let source = (source_y * image.width + source_x) * C::CHANNELS;
+            let target = ((y + dy) * atlas_width + x + dx) * C::CHANNELS;
+            data[target..(target + C::CHANNELS)]
+                .copy_from_slice(&image.data[source..(source + C::CHANNELS)]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::texture::{ColorSpace, Rgba};
+
+    /// An image whose texels are distinct; red is the seed, and green and blue are the position.
+    fn image(seed: u8, width: usize, height: usize) -> ImageData<u8, Rgba> {
+        let data: Vec<_> = (0..height)
+            .flat_map(|y| (0..width).flat_map(move |x| vec![seed, x as u8, y as u8, 255]))
+            .collect();
+        ImageData::new(&data, width, height).unwrap()
+    }
+
+    /// Gets the atlas texel at the position.
+    fn texel(atlas: &ImageData<u8, Rgba>, x: usize, y: usize) -> &[u8] {
+        let index = (y * atlas.width + x) * 4;
+        &atlas.data()[index..(index + 4)]
+    }
+
+    /// Gets the texel of the atlas which contains the point in texture coordinates.
+    fn sample(atlas: &ImageData<u8, Rgba>, uv: Vec2) -> &[u8] {
+        let x = uv.x * atlas.width as f32;
+        let y = uv.y * atlas.height as f32;
+        assert!((x.fract() - 0.5).abs() < 1e-4 && (y.fract() - 0.5).abs() < 1e-4);
+        texel(atlas, x as usize, y as usize)
+    }
+
+    #[test]
+    fn packs_without_overlaps() {
+        let sizes = [(5, 3), (2, 7), (4, 4), (1, 1), (3, 2), (6, 1), (2, 2)];
+        let images: Vec<_> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| image(i as u8 + 1, w, h))
+            .collect();
+        let mut builder = AtlasBuilder::new();
+        let entries: Vec<_> = images.iter().map(|i| builder.add(i)).collect();
+        let options = AtlasOptions {
+            max_size: 64,
+            padding: 2,
+            power_of_two: true,
+        };
+        let atlas = builder.build(&options).unwrap();
+        let atlas_image = atlas.image();
+        let (width, height) = atlas_image.dimensions();
+        assert!(width.is_power_of_two() && height.is_power_of_two());
+
+        // Padded rectangles are inside the atlas and do not overlap.
+        let rectangles: Vec<_> = entries
+            .iter()
+            .zip(&images)
+            .map(|(&entry, image)| {
+                let transform = atlas.transform(entry);
+                let x = (transform.offset.x * width as f32).round() as usize - options.padding;
+                let y = (transform.offset.y * height as f32).round() as usize - options.padding;
+                let (w, h) = image.dimensions();
+                (x, y, w + options.padding * 2, h + options.padding * 2)
+            })
+            .collect();
+        for (i, a) in rectangles.iter().enumerate() {
+            assert!(a.0 + a.2 <= width && a.1 + a.3 <= height, "{:?}", a);
+            for b in &rectangles[(i + 1)..] {
+                let separated =
+                    a.0 + a.2 <= b.0 || b.0 + b.2 <= a.0 || a.1 + a.3 <= b.1 || b.1 + b.3 <= a.1;
+                assert!(separated, "{:?} overlaps {:?}", a, b);
+            }
+        }
+
+        // Texel centers of each image map onto the centers of the same texels in the atlas.
+        for (&entry, image) in entries.iter().zip(&images) {
+            let transform = atlas.transform(entry);
+            let (w, h) = image.dimensions();
+            for y in 0..h {
+                for x in 0..w {
+                    let uv = Vec2::new((x as f32 + 0.5) / w as f32, (y as f32 + 0.5) / h as f32);
+                    assert_eq!(sample(atlas_image, transform.apply(uv)), texel(image, x, y));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn extends_edges_into_padding() {
+        let mut builder = AtlasBuilder::new();
+        let entry = builder.add(&image(7, 3, 2));
+        let options = AtlasOptions {
+            max_size: 16,
+            padding: 2,
+            power_of_two: false,
+        };
+        let atlas = builder.build(&options).unwrap();
+        let atlas_image = atlas.image();
+        assert_eq!(atlas_image.dimensions(), (7, 6));
+        assert_eq!(
+            atlas.transform(entry).offset,
+            Vec2::new(2.0 / 7.0, 2.0 / 6.0)
+        );
+
+        // Each padding texel has the nearest texel of the image.
+        for y in 0..6 {
+            for x in 0..7 {
+                let source_x = x.clamp(2, 4) - 2;
+                let source_y = y.clamp(2, 3) - 2;
+                assert_eq!(
+                    texel(atlas_image, x, y),
+                    &[7, source_x as u8, source_y as u8, 255],
+                    "({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn shares_identical_images() {
+        let red = ImageData::<u8, Rgba>::new(&[255, 0, 0, 255], 1, 1).unwrap();
+        let blue = ImageData::<u8, Rgba>::new(&[0, 0, 255, 255], 1, 1).unwrap();
+        let mut builder = AtlasBuilder::new();
+        let first = builder.add(&red);
+        let second = builder.add(&blue);
+        let third = builder.add(&red.clone());
+        assert_ne!(first, third);
+
+        let options = AtlasOptions {
+            max_size: 16,
+            padding: 0,
+            power_of_two: false,
+        };
+        let atlas = builder.build(&options).unwrap();
+        assert_eq!(atlas.image().dimensions(), (2, 1));
+        assert_eq!(atlas.transform(first), atlas.transform(third));
+        assert_ne!(atlas.transform(first), atlas.transform(second));
+        let center = Vec2::new(0.5, 0.5);
+        assert_eq!(
+            sample(atlas.image(), atlas.transform(third).apply(center)),
+            red.data()
+        );
+        assert_eq!(
+            sample(atlas.image(), atlas.transform(second).apply(center)),
+            blue.data()
+        );
+
+        // Images in different color spaces are not shared, and cannot be packed together.
+        builder.add(&red.clone().with_color_space(ColorSpace::Srgb));
+        assert!(builder.build(&options).is_err());
+    }
+
+    #[test]
+    fn fails_beyond_max_size() {
+        let options = AtlasOptions {
+            max_size: 8,
+            padding: 0,
+            power_of_two: true,
+        };
+        let mut builder = AtlasBuilder::new();
+        builder.add(&image(1, 9, 1));
+        assert!(builder.build(&options).is_err());
+
+        let mut builder = AtlasBuilder::new();
+        builder.add(&image(1, 1, 9));
+        assert!(builder.build(&options).is_err());
+
+        // Four images fill the atlas exactly, and the fifth one does not fit.
+        let mut builder = AtlasBuilder::new();
+        for seed in 0..4 {
+            builder.add(&image(seed, 4, 4));
+        }
+        assert_eq!(
+            builder.build(&options).unwrap().image().dimensions(),
+            (8, 8)
+        );
+        builder.add(&image(4, 4, 4));
+        assert!(builder.build(&options).is_err());
+
+        assert!(AtlasBuilder::<u8, Rgba>::new().build(&options).is_err());
+    }
+}
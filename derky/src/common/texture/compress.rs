@@ -0,0 +1,1002 @@
+//! Contains block compression (BCn) of images and their containers.
+//!
+//! Encoders fit endpoints along the principal axis of each block and refine them once
+//! by least squares; they are not as good as offline compressors, but fast and stable.
+
+use super::{dds, ktx2, ColorSpace, ImageData, MipChain, Rgba};
+use crate::common::asset::{AssetSource, DirectorySource};
+
+use std::{fs::write, mem::swap, path::Path};
+
+use anyhow::{bail, Result};
+use log::{debug, info};
+
+/// The weights of BC7 indices with 4 bits, in 1/64.
+const BC7_WEIGHTS: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+/// Represents block compression formats. Each block has 4x4 texels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlockFormat {
+    /// RGB with 1-bit alpha in 8 bytes; texels with alpha below 128 become transparent
+    Bc1,
+
+    /// RGBA in 16 bytes; the color is the same as BC1 and alpha is the same as BC4
+    Bc3,
+
+    /// Single channel (R) in 8 bytes; for masks such as roughness and occlusion
+    Bc4,
+
+    /// Two channels (RG) in 16 bytes; for tangent space normals
+    Bc5,
+
+    /// RGBA in 16 bytes with higher quality than BC3.
+    /// Only mode 6 (single subset) blocks are produced and decoded.
+    Bc7,
+}
+
+impl BlockFormat {
+    /// The number of bytes of a block.
+    pub fn block_bytes(self) -> usize {
+        match self {
+            BlockFormat::Bc1 | BlockFormat::Bc4 => 8,
+            BlockFormat::Bc3 | BlockFormat::Bc5 | BlockFormat::Bc7 => 16,
+        }
+    }
+
+    /// The number of channels which the format stores.
+    pub fn channels(self) -> usize {
+        match self {
+            BlockFormat::Bc4 => 1,
+            BlockFormat::Bc5 => 2,
+            BlockFormat::Bc1 | BlockFormat::Bc3 | BlockFormat::Bc7 => 4,
+        }
+    }
+
+    /// The number of bytes of an image with the dimensions.
+    pub fn image_bytes(self, width: usize, height: usize) -> usize {
+        block_count(width) * block_count(height) * self.block_bytes()
+    }
+}
+
+/// Represents a block-compressed image with its mip levels.
+#[derive(Debug, Clone)]
+pub struct CompressedImage {
+    format: BlockFormat,
+    color_space: ColorSpace,
+    width: usize,
+    height: usize,
+    levels: Box<[Box<[u8]>]>,
+}
+
+impl CompressedImage {
+    /// Creates from compressed levels; the first one is the base level,
+    /// and each level must have the half dimensions of the previous one.
+    pub fn new(
+        format: BlockFormat,
+        color_space: ColorSpace,
+        width: usize,
+        height: usize,
+        levels: Vec<Box<[u8]>>,
+    ) -> Result<CompressedImage> {
+        if width == 0 || height == 0 {
+            bail!("Dimensions must not be zero");
+        }
+        if levels.is_empty() {
+            bail!("At least one level is required");
+        }
+        for (level, data) in levels.iter().enumerate() {
+            let (level_width, level_height) = level_dimensions(width, height, level);
+            let expected = format.image_bytes(level_width, level_height);
+            if data.len() != expected {
+                bail!(
+                    "Level {} must have {} bytes, but {} given",
+                    level,
+                    expected,
+                    data.len()
+                );
+            }
+        }
+
+        Ok(CompressedImage {
+            format,
+            color_space,
+            width,
+            height,
+            levels: levels.into_boxed_slice(),
+        })
+    }
+
+    /// Compresses an image without mip levels, keeping its color space.
+    /// BC4 and BC5 take the first one and two channels respectively.
+    pub fn compress(image: &ImageData<u8, Rgba>, format: BlockFormat) -> CompressedImage {
+        let (width, height) = image.dimensions();
+        CompressedImage {
+            format,
+            color_space: image.color_space(),
+            width,
+            height,
+            levels: vec![compress_level(image, format)].into_boxed_slice(),
+        }
+    }
+
+    /// Compresses all levels of the mip chain.
+    pub fn compress_mipmaps(chain: &MipChain<u8, Rgba>, format: BlockFormat) -> CompressedImage {
+        let (width, height) = chain.base().dimensions();
+        let levels: Vec<_> = chain
+            .levels()
+            .iter()
+            .map(|level| compress_level(level, format))
+            .collect();
+
+        CompressedImage {
+            format,
+            color_space: chain.base().color_space(),
+            width,
+            height,
+            levels: levels.into_boxed_slice(),
+        }
+    }
+
+    /// The block format.
+    pub fn format(&self) -> BlockFormat {
+        self.format
+    }
+
+    /// The color space of color channels.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// The dimensions of the base level.
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    /// The dimensions of the level.
+    pub fn level_dimensions(&self, level: usize) -> (usize, usize) {
+        level_dimensions(self.width, self.height, level)
+    }
+
+    /// The number of levels.
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The compressed data of levels; blocks are stored in row-major order.
+    pub fn levels(&self) -> &[Box<[u8]>] {
+        &self.levels
+    }
+
+    /// Decompresses the level into RGBA, tagged with the color space.
+    ///
+    /// As GPUs do, missing channels of BC4 and BC5 are filled with zero,
+    /// and alpha with 255.
+    pub fn decompress(&self, level: usize) -> Result<ImageData<u8, Rgba>> {
+        let (width, height) = self.level_dimensions(level);
+        let data = match self.levels.get(level) {
+            Some(data) => data,
+            None => bail!("Level {} does not exist", level),
+        };
+
+        let mut texels = vec![0; width * height * 4];
+        let blocks = data.chunks_exact(self.format.block_bytes());
+        for (index, block) in blocks.enumerate() {
+            let (block_x, block_y) = (index % block_count(width), index / block_count(width));
+            let pixels = decompress_block(block, self.format)?;
+            for (i, pixel) in pixels.iter().enumerate() {
+                let (x, y) = (block_x * 4 + i % 4, block_y * 4 + i / 4);
+                if x < width && y < height {
+                    let base_index = (y * width + x) * 4;
+                    texels[base_index..(base_index + 4)].copy_from_slice(pixel);
+                }
+            }
+        }
+
+        Ok(ImageData::new(&texels, width, height)?.with_color_space(self.color_space))
+    }
+}
+
+/// Loads a compressed (DDS and KTX2) image.
+pub fn load_compressed_image(filename: impl AsRef<Path>) -> Result<CompressedImage> {
+    load_compressed_image_from(&DirectorySource::new(""), filename.as_ref())
+}
+
+/// Loads a compressed (DDS and KTX2) image from the asset source.
+pub fn load_compressed_image_from(
+    source: &dyn AssetSource,
+    path: &Path,
+) -> Result<CompressedImage> {
+    debug!("Loading compressed image {:?}", path);
+    decode_compressed_image(&source.read(path)?)
+}
+
+/// Decodes a compressed (DDS and KTX2) image in memory.
+/// The format is detected from the content, not the extension.
+///
+/// Blocks are not validated here. Note that `CompressedImage::decompress` decodes only
+/// mode 6 BC7 blocks, so BC7 files from other compressors such as texconv and
+/// Compressonator can be uploaded as they are, but cannot be decompressed.
+pub fn decode_compressed_image(data: &[u8]) -> Result<CompressedImage> {
+    let image = if data.starts_with(dds::MAGIC) {
+        dds::decode_dds(data)?
+    } else if data.starts_with(&ktx2::IDENTIFIER) {
+        ktx2::decode_ktx2(data)?
+    } else {
+        bail!("Unknown compressed image format");
+    };
+
+    info!(
+        "Loaded successfully; format is {:?}, dimensions are {:?}, {} levels",
+        image.format,
+        image.dimensions(),
+        image.level_count()
+    );
+    Ok(image)
+}
+
+/// Saves a compressed image as DDS.
+pub fn save_dds_image(image: &CompressedImage, filename: impl AsRef<Path>) -> Result<()> {
+    write(filename.as_ref(), encode_dds_image(image))?;
+    info!("Saved DDS image {:?}", filename.as_ref());
+    Ok(())
+}
+
+/// Encodes a compressed image into DDS in memory.
+/// The DX10 extended header is always written, so that sRGB and BC7 are represented.
+pub fn encode_dds_image(image: &CompressedImage) -> Vec<u8> {
+    dds::encode_dds(image)
+}
+
+/// Saves a compressed image as KTX2.
+pub fn save_ktx2_image(image: &CompressedImage, filename: impl AsRef<Path>) -> Result<()> {
+    write(filename.as_ref(), encode_ktx2_image(image))?;
+    info!("Saved KTX2 image {:?}", filename.as_ref());
+    Ok(())
+}
+
+/// Encodes a compressed image into KTX2 in memory, without supercompression.
+pub fn encode_ktx2_image(image: &CompressedImage) -> Vec<u8> {
+    ktx2::encode_ktx2(image)
+}
+
+/// Calculates the dimensions of the mip level.
+fn level_dimensions(width: usize, height: usize, level: usize) -> (usize, usize) {
+    ((width >> level).max(1), (height >> level).max(1))
+}
+
+/// Calculates the number of blocks along an edge.
+fn block_count(length: usize) -> usize {
+    (length + 3) >> 2
+}
+
+/// Compresses a level.
+fn compress_level(image: &ImageData<u8, Rgba>, format: BlockFormat) -> Box<[u8]> {
+    let (width, height) = image.dimensions();
+    let mut compressed = Vec::with_capacity(format.image_bytes(width, height));
+    for block_y in 0..block_count(height) {
+        for block_x in 0..block_count(width) {
+            let pixels = read_block(image, block_x, block_y);
+            match format {
+                BlockFormat::Bc1 => compressed.extend_from_slice(&encode_bc1(&pixels, true)),
+                BlockFormat::Bc3 => {
+                    compressed.extend_from_slice(&encode_bc4(&channel_of(&pixels, 3)));
+                    compressed.extend_from_slice(&encode_bc1(&pixels, false));
+                }
+                BlockFormat::Bc4 => {
+                    compressed.extend_from_slice(&encode_bc4(&channel_of(&pixels, 0)));
+                }
+                BlockFormat::Bc5 => {
+                    compressed.extend_from_slice(&encode_bc4(&channel_of(&pixels, 0)));
+                    compressed.extend_from_slice(&encode_bc4(&channel_of(&pixels, 1)));
+                }
+                BlockFormat::Bc7 => compressed.extend_from_slice(&encode_bc7(&pixels)),
+            }
+        }
+    }
+
+    compressed.into_boxed_slice()
+}
+
+/// Decompresses a block into 16 RGBA texels.
+fn decompress_block(block: &[u8], format: BlockFormat) -> Result<[[u8; 4]; 16]> {
+    let mut pixels = [[0, 0, 0, 255]; 16];
+    match format {
+        BlockFormat::Bc1 => decode_bc1(block, true, &mut pixels),
+        BlockFormat::Bc3 => {
+            decode_bc1(&block[8..], false, &mut pixels);
+            decode_bc4(&block[..8], 3, &mut pixels);
+        }
+        BlockFormat::Bc4 => decode_bc4(block, 0, &mut pixels),
+        BlockFormat::Bc5 => {
+            decode_bc4(&block[..8], 0, &mut pixels);
+            decode_bc4(&block[8..], 1, &mut pixels);
+        }
+        BlockFormat::Bc7 => decode_bc7(block, &mut pixels)?,
+    }
+    Ok(pixels)
+}
+
+/// Reads 4x4 texels of the block; texels outside the image repeat edges.
+fn read_block(image: &ImageData<u8, Rgba>, block_x: usize, block_y: usize) -> [[u8; 4]; 16] {
+    let (width, height) = image.dimensions();
+    let mut pixels = [[0; 4]; 16];
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let x = (block_x * 4 + i % 4).min(width - 1);
+        let y = (block_y * 4 + i / 4).min(height - 1);
+        let base_index = (y * width + x) * 4;
+        pixel.copy_from_slice(&image.data()[base_index..(base_index + 4)]);
+    }
+    pixels
+}
+
+/// Extracts a channel of the block.
+fn channel_of(pixels: &[[u8; 4]; 16], channel: usize) -> [u8; 16] {
+    let mut values = [0; 16];
+    for (value, pixel) in values.iter_mut().zip(pixels) {
+        *value = pixel[channel];
+    }
+    values
+}
+
+/// Encodes the color part of BC1 and BC3.
+/// If `punch_through`, texels with alpha below 128 are encoded as transparent.
+fn encode_bc1(pixels: &[[u8; 4]; 16], punch_through: bool) -> [u8; 8] {
+    let transparent: Vec<_> = pixels.iter().map(|p| punch_through && p[3] < 128).collect();
+    let opaque: Vec<_> = pixels
+        .iter()
+        .zip(&transparent)
+        .filter(|(_, &t)| !t)
+        .map(|(p, _)| [p[0] as f32, p[1] as f32, p[2] as f32, 0.0])
+        .collect();
+    if opaque.is_empty() {
+        return [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff];
+    }
+
+    let has_transparent = opaque.len() < 16;
+    let (start, end) = principal_endpoints(&opaque, 3);
+    let mut best = bc1_block(pixels, &transparent, start, end, has_transparent);
+
+    // In 4-color mode, endpoints are refined with the weights of the chosen indices.
+    if !has_transparent {
+        let weights: Vec<_> = (0..16)
+            .map(|i| [0.0, 1.0, 1.0 / 3.0, 2.0 / 3.0][best.1[i] as usize])
+            .collect();
+        if let Some((start, end)) = least_squares_endpoints(&opaque, &weights) {
+            let refined = bc1_block(pixels, &transparent, start, end, false);
+            if refined.2 < best.2 {
+                best = refined;
+            }
+        }
+    }
+
+    let (endpoints, indices, _) = best;
+    let mut block = [0; 8];
+    block[0..2].copy_from_slice(&endpoints[0].to_le_bytes());
+    block[2..4].copy_from_slice(&endpoints[1].to_le_bytes());
+    let packed = indices
+        .iter()
+        .enumerate()
+        .fold(0u32, |packed, (i, &index)| {
+            packed | (index as u32) << (i * 2)
+        });
+    block[4..8].copy_from_slice(&packed.to_le_bytes());
+    block
+}
+
+/// Quantizes endpoints and selects indices of a BC1 color block.
+/// Returns the endpoints in RGB565, the indices, and the squared error.
+fn bc1_block(
+    pixels: &[[u8; 4]; 16],
+    transparent: &[bool],
+    start: [f32; 4],
+    end: [f32; 4],
+    three_color: bool,
+) -> ([u16; 2], [u8; 16], f32) {
+    let (mut color0, mut color1) = (to_rgb565(start), to_rgb565(end));
+    if three_color == (color0 > color1) {
+        swap(&mut color0, &mut color1);
+    }
+    let palette = bc1_palette(color0, color1, color0 > color1);
+    // Equal endpoints always select 3-color mode, whose last entry is transparent.
+    let candidates = if color0 > color1 { 4 } else { 3 };
+
+    let mut indices = [0; 16];
+    let mut total_error = 0.0;
+    for i in 0..16 {
+        if transparent[i] {
+            indices[i] = 3;
+            continue;
+        }
+        let (index, error) = nearest(&palette[..candidates], &pixels[i], 3);
+        indices[i] = index as u8;
+        total_error += error;
+    }
+
+    ([color0, color1], indices, total_error)
+}
+
+/// Decodes the color part of BC1 and BC3.
+/// The order of endpoints selects the 3-color mode only in BC1; BC3 always uses 4 colors.
+fn decode_bc1(block: &[u8], punch_through: bool, pixels: &mut [[u8; 4]; 16]) {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let palette = bc1_palette(color0, color1, !punch_through || color0 > color1);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        let index = (indices >> (i * 2)) as usize & 0b11;
+        pixel[0..3].copy_from_slice(&palette[index][0..3]);
+        pixel[3] = if punch_through {
+            palette[index][3]
+        } else {
+            255
+        };
+    }
+}
+
+/// Builds the palette of a BC1 color block, in 4-color mode or 3-color mode with transparency.
+fn bc1_palette(color0: u16, color1: u16, four_color: bool) -> [[u8; 4]; 4] {
+    let (c0, c1) = (from_rgb565(color0), from_rgb565(color1));
+    let mix = |a: u8, b: u8, wa: u32, wb: u32| ((a as u32 * wa + b as u32 * wb) / (wa + wb)) as u8;
+    let mut palette = [c0, c1, [0, 0, 0, 255], [0, 0, 0, 0]];
+    for channel in 0..3 {
+        if four_color {
+            palette[2][channel] = mix(c0[channel], c1[channel], 2, 1);
+            palette[3][channel] = mix(c0[channel], c1[channel], 1, 2);
+        } else {
+            palette[2][channel] = mix(c0[channel], c1[channel], 1, 1);
+        }
+    }
+    if four_color {
+        palette[3][3] = 255;
+    }
+    palette
+}
+
+/// Converts a color into RGB565.
+fn to_rgb565(color: [f32; 4]) -> u16 {
+    let quantize = |value: f32, max: f32| (value / 255.0 * max).round().max(0.0).min(max) as u16;
+    quantize(color[0], 31.0) << 11 | quantize(color[1], 63.0) << 5 | quantize(color[2], 31.0)
+}
+
+/// Expands a RGB565 color.
+fn from_rgb565(color: u16) -> [u8; 4] {
+    let (r, g, b) = (
+        (color >> 11) as u8,
+        (color >> 5) as u8 & 0x3f,
+        color as u8 & 0x1f,
+    );
+    [r << 3 | r >> 2, g << 2 | g >> 4, b << 3 | b >> 2, 255]
+}
+
+/// Encodes a BC4 block, which is also the alpha part of BC3 and channels of BC5.
+/// The 8-value mode is always used.
+fn encode_bc4(values: &[u8; 16]) -> [u8; 8] {
+    let max = *values.iter().max().expect("Empty block");
+    let min = *values.iter().min().expect("Empty block");
+    let mut block = [max, min, 0, 0, 0, 0, 0, 0];
+    if max == min {
+        return block;
+    }
+
+    let palette = bc4_palette(max, min);
+    let packed = values.iter().enumerate().fold(0u64, |packed, (i, &value)| {
+        let index = (0..8)
+            .min_by_key(|&j| (palette[j] as i32 - value as i32).abs())
+            .expect("Empty palette");
+        packed | (index as u64) << (i * 3)
+    });
+    block[2..8].copy_from_slice(&packed.to_le_bytes()[0..6]);
+    block
+}
+
+/// Decodes a BC4 block into the channel.
+fn decode_bc4(block: &[u8], channel: usize, pixels: &mut [[u8; 4]; 16]) {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut index_bytes = [0; 8];
+    index_bytes[0..6].copy_from_slice(&block[2..8]);
+    let indices = u64::from_le_bytes(index_bytes);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        pixel[channel] = palette[(indices >> (i * 3)) as usize & 0b111];
+    }
+}
+
+/// Builds the palette of a BC4 block; the mode depends on the order of endpoints.
+fn bc4_palette(value0: u8, value1: u8) -> [u8; 8] {
+    let (v0, v1) = (value0 as u32, value1 as u32);
+    let mut palette = [value0, value1, 0, 0, 0, 0, 0, 255];
+    if value0 > value1 {
+        for i in 1..7 {
+            palette[i + 1] = (((7 - i as u32) * v0 + i as u32 * v1 + 3) / 7) as u8;
+        }
+    } else {
+        for i in 1..5 {
+            palette[i + 1] = (((5 - i as u32) * v0 + i as u32 * v1 + 2) / 5) as u8;
+        }
+    }
+    palette
+}
+
+/// Encodes a BC7 block in mode 6; RGBA endpoints with 7 bits and a P-bit each,
+/// and 4-bit indices.
+fn encode_bc7(pixels: &[[u8; 4]; 16]) -> [u8; 16] {
+    let points: Vec<_> = pixels
+        .iter()
+        .map(|p| [p[0] as f32, p[1] as f32, p[2] as f32, p[3] as f32])
+        .collect();
+    let (start, end) = principal_endpoints(&points, 4);
+    let mut best = bc7_block(pixels, start, end);
+
+    let weights: Vec<_> = best
+        .1
+        .iter()
+        .map(|&i| BC7_WEIGHTS[i as usize] as f32 / 64.0)
+        .collect();
+    if let Some((start, end)) = least_squares_endpoints(&points, &weights) {
+        let refined = bc7_block(pixels, start, end);
+        if refined.2 < best.2 {
+            best = refined;
+        }
+    }
+
+    let (mut endpoints, mut indices, _) = best;
+    // The most significant bit of the first index is implicitly zero.
+    if indices[0] >= 8 {
+        endpoints.swap(0, 1);
+        for index in indices.iter_mut() {
+            *index = 15 - *index;
+        }
+    }
+
+    let mut bits = BitWriter::default();
+    bits.write(1 << 6, 7);
+    for (&start, &end) in endpoints[0].iter().zip(&endpoints[1]) {
+        bits.write(start as u32 >> 1, 7);
+        bits.write(end as u32 >> 1, 7);
+    }
+    bits.write(endpoints[0][0] as u32 & 1, 1);
+    bits.write(endpoints[1][0] as u32 & 1, 1);
+    for (i, &index) in indices.iter().enumerate() {
+        bits.write(index as u32, if i == 0 { 3 } else { 4 });
+    }
+    bits.value.to_le_bytes()
+}
+
+/// Quantizes endpoints and selects indices of a BC7 mode 6 block.
+/// All combinations of P-bits are tried, since the closest ones may not give the best block,
+/// except that opaque blocks always use 1 to keep alpha exactly 255.
+/// Returns the endpoints with P-bits applied, the indices, and the squared error.
+fn bc7_block(
+    pixels: &[[u8; 4]; 16],
+    start: [f32; 4],
+    end: [f32; 4],
+) -> ([[u8; 4]; 2], [u8; 16], f32) {
+    let p_bits: &[(u8, u8)] = if pixels.iter().all(|p| p[3] == 255) {
+        &[(1, 1)]
+    } else {
+        &[(0, 0), (0, 1), (1, 0), (1, 1)]
+    };
+
+    let mut best = ([[0; 4]; 2], [0; 16], f32::MAX);
+    for &(start_p, end_p) in p_bits {
+        let endpoints = [
+            quantize_bc7_endpoint(start, start_p),
+            quantize_bc7_endpoint(end, end_p),
+        ];
+        let palette = bc7_palette(&endpoints);
+
+        let mut indices = [0; 16];
+        let mut total_error = 0.0;
+        for (index, pixel) in indices.iter_mut().zip(pixels) {
+            let (nearest_index, error) = nearest(&palette, pixel, 4);
+            *index = nearest_index as u8;
+            total_error += error;
+        }
+        if total_error < best.2 {
+            best = (endpoints, indices, total_error);
+        }
+    }
+
+    best
+}
+
+/// Quantizes an endpoint into 7 bits per channel with the P-bit.
+fn quantize_bc7_endpoint(endpoint: [f32; 4], p: u8) -> [u8; 4] {
+    let mut quantized = [0; 4];
+    for (q, &value) in quantized.iter_mut().zip(&endpoint) {
+        let high = ((value - p as f32) / 2.0).round().clamp(0.0, 127.0) as u8;
+        *q = high << 1 | p;
+    }
+    quantized
+}
+
+/// Builds the palette of a BC7 block with 4-bit indices.
+fn bc7_palette(endpoints: &[[u8; 4]; 2]) -> Vec<[u8; 4]> {
+    BC7_WEIGHTS
+        .iter()
+        .map(|&weight| {
+            let mut color = [0; 4];
+            for (channel, value) in color.iter_mut().enumerate() {
+                let (e0, e1) = (endpoints[0][channel] as u32, endpoints[1][channel] as u32);
+                *value = (((64 - weight) * e0 + weight * e1 + 32) >> 6) as u8;
+            }
+            color
+        })
+        .collect()
+}
+
+/// Decodes a BC7 block. Only mode 6 is supported.
+fn decode_bc7(block: &[u8], pixels: &mut [[u8; 4]; 16]) -> Result<()> {
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(block);
+    let mut bits = BitReader(u128::from_le_bytes(bytes));
+    if bits.read(7) != 1 << 6 {
+        bail!("Only mode 6 BC7 blocks are supported");
+    }
+
+    let mut endpoints = [[0u8; 4]; 2];
+    // Channels are stored in the order of R0, R1, G0, G1, and so on.
+    for i in 0..8 {
+        endpoints[i % 2][i / 2] = (bits.read(7) << 1) as u8;
+    }
+    for endpoint in endpoints.iter_mut() {
+        let p = bits.read(1) as u8;
+        for value in endpoint.iter_mut() {
+            *value |= p;
+        }
+    }
+
+    let palette = bc7_palette(&endpoints);
+    for (i, pixel) in pixels.iter_mut().enumerate() {
+        *pixel = palette[bits.read(if i == 0 { 3 } else { 4 }) as usize];
+    }
+    Ok(())
+}
+
+/// Finds the nearest palette entry with the first `channels` channels.
+/// Returns the index and the squared error.
+fn nearest(palette: &[[u8; 4]], pixel: &[u8; 4], channels: usize) -> (usize, f32) {
+    palette
+        .iter()
+        .map(|entry| {
+            (0..channels)
+                .map(|c| (entry[c] as f32 - pixel[c] as f32).powi(2))
+                .sum::<f32>()
+        })
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).expect("NaN error"))
+        .expect("Empty palette")
+}
+
+/// Calculates endpoints at the extent of points along their principal axis.
+fn principal_endpoints(points: &[[f32; 4]], channels: usize) -> ([f32; 4], [f32; 4]) {
+    let count = points.len() as f32;
+    let mut mean = [0.0; 4];
+    for point in points {
+        for c in 0..channels {
+            mean[c] += point[c] / count;
+        }
+    }
+
+    let mut covariance = [[0.0; 4]; 4];
+    for point in points {
+        for i in 0..channels {
+            for j in 0..channels {
+                covariance[i][j] += (point[i] - mean[i]) * (point[j] - mean[j]);
+            }
+        }
+    }
+
+    // Power iteration converges fast enough for 4x4 blocks.
+    let mut axis = [1.0; 4];
+    for _ in 0..8 {
+        let mut next = [0.0; 4];
+        for i in 0..channels {
+            for j in 0..channels {
+                next[i] += covariance[i][j] * axis[j];
+            }
+        }
+        let length = next.iter().map(|v| v * v).sum::<f32>().sqrt();
+        if length < 1e-6 {
+            return (mean, mean);
+        }
+        for (a, n) in axis.iter_mut().zip(&next) {
+            *a = n / length;
+        }
+    }
+
+    let (mut min, mut max) = (f32::MAX, f32::MIN);
+    for point in points {
+        let t: f32 = (0..channels).map(|c| (point[c] - mean[c]) * axis[c]).sum();
+        min = min.min(t);
+        max = max.max(t);
+    }
+    let (mut start, mut end) = ([0.0; 4], [0.0; 4]);
+    for c in 0..channels {
+        start[c] = (mean[c] + axis[c] * min).clamp(0.0, 255.0);
+        end[c] = (mean[c] + axis[c] * max).clamp(0.0, 255.0);
+    }
+    (start, end)
+}
+
+/// Solves endpoints which minimize the squared error for fixed interpolation weights,
+/// where the weight 0 is the start and 1 is the end. Returns `None` if degenerate.
+fn least_squares_endpoints(points: &[[f32; 4]], weights: &[f32]) -> Option<([f32; 4], [f32; 4])> {
+    let (mut aa, mut ab, mut bb) = (0.0, 0.0, 0.0);
+    let (mut ax, mut bx) = ([0.0; 4], [0.0; 4]);
+    for (point, &t) in points.iter().zip(weights) {
+        let s = 1.0 - t;
+        aa += s * s;
+        ab += s * t;
+        bb += t * t;
+        for c in 0..4 {
+            ax[c] += s * point[c];
+            bx[c] += t * point[c];
+        }
+    }
+
+    let determinant = aa * bb - ab * ab;
+    if determinant.abs() < 1e-6 {
+        return None;
+    }
+    let (mut start, mut end) = ([0.0; 4], [0.0; 4]);
+    for c in 0..4 {
+        start[c] = ((bb * ax[c] - ab * bx[c]) / determinant).clamp(0.0, 255.0);
+        end[c] = ((aa * bx[c] - ab * ax[c]) / determinant).clamp(0.0, 255.0);
+    }
+    Some((start, end))
+}
+
+/// Writes bits into a 128-bit block from the least significant bit.
+#[derive(Default)]
+struct BitWriter {
+    value: u128,
+    offset: u32,
+}
+
+impl BitWriter {
+    fn write(&mut self, value: u32, bits: u32) {
+        self.value |= (value as u128 & ((1 << bits) - 1)) << self.offset;
+        self.offset += bits;
+    }
+}
+
+/// Reads bits from a 128-bit block from the least significant bit.
+struct BitReader(u128);
+
+impl BitReader {
+    fn read(&mut self, bits: u32) -> u32 {
+        let value = self.0 as u32 & ((1 << bits) - 1);
+        self.0 >>= bits;
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A color block with `color0 < color1`, and each texel selecting the index `i % 4`.
+    fn ascending_color_block() -> [u8; 8] {
+        let (color0, color1) = (0x0000u16, 0xffffu16);
+        let mut block = [0; 8];
+        block[0..2].copy_from_slice(&color0.to_le_bytes());
+        block[2..4].copy_from_slice(&color1.to_le_bytes());
+        block[4..8].copy_from_slice(&0xe4e4_e4e4u32.to_le_bytes());
+        block
+    }
+
+    #[test]
+    fn bc1_ascending_endpoints_use_three_colors() {
+        let pixels = decompress_block(&ascending_color_block(), BlockFormat::Bc1).unwrap();
+        assert_eq!(pixels[2], [127, 127, 127, 255]);
+        assert_eq!(pixels[3], [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn bc3_always_uses_four_colors() {
+        // Alpha endpoints 255 and 0 with all indices 0 make the block opaque.
+        let mut block = [0; 16];
+        block[0] = 255;
+        block[8..].copy_from_slice(&ascending_color_block());
+
+        let pixels = decompress_block(&block, BlockFormat::Bc3).unwrap();
+        assert_eq!(pixels[0], [0, 0, 0, 255]);
+        assert_eq!(pixels[1], [255, 255, 255, 255]);
+        assert_eq!(pixels[2], [85, 85, 85, 255]);
+        assert_eq!(pixels[3], [170, 170, 170, 255]);
+    }
+
+    /// A smooth RGBA image with different gradients in each channel.
+    fn gradient_image(width: usize, height: usize, alpha: bool) -> ImageData<u8, Rgba> {
+        let mut data = Vec::with_capacity(width * height * 4);
+        for y in 0..height {
+            for x in 0..width {
+                let (u, v) = (x as f32 / width as f32, y as f32 / height as f32);
+                data.extend_from_slice(&[
+                    (u * 255.0) as u8,
+                    (v * 255.0) as u8,
+                    ((u + v) * 127.0) as u8,
+                    if alpha {
+                        (255.0 - u * v * 255.0) as u8
+                    } else {
+                        255
+                    },
+                ]);
+            }
+        }
+        ImageData::new(&data, width, height).unwrap()
+    }
+
+    /// The maximum absolute difference of the channels between images.
+    fn max_error(a: &ImageData<u8, Rgba>, b: &ImageData<u8, Rgba>, channels: &[usize]) -> i32 {
+        a.data()
+            .chunks_exact(4)
+            .zip(b.data().chunks_exact(4))
+            .flat_map(|(a, b)| {
+                channels
+                    .iter()
+                    .map(move |&c| (a[c] as i32 - b[c] as i32).abs())
+            })
+            .max()
+            .unwrap()
+    }
+
+    #[test]
+    fn bc4_and_bc5_error_bounds() {
+        let image = gradient_image(16, 16, false);
+
+        let bc4 = CompressedImage::compress(&image, BlockFormat::Bc4)
+            .decompress(0)
+            .unwrap();
+        // Half a palette step of 8 values over the range of each block.
+        assert!(max_error(&image, &bc4, &[0]) <= 4);
+        assert!(bc4.data().chunks_exact(4).all(|p| p[1..] == [0, 0, 255]));
+
+        let bc5 = CompressedImage::compress(&image, BlockFormat::Bc5)
+            .decompress(0)
+            .unwrap();
+        assert!(max_error(&image, &bc5, &[0, 1]) <= 4);
+        assert!(bc5.data().chunks_exact(4).all(|p| p[2..] == [0, 255]));
+
+        // Each value is within half a palette step of 8 values.
+        let values: [u8; 16] = [
+            0, 200, 13, 77, 255, 128, 64, 32, 16, 8, 4, 2, 1, 99, 180, 240,
+        ];
+        let mut pixels = [[0; 4]; 16];
+        decode_bc4(&encode_bc4(&values), 0, &mut pixels);
+        for (pixel, &value) in pixels.iter().zip(&values) {
+            assert!((pixel[0] as i32 - value as i32).abs() <= 255 / 14 + 1);
+        }
+    }
+
+    /// The root mean square error of all channels between images.
+    fn rms_error(a: &ImageData<u8, Rgba>, b: &ImageData<u8, Rgba>) -> f32 {
+        let squared: f32 = a
+            .data()
+            .iter()
+            .zip(b.data())
+            .map(|(&a, &b)| (a as f32 - b as f32).powi(2))
+            .sum();
+        (squared / a.data().len() as f32).sqrt()
+    }
+
+    #[test]
+    fn bc7_error_bounds() {
+        // Colors on a line in each block are fitted closely by a single subset.
+        let mut data = vec![];
+        for i in 0..256usize {
+            let (x, y) = (i % 16, i / 16);
+            let t = ((x % 4 + y % 4 * 4) * 4 + x / 4 * 16 + y / 4 * 32) as u8;
+            data.extend_from_slice(&[t, 200 - t / 2, t / 4 + 30, 255 - t]);
+        }
+        let image = ImageData::<u8, Rgba>::new(&data, 16, 16).unwrap();
+        let decompressed = CompressedImage::compress(&image, BlockFormat::Bc7)
+            .decompress(0)
+            .unwrap();
+        assert!(max_error(&image, &decompressed, &[0, 1, 2, 3]) <= 2);
+
+        // Mode 6 approximates independent gradients with a line.
+        for &alpha in &[false, true] {
+            let image = gradient_image(16, 16, alpha);
+            let decompressed = CompressedImage::compress(&image, BlockFormat::Bc7)
+                .decompress(0)
+                .unwrap();
+            assert!(rms_error(&image, &decompressed) < 10.0);
+            assert!(max_error(&image, &decompressed, &[0, 1, 2, 3]) <= 32);
+        }
+
+        // Uniform blocks are reproduced within the rounding of P-bits.
+        let data = [200, 100, 50, 128].repeat(16);
+        let image = ImageData::<u8, Rgba>::new(&data, 4, 4).unwrap();
+        let decompressed = CompressedImage::compress(&image, BlockFormat::Bc7)
+            .decompress(0)
+            .unwrap();
+        assert!(max_error(&image, &decompressed, &[0, 1, 2, 3]) <= 1);
+    }
+
+    #[test]
+    fn opaque_bc7_blocks_keep_alpha() {
+        // Colors which do not lie on a line, so that endpoints differ in every channel.
+        let mut data = vec![];
+        for i in 0..64u32 {
+            data.extend_from_slice(&[
+                (i * 37 % 256) as u8,
+                (i * 91 % 256) as u8,
+                (i * 13) as u8,
+                255,
+            ]);
+        }
+        let image = ImageData::<u8, Rgba>::new(&data, 8, 8).unwrap();
+        let compressed = CompressedImage::compress(&image, BlockFormat::Bc7);
+        for block in compressed.levels()[0].chunks_exact(16) {
+            assert_eq!(block[0] & 0x7f, 1 << 6);
+        }
+        let decompressed = compressed.decompress(0).unwrap();
+        assert!(decompressed.data().chunks_exact(4).all(|p| p[3] == 255));
+    }
+
+    #[test]
+    fn only_mode_6_bc7_blocks_are_decoded() {
+        // Mode 5 block, as produced by other compressors.
+        let mut block = [0; 16];
+        block[0] = 1 << 5;
+        assert!(decompress_block(&block, BlockFormat::Bc7).is_err());
+    }
+
+    #[test]
+    fn containers_round_trip() {
+        // 13x7 has levels of 6x3, 3x1, and 1x1, which are not multiples of 4.
+        let image = gradient_image(13, 7, true);
+        let formats = [
+            (BlockFormat::Bc1, 71, 72, 133, 134),
+            (BlockFormat::Bc3, 77, 78, 137, 138),
+            (BlockFormat::Bc4, 80, 80, 139, 139),
+            (BlockFormat::Bc5, 83, 83, 141, 141),
+            (BlockFormat::Bc7, 98, 99, 145, 146),
+        ];
+        for &(format, dxgi_linear, dxgi_srgb, vk_linear, vk_srgb) in &formats {
+            for &(color_space, dxgi, vk) in &[
+                (ColorSpace::Linear, dxgi_linear, vk_linear),
+                (ColorSpace::Srgb, dxgi_srgb, vk_srgb),
+                (ColorSpace::LinearRec2020, dxgi_linear, vk_linear),
+            ] {
+                let chain = image
+                    .clone()
+                    .with_color_space(color_space)
+                    .generate_mipmaps(&Default::default());
+                let compressed = CompressedImage::compress_mipmaps(&chain, format);
+                assert_eq!(compressed.level_count(), 4);
+                assert_eq!(compressed.level_dimensions(1), (6, 3));
+
+                // The DXGI format follows the magic, the header, and the pixel format.
+                let dds = encode_dds_image(&compressed);
+                assert_eq!(dds[128..132], (dxgi as u32).to_le_bytes());
+                let decoded = decode_compressed_image(&dds).unwrap();
+                assert_eq!(decoded.format(), format);
+                assert_eq!(decoded.dimensions(), (13, 7));
+                assert_eq!(decoded.levels(), compressed.levels());
+                // DXGI formats do not have primaries, and BC4 and BC5 do not have sRGB.
+                let expected = match color_space {
+                    ColorSpace::Srgb if dxgi_srgb == dxgi_linear => ColorSpace::Linear,
+                    ColorSpace::LinearRec2020 => ColorSpace::Linear,
+                    c => c,
+                };
+                assert_eq!(decoded.color_space(), expected);
+
+                let ktx2 = encode_ktx2_image(&compressed);
+                assert_eq!(ktx2[12..16], (vk as u32).to_le_bytes());
+                let decoded = decode_compressed_image(&ktx2).unwrap();
+                assert_eq!(decoded.format(), format);
+                assert_eq!(decoded.dimensions(), (13, 7));
+                assert_eq!(decoded.levels(), compressed.levels());
+                let expected = match color_space {
+                    ColorSpace::Srgb if vk_srgb == vk_linear => ColorSpace::Linear,
+                    c => c,
+                };
+                assert_eq!(decoded.color_space(), expected);
+            }
+        }
+
+        assert!(decode_compressed_image(b"PNG?").is_err());
+    }
+}
@@ -0,0 +1,400 @@
+//! Contains cube maps and conversions from/to equirectangular images.
+
+use super::{Channels, ImageData, ImageElement};
+
+use std::f32::consts::PI;
+
+use anyhow::{bail, Result};
+use ultraviolet::Vec3;
+
+/// Represents a face of cube maps.
+///
+/// Faces follow the layout of D3D11 and OpenGL cube textures; for each face,
+/// `u` goes right and `v` goes down in the image (rows are stored from top).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CubeFace {
+    /// +X; `u` goes to -Z, `v` goes to -Y
+    PositiveX,
+
+    /// -X; `u` goes to +Z, `v` goes to -Y
+    NegativeX,
+
+    /// +Y; `u` goes to +X, `v` goes to +Z
+    PositiveY,
+
+    /// -Y; `u` goes to +X, `v` goes to -Z
+    NegativeY,
+
+    /// +Z; `u` goes to +X, `v` goes to -Y
+    PositiveZ,
+
+    /// -Z; `u` goes to -X, `v` goes to -Y
+    NegativeZ,
+}
+
+impl CubeFace {
+    /// All faces in the order of array slices of cube textures.
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// The index of this face in `ALL`.
+    pub fn index(self) -> usize {
+        match self {
+            CubeFace::PositiveX => 0,
+            CubeFace::NegativeX => 1,
+            CubeFace::PositiveY => 2,
+            CubeFace::NegativeY => 3,
+            CubeFace::PositiveZ => 4,
+            CubeFace::NegativeZ => 5,
+        }
+    }
+
+    /// Calculates the (not normalized) direction to the point on this face,
+    /// where `u` and `v` are in [0, 1].
+    pub fn direction(self, u: f32, v: f32) -> Vec3 {
+        let s = u * 2.0 - 1.0;
+        let t = v * 2.0 - 1.0;
+        match self {
+            CubeFace::PositiveX => Vec3::new(1.0, -t, -s),
+            CubeFace::NegativeX => Vec3::new(-1.0, -t, s),
+            CubeFace::PositiveY => Vec3::new(s, 1.0, t),
+            CubeFace::NegativeY => Vec3::new(s, -1.0, -t),
+            CubeFace::PositiveZ => Vec3::new(s, -t, 1.0),
+            CubeFace::NegativeZ => Vec3::new(-s, -t, -1.0),
+        }
+    }
+
+    /// Finds the face and its `(u, v)` which the direction points.
+    pub fn from_direction(direction: Vec3) -> (CubeFace, f32, f32) {
+        let Vec3 { x, y, z } = direction;
+        let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+        let (face, s, t, major) = if ax >= ay && ax >= az {
+            if x >= 0.0 {
+                (CubeFace::PositiveX, -z, -y, ax)
+            } else {
+                (CubeFace::NegativeX, z, -y, ax)
+            }
+        } else if ay >= az {
+            if y >= 0.0 {
+                (CubeFace::PositiveY, x, z, ay)
+            } else {
+                (CubeFace::NegativeY, x, -z, ay)
+            }
+        } else if z >= 0.0 {
+            (CubeFace::PositiveZ, x, -y, az)
+        } else {
+            (CubeFace::NegativeZ, -x, -y, az)
+        };
+
+        (face, (s / major + 1.0) * 0.5, (t / major + 1.0) * 0.5)
+    }
+}
+
+/// Represents a cube map image, which consists of six square faces.
+pub struct CubeMap<T: Copy, C: Channels> {
+    faces: Box<[ImageData<T, C>]>,
+    size: usize,
+}
+
+impl<T: Copy, C: Channels> Clone for CubeMap<T, C> {
+    fn clone(&self) -> CubeMap<T, C> {
+        CubeMap {
+            faces: self.faces.clone(),
+            size: self.size,
+        }
+    }
+}
+
+impl<T: ImageElement, C: Channels> CubeMap<T, C> {
+    /// Creates a cube map from six faces in the order of `CubeFace::ALL`.
+    pub fn new(faces: Vec<ImageData<T, C>>) -> Result<CubeMap<T, C>> {
+        if faces.len() != 6 {
+            bail!("Cube maps must have 6 faces, but {} given", faces.len());
+        }
+        let size = faces[0].width;
+        if faces.iter().any(|f| f.width != size || f.height != size) {
+            bail!("Faces must be squares of the same size");
+        }
+
+        Ok(CubeMap {
+            faces: faces.into_boxed_slice(),
+            size,
+        })
+    }
+
+    /// The length of edges of faces.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Gets the face.
+    pub fn face(&self, face: CubeFace) -> &ImageData<T, C> {
+        &self.faces[face.index()]
+    }
+
+    /// Gets all faces in the order of `CubeFace::ALL`.
+    pub fn faces(&self) -> &[ImageData<T, C>] {
+        &self.faces
+    }
+
+    /// Consumes this instance and gets all faces in the order of `CubeFace::ALL`.
+    pub fn into_faces(self) -> Box<[ImageData<T, C>]> {
+        self.faces
+    }
+
+    /// Samples the color in the direction with bilinear filtering into `pixel`,
+    /// which must have `C::CHANNELS` elements.
+    ///
+    /// Texels are not blended across faces; edges of faces are clamped.
+    pub fn sample(&self, direction: Vec3, pixel: &mut [f32]) {
+        let (face, u, v) = CubeFace::from_direction(direction);
+        let size = self.size as f32;
+        sample_bilinear(self.face(face), u * size, v * size, false, pixel);
+    }
+
+    /// Converts into an equirectangular image with the dimensions.
+    ///
+    /// The mapping is the same as the image lighting shaders; the top row is +Y,
+    /// and `u` = 0 is -X, going through -Z, +X and +Z as `u` increases.
+    pub fn to_equirect(&self, width: usize, height: usize) -> ImageData<T, C> {
+        let mut data = Vec::with_capacity(width * height * C::CHANNELS);
+        let mut pixel = vec![0.0; C::CHANNELS];
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                self.sample(equirect_direction(u, v), &mut pixel);
+                data.extend(pixel.iter().map(|&value| T::from_f32(value)));
+            }
+        }
+
+        ImageData {
+            data: data.into_boxed_slice(),
+            width,
+            height,
+            color_space: self.faces[0].color_space,
+            _channels: Default::default(),
+        }
+    }
+}
+
+impl<T: ImageElement, C: Channels> ImageData<T, C> {
+    /// Converts this equirectangular image into a cube map with the face size.
+    /// See `CubeMap::to_equirect` for the mapping.
+    pub fn equirect_to_cubemap(&self, size: usize) -> CubeMap<T, C> {
+        let mut pixel = vec![0.0; C::CHANNELS];
+        let faces = CubeFace::ALL
+            .iter()
+            .map(|&face| {
+                let mut data = Vec::with_capacity(size * size * C::CHANNELS);
+                for y in 0..size {
+                    for x in 0..size {
+                        let u = (x as f32 + 0.5) / size as f32;
+                        let v = (y as f32 + 0.5) / size as f32;
+                        let (u, v) = equirect_uv(face.direction(u, v));
+                        let (ex, ey) = (u * self.width as f32, v * self.height as f32);
+                        sample_bilinear(self, ex, ey, true, &mut pixel);
+                        data.extend(pixel.iter().map(|&value| T::from_f32(value)));
+                    }
+                }
+
+                ImageData {
+                    data: data.into_boxed_slice(),
+                    width: size,
+                    height: size,
+                    color_space: self.color_space,
+                    _channels: Default::default(),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        CubeMap {
+            faces: faces.into_boxed_slice(),
+            size,
+        }
+    }
+}
+
+/// Calculates the normalized direction of the point on equirectangular images.
+pub fn equirect_direction(u: f32, v: f32) -> Vec3 {
+    let azimuth = u * 2.0 * PI - PI;
+    let elevation = (0.5 - v) * PI;
+    Vec3::new(
+        elevation.cos() * azimuth.cos(),
+        elevation.sin(),
+        elevation.cos() * azimuth.sin(),
+    )
+}
+
+/// Calculates the point on equirectangular images in the direction.
+pub fn equirect_uv(direction: Vec3) -> (f32, f32) {
+    let direction = direction.normalized();
+    let azimuth = direction.z.atan2(direction.x);
+    let elevation = direction.y.clamp(-1.0, 1.0).asin();
+    ((azimuth + PI) / (2.0 * PI), 0.5 - elevation / PI)
+}
+
+/// Samples an image with bilinear filtering, where `(x, y)` is in texels.
+/// Horizontal edges wrap around if `wrap_x`, and other edges are clamped.
+fn sample_bilinear<T: ImageElement, C: Channels>(
+    image: &ImageData<T, C>,
+    x: f32,
+    y: f32,
+    wrap_x: bool,
+    pixel: &mut [f32],
+) {
+    let (width, height) = (image.width as isize, image.height as isize);
+    let (x, y) = (x - 0.5, y - 0.5);
+    let (x0, y0) = (x.floor(), y.floor());
+    let (fx, fy) = (x - x0, y - y0);
+
+    let column = |i: isize| {
+        if wrap_x {
+            i.rem_euclid(width) as usize
+        } else {
+            i.max(0).min(width - 1) as usize
+        }
+    };
+    let row = |i: isize| i.max(0).min(height - 1) as usize;
+    let (left, right) = (column(x0 as isize), column(x0 as isize + 1));
+    let (top, bottom) = (row(y0 as isize), row(y0 as isize + 1));
+    let taps = [
+        (left, top, (1.0 - fx) * (1.0 - fy)),
+        (right, top, fx * (1.0 - fy)),
+        (left, bottom, (1.0 - fx) * fy),
+        (right, bottom, fx * fy),
+    ];
+
+    for value in pixel.iter_mut() {
+        *value = 0.0;
+    }
+    for &(tx, ty, weight) in &taps {
+        let base_index = (ty * image.width + tx) * C::CHANNELS;
+        let texel = &image.data[base_index..(base_index + C::CHANNELS)];
+        for (value, element) in pixel.iter_mut().zip(texel) {
+            *value += element.to_f32() * weight;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::texture::Rgb;
+
+    /// Points inside faces, avoiding edges where faces tie.
+    fn grid() -> impl Iterator<Item = (f32, f32)> {
+        (0..8).flat_map(|y| (0..8).map(move |x| ((x as f32 + 0.5) / 8.0, (y as f32 + 0.5) / 8.0)))
+    }
+
+    /// A smooth color depending only on the direction.
+    fn color(direction: Vec3) -> [f32; 3] {
+        let d = direction.normalized();
+        [0.5 + 0.5 * d.x, 0.5 + 0.5 * d.y, 0.5 + 0.5 * d.z]
+    }
+
+    #[test]
+    fn face_round_trip() {
+        for (index, &face) in CubeFace::ALL.iter().enumerate() {
+            assert_eq!(face.index(), index);
+            for (u, v) in grid() {
+                let direction = face.direction(u, v);
+                let (found, found_u, found_v) = CubeFace::from_direction(direction);
+                assert_eq!(found, face, "({}, {})", u, v);
+                assert!((found_u - u).abs() < 1e-6, "{:?} ({}, {})", face, u, v);
+                assert!((found_v - v).abs() < 1e-6, "{:?} ({}, {})", face, u, v);
+
+                // Scaling does not change the face.
+                assert_eq!(CubeFace::from_direction(direction * 3.0).0, face);
+            }
+        }
+
+        // The center of each face points its axis.
+        let centers = [
+            Vec3::unit_x(),
+            -Vec3::unit_x(),
+            Vec3::unit_y(),
+            -Vec3::unit_y(),
+            Vec3::unit_z(),
+            -Vec3::unit_z(),
+        ];
+        for (&face, &center) in CubeFace::ALL.iter().zip(&centers) {
+            assert_eq!(face.direction(0.5, 0.5), center);
+        }
+    }
+
+    #[test]
+    fn equirect_round_trip() {
+        for y in 1..16 {
+            for x in 0..32 {
+                let u = (x as f32 + 0.5) / 32.0;
+                let v = y as f32 / 16.0;
+                let direction = equirect_direction(u, v);
+                assert!((direction.mag() - 1.0).abs() < 1e-6);
+                let (found_u, found_v) = equirect_uv(direction);
+                assert!((found_u - u).abs() < 1e-5, "{} != {}", found_u, u);
+                assert!((found_v - v).abs() < 1e-5, "{} != {}", found_v, v);
+            }
+        }
+
+        assert!((equirect_direction(0.5, 0.5) - Vec3::unit_x()).mag() < 1e-6);
+        assert!((equirect_direction(0.0, 0.5) + Vec3::unit_x()).mag() < 1e-6);
+        assert!((equirect_direction(0.75, 0.5) - Vec3::unit_z()).mag() < 1e-6);
+        assert!((equirect_direction(0.3, 0.0) - Vec3::unit_y()).mag() < 1e-6);
+        assert_eq!(equirect_uv(Vec3::new(0.0, 2.0, 0.0)).1, 0.0);
+    }
+
+    #[test]
+    fn cubemap_round_trip() {
+        let (width, height) = (128, 64);
+        let mut data = vec![];
+        for y in 0..height {
+            for x in 0..width {
+                let u = (x as f32 + 0.5) / width as f32;
+                let v = (y as f32 + 0.5) / height as f32;
+                data.extend_from_slice(&color(equirect_direction(u, v)));
+            }
+        }
+        let equirect = ImageData::<f32, Rgb>::new(&data, width, height).unwrap();
+
+        // Texels of faces have the color of their directions.
+        let cubemap = equirect.equirect_to_cubemap(32);
+        assert_eq!(cubemap.size(), 32);
+        for &face in &CubeFace::ALL {
+            let image = cubemap.face(face);
+            for (i, texel) in image.data().chunks_exact(3).enumerate() {
+                let u = ((i % 32) as f32 + 0.5) / 32.0;
+                let v = ((i / 32) as f32 + 0.5) / 32.0;
+                let expected = color(face.direction(u, v));
+                for (a, b) in texel.iter().zip(&expected) {
+                    assert!(
+                        (a - b).abs() < 0.02,
+                        "{:?} ({}, {}): {} != {}",
+                        face,
+                        u,
+                        v,
+                        a,
+                        b
+                    );
+                }
+            }
+        }
+
+        let restored = cubemap.to_equirect(width, height);
+        assert_eq!(restored.dimensions(), (width, height));
+        let max_error = restored
+            .data()
+            .iter()
+            .zip(equirect.data())
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(max_error < 0.03, "{}", max_error);
+    }
+}
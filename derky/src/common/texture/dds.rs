@@ -0,0 +1,169 @@
+//! Contains the encoder and decoder of DirectDraw Surface files of compressed images.
+
+use super::{BlockFormat, ColorSpace, CompressedImage};
+
+use std::convert::TryInto;
+
+use anyhow::{bail, format_err, Result};
+
+/// The magic bytes of DDS files.
+pub(super) const MAGIC: &[u8; 4] = b"DDS ";
+
+/// The size of `DDS_HEADER`.
+const HEADER_SIZE: usize = 124;
+
+/// The offset of `DDS_PIXELFORMAT` in the file.
+const PIXEL_FORMAT_OFFSET: usize = 4 + 72;
+
+/// The offset of `DDS_HEADER_DXT10` in the file.
+const DX10_OFFSET: usize = 4 + HEADER_SIZE;
+
+const DDSD_CAPS: u32 = 0x1;
+const DDSD_HEIGHT: u32 = 0x2;
+const DDSD_WIDTH: u32 = 0x4;
+const DDSD_PIXELFORMAT: u32 = 0x1000;
+const DDSD_MIPMAPCOUNT: u32 = 0x2_0000;
+const DDSD_LINEARSIZE: u32 = 0x8_0000;
+const DDPF_FOURCC: u32 = 0x4;
+const DDSCAPS_COMPLEX: u32 = 0x8;
+const DDSCAPS_TEXTURE: u32 = 0x1000;
+const DDSCAPS_MIPMAP: u32 = 0x40_0000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+const D3D10_RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const D3D11_RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+/// Encodes a compressed image into DDS with the DX10 header.
+pub(super) fn encode_dds(image: &CompressedImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let level_count = image.level_count() as u32;
+    let mipmapped = level_count > 1;
+
+    let mut header = [0u32; HEADER_SIZE / 4];
+    header[0] = HEADER_SIZE as u32;
+    header[1] = DDSD_CAPS | DDSD_HEIGHT | DDSD_WIDTH | DDSD_PIXELFORMAT | DDSD_LINEARSIZE;
+    header[2] = height as u32;
+    header[3] = width as u32;
+    header[4] = image.levels()[0].len() as u32;
+    header[6] = level_count;
+    // DDS_PIXELFORMAT starts at the 19th element.
+    header[18] = 32;
+    header[19] = DDPF_FOURCC;
+    header[20] = u32::from_le_bytes(*b"DX10");
+    header[26] = DDSCAPS_TEXTURE;
+    if mipmapped {
+        header[1] |= DDSD_MIPMAPCOUNT;
+        header[26] |= DDSCAPS_COMPLEX | DDSCAPS_MIPMAP;
+    }
+
+    let dx10_header = [
+        dxgi_format(image.format(), image.color_space()),
+        D3D10_RESOURCE_DIMENSION_TEXTURE2D,
+        0,
+        1,
+        0,
+    ];
+
+    let mut encoded = MAGIC.to_vec();
+    for value in header.iter().chain(&dx10_header) {
+        encoded.extend_from_slice(&value.to_le_bytes());
+    }
+    for level in image.levels() {
+        encoded.extend_from_slice(level);
+    }
+    encoded
+}
+
+/// Decodes a DDS file of a block-compressed 2D texture.
+/// Both legacy FourCC formats and the DX10 header are accepted.
+pub(super) fn decode_dds(data: &[u8]) -> Result<CompressedImage> {
+    let read_u32 = |offset: usize| -> Result<u32> {
+        data.get(offset..(offset + 4))
+            .map(|b| u32::from_le_bytes(b.try_into().expect("Invalid length")))
+            .ok_or_else(|| format_err!("Unexpected end of DDS file"))
+    };
+    if !data.starts_with(MAGIC) || read_u32(4)? as usize != HEADER_SIZE {
+        bail!("Invalid DDS header");
+    }
+
+    let flags = read_u32(8)?;
+    let height = read_u32(12)? as usize;
+    let width = read_u32(16)? as usize;
+    let level_count = match read_u32(28)? {
+        count if flags & DDSD_MIPMAPCOUNT != 0 && count > 0 => count as usize,
+        _ => 1,
+    };
+    if read_u32(4 + 108)? & (DDSCAPS2_CUBEMAP | DDSCAPS2_VOLUME) != 0 {
+        bail!("Cube map and volume DDS files are not supported");
+    }
+
+    if read_u32(PIXEL_FORMAT_OFFSET + 4)? & DDPF_FOURCC == 0 {
+        bail!("Uncompressed DDS files are not supported");
+    }
+    let (format, color_space, data_offset) = match &read_u32(PIXEL_FORMAT_OFFSET + 8)?.to_le_bytes()
+    {
+        b"DX10" => {
+            if read_u32(DX10_OFFSET + 4)? != D3D10_RESOURCE_DIMENSION_TEXTURE2D
+                || read_u32(DX10_OFFSET + 8)? & D3D11_RESOURCE_MISC_TEXTURECUBE != 0
+                || read_u32(DX10_OFFSET + 12)? != 1
+            {
+                bail!("Only single 2D textures are supported");
+            }
+            let (format, color_space) = from_dxgi_format(read_u32(DX10_OFFSET)?)?;
+            (format, color_space, DX10_OFFSET + 20)
+        }
+        b"DXT1" => (BlockFormat::Bc1, ColorSpace::Linear, DX10_OFFSET),
+        b"DXT5" => (BlockFormat::Bc3, ColorSpace::Linear, DX10_OFFSET),
+        b"ATI1" | b"BC4U" => (BlockFormat::Bc4, ColorSpace::Linear, DX10_OFFSET),
+        b"ATI2" | b"BC5U" => (BlockFormat::Bc5, ColorSpace::Linear, DX10_OFFSET),
+        fourcc => bail!(
+            "Unsupported DDS format {:?}",
+            String::from_utf8_lossy(fourcc)
+        ),
+    };
+
+    let mut levels = Vec::with_capacity(level_count);
+    let mut offset = data_offset;
+    for level in 0..level_count {
+        let length = format.image_bytes((width >> level).max(1), (height >> level).max(1));
+        let level_data = data
+            .get(offset..(offset + length))
+            .ok_or_else(|| format_err!("Unexpected end of DDS file"))?;
+        levels.push(level_data.into());
+        offset += length;
+    }
+
+    CompressedImage::new(format, color_space, width, height, levels)
+}
+
+/// Gets the `DXGI_FORMAT` value of the format. DXGI formats do not have primaries.
+fn dxgi_format(format: BlockFormat, color_space: ColorSpace) -> u32 {
+    let srgb = color_space == ColorSpace::Srgb;
+    match format {
+        BlockFormat::Bc1 if srgb => 72,
+        BlockFormat::Bc1 => 71,
+        BlockFormat::Bc3 if srgb => 78,
+        BlockFormat::Bc3 => 77,
+        BlockFormat::Bc4 => 80,
+        BlockFormat::Bc5 => 83,
+        BlockFormat::Bc7 if srgb => 99,
+        BlockFormat::Bc7 => 98,
+    }
+}
+
+/// Gets the format from the `DXGI_FORMAT` value; typeless formats are treated as linear.
+fn from_dxgi_format(value: u32) -> Result<(BlockFormat, ColorSpace)> {
+    let format = match value {
+        70..=72 => BlockFormat::Bc1,
+        76..=78 => BlockFormat::Bc3,
+        79 | 80 => BlockFormat::Bc4,
+        82 | 83 => BlockFormat::Bc5,
+        97..=99 => BlockFormat::Bc7,
+        _ => bail!("Unsupported DXGI format {}", value),
+    };
+    let color_space = match value {
+        72 | 78 | 99 => ColorSpace::Srgb,
+        _ => ColorSpace::Linear,
+    };
+    Ok((format, color_space))
+}
@@ -0,0 +1,245 @@
+//! Contains image writers.
+
+use super::{pfm, ChannelPixel, Channels, ImageData, Rgba};
+
+use std::{fs::write, io::Cursor, path::Path};
+
+use anyhow::{format_err, Result};
+use exr::prelude::rgba_image::{
+    write_options, Compression, Encoding, ImageInfo, Pixel as ExrPixel, SampleType, Vec2,
+};
+use image::{png::PngEncoder, Pixel};
+use log::info;
+
+/// Represents precision of OpenEXR samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    /// 16-bit half floats; values not representable in half are rounded
+    Half,
+
+    /// 32-bit floats; lossless
+    Full,
+}
+
+/// Represents compression methods of OpenEXR files. All of them are lossless.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrCompression {
+    /// No compression; fastest
+    None,
+
+    /// Run-length encoding; fast, suitable for masks and flat images
+    Rle,
+
+    /// Deflate per 16 scanlines; small, but slow
+    Zip,
+
+    /// Wavelet compression; suitable for noisy images
+    Piz,
+}
+
+/// Represents options of OpenEXR writing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExrOptions {
+    /// Sample precision
+    pub precision: ExrPrecision,
+
+    /// Compression method
+    pub compression: ExrCompression,
+}
+
+impl Default for ExrOptions {
+    fn default() -> ExrOptions {
+        ExrOptions {
+            precision: ExrPrecision::Full,
+            compression: ExrCompression::Zip,
+        }
+    }
+}
+
+/// Saves an 8-bit image as PNG.
+pub fn save_png_image<C: ChannelPixel<u8>>(
+    image: &ImageData<u8, C>,
+    filename: impl AsRef<Path>,
+) -> Result<()> {
+    write(filename.as_ref(), encode_png_image(image)?)?;
+    info!("Saved PNG image {:?}", filename.as_ref());
+    Ok(())
+}
+
+/// Encodes an 8-bit image into PNG in memory.
+///
+/// Single and two channel images are stored as grayscale (with alpha). `decode_ldr_image`
+/// always returns RGBA tagged as sRGB, so convert them back with `ImageData::convert`
+/// and `with_color_space` to restore the original layout.
+pub fn encode_png_image<C: ChannelPixel<u8>>(image: &ImageData<u8, C>) -> Result<Vec<u8>> {
+    let (width, height) = image.dimensions();
+    let mut encoded = vec![];
+    PngEncoder::new(&mut encoded).encode(
+        image.data(),
+        width as u32,
+        height as u32,
+        C::Pixel::COLOR_TYPE,
+    )?;
+    Ok(encoded)
+}
+
+/// Saves a float image as OpenEXR.
+pub fn save_exr_image<C: Channels>(
+    image: &ImageData<f32, C>,
+    filename: impl AsRef<Path>,
+    options: &ExrOptions,
+) -> Result<()> {
+    write(filename.as_ref(), encode_exr_image(image, options)?)?;
+    info!("Saved OpenEXR image {:?}", filename.as_ref());
+    Ok(())
+}
+
+/// Encodes a float image into OpenEXR in memory.
+///
+/// The alpha channel is stored only if the image has it. Single channel images are
+/// stored as gray RGB and two channel images as RG with zero B, since `load_hdr_image`
+/// reads RGB(A) layers.
+pub fn encode_exr_image<C: Channels>(
+    image: &ImageData<f32, C>,
+    options: &ExrOptions,
+) -> Result<Vec<u8>> {
+    let sample_type = match options.precision {
+        ExrPrecision::Half => SampleType::F16,
+        ExrPrecision::Full => SampleType::F32,
+    };
+    let compression = match options.compression {
+        ExrCompression::None => Compression::Uncompressed,
+        ExrCompression::Rle => Compression::RLE,
+        ExrCompression::Zip => Compression::ZIP16,
+        ExrCompression::Piz => Compression::PIZ,
+    };
+
+    let (width, height) = image.dimensions();
+    let info = if C::ALPHA {
+        ImageInfo::rgba((width, height), sample_type)
+    } else {
+        ImageInfo::rgb((width, height), sample_type)
+    }
+    .with_encoding(Encoding::for_compression(compression));
+
+    let rgba = image.convert::<Rgba>()?;
+    let data = rgba.data();
+    let mut encoded = Cursor::new(vec![]);
+    info.write_pixels_to_buffered(&mut encoded, write_options::high(), |pos: Vec2<usize>| {
+        let base_index = (pos.y() * width + pos.x()) * 4;
+        let pixel = &data[base_index..(base_index + 4)];
+        if C::ALPHA {
+            ExrPixel::rgba(pixel[0], pixel[1], pixel[2], pixel[3])
+        } else {
+            ExrPixel::rgb(pixel[0], pixel[1], pixel[2])
+        }
+    })
+    .map_err(|e| format_err!("Failed to encode OpenEXR image: {}", e))?;
+
+    Ok(encoded.into_inner())
+}
+
+/// Saves a single or three channel float image as PFM (Portable Float Map).
+/// PFM is easy to inspect, so it is intended for debugging.
+pub fn save_pfm_image<C: Channels>(
+    image: &ImageData<f32, C>,
+    filename: impl AsRef<Path>,
+) -> Result<()> {
+    write(filename.as_ref(), encode_pfm_image(image)?)?;
+    info!("Saved PFM image {:?}", filename.as_ref());
+    Ok(())
+}
+
+/// Encodes a single or three channel float image into PFM in memory.
+/// Images with alpha should be converted beforehand, since PFM can't store it,
+/// and empty images are rejected.
+pub fn encode_pfm_image<C: Channels>(image: &ImageData<f32, C>) -> Result<Vec<u8>> {
+    pfm::encode_pfm(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::texture::{decode_hdr_image, decode_ldr_image, ColorSpace, Rgb, R};
+
+    /// A float image with values outside [0, 1] and distinct texels.
+    fn hdr_image() -> ImageData<f32, Rgba> {
+        let data: Vec<f32> = (0..(5 * 3 * 4)).map(|i| (i as f32 - 10.0) * 0.37).collect();
+        ImageData::new(&data, 5, 3).unwrap()
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let data: Vec<u8> = (0..(7 * 2 * 4)).map(|i| (i * 37 % 256) as u8).collect();
+        let image = ImageData::<u8, Rgba>::new(&data, 7, 2).unwrap();
+        let decoded = decode_ldr_image(&encode_png_image(&image).unwrap()).unwrap();
+        assert_eq!(decoded.dimensions(), (7, 2));
+        assert_eq!(decoded.data(), image.data());
+    }
+
+    #[test]
+    fn png_gray_is_decoded_as_rgba() {
+        let image = ImageData::<u8, R>::new(&[0, 64, 128, 255], 2, 2).unwrap();
+        let decoded = decode_ldr_image(&encode_png_image(&image).unwrap()).unwrap();
+        assert_eq!(&decoded.data()[4..8], &[64, 64, 64, 255]);
+
+        let restored = decoded
+            .convert::<R>()
+            .unwrap()
+            .with_color_space(ColorSpace::Linear);
+        assert_eq!(restored.data(), image.data());
+        assert_eq!(restored.color_space, image.color_space);
+    }
+
+    #[test]
+    fn exr_round_trip() {
+        let image = hdr_image();
+        for &compression in &[
+            ExrCompression::None,
+            ExrCompression::Rle,
+            ExrCompression::Zip,
+            ExrCompression::Piz,
+        ] {
+            let options = ExrOptions {
+                precision: ExrPrecision::Full,
+                compression,
+            };
+            let decoded = decode_hdr_image(&encode_exr_image(&image, &options).unwrap()).unwrap();
+            assert_eq!(decoded.dimensions(), (5, 3));
+            assert_eq!(decoded.data(), image.data(), "{:?}", compression);
+        }
+    }
+
+    #[test]
+    fn exr_half_rounds_values() {
+        let options = ExrOptions {
+            precision: ExrPrecision::Half,
+            ..Default::default()
+        };
+        let image = hdr_image();
+        let decoded = decode_hdr_image(&encode_exr_image(&image, &options).unwrap()).unwrap();
+        for (actual, expected) in decoded.data().iter().zip(image.data()) {
+            assert!((actual - expected).abs() <= expected.abs() / 1024.0);
+        }
+    }
+
+    #[test]
+    fn pfm_round_trip() {
+        let data: Vec<f32> = (0..(4 * 3 * 3)).map(|i| i as f32 * 1.5 - 7.0).collect();
+        let image = ImageData::<f32, Rgb>::new(&data, 4, 3).unwrap();
+        let decoded = decode_hdr_image(&encode_pfm_image(&image).unwrap()).unwrap();
+        assert_eq!(decoded.convert::<Rgb>().unwrap().data(), image.data());
+
+        let gray = ImageData::<f32, R>::new(&data[..12], 3, 4).unwrap();
+        let decoded = decode_hdr_image(&encode_pfm_image(&gray).unwrap()).unwrap();
+        assert_eq!(decoded.convert::<R>().unwrap().data(), gray.data());
+    }
+
+    #[test]
+    fn pfm_rejects_invalid_images() {
+        let empty = ImageData::<f32, Rgb>::new(&[], 0, 0).unwrap();
+        assert!(encode_pfm_image(&empty).is_err());
+        assert!(encode_pfm_image(&hdr_image()).is_err());
+    }
+}
@@ -0,0 +1,205 @@
+//! Contains the encoder and decoder of KTX 2.0 files of compressed images.
+
+use super::{BlockFormat, ColorSpace, CompressedImage};
+
+use std::convert::TryInto;
+
+use anyhow::{bail, format_err, Result};
+
+/// The file identifier of KTX 2.0.
+pub(super) const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+
+/// The size of the identifier, the header, and the index.
+const HEADER_SIZE: usize = 80;
+
+/// The size of an element of the level index.
+const LEVEL_INDEX_SIZE: usize = 24;
+
+const KHR_DF_MODEL_BC1A: u8 = 128;
+const KHR_DF_MODEL_BC3: u8 = 130;
+const KHR_DF_MODEL_BC4: u8 = 131;
+const KHR_DF_MODEL_BC5: u8 = 132;
+const KHR_DF_MODEL_BC7: u8 = 134;
+const KHR_DF_PRIMARIES_BT709: u8 = 1;
+const KHR_DF_PRIMARIES_BT2020: u8 = 9;
+const KHR_DF_TRANSFER_LINEAR: u8 = 1;
+const KHR_DF_TRANSFER_SRGB: u8 = 2;
+
+/// Encodes a compressed image into KTX 2.0 without supercompression.
+pub(super) fn encode_ktx2(image: &CompressedImage) -> Vec<u8> {
+    let (width, height) = image.dimensions();
+    let format = image.format();
+    let level_count = image.level_count();
+    let descriptor = data_format_descriptor(format, image.color_space());
+
+    // Levels are stored from the smallest one, aligned to the block size.
+    let dfd_offset = HEADER_SIZE + LEVEL_INDEX_SIZE * level_count;
+    let mut level_offsets = vec![0; level_count];
+    let mut offset = dfd_offset + descriptor.len();
+    for level in (0..level_count).rev() {
+        offset = align(offset, format.block_bytes());
+        level_offsets[level] = offset;
+        offset += image.levels()[level].len();
+    }
+
+    let mut encoded = IDENTIFIER.to_vec();
+    let header = [
+        vk_format(format, image.color_space()),
+        1,
+        width as u32,
+        height as u32,
+        0,
+        0,
+        1,
+        level_count as u32,
+        0,
+        dfd_offset as u32,
+        descriptor.len() as u32,
+        0,
+        0,
+    ];
+    for value in &header {
+        encoded.extend_from_slice(&value.to_le_bytes());
+    }
+    encoded.extend_from_slice(&[0; 16]);
+    for (level, data) in image.levels().iter().enumerate() {
+        let length = data.len() as u64;
+        for value in &[level_offsets[level] as u64, length, length] {
+            encoded.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    encoded.extend_from_slice(&descriptor);
+    for level in (0..level_count).rev() {
+        encoded.resize(level_offsets[level], 0);
+        encoded.extend_from_slice(&image.levels()[level]);
+    }
+    encoded
+}
+
+/// Decodes a KTX 2.0 file of a block-compressed 2D texture.
+/// The format is determined by `vkFormat`, and only primaries are read from
+/// the data format descriptor.
+pub(super) fn decode_ktx2(data: &[u8]) -> Result<CompressedImage> {
+    let read_u32 = |offset: usize| -> Result<u32> {
+        data.get(offset..(offset + 4))
+            .map(|b| u32::from_le_bytes(b.try_into().expect("Invalid length")))
+            .ok_or_else(|| format_err!("Unexpected end of KTX2 file"))
+    };
+    let read_u64 = |offset: usize| -> Result<usize> {
+        data.get(offset..(offset + 8))
+            .map(|b| u64::from_le_bytes(b.try_into().expect("Invalid length")) as usize)
+            .ok_or_else(|| format_err!("Unexpected end of KTX2 file"))
+    };
+    if !data.starts_with(&IDENTIFIER) {
+        bail!("Invalid KTX2 identifier");
+    }
+
+    let (format, mut color_space) = from_vk_format(read_u32(12)?)?;
+    let width = read_u32(20)? as usize;
+    let height = read_u32(24)? as usize;
+    if read_u32(28)? != 0 || read_u32(32)? > 1 || read_u32(36)? != 1 {
+        bail!("Only single 2D textures are supported");
+    }
+    // Zero means that the mip chain should be generated at runtime.
+    let level_count = (read_u32(40)? as usize).max(1);
+    if read_u32(44)? != 0 {
+        bail!("Supercompressed KTX2 files are not supported");
+    }
+
+    // The primaries follow the total size, the descriptor type, the version, and the model.
+    let primaries_offset = read_u32(48)? as usize + 13;
+    if color_space == ColorSpace::Linear
+        && data.get(primaries_offset) == Some(&KHR_DF_PRIMARIES_BT2020)
+    {
+        color_space = ColorSpace::LinearRec2020;
+    }
+
+    let mut levels = Vec::with_capacity(level_count);
+    for level in 0..level_count {
+        let index_offset = HEADER_SIZE + LEVEL_INDEX_SIZE * level;
+        let offset = read_u64(index_offset)?;
+        let length = read_u64(index_offset + 8)?;
+        let level_data = data
+            .get(offset..(offset + length))
+            .ok_or_else(|| format_err!("Unexpected end of KTX2 file"))?;
+        levels.push(level_data.into());
+    }
+
+    CompressedImage::new(format, color_space, width, height, levels)
+}
+
+/// Builds the data format descriptor with a basic descriptor block.
+fn data_format_descriptor(format: BlockFormat, color_space: ColorSpace) -> Vec<u8> {
+    // Each sample is the channel ID and the bit range in the block.
+    let (model, samples): (_, &[(u8, u16, u8)]) = match format {
+        BlockFormat::Bc1 => (KHR_DF_MODEL_BC1A, &[(1, 0, 64)]),
+        BlockFormat::Bc3 => (KHR_DF_MODEL_BC3, &[(15, 0, 64), (0, 64, 64)]),
+        BlockFormat::Bc4 => (KHR_DF_MODEL_BC4, &[(0, 0, 64)]),
+        BlockFormat::Bc5 => (KHR_DF_MODEL_BC5, &[(0, 0, 64), (1, 64, 64)]),
+        BlockFormat::Bc7 => (KHR_DF_MODEL_BC7, &[(0, 0, 128)]),
+    };
+    let (primaries, transfer) = match color_space {
+        ColorSpace::Srgb => (KHR_DF_PRIMARIES_BT709, KHR_DF_TRANSFER_SRGB),
+        ColorSpace::Linear => (KHR_DF_PRIMARIES_BT709, KHR_DF_TRANSFER_LINEAR),
+        ColorSpace::LinearRec2020 => (KHR_DF_PRIMARIES_BT2020, KHR_DF_TRANSFER_LINEAR),
+    };
+    let block_size = 24 + 16 * samples.len();
+
+    let mut descriptor = vec![];
+    descriptor.extend_from_slice(&(4 + block_size as u32).to_le_bytes());
+    descriptor.extend_from_slice(&0u32.to_le_bytes());
+    descriptor.extend_from_slice(&2u16.to_le_bytes());
+    descriptor.extend_from_slice(&(block_size as u16).to_le_bytes());
+    descriptor.extend_from_slice(&[model, primaries, transfer, 0]);
+    descriptor.extend_from_slice(&[3, 3, 0, 0]);
+    descriptor.extend_from_slice(&[format.block_bytes() as u8, 0, 0, 0, 0, 0, 0, 0]);
+    for &(channel, bit_offset, bit_length) in samples {
+        descriptor.extend_from_slice(&bit_offset.to_le_bytes());
+        descriptor.extend_from_slice(&[bit_length - 1, channel, 0, 0, 0, 0]);
+        descriptor.extend_from_slice(&0u32.to_le_bytes());
+        descriptor.extend_from_slice(&u32::MAX.to_le_bytes());
+    }
+    descriptor
+}
+
+/// Gets the `VkFormat` value of the format.
+fn vk_format(format: BlockFormat, color_space: ColorSpace) -> u32 {
+    let srgb = color_space == ColorSpace::Srgb;
+    match format {
+        BlockFormat::Bc1 if srgb => 134,
+        BlockFormat::Bc1 => 133,
+        BlockFormat::Bc3 if srgb => 138,
+        BlockFormat::Bc3 => 137,
+        BlockFormat::Bc4 => 139,
+        BlockFormat::Bc5 => 141,
+        BlockFormat::Bc7 if srgb => 146,
+        BlockFormat::Bc7 => 145,
+    }
+}
+
+/// Gets the format from the `VkFormat` value. BC1 without alpha is read as BC1.
+fn from_vk_format(value: u32) -> Result<(BlockFormat, ColorSpace)> {
+    let format = match value {
+        131..=134 => BlockFormat::Bc1,
+        137 | 138 => BlockFormat::Bc3,
+        139 => BlockFormat::Bc4,
+        141 => BlockFormat::Bc5,
+        145 | 146 => BlockFormat::Bc7,
+        _ => bail!("Unsupported VkFormat {}", value),
+    };
+    let color_space = match value {
+        132 | 134 | 138 | 146 => ColorSpace::Srgb,
+        _ => ColorSpace::Linear,
+    };
+    Ok((format, color_space))
+}
+
+/// Rounds up the offset to the multiple of the alignment.
+fn align(offset: usize, alignment: usize) -> usize {
+    match offset % alignment {
+        0 => offset,
+        remainder => offset + alignment - remainder,
+    }
+}
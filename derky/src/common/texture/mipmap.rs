@@ -0,0 +1,315 @@
+//! Contains mip chain generation.
+
+use super::{
+    linear_to_srgb, resize::resample_axis, srgb_to_linear, Channels, ColorSpace, ImageData,
+    ImageElement,
+};
+
+use std::f32::consts::PI;
+
+/// The half width of the Kaiser-windowed sinc filter in destination pixels.
+const KAISER_WIDTH: f32 = 2.0;
+
+/// The shape parameter of the Kaiser window.
+const KAISER_ALPHA: f32 = 4.0;
+
+/// Represents filters used to downsample mip levels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipmapFilter {
+    /// Averages covered texels; fast and never rings
+    Box,
+
+    /// Kaiser-windowed sinc; sharper, but may slightly ring around edges
+    Kaiser,
+}
+
+/// Represents options of mip chain generation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MipmapOptions {
+    /// Downsampling filter
+    pub filter: MipmapFilter,
+
+    /// Whether the image wraps around edges, as tiled textures do
+    pub wrap: bool,
+
+    /// Alpha cutoff of cutout textures; if set, alpha is scaled in each level
+    /// so that the ratio of texels passing the alpha test is preserved
+    pub alpha_cutoff: Option<f32>,
+}
+
+impl Default for MipmapOptions {
+    fn default() -> MipmapOptions {
+        MipmapOptions {
+            filter: MipmapFilter::Box,
+            wrap: false,
+            alpha_cutoff: None,
+        }
+    }
+}
+
+/// Represents a full mip chain, from the base level down to 1x1.
+pub struct MipChain<T: Copy, C: Channels> {
+    levels: Box<[ImageData<T, C>]>,
+}
+
+impl<T: Copy, C: Channels> MipChain<T, C> {
+    /// The levels; the first one is the base level.
+    pub fn levels(&self) -> &[ImageData<T, C>] {
+        &self.levels
+    }
+
+    /// The base level.
+    pub fn base(&self) -> &ImageData<T, C> {
+        &self.levels[0]
+    }
+
+    /// The number of levels.
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Consumes this instance and gets the levels.
+    pub fn into_levels(self) -> Box<[ImageData<T, C>]> {
+        self.levels
+    }
+}
+
+/// Calculates the number of levels in the full mip chain for the dimensions.
+pub fn mip_level_count(width: usize, height: usize) -> usize {
+    let mut size = width.max(height);
+    let mut levels = 1;
+    while size > 1 {
+        size /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+impl<T: ImageElement, C: Channels> ImageData<T, C> {
+    /// Generates the full mip chain of this image.
+    ///
+    /// Each level has the half size of the previous one, rounded down, so images
+    /// with non-power-of-two dimensions are supported. The first level is this image.
+    /// sRGB images are filtered in linear space. Results are clamped to non-negative values,
+    /// since sharp filters ring into negative ones around bright texels of HDR images.
+    pub fn generate_mipmaps(&self, options: &MipmapOptions) -> MipChain<T, C> {
+        let colors = C::CHANNELS - C::ALPHA as usize;
+        let srgb = self.color_space == ColorSpace::Srgb;
+
+        let mut pixels: Vec<f32> = self.data.iter().map(|v| v.to_f32()).collect();
+        if srgb {
+            for pixel in pixels.chunks_exact_mut(C::CHANNELS) {
+                for value in &mut pixel[..colors] {
+                    *value = srgb_to_linear(*value);
+                }
+            }
+        }
+        let cutoff = options.alpha_cutoff.filter(|_| C::ALPHA);
+        let coverage = cutoff.map(|c| alpha_coverage::<C>(&pixels, 1.0, c));
+
+        let mut levels = vec![self.clone()];
+        let (mut width, mut height) = (self.width, self.height);
+        for _ in 1..mip_level_count(self.width, self.height) {
+            let next_width = (width / 2).max(1);
+            let next_height = (height / 2).max(1);
+            let horizontal = resample::<C>(&pixels, (width, height), next_width, true, options);
+            pixels = resample::<C>(
+                &horizontal,
+                (next_width, height),
+                next_height,
+                false,
+                options,
+            );
+            width = next_width;
+            height = next_height;
+
+            // The unscaled alpha is kept for the next level, so that scales do not accumulate.
+            let alpha_scale = match (cutoff, coverage) {
+                (Some(cutoff), Some(coverage)) => coverage_scale::<C>(&pixels, cutoff, coverage),
+                _ => 1.0,
+            };
+            let data: Box<[T]> = pixels
+                .chunks_exact(C::CHANNELS)
+                .flat_map(|pixel| {
+                    pixel.iter().enumerate().map(move |(i, &value)| {
+                        if i >= colors {
+                            T::from_f32((value * alpha_scale).clamp(0.0, 1.0))
+                        } else if srgb {
+                            T::from_f32(linear_to_srgb(value.max(0.0)))
+                        } else {
+                            T::from_f32(value.max(0.0))
+                        }
+                    })
+                })
+                .collect();
+            levels.push(ImageData {
+                data,
+                width,
+                height,
+                color_space: self.color_space,
+                _channels: Default::default(),
+            });
+        }
+
+        MipChain {
+            levels: levels.into_boxed_slice(),
+        }
+    }
+}
+
+/// Resamples interleaved pixels along an axis.
+fn resample<C: Channels>(
+    pixels: &[f32],
+    (width, height): (usize, usize),
+    target_length: usize,
+    horizontal: bool,
+    options: &MipmapOptions,
+) -> Vec<f32> {
+    let source_length = if horizontal { width } else { height };
+    let weights = filter_weights(source_length, target_length, options);
+    resample_axis::<C>(pixels, (width, height), &weights, horizontal)
+}
+
+/// Calculates normalized source texels and their weights for each target texel.
+fn filter_weights(
+    source_length: usize,
+    target_length: usize,
+    options: &MipmapOptions,
+) -> Vec<Vec<(usize, f32)>> {
+    let scale = source_length as f32 / target_length as f32;
+    let address = |i: isize| {
+        if options.wrap {
+            i.rem_euclid(source_length as isize) as usize
+        } else {
+            i.max(0).min(source_length as isize - 1) as usize
+        }
+    };
+
+    (0..target_length)
+        .map(|target| {
+            let mut taps: Vec<(usize, f32)> = vec![];
+            match options.filter {
+                MipmapFilter::Box => {
+                    // Weights are the lengths covered by the target texel.
+                    let start = target as f32 * scale;
+                    let end = start + scale;
+                    for i in (start.floor() as isize)..(end.ceil() as isize) {
+                        let covered = end.min(i as f32 + 1.0) - start.max(i as f32);
+                        if covered > 0.0 {
+                            taps.push((address(i), covered));
+                        }
+                    }
+                }
+                MipmapFilter::Kaiser => {
+                    let center = (target as f32 + 0.5) * scale;
+                    let radius = KAISER_WIDTH * scale.max(1.0);
+                    let first = (center - radius).floor() as isize;
+                    let last = (center + radius).ceil() as isize;
+                    for i in first..=last {
+                        let t = (i as f32 + 0.5 - center) / scale.max(1.0);
+                        let weight = sinc(t) * kaiser(t / KAISER_WIDTH);
+                        if weight != 0.0 {
+                            taps.push((address(i), weight));
+                        }
+                    }
+                }
+            }
+
+            let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+            for (_, weight) in &mut taps {
+                *weight /= sum;
+            }
+            taps
+        })
+        .collect()
+}
+
+/// The normalized sinc function.
+pub(super) fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+/// The Kaiser window over [-1, 1].
+fn kaiser(x: f32) -> f32 {
+    if x.abs() > 1.0 {
+        return 0.0;
+    }
+    bessel_i0(KAISER_ALPHA * (1.0 - x * x).sqrt()) / bessel_i0(KAISER_ALPHA)
+}
+
+/// The modified Bessel function of the first kind of order 0.
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let half_sq = x * x / 4.0;
+    for k in 1..32 {
+        term *= half_sq / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
+}
+
+/// Calculates the ratio of texels passing the alpha test with scaled alpha.
+fn alpha_coverage<C: Channels>(pixels: &[f32], scale: f32, cutoff: f32) -> f32 {
+    let alpha = C::CHANNELS - 1;
+    let texels = pixels.len() / C::CHANNELS;
+    let passed = pixels
+        .chunks_exact(C::CHANNELS)
+        .filter(|p| p[alpha] * scale >= cutoff)
+        .count();
+    passed as f32 / texels as f32
+}
+
+/// Searches the alpha scale which gives the coverage closest to the target.
+fn coverage_scale<C: Channels>(pixels: &[f32], cutoff: f32, target: f32) -> f32 {
+    let (mut low, mut high) = (0.0f32, 4.0f32);
+    for _ in 0..16 {
+        let middle = (low + high) / 2.0;
+        if alpha_coverage::<C>(pixels, middle, cutoff) < target {
+            low = middle;
+        } else {
+            high = middle;
+        }
+    }
+
+    // The coverage is a step function, so pick the closer side of the step.
+    let low_error = (alpha_coverage::<C>(pixels, low, cutoff) - target).abs();
+    let high_error = (alpha_coverage::<C>(pixels, high, cutoff) - target).abs();
+    if low_error < high_error {
+        low
+    } else {
+        high
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::texture::Rgba;
+
+    #[test]
+    fn kaiser_keeps_hdr_non_negative() {
+        // A bright texel on a dark background rings the most.
+        let mut data = vec![0.01; 16 * 16 * 4];
+        data[(8 * 16 + 8) * 4..(8 * 16 + 9) * 4].copy_from_slice(&[1000.0, 1000.0, 1000.0, 1.0]);
+        let image = ImageData::<f32, Rgba>::new(&data, 16, 16).unwrap();
+
+        let mipmaps = image.generate_mipmaps(&MipmapOptions {
+            filter: MipmapFilter::Kaiser,
+            ..Default::default()
+        });
+        assert_eq!(mipmaps.level_count(), 5);
+        for level in mipmaps.levels() {
+            assert!(level.data().iter().all(|&v| v >= 0.0));
+        }
+        assert!(mipmaps.levels()[4].data()[0] > 1.0);
+    }
+}
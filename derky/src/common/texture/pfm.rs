@@ -0,0 +1,109 @@
+//! Contains the encoder and decoder of Portable Float Map images.
+
+use super::{Channels, ColorSpace, ImageData, Rgba};
+
+use std::io::Write;
+
+use anyhow::{bail, format_err, Result};
+
+/// Checks whether the data looks like a PFM image.
+pub(super) fn is_pfm(data: &[u8]) -> bool {
+    (data.starts_with(b"PF") || data.starts_with(b"Pf"))
+        && matches!(data.get(2), Some(b) if b.is_ascii_whitespace())
+}
+
+/// Encodes a single or three channel image into PFM.
+///
+/// The header is ASCII so that it can be inspected with text tools, and samples are
+/// little-endian floats. Rows are stored from bottom to top as the format requires.
+pub(super) fn encode_pfm<C: Channels>(image: &ImageData<f32, C>) -> Result<Vec<u8>> {
+    let channels = C::CHANNELS;
+    let magic = match channels {
+        1 => "Pf",
+        3 => "PF",
+        _ => bail!("PFM supports only 1 or 3 channels"),
+    };
+
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        bail!("Empty images cannot be stored as PFM");
+    }
+
+    let mut encoded = vec![];
+    write!(encoded, "{}\n{} {}\n-1.0\n", magic, width, height)?;
+    for row in image.data.chunks_exact(width * channels).rev() {
+        for value in row {
+            encoded.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    Ok(encoded)
+}
+
+/// Decodes a PFM image into RGBA floats, with opaque alpha.
+pub(super) fn decode_pfm(data: &[u8]) -> Result<ImageData<f32, Rgba>> {
+    // The header consists of 4 whitespace-separated tokens and a single whitespace.
+    let is_space = |p: usize| matches!(data.get(p), Some(b) if b.is_ascii_whitespace());
+    let mut tokens = vec![];
+    let mut position = 0;
+    while tokens.len() < 4 {
+        while is_space(position) {
+            position += 1;
+        }
+        let start = position;
+        while position < data.len() && !is_space(position) {
+            position += 1;
+        }
+        if start == position {
+            bail!("Unexpected end of header");
+        }
+        tokens.push(String::from_utf8_lossy(&data[start..position]).to_string());
+    }
+    position += 1;
+
+    let channels = match &tokens[0][..] {
+        "Pf" => 1,
+        "PF" => 3,
+        _ => bail!("Not a PFM image"),
+    };
+    let width: usize = tokens[1].parse()?;
+    let height: usize = tokens[2].parse()?;
+    let scale: f32 = tokens[3].parse()?;
+    let little_endian = scale < 0.0;
+
+    let samples = data
+        .get(position..)
+        .filter(|s| s.len() >= width * height * channels * 4)
+        .ok_or_else(|| format_err!("The data is not enough for the dimensions"))?;
+
+    let mut pixels = vec![0.0; width * height * 4];
+    for (i, sample) in samples
+        .chunks_exact(4)
+        .take(width * height * channels)
+        .enumerate()
+    {
+        let bytes = [sample[0], sample[1], sample[2], sample[3]];
+        let value = if little_endian {
+            f32::from_le_bytes(bytes)
+        } else {
+            f32::from_be_bytes(bytes)
+        };
+
+        let (texel, channel) = (i / channels, i % channels);
+        let (x, y) = (texel % width, height - 1 - texel / width);
+        let base_index = (y * width + x) * 4;
+        if channels == 1 {
+            pixels[base_index..(base_index + 3)].copy_from_slice(&[value; 3]);
+        } else {
+            pixels[base_index + channel] = value;
+        }
+        pixels[base_index + 3] = 1.0;
+    }
+
+    Ok(ImageData {
+        data: pixels.into_boxed_slice(),
+        width,
+        height,
+        color_space: ColorSpace::Linear,
+        _channels: Default::default(),
+    })
+}
@@ -0,0 +1,372 @@
+//! Contains resizing of images into arbitrary dimensions.
+
+use super::{
+    linear_to_srgb, mipmap::sinc, srgb_to_linear, Channels, ColorSpace, ImageData, ImageElement,
+};
+
+use anyhow::{bail, Result};
+
+/// Represents filters used to resize images.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeFilter {
+    /// Takes the nearest texel; keeps hard edges of masks and pixel art
+    Nearest,
+
+    /// Tent filter; interpolates linearly when upscaling, and never rings
+    Triangle,
+
+    /// Catmull-Rom cubic; sharper, but slightly rings around edges
+    CatmullRom,
+
+    /// Lanczos with 3 lobes; the sharpest, but rings around edges
+    Lanczos3,
+}
+
+impl ResizeFilter {
+    /// The radius of the filter in source texels, when not downscaling.
+    fn support(self) -> f32 {
+        match self {
+            ResizeFilter::Nearest => 0.5,
+            ResizeFilter::Triangle => 1.0,
+            ResizeFilter::CatmullRom => 2.0,
+            ResizeFilter::Lanczos3 => 3.0,
+        }
+    }
+
+    /// Evaluates the filter kernel.
+    fn evaluate(self, x: f32) -> f32 {
+        let x = x.abs();
+        match self {
+            ResizeFilter::Nearest if x < 0.5 => 1.0,
+            ResizeFilter::Nearest => 0.0,
+            ResizeFilter::Triangle => (1.0 - x).max(0.0),
+            ResizeFilter::CatmullRom if x < 1.0 => 1.5 * x * x * x - 2.5 * x * x + 1.0,
+            ResizeFilter::CatmullRom if x < 2.0 => -0.5 * x * x * x + 2.5 * x * x - 4.0 * x + 2.0,
+            ResizeFilter::CatmullRom => 0.0,
+            ResizeFilter::Lanczos3 if x < 3.0 => sinc(x) * sinc(x / 3.0),
+            ResizeFilter::Lanczos3 => 0.0,
+        }
+    }
+}
+
+/// Represents how the aspect ratio is treated on resizing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Stretches into the exact dimensions, ignoring the aspect ratio
+    Stretch,
+
+    /// Keeps the aspect ratio and fits inside the dimensions;
+    /// the result is smaller than them along one axis
+    Fit,
+
+    /// Keeps the aspect ratio and covers the dimensions, cropping the center
+    Fill,
+}
+
+/// Represents options of resizing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ResizeOptions {
+    /// Resampling filter
+    pub filter: ResizeFilter,
+
+    /// Treatment of the aspect ratio
+    pub mode: ResizeMode,
+
+    /// Whether the image wraps around edges, as tiled textures do
+    pub wrap: bool,
+}
+
+impl Default for ResizeOptions {
+    fn default() -> ResizeOptions {
+        ResizeOptions {
+            filter: ResizeFilter::Triangle,
+            mode: ResizeMode::Stretch,
+            wrap: false,
+        }
+    }
+}
+
+impl<T: ImageElement, C: Channels> ImageData<T, C> {
+    /// Resizes this image into the dimensions.
+    ///
+    /// Values are filtered as floats, so float images keep values above 1 and HDR images
+    /// can be resized; sRGB images are filtered in linear space. Sharp filters may produce
+    /// negative values around bright edges of float images, and only alpha is clamped.
+    pub fn resize(
+        &self,
+        width: usize,
+        height: usize,
+        options: &ResizeOptions,
+    ) -> Result<ImageData<T, C>> {
+        if width == 0 || height == 0 {
+            bail!("Invalid dimensions: {}x{}", width, height);
+        }
+        if self.width == 0 || self.height == 0 {
+            bail!("The image is empty");
+        }
+
+        // The source window is a rectangle of (x, y, width, height) in texels.
+        let (source_width, source_height) = (self.width as f32, self.height as f32);
+        let ((width, height), window) = match options.mode {
+            ResizeMode::Stretch => ((width, height), (0.0, 0.0, source_width, source_height)),
+            ResizeMode::Fit => {
+                let scale = (width as f32 / source_width).min(height as f32 / source_height);
+                let fitted = |length: f32, limit: usize| {
+                    ((length * scale).round() as usize).max(1).min(limit)
+                };
+                (
+                    (fitted(source_width, width), fitted(source_height, height)),
+                    (0.0, 0.0, source_width, source_height),
+                )
+            }
+            ResizeMode::Fill => {
+                let scale = (width as f32 / source_width).max(height as f32 / source_height);
+                let window_width = width as f32 / scale;
+                let window_height = height as f32 / scale;
+                (
+                    (width, height),
+                    (
+                        (source_width - window_width) / 2.0,
+                        (source_height - window_height) / 2.0,
+                        window_width,
+                        window_height,
+                    ),
+                )
+            }
+        };
+
+        let colors = C::CHANNELS - C::ALPHA as usize;
+        let srgb = self.color_space == ColorSpace::Srgb;
+        let mut pixels: Vec<f32> = self.data.iter().map(|v| v.to_f32()).collect();
+        if srgb {
+            for pixel in pixels.chunks_exact_mut(C::CHANNELS) {
+                for value in &mut pixel[..colors] {
+                    *value = srgb_to_linear(*value);
+                }
+            }
+        }
+
+        let horizontal_weights = filter_weights(options, self.width, (window.0, window.2), width);
+        let vertical_weights = filter_weights(options, self.height, (window.1, window.3), height);
+        let horizontal = resample_axis::<C>(
+            &pixels,
+            (self.width, self.height),
+            &horizontal_weights,
+            true,
+        );
+        let pixels =
+            resample_axis::<C>(&horizontal, (width, self.height), &vertical_weights, false);
+
+        let data: Box<[T]> = pixels
+            .chunks_exact(C::CHANNELS)
+            .flat_map(|pixel| {
+                pixel.iter().enumerate().map(move |(i, &value)| {
+                    if i >= colors {
+                        T::from_f32(value.clamp(0.0, 1.0))
+                    } else if srgb {
+                        T::from_f32(linear_to_srgb(value.max(0.0)))
+                    } else {
+                        T::from_f32(value)
+                    }
+                })
+            })
+            .collect();
+
+        Ok(ImageData {
+            data,
+            width,
+            height,
+            color_space: self.color_space,
+            _channels: Default::default(),
+        })
+    }
+}
+
+/// Resamples interleaved pixels along an axis with source texels and weights
+/// for each target texel.
+pub(super) fn resample_axis<C: Channels>(
+    pixels: &[f32],
+    (width, height): (usize, usize),
+    weights: &[Vec<(usize, f32)>],
+    horizontal: bool,
+) -> Vec<f32> {
+    let (target_width, target_height) = if horizontal {
+        (weights.len(), height)
+    } else {
+        (width, weights.len())
+    };
+
+    let mut result = vec![0.0; target_width * target_height * C::CHANNELS];
+    for y in 0..target_height {
+        for x in 0..target_width {
+            let target_index = (y * target_width + x) * C::CHANNELS;
+            let taps = if horizontal { &weights[x] } else { &weights[y] };
+            for &(source, weight) in taps {
+                let source_index = if horizontal {
+                    (y * width + source) * C::CHANNELS
+                } else {
+                    (source * width + x) * C::CHANNELS
+                };
+                for c in 0..C::CHANNELS {
+                    result[target_index + c] += pixels[source_index + c] * weight;
+                }
+            }
+        }
+    }
+    result
+}
+
+/// Calculates normalized source texels and their weights for each target texel,
+/// mapping the window of (start, length) onto the target.
+fn filter_weights(
+    options: &ResizeOptions,
+    source_length: usize,
+    (start, length): (f32, f32),
+    target_length: usize,
+) -> Vec<Vec<(usize, f32)>> {
+    let scale = length / target_length as f32;
+    // The filter is widened on downscaling, so that it covers all source texels.
+    let stretch = scale.max(1.0);
+    let radius = options.filter.support() * stretch;
+    let address = |i: isize| {
+        if options.wrap {
+            i.rem_euclid(source_length as isize) as usize
+        } else {
+            i.max(0).min(source_length as isize - 1) as usize
+        }
+    };
+
+    (0..target_length)
+        .map(|target| {
+            let center = start + (target as f32 + 0.5) * scale;
+            if options.filter == ResizeFilter::Nearest {
+                return vec![(address(center.floor() as isize), 1.0)];
+            }
+
+            let first = (center - radius - 0.5).floor() as isize;
+            let last = (center + radius - 0.5).ceil() as isize;
+            let mut taps: Vec<(usize, f32)> = (first..=last)
+                .filter_map(|i| {
+                    let weight = options.filter.evaluate((i as f32 + 0.5 - center) / stretch);
+                    if weight != 0.0 {
+                        Some((address(i), weight))
+                    } else {
+                        None
+                    }
+                })
+                .collect();
+
+            let sum: f32 = taps.iter().map(|(_, w)| w).sum();
+            for (_, weight) in &mut taps {
+                *weight /= sum;
+            }
+            taps
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::common::texture::{Rgb, Rgba, R};
+
+    /// A float image whose value is the column index.
+    fn columns(width: usize, height: usize) -> ImageData<f32, R> {
+        let data: Vec<_> = (0..height)
+            .flat_map(|_| (0..width).map(|x| x as f32))
+            .collect();
+        ImageData::new(&data, width, height).unwrap()
+    }
+
+    fn options(filter: ResizeFilter, mode: ResizeMode) -> ResizeOptions {
+        ResizeOptions {
+            filter,
+            mode,
+            wrap: false,
+        }
+    }
+
+    #[test]
+    fn aspect_ratio_modes() {
+        let image = columns(4, 2);
+        let resize = |width, height, mode| {
+            image
+                .resize(width, height, &options(ResizeFilter::Nearest, mode))
+                .unwrap()
+        };
+
+        assert_eq!(resize(8, 8, ResizeMode::Stretch).dimensions(), (8, 8));
+        assert_eq!(resize(8, 8, ResizeMode::Fit).dimensions(), (8, 4));
+        assert_eq!(resize(3, 3, ResizeMode::Fit).dimensions(), (3, 2));
+        assert_eq!(resize(100, 1, ResizeMode::Fit).dimensions(), (2, 1));
+
+        // Filling crops the center columns.
+        let filled = resize(2, 2, ResizeMode::Fill);
+        assert_eq!(filled.dimensions(), (2, 2));
+        assert_eq!(filled.data(), &[1.0, 2.0, 1.0, 2.0]);
+        let filled = resize(4, 1, ResizeMode::Fill);
+        assert_eq!(filled.data(), &[0.0, 1.0, 2.0, 3.0]);
+
+        assert!(image.resize(0, 4, &ResizeOptions::default()).is_err());
+        let empty = ImageData::<f32, R>::new(&[], 0, 0).unwrap();
+        assert!(empty.resize(4, 4, &ResizeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn keeps_values_above_one() {
+        // Upscaling interpolates between texel centers, with clamped edges.
+        let image = ImageData::<f32, R>::new(&[0.0, 8.0], 2, 1).unwrap();
+        let upscaled = image
+            .resize(4, 1, &options(ResizeFilter::Triangle, ResizeMode::Stretch))
+            .unwrap();
+        assert_eq!(upscaled.data(), &[0.0, 2.0, 6.0, 8.0]);
+
+        // Weights are normalized, so flat HDR images stay flat with any filter.
+        let flat = ImageData::<f32, Rgb>::new(&[10.0, 20.0, 40.0].repeat(35), 7, 5).unwrap();
+        for &filter in &[
+            ResizeFilter::Nearest,
+            ResizeFilter::Triangle,
+            ResizeFilter::CatmullRom,
+            ResizeFilter::Lanczos3,
+        ] {
+            for &(width, height) in &[(3, 2), (16, 9)] {
+                let resized = flat
+                    .resize(width, height, &options(filter, ResizeMode::Stretch))
+                    .unwrap();
+                for pixel in resized.data().chunks_exact(3) {
+                    for (value, expected) in pixel.iter().zip(&[10.0, 20.0, 40.0]) {
+                        assert!((value - expected).abs() < 1e-3, "{:?}", filter);
+                    }
+                }
+            }
+        }
+
+        // Only alpha is clamped.
+        let bright =
+            ImageData::<f32, Rgba>::new(&[4.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0, 0.0], 2, 1).unwrap();
+        let resized = bright
+            .resize(8, 1, &options(ResizeFilter::Lanczos3, ResizeMode::Stretch))
+            .unwrap();
+        assert!(resized.data()[0] > 1.0);
+        assert!(resized.data().chunks_exact(4).any(|p| p[0] < 0.0));
+        assert!(resized
+            .data()
+            .chunks_exact(4)
+            .all(|p| (0.0..=1.0).contains(&p[3])));
+    }
+
+    #[test]
+    fn filters_srgb_in_linear_space() {
+        let image = ImageData::<u8, R>::new(&[0, 255], 2, 1)
+            .unwrap()
+            .with_color_space(ColorSpace::Srgb);
+        let resized = image.resize(1, 1, &ResizeOptions::default()).unwrap();
+        assert_eq!(resized.color_space(), ColorSpace::Srgb);
+        assert_eq!(resized.data(), &[188]);
+
+        let linear = image.with_color_space(ColorSpace::Linear);
+        let resized = linear.resize(1, 1, &ResizeOptions::default()).unwrap();
+        assert_eq!(resized.data(), &[128]);
+    }
+}
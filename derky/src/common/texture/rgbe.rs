@@ -0,0 +1,448 @@
+//! Contains the decoder of Radiance HDR (RGBE) images.
+
+use super::{linear_color_space, ColorSpace, ImageData, Rgba};
+
+use anyhow::{bail, format_err, Result};
+
+/// The bias of shared exponents, including 8 bits of mantissas.
+const EXPONENT_BIAS: i32 = 128 + 8;
+
+/// Checks whether the data looks like a Radiance HDR image.
+pub(super) fn is_rgbe(data: &[u8]) -> bool {
+    data.starts_with(b"#?")
+}
+
+/// Decodes a Radiance HDR image into RGBA floats, with opaque alpha.
+///
+/// Both flat and run-length encoded (old and new style) scanlines are supported,
+/// as well as all scanline orientations. `EXPOSURE` is ignored, so pixel values
+/// are returned as they are stored. `PRIMARIES` determines the color space.
+pub(super) fn decode_rgbe(data: &[u8]) -> Result<ImageData<f32, Rgba>> {
+    let mut reader = Reader { data, position: 0 };
+
+    let magic = reader.read_line()?;
+    if !magic.starts_with("#?") {
+        bail!("Not a Radiance HDR image");
+    }
+    let mut color_space = ColorSpace::Linear;
+    loop {
+        let line = reader.read_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                bail!("Unsupported pixel format: {}", format.trim());
+            }
+        }
+        if let Some(primaries) = line.strip_prefix("PRIMARIES=") {
+            let values: Vec<f32> = primaries
+                .split_whitespace()
+                .map(|v| v.parse())
+                .collect::<Result<_, _>>()
+                .map_err(|_| format_err!("Invalid primaries: {}", primaries))?;
+            if values.len() != 8 {
+                bail!("Invalid primaries: {}", primaries);
+            }
+            color_space = linear_color_space([
+                (values[0], values[1]),
+                (values[2], values[3]),
+                (values[4], values[5]),
+            ]);
+        }
+    }
+
+    let resolution = reader.read_line()?;
+    let orientation = Orientation::parse(&resolution)?;
+    let (width, height) = orientation.dimensions();
+
+    let mut data = vec![0.0; width * height * 4];
+    let mut scanline = vec![[0u8; 4]; orientation.scanline_length];
+    for i in 0..orientation.scanline_count {
+        reader.read_scanline(&mut scanline)?;
+        for (j, rgbe) in scanline.iter().enumerate() {
+            let (x, y) = orientation.position(i, j);
+            let base_index = (y * width + x) * 4;
+            data[base_index..(base_index + 4)].copy_from_slice(&rgbe_to_rgba(*rgbe));
+        }
+    }
+
+    Ok(ImageData {
+        data: data.into_boxed_slice(),
+        width,
+        height,
+        color_space,
+        _channels: Default::default(),
+    })
+}
+
+/// Converts a RGBE pixel into RGBA floats.
+fn rgbe_to_rgba([r, g, b, e]: [u8; 4]) -> [f32; 4] {
+    if e == 0 {
+        return [0.0, 0.0, 0.0, 1.0];
+    }
+
+    // Mantissas are the lower bounds of their ranges, so the centers are taken.
+    let scale = 2.0f32.powi(e as i32 - EXPONENT_BIAS);
+    [
+        (r as f32 + 0.5) * scale,
+        (g as f32 + 0.5) * scale,
+        (b as f32 + 0.5) * scale,
+        1.0,
+    ]
+}
+
+/// Represents the order of scanlines, given by the resolution string such as `-Y 512 +X 768`.
+struct Orientation {
+    /// Whether scanlines are rows (Y comes first)
+    rows: bool,
+
+    /// Whether scanlines are stored in the decreasing order of the image coordinate
+    scanline_reversed: bool,
+
+    /// Whether pixels in a scanline are stored in the decreasing order
+    pixel_reversed: bool,
+
+    scanline_count: usize,
+    scanline_length: usize,
+}
+
+impl Orientation {
+    /// Parses the resolution string.
+    fn parse(line: &str) -> Result<Orientation> {
+        let tokens: Vec<_> = line.split_whitespace().collect();
+        if tokens.len() != 4 {
+            bail!("Invalid resolution string: {}", line);
+        }
+
+        let scanline_count = tokens[1].parse()?;
+        let scanline_length = tokens[3].parse()?;
+        if scanline_count == 0 || scanline_length == 0 {
+            bail!("The image is empty");
+        }
+        // Image Y goes down while +Y of Radiance goes up, so -Y is the natural order.
+        let (rows, scanline_reversed, pixel_reversed) = match (tokens[0], tokens[2]) {
+            ("-Y", "+X") => (true, false, false),
+            ("-Y", "-X") => (true, false, true),
+            ("+Y", "+X") => (true, true, false),
+            ("+Y", "-X") => (true, true, true),
+            ("+X", "-Y") => (false, false, false),
+            ("+X", "+Y") => (false, false, true),
+            ("-X", "-Y") => (false, true, false),
+            ("-X", "+Y") => (false, true, true),
+            _ => bail!("Invalid resolution string: {}", line),
+        };
+
+        Ok(Orientation {
+            rows,
+            scanline_reversed,
+            pixel_reversed,
+            scanline_count,
+            scanline_length,
+        })
+    }
+
+    /// The dimensions of the image.
+    fn dimensions(&self) -> (usize, usize) {
+        if self.rows {
+            (self.scanline_length, self.scanline_count)
+        } else {
+            (self.scanline_count, self.scanline_length)
+        }
+    }
+
+    /// Calculates the image position of `j`-th pixel in `i`-th scanline.
+    fn position(&self, i: usize, j: usize) -> (usize, usize) {
+        let scanline = if self.scanline_reversed {
+            self.scanline_count - 1 - i
+        } else {
+            i
+        };
+        let pixel = if self.pixel_reversed {
+            self.scanline_length - 1 - j
+        } else {
+            j
+        };
+
+        if self.rows {
+            (pixel, scanline)
+        } else {
+            (scanline, pixel)
+        }
+    }
+}
+
+/// Reads RGBE data sequentially.
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    /// Reads a byte.
+    fn read_byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| format_err!("Unexpected end of data"))?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    /// Reads a RGBE pixel.
+    fn read_pixel(&mut self) -> Result<[u8; 4]> {
+        Ok([
+            self.read_byte()?,
+            self.read_byte()?,
+            self.read_byte()?,
+            self.read_byte()?,
+        ])
+    }
+
+    /// Reads a header line without the line feed.
+    fn read_line(&mut self) -> Result<String> {
+        let rest = &self.data[self.position..];
+        let length = rest
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| format_err!("Unexpected end of header"))?;
+        self.position += length + 1;
+        Ok(String::from_utf8_lossy(&rest[..length])
+            .trim_end()
+            .to_string())
+    }
+
+    /// Reads a scanline, which can be flat, old-style RLE, or new-style RLE.
+    fn read_scanline(&mut self, scanline: &mut [[u8; 4]]) -> Result<()> {
+        let length = scanline.len();
+        let first = self.read_pixel()?;
+        let new_style =
+            (8..0x8000).contains(&length) && first[0] == 2 && first[1] == 2 && first[2] & 0x80 == 0;
+        if !new_style {
+            return self.read_old_scanline(scanline, first);
+        }
+        if ((first[2] as usize) << 8 | first[3] as usize) != length {
+            bail!("Scanline length mismatch");
+        }
+
+        // New-style scanlines store each component separately.
+        for component in 0..4 {
+            let mut x = 0;
+            while x < length {
+                let count = self.read_byte()? as usize;
+                let (count, run) = if count > 128 {
+                    (count - 128, true)
+                } else {
+                    (count, false)
+                };
+                if count == 0 || x + count > length {
+                    bail!("Invalid run length in scanline");
+                }
+
+                if run {
+                    let value = self.read_byte()?;
+                    for pixel in &mut scanline[x..(x + count)] {
+                        pixel[component] = value;
+                    }
+                } else {
+                    for pixel in &mut scanline[x..(x + count)] {
+                        pixel[component] = self.read_byte()?;
+                    }
+                }
+                x += count;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads a flat or old-style RLE scanline, whose first pixel is already read.
+    fn read_old_scanline(&mut self, scanline: &mut [[u8; 4]], first: [u8; 4]) -> Result<()> {
+        let mut pixel = first;
+        let mut x = 0;
+        let mut shift = 0;
+        loop {
+            if pixel[0] == 1 && pixel[1] == 1 && pixel[2] == 1 {
+                // Repeats the previous pixel; consecutive repeats make larger counts.
+                if x == 0 || shift > 16 {
+                    bail!("Invalid repeat in scanline");
+                }
+                let count = (pixel[3] as usize) << shift;
+                if x + count > scanline.len() {
+                    bail!("Invalid run length in scanline");
+                }
+                let previous = scanline[x - 1];
+                for target in &mut scanline[x..(x + count)] {
+                    *target = previous;
+                }
+                x += count;
+                shift += 8;
+            } else {
+                scanline[x] = pixel;
+                x += 1;
+                shift = 0;
+            }
+
+            if x == scanline.len() {
+                return Ok(());
+            }
+            pixel = self.read_pixel()?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a file from the resolution string and the encoded scanlines.
+    fn file(resolution: &str, scanlines: &[u8]) -> Vec<u8> {
+        let mut data = format!(
+            "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2.0\n\n{}\n",
+            resolution
+        )
+        .into_bytes();
+        data.extend_from_slice(scanlines);
+        data
+    }
+
+    /// Distinct pixels, whose red mantissa is the index.
+    fn pixel(index: u8) -> [u8; 4] {
+        [index, 64, 32, 129]
+    }
+
+    /// Gets the red mantissas of the decoded image in row-major order.
+    fn red_mantissas(image: &ImageData<f32, Rgba>) -> Vec<u8> {
+        image
+            .data()
+            .chunks_exact(4)
+            .map(|p| (p[0] * 128.0 - 0.5).round() as u8)
+            .collect()
+    }
+
+    #[test]
+    fn flat_scanlines() {
+        let pixels: Vec<u8> = (0..6).flat_map(pixel).collect();
+        let image = decode_rgbe(&file("-Y 2 +X 3", &pixels)).unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+        assert_eq!(image.color_space(), ColorSpace::Linear);
+        assert_eq!(&image.data()[0..4], &rgbe_to_rgba(pixel(0)));
+        assert_eq!(&image.data()[20..24], &rgbe_to_rgba(pixel(5)));
+        // The mantissa 64 with the exponent 129 is centered in [0.5, 0.5078125).
+        assert_eq!(image.data()[1], 64.5 / 128.0);
+        assert_eq!(red_mantissas(&image), vec![0, 1, 2, 3, 4, 5]);
+
+        // Zero exponents are black.
+        assert_eq!(rgbe_to_rgba([10, 20, 30, 0]), [0.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn orientations() {
+        let pixels: Vec<u8> = (0..6).flat_map(pixel).collect();
+        let cases: &[(&str, (usize, usize), &[u8])] = &[
+            ("-Y 2 +X 3", (3, 2), &[0, 1, 2, 3, 4, 5]),
+            ("-Y 2 -X 3", (3, 2), &[2, 1, 0, 5, 4, 3]),
+            ("+Y 2 +X 3", (3, 2), &[3, 4, 5, 0, 1, 2]),
+            ("+Y 2 -X 3", (3, 2), &[5, 4, 3, 2, 1, 0]),
+            ("+X 2 -Y 3", (2, 3), &[0, 3, 1, 4, 2, 5]),
+            ("+X 2 +Y 3", (2, 3), &[2, 5, 1, 4, 0, 3]),
+            ("-X 2 -Y 3", (2, 3), &[3, 0, 4, 1, 5, 2]),
+            ("-X 2 +Y 3", (2, 3), &[5, 2, 4, 1, 3, 0]),
+        ];
+        for &(resolution, dimensions, expected) in cases {
+            let image = decode_rgbe(&file(resolution, &pixels)).unwrap();
+            assert_eq!(image.dimensions(), dimensions, "{}", resolution);
+            assert_eq!(red_mantissas(&image), expected, "{}", resolution);
+        }
+
+        for invalid in &["-Y 2 +Y 3", "-Y 2", "-Y 0 +X 3", "-Z 2 +X 3", "-Y a +X 3"] {
+            assert!(Orientation::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn old_rle_scanlines() {
+        // Each repeat copies the previous pixel; consecutive ones are shifted by 8 bits.
+        let mut scanline = vec![];
+        scanline.extend_from_slice(&pixel(7));
+        scanline.extend_from_slice(&[1, 1, 1, 3]);
+        scanline.extend_from_slice(&pixel(9));
+        scanline.extend_from_slice(&[1, 1, 1, 3]);
+        let image = decode_rgbe(&file("-Y 1 +X 8", &scanline)).unwrap();
+        assert_eq!(red_mantissas(&image), vec![7, 7, 7, 7, 9, 9, 9, 9]);
+
+        let mut scanline = vec![];
+        scanline.extend_from_slice(&pixel(7));
+        scanline.extend_from_slice(&[1, 1, 1, 1]);
+        scanline.extend_from_slice(&[1, 1, 1, 1]);
+        let image = decode_rgbe(&file("-Y 1 +X 258", &scanline)).unwrap();
+        assert!(red_mantissas(&image).iter().all(|&r| r == 7));
+
+        // A repeat without previous pixels, and one overrunning the scanline.
+        assert!(decode_rgbe(&file("-Y 1 +X 4", &[1, 1, 1, 3])).is_err());
+        let mut scanline = pixel(7).to_vec();
+        scanline.extend_from_slice(&[1, 1, 1, 4]);
+        assert!(decode_rgbe(&file("-Y 1 +X 4", &scanline)).is_err());
+    }
+
+    #[test]
+    fn new_rle_scanlines() {
+        // Components are stored separately, mixing runs and literals.
+        let mut scanline = vec![2, 2, 0, 8];
+        scanline.extend_from_slice(&[130, 10, 6, 11, 12, 13, 14, 15, 16]);
+        scanline.extend_from_slice(&[136, 64]);
+        scanline.extend_from_slice(&[8, 32, 32, 32, 32, 32, 32, 32, 32]);
+        scanline.extend_from_slice(&[136, 129]);
+        let image = decode_rgbe(&file("-Y 1 +X 8", &scanline)).unwrap();
+        assert_eq!(red_mantissas(&image), vec![10, 10, 11, 12, 13, 14, 15, 16]);
+        assert_eq!(&image.data()[4..8], &rgbe_to_rgba(pixel(10)));
+
+        // Two scanlines, each of which has its own header.
+        let mut scanlines = scanline.clone();
+        scanlines.extend_from_slice(&scanline);
+        let image = decode_rgbe(&file("-Y 2 +X 8", &scanlines)).unwrap();
+        assert_eq!(image.data()[0..32], image.data()[32..64]);
+
+        // The length in the header must match the resolution.
+        let mut mismatched = scanline.clone();
+        mismatched[3] = 9;
+        assert!(decode_rgbe(&file("-Y 1 +X 8", &mismatched)).is_err());
+
+        // Runs must fit in the scanline.
+        let mut overrun = scanline;
+        overrun[4] = 137;
+        assert!(decode_rgbe(&file("-Y 1 +X 8", &overrun)).is_err());
+    }
+
+    #[test]
+    fn truncated_input() {
+        let pixels: Vec<u8> = (0..6).flat_map(pixel).collect();
+        let complete = file("-Y 2 +X 3", &pixels);
+        for length in &[0, 5, 12, complete.len() - pixels.len(), complete.len() - 1] {
+            assert!(decode_rgbe(&complete[..*length]).is_err(), "{}", length);
+        }
+
+        let mut scanline = vec![2, 2, 0, 8, 136, 10, 136, 64, 136, 32];
+        assert!(decode_rgbe(&file("-Y 1 +X 8", &scanline)).is_err());
+        scanline.extend_from_slice(&[136, 129]);
+        assert!(decode_rgbe(&file("-Y 1 +X 8", &scanline)).is_ok());
+    }
+
+    #[test]
+    fn header_fields() {
+        let pixels: Vec<u8> = (0..6).flat_map(pixel).collect();
+        let mut data = b"#?RADIANCE\nPRIMARIES=0.708 0.292 0.170 0.797 0.131 0.046 0.3127 0.3290\n\n-Y 2 +X 3\n".to_vec();
+        data.extend_from_slice(&pixels);
+        assert_eq!(
+            decode_rgbe(&data).unwrap().color_space(),
+            ColorSpace::LinearRec2020
+        );
+
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 2 +X 3\n".to_vec();
+        data.extend_from_slice(&pixels);
+        assert!(decode_rgbe(&data).is_err());
+
+        let mut data = b"#?RADIANCE\nPRIMARIES=0.64 0.33\n\n-Y 2 +X 3\n".to_vec();
+        data.extend_from_slice(&pixels);
+        assert!(decode_rgbe(&data).is_err());
+    }
+}
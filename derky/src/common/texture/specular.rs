@@ -0,0 +1,356 @@
+//! Contains precomputation of specular image-based lighting with the GGX distribution.
+//!
+//! Both generators use the Hammersley sequence instead of random numbers,
+//! so results are deterministic.
+
+use super::{CubeFace, CubeMap, ImageData, MipmapOptions, Rg, Rgba};
+
+use std::f32::consts::PI;
+
+use ultraviolet::Vec3;
+
+/// Represents options of environment prefiltering.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct PrefilterOptions {
+    /// Face size of the first level; each level has the half size of the previous one
+    pub base_size: usize,
+
+    /// Number of levels; roughness goes from 0 to 1 linearly across them
+    pub levels: usize,
+
+    /// Number of importance samples per texel
+    pub sample_count: u32,
+}
+
+impl Default for PrefilterOptions {
+    fn default() -> PrefilterOptions {
+        PrefilterOptions {
+            base_size: 128,
+            levels: 6,
+            sample_count: 256,
+        }
+    }
+}
+
+/// Represents a prefiltered environment; a mip chain of cube maps indexed by roughness.
+#[derive(Clone)]
+pub struct PrefilteredEnvironment {
+    levels: Box<[CubeMap<f32, Rgba>]>,
+}
+
+impl PrefilteredEnvironment {
+    /// Creates from levels; the first one is for roughness 0, and the last one is for 1.
+    pub fn new(levels: Vec<CubeMap<f32, Rgba>>) -> PrefilteredEnvironment {
+        PrefilteredEnvironment {
+            levels: levels.into_boxed_slice(),
+        }
+    }
+
+    /// The levels.
+    pub fn levels(&self) -> &[CubeMap<f32, Rgba>] {
+        &self.levels
+    }
+
+    /// The number of levels.
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// The roughness which the level is filtered for.
+    /// Shaders should select the level with `roughness * (level_count - 1)`.
+    pub fn roughness(&self, level: usize) -> f32 {
+        level_roughness(level, self.levels.len())
+    }
+}
+
+/// Prefilters an environment cube map with the GGX distribution for each roughness,
+/// assuming the view direction equals the normal as in the split sum approximation.
+///
+/// Samples are fetched from the mip chain of the source according to their density,
+/// which suppresses aliasing from bright spots with few samples.
+/// The source must be linear; results keep its color space.
+pub fn prefilter_environment(
+    source: &CubeMap<f32, Rgba>,
+    options: &PrefilterOptions,
+) -> PrefilteredEnvironment {
+    let source_mips = source_mipmaps(source);
+    let source_size = source.size() as f32;
+    let texel_solid_angle = 4.0 * PI / (6.0 * source_size * source_size);
+    let color_space = source.face(CubeFace::PositiveX).color_space();
+
+    let levels = (0..options.levels.max(1))
+        .map(|level| {
+            let size = (options.base_size >> level).max(1);
+            let roughness = level_roughness(level, options.levels);
+            let faces = CubeFace::ALL
+                .iter()
+                .map(|&face| {
+                    let mut data = Vec::with_capacity(size * size * 4);
+                    for y in 0..size {
+                        for x in 0..size {
+                            let u = (x as f32 + 0.5) / size as f32;
+                            let v = (y as f32 + 0.5) / size as f32;
+                            let normal = face.direction(u, v).normalized();
+                            let color = if roughness == 0.0 {
+                                sample_lod(&source_mips, normal, 0.0)
+                            } else {
+                                prefilter_texel(
+                                    &source_mips,
+                                    normal,
+                                    roughness,
+                                    options.sample_count,
+                                    texel_solid_angle,
+                                )
+                            };
+                            data.extend_from_slice(&[color.x, color.y, color.z, 1.0]);
+                        }
+                    }
+                    ImageData::new(&data, size, size)
+                        .expect("Invalid dimensions")
+                        .with_color_space(color_space)
+                })
+                .collect();
+            CubeMap::new(faces).expect("Invalid faces")
+        })
+        .collect();
+
+    PrefilteredEnvironment::new(levels)
+}
+
+/// Generates the BRDF integration LUT of the split sum approximation.
+///
+/// Columns are `dot(N, V)` and rows are roughness, both sampled at texel centers
+/// and increasing from the top-left. The first channel is the scale to F0 and
+/// the second is the bias, so that the specular color is `F0 * R + G`.
+pub fn brdf_lut(size: usize, sample_count: u32) -> ImageData<f32, Rg> {
+    let mut data = Vec::with_capacity(size * size * 2);
+    for y in 0..size {
+        let roughness = (y as f32 + 0.5) / size as f32;
+        for x in 0..size {
+            let n_dot_v = (x as f32 + 0.5) / size as f32;
+            let (scale, bias) = integrate_brdf(n_dot_v, roughness, sample_count);
+            data.push(scale);
+            data.push(bias);
+        }
+    }
+
+    ImageData::new(&data, size, size).expect("Invalid dimensions")
+}
+
+/// Integrates the GGX specular BRDF with Schlick's Fresnel into the scale and the bias.
+pub fn integrate_brdf(n_dot_v: f32, roughness: f32, sample_count: u32) -> (f32, f32) {
+    let normal = Vec3::unit_z();
+    let view = Vec3::new((1.0 - n_dot_v * n_dot_v).sqrt(), 0.0, n_dot_v);
+
+    let (mut scale, mut bias) = (0.0, 0.0);
+    for i in 0..sample_count {
+        let half = importance_sample_ggx(hammersley(i, sample_count), normal, roughness);
+        let light = half * (2.0 * view.dot(half)) - view;
+
+        let n_dot_l = light.z;
+        let n_dot_h = half.z.max(0.0);
+        let v_dot_h = view.dot(half).max(0.0);
+        if n_dot_l > 0.0 {
+            let visibility = smith_ggx(n_dot_v, n_dot_l, roughness);
+            let weight = visibility * v_dot_h / (n_dot_h * n_dot_v);
+            let fresnel = (1.0 - v_dot_h).powi(5);
+            scale += (1.0 - fresnel) * weight;
+            bias += fresnel * weight;
+        }
+    }
+
+    (scale / sample_count as f32, bias / sample_count as f32)
+}
+
+/// Calculates the roughness of the level.
+fn level_roughness(level: usize, levels: usize) -> f32 {
+    if levels <= 1 {
+        0.0
+    } else {
+        level as f32 / (levels - 1) as f32
+    }
+}
+
+/// Prefilters a texel by importance sampling.
+fn prefilter_texel(
+    source_mips: &[CubeMap<f32, Rgba>],
+    normal: Vec3,
+    roughness: f32,
+    sample_count: u32,
+    texel_solid_angle: f32,
+) -> Vec3 {
+    let alpha = roughness * roughness;
+    let mut color = Vec3::zero();
+    let mut total_weight = 0.0;
+    for i in 0..sample_count {
+        let half = importance_sample_ggx(hammersley(i, sample_count), normal, roughness);
+        let light = half * (2.0 * normal.dot(half)) - normal;
+        let n_dot_l = normal.dot(light);
+        if n_dot_l <= 0.0 {
+            continue;
+        }
+
+        // With N = V, the PDF of the light direction is D / 4.
+        let n_dot_h = normal.dot(half).max(0.0);
+        let pdf = ggx_distribution(n_dot_h, alpha) / 4.0;
+        let sample_solid_angle = 1.0 / (sample_count as f32 * pdf + 1e-4);
+        let lod = (0.5 * (sample_solid_angle / texel_solid_angle).log2() + 1.0).max(0.0);
+
+        color += sample_lod(source_mips, light, lod) * n_dot_l;
+        total_weight += n_dot_l;
+    }
+
+    color / total_weight.max(1e-6)
+}
+
+/// Builds the mip chain of a cube map.
+fn source_mipmaps(source: &CubeMap<f32, Rgba>) -> Vec<CubeMap<f32, Rgba>> {
+    let chains: Vec<_> = source
+        .faces()
+        .iter()
+        .map(|face| face.generate_mipmaps(&MipmapOptions::default()))
+        .collect();
+
+    (0..chains[0].level_count())
+        .map(|level| {
+            let faces = chains.iter().map(|c| c.levels()[level].clone()).collect();
+            CubeMap::new(faces).expect("Invalid faces")
+        })
+        .collect()
+}
+
+/// Samples the mip chain trilinearly.
+fn sample_lod(source_mips: &[CubeMap<f32, Rgba>], direction: Vec3, lod: f32) -> Vec3 {
+    let max_level = source_mips.len() - 1;
+    let lod = lod.min(max_level as f32);
+    let lower = lod.floor() as usize;
+    let upper = (lower + 1).min(max_level);
+    let t = lod - lower as f32;
+
+    let mut pixel = [0.0; 4];
+    source_mips[lower].sample(direction, &mut pixel);
+    let lower_color = Vec3::new(pixel[0], pixel[1], pixel[2]);
+    if t == 0.0 || upper == lower {
+        return lower_color;
+    }
+    source_mips[upper].sample(direction, &mut pixel);
+    let upper_color = Vec3::new(pixel[0], pixel[1], pixel[2]);
+    lower_color * (1.0 - t) + upper_color * t
+}
+
+/// Gets the `i`-th point of the Hammersley sequence in [0, 1)^2.
+fn hammersley(i: u32, count: u32) -> (f32, f32) {
+    let radical_inverse = i.reverse_bits() as f32 * 2.328_306_4e-10;
+    (i as f32 / count as f32, radical_inverse)
+}
+
+/// Samples a half vector around the normal from the GGX distribution.
+fn importance_sample_ggx((u, v): (f32, f32), normal: Vec3, roughness: f32) -> Vec3 {
+    let alpha = roughness * roughness;
+    let phi = 2.0 * PI * u;
+    let cos_theta = ((1.0 - v) / (1.0 + (alpha * alpha - 1.0) * v)).sqrt();
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+
+    let up = if normal.z.abs() < 0.999 {
+        Vec3::unit_z()
+    } else {
+        Vec3::unit_x()
+    };
+    let tangent = up.cross(normal).normalized();
+    let bitangent = normal.cross(tangent);
+    (tangent * (sin_theta * phi.cos()) + bitangent * (sin_theta * phi.sin()) + normal * cos_theta)
+        .normalized()
+}
+
+/// The GGX normal distribution function.
+fn ggx_distribution(n_dot_h: f32, alpha: f32) -> f32 {
+    let alpha_sq = alpha * alpha;
+    let denominator = n_dot_h * n_dot_h * (alpha_sq - 1.0) + 1.0;
+    alpha_sq / (PI * denominator * denominator)
+}
+
+/// The Smith geometry term with the Schlick-GGX approximation for image lighting.
+fn smith_ggx(n_dot_v: f32, n_dot_l: f32, roughness: f32) -> f32 {
+    let k = roughness * roughness / 2.0;
+    let g1 = |n_dot_x: f32| n_dot_x / (n_dot_x * (1.0 - k) + k);
+    g1(n_dot_v) * g1(n_dot_l)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn integrate_brdf_smooth_head_on() {
+        let (scale, bias) = integrate_brdf(1.0, 0.0, 64);
+        assert!((scale - 1.0).abs() < 1e-5, "{}", scale);
+        assert!(bias.abs() < 1e-5, "{}", bias);
+    }
+
+    #[test]
+    fn integrate_brdf_matches_reference() {
+        // (N・V, roughness, scale, bias), integrated by quadrature over half vectors
+        // with the same GGX distribution and Smith visibility.
+        let references = [
+            (0.5, 0.5, 0.7285, 0.01855),
+            (1.0, 0.5, 0.8950, 0.0),
+            (0.75, 0.25, 0.9717, 0.00144),
+            (0.25, 0.75, 0.5935, 0.02063),
+            (0.5, 1.0, 0.4067, 0.00246),
+            (0.1, 0.3, 0.4284, 0.2316),
+        ];
+        for &(n_dot_v, roughness, scale, bias) in &references {
+            let actual = integrate_brdf(n_dot_v, roughness, 16384);
+            assert!(
+                (actual.0 - scale).abs() < 2e-3 && (actual.1 - bias).abs() < 1e-3,
+                "{:?} at ({}, {})",
+                actual,
+                n_dot_v,
+                roughness
+            );
+        }
+    }
+
+    #[test]
+    fn brdf_lut_layout() {
+        let lut = brdf_lut(4, 128);
+        assert_eq!(lut.dimensions(), (4, 4));
+
+        // Rougher rows reflect less at the same N・V, and grazing columns get more bias.
+        let texel = |x: usize, y: usize| {
+            let index = (y * 4 + x) * 2;
+            (lut.data()[index], lut.data()[index + 1])
+        };
+        assert!(texel(3, 0).0 > texel(3, 3).0);
+        assert!(texel(0, 1).1 > texel(3, 1).1);
+    }
+
+    #[test]
+    fn prefilter_keeps_constant_environment() {
+        let color = [0.25, 0.5, 2.0, 1.0];
+        let face = || {
+            let data: Vec<f32> = color.iter().copied().cycle().take(16 * 16 * 4).collect();
+            ImageData::new(&data, 16, 16).unwrap()
+        };
+        let source = CubeMap::new((0..6).map(|_| face()).collect()).unwrap();
+        let options = PrefilterOptions {
+            base_size: 8,
+            levels: 4,
+            sample_count: 32,
+        };
+
+        let environment = prefilter_environment(&source, &options);
+        assert_eq!(environment.level_count(), 4);
+        for level in environment.levels() {
+            for face in level.faces() {
+                for pixel in face.data().chunks_exact(4) {
+                    for (actual, expected) in pixel.iter().zip(&color) {
+                        assert!((actual - expected).abs() < 1e-4, "{:?}", pixel);
+                    }
+                }
+            }
+        }
+        assert_eq!(environment.roughness(0), 0.0);
+        assert_eq!(environment.roughness(3), 1.0);
+    }
+}
@@ -1,7 +1,10 @@
 //! Contains types of textures.
 
 use crate::{
-    common::texture::{load_hdr_image, load_ldr_image, Channels, ImageData, Rgba},
+    common::texture::{
+        load_hdr_image, load_ldr_image, BlockFormat, Channels, ColorSpace, CompressedImage,
+        ImageData, MipChain, Rgba,
+    },
     comptrize,
     d3d11::{
         com_support::{ComPtr, HresultErrorExt},
@@ -51,6 +54,19 @@ impl TextureElement for f32 {
     }
 }
 
+/// Returns `DXGI_FORMAT` of the image; 8-bit RGBA images tagged as sRGB are decoded
+/// into linear on sampling.
+fn image_format<T: TextureElement, C: Channels>(
+    image: &ImageData<T, C>,
+) -> dxgiformat::DXGI_FORMAT {
+    match T::get_format(C::CHANNELS) {
+        dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM if image.color_space() == ColorSpace::Srgb => {
+            dxgiformat::DXGI_FORMAT_R8G8B8A8_UNORM_SRGB
+        }
+        format => format,
+    }
+}
+
 /// Contains `ID3D11Texture2D`, `ID3D11ShaderResourceView`, and `ID3D11SamplerState`.
 pub struct Texture {
     pub(crate) _texture: ComPtr<d3d11::ID3D11Texture2D>,
@@ -67,10 +83,32 @@ impl Texture {
         data: &ImageData<T, C>,
     ) -> Result<Texture> {
         let channels = C::CHANNELS;
-        let format = T::get_format(channels);
+        let format = image_format(data);
         let dimensions = data.dimensions();
-        let texture = unsafe { Texture::create_texture(device, data)? };
-        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format)? };
+        let texture = unsafe { Texture::create_texture(device, &[data])? };
+        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format, 1)? };
+
+        Ok(Texture {
+            _texture: texture,
+            view,
+            format,
+            channels,
+            dimensions,
+        })
+    }
+
+    /// Creates a `Texture` with all levels of the mip chain.
+    pub fn with_mipmaps<T: TextureElement, C: Channels>(
+        device: &Device,
+        chain: &MipChain<T, C>,
+    ) -> Result<Texture> {
+        let channels = C::CHANNELS;
+        let format = image_format(chain.base());
+        let dimensions = chain.base().dimensions();
+        let levels: Vec<_> = chain.levels().iter().collect();
+        let texture = unsafe { Texture::create_texture(device, &levels)? };
+        let view =
+            unsafe { Texture::create_view(device, texture.as_ptr(), format, levels.len() as u32)? };
 
         Ok(Texture {
             _texture: texture,
@@ -81,6 +119,50 @@ impl Texture {
         })
     }
 
+    /// Creates a `Texture` from block-compressed data with all of its levels.
+    pub fn compressed(device: &Device, image: &CompressedImage) -> Result<Texture> {
+        let srgb = image.color_space() == ColorSpace::Srgb;
+        let format = match image.format() {
+            BlockFormat::Bc1 if srgb => dxgiformat::DXGI_FORMAT_BC1_UNORM_SRGB,
+            BlockFormat::Bc1 => dxgiformat::DXGI_FORMAT_BC1_UNORM,
+            BlockFormat::Bc3 if srgb => dxgiformat::DXGI_FORMAT_BC3_UNORM_SRGB,
+            BlockFormat::Bc3 => dxgiformat::DXGI_FORMAT_BC3_UNORM,
+            BlockFormat::Bc4 => dxgiformat::DXGI_FORMAT_BC4_UNORM,
+            BlockFormat::Bc5 => dxgiformat::DXGI_FORMAT_BC5_UNORM,
+            BlockFormat::Bc7 if srgb => dxgiformat::DXGI_FORMAT_BC7_UNORM_SRGB,
+            BlockFormat::Bc7 => dxgiformat::DXGI_FORMAT_BC7_UNORM,
+        };
+        let dimensions = image.dimensions();
+
+        // The pitch of compressed levels is the size of a row of blocks.
+        let initials: Vec<_> = image
+            .levels()
+            .iter()
+            .enumerate()
+            .map(|(level, data)| {
+                let (width, _) = image.level_dimensions(level);
+                d3d11::D3D11_SUBRESOURCE_DATA {
+                    pSysMem: data.as_ptr() as *const c_void,
+                    SysMemPitch: image.format().image_bytes(width, 1) as u32,
+                    SysMemSlicePitch: data.len() as u32,
+                }
+            })
+            .collect();
+        let texture =
+            unsafe { Texture::create_texture_raw(device, format, dimensions, &initials)? };
+        let view = unsafe {
+            Texture::create_view(device, texture.as_ptr(), format, initials.len() as u32)?
+        };
+
+        Ok(Texture {
+            _texture: texture,
+            view,
+            format,
+            channels: image.format().channels(),
+            dimensions,
+        })
+    }
+
     /// Returns channel count.
     pub fn channels(&self) -> usize {
         self.channels
@@ -103,7 +185,7 @@ impl Texture {
         data: &ImageData<T, C>,
     ) -> Result<()> {
         let channels = C::CHANNELS;
-        let format = T::get_format(channels);
+        let format = image_format(data);
         let dimensions = data.dimensions();
 
         if self.format != format {
@@ -138,14 +220,24 @@ impl Texture {
     }
 
     /// Loads and creates a texture from LDR image file (JPEG, PNG, etc.).
-    pub fn load_ldr(device: &Device, filename: impl AsRef<Path>) -> Result<Texture> {
-        let image = load_ldr_image(filename)?.resize_to_power_of_2();
+    ///
+    /// The image is tagged with `color_space`; pass `ColorSpace::Srgb` for colors such as
+    /// albedo, and `ColorSpace::Linear` for data such as normal maps, so that
+    /// only colors are decoded from sRGB on sampling.
+    pub fn load_ldr(
+        device: &Device,
+        filename: impl AsRef<Path>,
+        color_space: ColorSpace,
+    ) -> Result<Texture> {
+        let image = load_ldr_image(filename)?
+            .with_color_space(color_space)
+            .resize_to_power_of_2();
 
         let channels = Rgba::CHANNELS;
-        let format = u8::get_format(channels);
+        let format = image_format(&image);
         let dimensions = image.dimensions();
-        let texture = unsafe { Texture::create_texture(device, &image)? };
-        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format)? };
+        let texture = unsafe { Texture::create_texture(device, &[&image])? };
+        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format, 1)? };
 
         Ok(Texture {
             _texture: texture,
@@ -156,15 +248,15 @@ impl Texture {
         })
     }
 
-    /// Loads and creates a texture from HDR image file (OpenEXR only).
+    /// Loads and creates a texture from HDR image file (OpenEXR or Radiance HDR).
     pub fn load_hdr(device: &Device, filename: impl AsRef<Path>) -> Result<Texture> {
         let image = load_hdr_image(filename)?.resize_to_power_of_2();
 
         let channels = Rgba::CHANNELS;
         let format = f32::get_format(channels);
         let dimensions = image.dimensions();
-        let texture = unsafe { Texture::create_texture(device, &image)? };
-        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format)? };
+        let texture = unsafe { Texture::create_texture(device, &[&image])? };
+        let view = unsafe { Texture::create_view(device, texture.as_ptr(), format, 1)? };
 
         Ok(Texture {
             _texture: texture,
@@ -175,19 +267,43 @@ impl Texture {
         })
     }
 
-    /// Creates a `ID3D11Texture2D`.
+    /// Creates a `ID3D11Texture2D` from mip levels; the first one is the base level.
     unsafe fn create_texture<T: TextureElement, C: Channels>(
         device: &Device,
-        image: &ImageData<T, C>,
+        levels: &[&ImageData<T, C>],
     ) -> Result<ComPtr<d3d11::ID3D11Texture2D>> {
-        let (width, height) = image.dimensions();
         let channels = C::CHANNELS;
-        let format = T::get_format(channels);
+        let format = image_format(levels[0]);
+
+        let initials: Vec<_> = levels
+            .iter()
+            .map(|image| {
+                let (width, height) = image.dimensions();
+                d3d11::D3D11_SUBRESOURCE_DATA {
+                    pSysMem: image.data().as_ptr() as *const c_void,
+                    SysMemPitch: size_of::<T>() as u32 * width as u32 * channels as u32,
+                    SysMemSlicePitch: size_of::<T>() as u32
+                        * width as u32
+                        * height as u32
+                        * channels as u32,
+                }
+            })
+            .collect();
+
+        Texture::create_texture_raw(device, format, levels[0].dimensions(), &initials)
+    }
 
+    /// Creates a `ID3D11Texture2D` from initial data of each mip level.
+    unsafe fn create_texture_raw(
+        device: &Device,
+        format: dxgiformat::DXGI_FORMAT,
+        (width, height): (usize, usize),
+        initials: &[d3d11::D3D11_SUBRESOURCE_DATA],
+    ) -> Result<ComPtr<d3d11::ID3D11Texture2D>> {
         let desc = d3d11::D3D11_TEXTURE2D_DESC {
             Width: width as u32,
             Height: height as u32,
-            MipLevels: 1,
+            MipLevels: initials.len() as u32,
             ArraySize: 1,
             Format: format,
             SampleDesc: dxgitype::DXGI_SAMPLE_DESC {
@@ -200,21 +316,12 @@ impl Texture {
             MiscFlags: 0,
         };
 
-        let initial = d3d11::D3D11_SUBRESOURCE_DATA {
-            pSysMem: image.data().as_ptr() as *const c_void,
-            SysMemPitch: size_of::<T>() as u32 * width as u32 * channels as u32,
-            SysMemSlicePitch: size_of::<T>() as u32
-                * width as u32
-                * height as u32
-                * channels as u32,
-        };
-
         let mut texture = null!(d3d11::ID3D11Texture2D);
         device
             .device
             .CreateTexture2D(
                 &desc,
-                &initial,
+                initials.as_ptr(),
                 &mut texture as *mut *mut d3d11::ID3D11Texture2D,
             )
             .err()
@@ -229,13 +336,14 @@ impl Texture {
         device: &Device,
         texture_ptr: *mut d3d11::ID3D11Texture2D,
         format: dxgiformat::DXGI_FORMAT,
+        mip_levels: u32,
     ) -> Result<ComPtr<d3d11::ID3D11ShaderResourceView>> {
         let mut srv_desc = d3d11::D3D11_SHADER_RESOURCE_VIEW_DESC {
             Format: format,
             ViewDimension: d3dcommon::D3D11_SRV_DIMENSION_TEXTURE2D,
             u: zeroed(),
         };
-        srv_desc.u.Texture2D_mut().MipLevels = 1;
+        srv_desc.u.Texture2D_mut().MipLevels = mip_levels;
 
         let mut view = null!(d3d11::ID3D11ShaderResourceView);
         device
@@ -371,7 +479,7 @@ impl RenderTarget {
     /// Creates a `Texture` referencing this Render Target.
     pub fn create_texture(&self, device: &Device) -> Result<Texture> {
         let texture = self.texture.clone();
-        let view = unsafe { Texture::create_view(device, texture.as_ptr(), self.format)? };
+        let view = unsafe { Texture::create_view(device, texture.as_ptr(), self.format, 1)? };
 
         Ok(Texture {
             _texture: texture,
@@ -469,6 +577,7 @@ impl DepthStencil {
                 device,
                 texture.as_ptr(),
                 dxgiformat::DXGI_FORMAT_R32_FLOAT,
+                1,
             )?
         };
 
@@ -3,8 +3,18 @@
 
 /// Common operations
 pub mod common {
+    pub mod animation;
+    pub mod asset;
+    pub mod bounds;
+    pub mod cache;
     pub mod environment;
+    pub mod frustum;
+    pub mod ibl;
+    pub mod material;
+    pub mod mesh;
     pub mod model;
+    pub mod scene;
+    pub mod skeleton;
     pub mod texture;
 }
 
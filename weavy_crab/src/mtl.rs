@@ -6,7 +6,7 @@ use ultraviolet::Vec3;
 #[derive(Debug, Clone, PartialEq)]
 pub enum MaterialProperty {
     /// Float value.
-    /// Property name starts with `N`.
+    /// Property name starts with `N`, or is `d`, `Tr` or one of PBR extensions such as `Pr`.
     Float(f32),
 
     /// Integer value.
@@ -17,7 +17,7 @@ pub enum MaterialProperty {
     Vector(Vec3),
 
     /// Path value.
-    /// Property name starts with `map_`.
+    /// Property name starts with `map_`, or is one of `bump`, `disp`, `decal`, `refl` and `norm`.
     Path(Box<Path>),
 }
 
@@ -82,6 +82,89 @@ impl Material {
         }
     }
 
+    /// The emissive color, which is defined with `Ke`.
+    pub fn emissive_color(&self) -> Option<Vec3> {
+        match self.properties.get("Ke") {
+            Some(MaterialProperty::Vector(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The opacity, which is defined with `d` or `Tr` (transparency).
+    pub fn dissolve(&self) -> Option<f32> {
+        match (self.properties.get("d"), self.properties.get("Tr")) {
+            (Some(MaterialProperty::Float(v)), _) => Some(*v),
+            (_, Some(MaterialProperty::Float(v))) => Some(1.0 - *v),
+            _ => None,
+        }
+    }
+
+    /// The PBR roughness, which is defined with `Pr`.
+    pub fn roughness(&self) -> Option<f32> {
+        match self.properties.get("Pr") {
+            Some(MaterialProperty::Float(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The PBR metalness, which is defined with `Pm`.
+    pub fn metalness(&self) -> Option<f32> {
+        match self.properties.get("Pm") {
+            Some(MaterialProperty::Float(v)) => Some(*v),
+            _ => None,
+        }
+    }
+
+    /// The specular color map, which is defined with `map_Ks`.
+    pub fn specular_map(&self) -> Option<&Path> {
+        match self.properties.get("map_Ks") {
+            Some(MaterialProperty::Path(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The roughness map, which is defined with `map_Pr`.
+    pub fn roughness_map(&self) -> Option<&Path> {
+        match self.properties.get("map_Pr") {
+            Some(MaterialProperty::Path(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The metalness map, which is defined with `map_Pm`.
+    pub fn metalness_map(&self) -> Option<&Path> {
+        match self.properties.get("map_Pm") {
+            Some(MaterialProperty::Path(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The dissolve map, which is defined with `map_d`.
+    pub fn dissolve_map(&self) -> Option<&Path> {
+        match self.properties.get("map_d") {
+            Some(MaterialProperty::Path(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The emissive map, which is defined with `map_Ke`.
+    pub fn emissive_map(&self) -> Option<&Path> {
+        match self.properties.get("map_Ke") {
+            Some(MaterialProperty::Path(v)) => Some(v),
+            _ => None,
+        }
+    }
+
+    /// The normal map, which is defined with `norm`, `map_Bump` or `bump`.
+    pub fn normal_map(&self) -> Option<&Path> {
+        ["norm", "map_Bump", "bump"]
+            .iter()
+            .find_map(|key| match self.properties.get(*key) {
+                Some(MaterialProperty::Path(v)) => Some(v.as_ref()),
+                _ => None,
+            })
+    }
+
     /// Returns defined value with specified key.
     pub fn get(&self, key: &str) -> Option<&MaterialProperty> {
         self.properties.get(key)
@@ -369,12 +369,17 @@ fn parse_mtl_line(keyword: &str, data: &[&str]) -> Result<MtlCommand> {
             let value = take_vec3(data)?;
             MtlCommand::Vector(keyword.into(), value)
         }
+        "d" | "Tr" | "Pr" | "Pm" | "Ps" | "Pc" | "Pcr" | "aniso" | "anisor" => {
+            let value = take_single(data)?;
+            MtlCommand::Float(keyword.into(), value)
+        }
         k if k.starts_with("N") => {
             let value = take_single(data)?;
             MtlCommand::Float(keyword.into(), value)
         }
-        k if k.starts_with("map_") => {
-            let value = data.get(0).unwrap_or(&"").replace("\\\\", "\\");
+        k if k.starts_with("map_") || ["bump", "disp", "decal", "refl", "norm"].contains(&k) => {
+            // Options such as `-bm 1.0` precede the filename.
+            let value = data.last().unwrap_or(&"").replace("\\\\", "\\");
             let value = PathBuf::from_str(&value).map_err(|_| Error::PathNotFound(value))?;
             MtlCommand::Path(keyword.into(), value.into_boxed_path())
         }